@@ -2,7 +2,10 @@
 
 use moon_driver_utils::registry::query_registry_string;
 use moon_instructions::cpuidex;
-use moon_struct::x86::{X86_CPUID_FEATURE_ECX_HVP, X86_CPUID_FEATURE_ECX_VMX};
+use moon_struct::x86::{
+    X86_CPUID_AMD_FEATURE_ECX_SVM, X86_CPUID_FEATURE_ECX_HVP, X86_CPUID_FEATURE_ECX_VMX,
+    X86_CPUID_SVM_FEATURE_EDX_NESTED_PAGING, X86_CPUID_SVM_FEATURE_EDX_NRIP_SAVE,
+};
 
 #[derive(PartialEq, Eq)]
 pub enum CpuManufacturer {
@@ -21,6 +24,36 @@ pub fn vmx_support() -> bool {
     true
 }
 
+pub fn svm_support() -> bool {
+    let cpuid_result = cpuidex(0x80000001, 0);
+
+    if cpuid_result.ecx & X86_CPUID_AMD_FEATURE_ECX_SVM == 0 {
+        return false;
+    }
+
+    true
+}
+
+pub fn svm_nested_paging_support() -> bool {
+    let cpuid_result = cpuidex(0x8000000A, 0);
+
+    if cpuid_result.edx & X86_CPUID_SVM_FEATURE_EDX_NESTED_PAGING == 0 {
+        return false;
+    }
+
+    true
+}
+
+pub fn svm_next_rip_support() -> bool {
+    let cpuid_result = cpuidex(0x8000000A, 0);
+
+    if cpuid_result.edx & X86_CPUID_SVM_FEATURE_EDX_NRIP_SAVE == 0 {
+        return false;
+    }
+
+    true
+}
+
 pub fn hypervisor_present() -> bool {
     let cpuid_result = cpuidex(1, 0);
 
@@ -78,6 +78,28 @@ pub fn write_cr0(value: u64) {
     }
 }
 
+pub fn read_cr2() -> u64 {
+    let mut result: u64;
+    unsafe {
+        asm!(
+            "mov rax,cr2",
+            out("rax") result,
+            options(nostack, nomem)
+        );
+    }
+    result
+}
+
+pub fn write_cr2(value: u64) {
+    unsafe {
+        asm!(
+            "mov cr2,rcx",
+            in("rcx") value,
+            options(nostack, nomem)
+        );
+    }
+}
+
 pub fn read_cr3() -> u64 {
     let mut result: u64;
     unsafe {
@@ -148,6 +170,16 @@ pub fn write_cr8(value: u64) {
     }
 }
 
+pub fn invlpg(address: u64) {
+    unsafe {
+        asm!(
+            "invlpg [rcx]",
+            in("rcx") address,
+            options(nostack)
+        );
+    }
+}
+
 pub fn lgdt(addr: &KDESCRIPTOR) {
     unsafe {
         asm!(
@@ -168,6 +200,16 @@ pub fn lidt(addr: &KDESCRIPTOR) {
     }
 }
 
+pub fn ltr(selector: u16) {
+    unsafe {
+        asm!(
+            "ltr ax",
+            in("ax") selector,
+            options(nostack, nomem)
+        );
+    }
+}
+
 pub fn stosq(destination: *mut u64, value: u64, count: u64) {
     unsafe {
         asm!(
@@ -180,6 +222,20 @@ pub fn stosq(destination: *mut u64, value: u64, count: u64) {
     }
 }
 
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nostack, nomem)
+        );
+    }
+    ((high as u64) << 32) | low as u64
+}
+
 // find bit value eq 1 in binary range mask
 pub fn bit_scan_forward64(index: *mut u32, mask: u64) {
     unsafe {
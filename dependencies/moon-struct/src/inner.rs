@@ -173,3 +173,28 @@ pub union KGDTENTRY64 {
     pub alignment: u64,
     pub dummy: KGDTENTRY64_A,
 }
+
+// SystemModuleInformation
+pub const SYSTEM_MODULE_INFORMATION: u32 = 11;
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct RTL_PROCESS_MODULE_INFORMATION {
+    pub Section: u64,
+    pub MappedBase: u64,
+    pub ImageBase: u64,
+    pub ImageSize: u32,
+    pub Flags: u32,
+    pub LoadOrderIndex: u16,
+    pub InitOrderIndex: u16,
+    pub LoadCount: u16,
+    pub OffsetToFileName: u16,
+    pub FullPathName: [u8; 256],
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct RTL_PROCESS_MODULES {
+    pub NumberOfModules: u32,
+    pub Modules: [RTL_PROCESS_MODULE_INFORMATION; 1],
+}
@@ -29,11 +29,20 @@ pub mod msr_index {
     pub const MSR_IA32_SYSENTER_ESP: u32 = 0x175;
     pub const MSR_IA32_SYSENTER_EIP: u32 = 0x176;
     pub const MSR_IA32_DEBUGCTL: u32 = 0x1D9;
+    pub const MSR_IA32_PAT: u32 = 0x277;
+    pub const MSR_EFER: u32 = 0xC0000080;
+    pub const MSR_STAR: u32 = 0xC0000081;
     pub const MSR_LSTAR: u32 = 0xC0000082;
+    pub const MSR_CSTAR: u32 = 0xC0000083;
+    pub const MSR_SFMASK: u32 = 0xC0000084;
     pub const MSR_FS_BASE: u32 = 0xC0000100;
     pub const MSR_GS_BASE: u32 = 0xC0000101;
     pub const MSR_SHADOW_GS_BASE: u32 = 0xC0000102; // SwapGS GS shadow
 
+    // amd svm
+    pub const MSR_VM_CR: u32 = 0xC0010114;
+    pub const MSR_VM_HSAVE_PA: u32 = 0xC0010117;
+
     pub const MSR_IA32_MTRR_DEF_TYPE: u32 = 0x000002FF;
 
     // MTRR Capabilities MSR
@@ -105,6 +114,28 @@ pub mod ia32_mtrr_phys_mask_msr {
     pub const PAGE_FRAME_NUMBER_LEN: u64 = 36;
 }
 
+pub mod efer_msr {
+    use crate::RT_BIT_64;
+
+    /** EFER - Long Mode Enable. */
+    pub const EFER_LME: u64 = RT_BIT_64!(8);
+    /** EFER - Long Mode Active. */
+    pub const EFER_LMA: u64 = RT_BIT_64!(10);
+    /** EFER - No-Execute Enable. */
+    pub const EFER_NXE: u64 = RT_BIT_64!(11);
+    /** EFER - Secure Virtual Machine Enable,amd. */
+    pub const EFER_SVME: u64 = RT_BIT_64!(12);
+}
+
+pub mod amd_vm_cr_msr {
+    use crate::RT_BIT_64;
+
+    /** VM_CR - SVMDIS and SVM_LOCK can no longer be changed. */
+    pub const VM_CR_LOCK: u64 = RT_BIT_64!(3);
+    /** VM_CR - EFER.SVME can not be set,svm was disabled by the bios. */
+    pub const VM_CR_SVMDIS: u64 = RT_BIT_64!(4);
+}
+
 pub mod ia32_feature_control_msr {
     use crate::RT_BIT_64;
 
@@ -138,6 +169,12 @@ pub mod ia32_vmx_basic_msr {
     pub const VMX_CAPABILITY_HINT_MASK: u64 = RT_BIT_64!(55);
 }
 
+/// VMX MSR - Miscellaneous data.
+pub mod ia32_vmx_misc_msr {
+    /** Bits 4:0,the preemption timer counts down once every time this tsc bit changes. */
+    pub const MSR_IA32_VMX_MISC_PREEMPT_TIMER_TSC_SHIFT_MASK: u64 = 0x1F;
+}
+
 pub mod ia32_mtrr_def_type_msr {
     use crate::RT_BIT_64;
 
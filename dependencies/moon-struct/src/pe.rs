@@ -83,13 +83,33 @@ pub struct ImageExportDirectory {
 }
 
 #[repr(C)]
-#[allow(dead_code)]
 pub struct ImageFileHeader {
-    machine: u16,                 // WORD -> u16
-    number_of_sections: u16,      // WORD -> u16
-    time_date_stamp: u32,         // DWORD -> u32
-    pointer_to_symbol_table: u32, // DWORD -> u32
-    number_of_symbols: u32,       // DWORD -> u32
-    size_of_optional_header: u16, // WORD -> u16
-    characteristics: u16,         // WORD -> u16
+    pub machine: u16,                 // WORD -> u16
+    pub number_of_sections: u16,      // WORD -> u16
+    pub time_date_stamp: u32,         // DWORD -> u32
+    pub pointer_to_symbol_table: u32, // DWORD -> u32
+    pub number_of_symbols: u32,       // DWORD -> u32
+    pub size_of_optional_header: u16, // WORD -> u16
+    pub characteristics: u16,         // WORD -> u16
+}
+
+pub const IMAGE_SIZEOF_SHORT_NAME: usize = 8;
+
+pub const IMAGE_SCN_CNT_CODE: u32 = 0x00000020;
+pub const IMAGE_SCN_MEM_DISCARDABLE: u32 = 0x02000000;
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+pub const IMAGE_SCN_MEM_WRITE: u32 = 0x80000000;
+
+#[repr(C)]
+pub struct ImageSectionHeader {
+    pub name: [u8; IMAGE_SIZEOF_SHORT_NAME], // BYTE[8] -> [u8; 8]
+    pub virtual_size: u32,                   // DWORD -> u32
+    pub virtual_address: u32,                // DWORD -> u32
+    pub size_of_raw_data: u32,               // DWORD -> u32
+    pub pointer_to_raw_data: u32,            // DWORD -> u32
+    pub pointer_to_relocations: u32,         // DWORD -> u32
+    pub pointer_to_linenumbers: u32,         // DWORD -> u32
+    pub number_of_relocations: u16,          // WORD -> u16
+    pub number_of_linenumbers: u16,          // WORD -> u16
+    pub characteristics: u32,                // DWORD -> u32
 }
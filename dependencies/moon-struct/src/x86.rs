@@ -65,6 +65,16 @@ pub const X86_CPUID_FEATURE_ECX_RDRAND: u32 = RT_BIT_32!(30);
 /** ECX Bit 31 - Hypervisor Present (software only). */
 pub const X86_CPUID_FEATURE_ECX_HVP: u32 = RT_BIT_32!(31);
 
+/// Cpuid 0x80000001,amd
+/** ECX Bit 2 - SVM - Secure Virtual Machine. */
+pub const X86_CPUID_AMD_FEATURE_ECX_SVM: u32 = RT_BIT_32!(2);
+
+/// Cpuid 0x8000000A,amd svm features
+/** EDX Bit 0 - NP - Nested Paging. */
+pub const X86_CPUID_SVM_FEATURE_EDX_NESTED_PAGING: u32 = RT_BIT_32!(0);
+/** EDX Bit 3 - NRIPS - Next RIP is saved on #VMEXIT. */
+pub const X86_CPUID_SVM_FEATURE_EDX_NRIP_SAVE: u32 = RT_BIT_32!(3);
+
 /// Cr0
 /** Bit 0 - PE - Protection Enabled */
 pub const X86_CR0_PE: u32 = RT_BIT_32!(0);
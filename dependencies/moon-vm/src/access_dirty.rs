@@ -0,0 +1,226 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::data::ptee;
+
+pub const EPT_PAGE_SIZE: u64 = 0x1000;
+pub const EPT_LARGE_PAGE_SIZE: u64 = 0x20_0000;
+// same bits in 2mb and 4kb leaf entries
+pub const EPT_ACCESSED_DIRTY: u64 = ptee::ACCESSED | ptee::DIRTY;
+
+// leaf entries of an ept,the cpu sets the flags with locked writes so they are read as atomics
+pub trait EptLeafEntries {
+    // the leaf entry mapping physical and the size of the page it maps
+    fn leaf_entry(&self, physical: u64) -> Option<(&AtomicU64, u64)>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccessDirtyClear {
+    None,
+    Accessed,
+    Dirty,
+    Both,
+}
+
+impl AccessDirtyClear {
+    pub fn mask(&self) -> u64 {
+        match self {
+            AccessDirtyClear::None => 0,
+            AccessDirtyClear::Accessed => ptee::ACCESSED,
+            AccessDirtyClear::Dirty => ptee::DIRTY,
+            AccessDirtyClear::Both => EPT_ACCESSED_DIRTY,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AccessDirtyPage {
+    // start of the leaf,a 2mb page is reported once
+    pub physical: u64,
+    pub size: u64,
+    pub accessed: bool,
+    pub dirty: bool,
+}
+
+// walks [start,end) leaf by leaf and reports leaves with either flag set. a large page that
+// only overlaps the range is reported and cleared whole. the cpu only sets a cleared flag
+// again once its cached translation is gone,so clearing must be followed by an invept
+pub fn harvest_access_dirty<T: EptLeafEntries + ?Sized>(
+    tables: &T,
+    start: u64,
+    end: u64,
+    clear: AccessDirtyClear,
+    out: &mut Vec<AccessDirtyPage>,
+) {
+    let mut physical = start & !(EPT_PAGE_SIZE - 1);
+
+    while physical < end {
+        let (entry, size) = match tables.leaf_entry(physical) {
+            Some(leaf) => leaf,
+            // outside the ept
+            None => return,
+        };
+
+        let flags = match clear.mask() {
+            0 => entry.load(Ordering::SeqCst),
+            mask => entry.fetch_and(!mask, Ordering::SeqCst),
+        };
+
+        let page = physical & !(size - 1);
+        if (flags & EPT_ACCESSED_DIRTY) != 0 {
+            out.push(AccessDirtyPage {
+                physical: page,
+                size,
+                accessed: (flags & ptee::ACCESSED) != 0,
+                dirty: (flags & ptee::DIRTY) != 0,
+            });
+        }
+
+        physical = match page.checked_add(size) {
+            Some(next) => next,
+            None => return,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    // leaves keyed by the address they start at
+    struct TestLeaves {
+        leaves: BTreeMap<u64, (AtomicU64, u64)>,
+    }
+
+    impl TestLeaves {
+        fn new(leaves: &[(u64, u64, u64)]) -> Self {
+            Self {
+                leaves: leaves
+                    .iter()
+                    .map(|(physical, size, flags)| (*physical, (AtomicU64::new(*flags), *size)))
+                    .collect(),
+            }
+        }
+
+        fn flags(&self, physical: u64) -> u64 {
+            self.leaves[&physical].0.load(Ordering::SeqCst)
+        }
+    }
+
+    impl EptLeafEntries for TestLeaves {
+        fn leaf_entry(&self, physical: u64) -> Option<(&AtomicU64, u64)> {
+            let (start, (entry, size)) = self.leaves.range(..=physical).next_back()?;
+            if physical < start + size {
+                Some((entry, *size))
+            } else {
+                None
+            }
+        }
+    }
+
+    const RWX: u64 = 0x7;
+
+    // 4kb pages at 0x1000 and 0x2000,a 2mb page right after the 4kb table
+    fn leaves() -> TestLeaves {
+        TestLeaves::new(&[
+            (0x0, EPT_PAGE_SIZE, RWX),
+            (0x1000, EPT_PAGE_SIZE, RWX | ptee::ACCESSED),
+            (0x2000, EPT_PAGE_SIZE, RWX | EPT_ACCESSED_DIRTY),
+            (0x3000, EPT_PAGE_SIZE, RWX),
+            (
+                EPT_LARGE_PAGE_SIZE,
+                EPT_LARGE_PAGE_SIZE,
+                RWX | ptee::DIRTY | ptee::ACCESSED,
+            ),
+        ])
+    }
+
+    fn harvest(
+        tables: &TestLeaves,
+        start: u64,
+        end: u64,
+        clear: AccessDirtyClear,
+    ) -> Vec<AccessDirtyPage> {
+        let mut pages = Vec::new();
+        harvest_access_dirty(tables, start, end, clear, &mut pages);
+        pages
+    }
+
+    #[test]
+    fn reports_pages_with_either_flag() {
+        let tables = leaves();
+
+        assert_eq!(
+            harvest(&tables, 0, 0x4000, AccessDirtyClear::None),
+            [
+                AccessDirtyPage {
+                    physical: 0x1000,
+                    size: EPT_PAGE_SIZE,
+                    accessed: true,
+                    dirty: false,
+                },
+                AccessDirtyPage {
+                    physical: 0x2000,
+                    size: EPT_PAGE_SIZE,
+                    accessed: true,
+                    dirty: true,
+                },
+            ]
+        );
+        // nothing was cleared
+        assert_eq!(tables.flags(0x2000), RWX | EPT_ACCESSED_DIRTY);
+    }
+
+    #[test]
+    fn large_page_is_reported_once_and_cleared_whole() {
+        let tables = leaves();
+
+        // starts inside the 2mb page
+        let pages = harvest(
+            &tables,
+            EPT_LARGE_PAGE_SIZE + 0x5000,
+            EPT_LARGE_PAGE_SIZE + 0x9000,
+            AccessDirtyClear::Dirty,
+        );
+        assert_eq!(
+            pages,
+            [AccessDirtyPage {
+                physical: EPT_LARGE_PAGE_SIZE,
+                size: EPT_LARGE_PAGE_SIZE,
+                accessed: true,
+                dirty: true,
+            }]
+        );
+        assert_eq!(tables.flags(EPT_LARGE_PAGE_SIZE), RWX | ptee::ACCESSED);
+    }
+
+    #[test]
+    fn clear_only_takes_the_requested_flags() {
+        let tables = leaves();
+
+        harvest(&tables, 0x2000, 0x3000, AccessDirtyClear::Accessed);
+        assert_eq!(tables.flags(0x2000), RWX | ptee::DIRTY);
+
+        harvest(&tables, 0x1000, 0x3000, AccessDirtyClear::Both);
+        assert_eq!(tables.flags(0x1000), RWX);
+        assert_eq!(tables.flags(0x2000), RWX);
+        assert!(harvest(&tables, 0, 0x4000, AccessDirtyClear::None).is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_ept() {
+        let tables = leaves();
+
+        // 0x4000 up to the 2mb page is not mapped by any leaf
+        let pages = harvest(
+            &tables,
+            0x2000,
+            2 * EPT_LARGE_PAGE_SIZE,
+            AccessDirtyClear::None,
+        );
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].physical, 0x2000);
+    }
+}
@@ -0,0 +1,555 @@
+use alloc::vec::Vec;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+use crate::{
+    data::{
+        vmx_cpu_based_controls::*, vmx_pin_based_controls::*, vmx_secondary_cpu_based_controls::*,
+        vmx_vm_enter_controls::*, vmx_vm_exit_controls::*,
+    },
+    dump::{
+        CPU_BASED_CONTROL_NAMES, ENTRY_CONTROL_NAMES, EXIT_CONTROL_NAMES, PIN_BASED_CONTROL_NAMES,
+        SECONDARY_CONTROL_NAMES,
+    },
+    entry_check::VmxCapabilities,
+};
+
+// bits of one control field,the type says which field they belong to
+macro_rules! control_flags {
+    ($name:ident, $names:expr, { $($flag:ident = $bits:expr),* $(,)? }) => {
+        #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+        pub struct $name(u32);
+
+        impl $name {
+            $(pub const $flag: Self = Self($bits);)*
+
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            pub const fn bits(self) -> u32 {
+                self.0
+            }
+
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            pub const fn contains(self, other: Self) -> bool {
+                (self.0 & other.0) == other.0
+            }
+
+            // one value per set bit
+            pub fn iter(self) -> impl Iterator<Item = Self> {
+                (0..32)
+                    .map(|bit| 1u32 << bit)
+                    .filter(move |bit| (self.0 & bit) != 0)
+                    .map(Self)
+            }
+
+            // only meaningful for a single bit
+            pub fn name(self) -> &'static str {
+                $names
+                    .iter()
+                    .find(|(bit, _)| *bit == self.0)
+                    .map(|(_, name)| *name)
+                    .unwrap_or("UNKNOWN")
+            }
+
+            // the bits inside mask,or outside it
+            fn select(self, mask: Self, inside: bool) -> Self {
+                if inside {
+                    Self(self.0 & mask.0)
+                } else {
+                    Self(self.0 & !mask.0)
+                }
+            }
+
+            // bits set in allowed0 are forced on,bits clear in allowed1 can not be set.
+            // returns the resolved field and the requested bits that were refused
+            fn resolve(self, capability: u64) -> (Self, Self) {
+                let allowed0 = capability as u32;
+                let allowed1 = (capability >> 32) as u32;
+                (
+                    Self((self.0 & allowed1) | allowed0),
+                    Self(self.0 & !allowed1),
+                )
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+
+        impl BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.0 |= rhs.0;
+            }
+        }
+
+        impl BitAnd for $name {
+            type Output = Self;
+
+            fn bitand(self, rhs: Self) -> Self {
+                Self(self.0 & rhs.0)
+            }
+        }
+
+        impl From<$name> for VmxControl {
+            fn from(flags: $name) -> Self {
+                VmxControl::$name(flags)
+            }
+        }
+    };
+}
+
+control_flags! {
+    // pin-based vm-execution controls
+    PinControls,
+    PIN_BASED_CONTROL_NAMES,
+    {
+        EXT_INT_EXIT = VMX_PIN_CTLS_EXT_INT_EXIT,
+        NMI_EXIT = VMX_PIN_CTLS_NMI_EXIT,
+        VIRT_NMI = VMX_PIN_CTLS_VIRT_NMI,
+        PREEMPT_TIMER = VMX_PIN_CTLS_PREEMPT_TIMER,
+        POSTED_INT = VMX_PIN_CTLS_POSTED_INT,
+    }
+}
+
+control_flags! {
+    // primary processor-based vm-execution controls
+    PrimaryControls,
+    CPU_BASED_CONTROL_NAMES,
+    {
+        INT_WINDOW_EXIT = VMX_PROC_CTLS_INT_WINDOW_EXIT,
+        USE_TSC_OFFSETTING = VMX_PROC_CTLS_USE_TSC_OFFSETTING,
+        HLT_EXIT = VMX_PROC_CTLS_HLT_EXIT,
+        INVLPG_EXIT = VMX_PROC_CTLS_INVLPG_EXIT,
+        MWAIT_EXIT = VMX_PROC_CTLS_MWAIT_EXIT,
+        RDPMC_EXIT = VMX_PROC_CTLS_RDPMC_EXIT,
+        RDTSC_EXIT = VMX_PROC_CTLS_RDTSC_EXIT,
+        CR3_LOAD_EXIT = VMX_PROC_CTLS_CR3_LOAD_EXIT,
+        CR3_STORE_EXIT = VMX_PROC_CTLS_CR3_STORE_EXIT,
+        USE_TERTIARY_CTLS = VMX_PROC_CTLS_USE_TERTIARY_CTLS,
+        CR8_LOAD_EXIT = VMX_PROC_CTLS_CR8_LOAD_EXIT,
+        CR8_STORE_EXIT = VMX_PROC_CTLS_CR8_STORE_EXIT,
+        USE_TPR_SHADOW = VMX_PROC_CTLS_USE_TPR_SHADOW,
+        NMI_WINDOW_EXIT = VMX_PROC_CTLS_NMI_WINDOW_EXIT,
+        MOV_DR_EXIT = VMX_PROC_CTLS_MOV_DR_EXIT,
+        UNCOND_IO_EXIT = VMX_PROC_CTLS_UNCOND_IO_EXIT,
+        USE_IO_BITMAPS = VMX_PROC_CTLS_USE_IO_BITMAPS,
+        MONITOR_TRAP_FLAG = VMX_PROC_CTLS_MONITOR_TRAP_FLAG,
+        USE_MSR_BITMAPS = VMX_PROC_CTLS_USE_MSR_BITMAPS,
+        MONITOR_EXIT = VMX_PROC_CTLS_MONITOR_EXIT,
+        PAUSE_EXIT = VMX_PROC_CTLS_PAUSE_EXIT,
+        USE_SECONDARY_CTLS = VMX_PROC_CTLS_USE_SECONDARY_CTLS,
+    }
+}
+
+control_flags! {
+    // secondary processor-based vm-execution controls
+    SecondaryControls,
+    SECONDARY_CONTROL_NAMES,
+    {
+        VIRT_APIC_ACCESS = VMX_PROC_CTLS2_VIRT_APIC_ACCESS,
+        EPT = VMX_PROC_CTLS2_EPT,
+        DESC_TABLE_EXIT = VMX_PROC_CTLS2_DESC_TABLE_EXIT,
+        RDTSCP = VMX_PROC_CTLS2_RDTSCP,
+        VIRT_X2APIC_MODE = VMX_PROC_CTLS2_VIRT_X2APIC_MODE,
+        VPID = VMX_PROC_CTLS2_VPID,
+        WBINVD_EXIT = VMX_PROC_CTLS2_WBINVD_EXIT,
+        UNRESTRICTED_GUEST = VMX_PROC_CTLS2_UNRESTRICTED_GUEST,
+        APIC_REG_VIRT = VMX_PROC_CTLS2_APIC_REG_VIRT,
+        VIRT_INT_DELIVERY = VMX_PROC_CTLS2_VIRT_INT_DELIVERY,
+        PAUSE_LOOP_EXIT = VMX_PROC_CTLS2_PAUSE_LOOP_EXIT,
+        RDRAND_EXIT = VMX_PROC_CTLS2_RDRAND_EXIT,
+        INVPCID = VMX_PROC_CTLS2_INVPCID,
+        VMFUNC = VMX_PROC_CTLS2_VMFUNC,
+        VMCS_SHADOWING = VMX_PROC_CTLS2_VMCS_SHADOWING,
+        ENCLS_EXIT = VMX_PROC_CTLS2_ENCLS_EXIT,
+        RDSEED_EXIT = VMX_PROC_CTLS2_RDSEED_EXIT,
+        PML = VMX_PROC_CTLS2_PML,
+        EPT_XCPT_VE = VMX_PROC_CTLS2_EPT_XCPT_VE,
+        CONCEAL_VMX_FROM_PT = VMX_PROC_CTLS2_CONCEAL_VMX_FROM_PT,
+        XSAVES_XRSTORS = VMX_PROC_CTLS2_XSAVES_XRSTORS,
+        MODE_BASED_EPT_PERM = VMX_PROC_CTLS2_MODE_BASED_EPT_PERM,
+        SPP_EPT = VMX_PROC_CTLS2_SPP_EPT,
+        PT_EPT = VMX_PROC_CTLS2_PT_EPT,
+        TSC_SCALING = VMX_PROC_CTLS2_TSC_SCALING,
+        USER_WAIT_PAUSE = VMX_PROC_CTLS2_USER_WAIT_PAUSE,
+        ENCLV_EXIT = VMX_PROC_CTLS2_ENCLV_EXIT,
+    }
+}
+
+control_flags! {
+    // vm-entry controls
+    EntryControls,
+    ENTRY_CONTROL_NAMES,
+    {
+        LOAD_DEBUG = VMX_ENTRY_CTLS_LOAD_DEBUG,
+        IA32E_MODE_GUEST = VMX_ENTRY_CTLS_IA32E_MODE_GUEST,
+        ENTRY_TO_SMM = VMX_ENTRY_CTLS_ENTRY_TO_SMM,
+        DEACTIVATE_DUAL_MON = VMX_ENTRY_CTLS_DEACTIVATE_DUAL_MON,
+        LOAD_PERF_MSR = VMX_ENTRY_CTLS_LOAD_PERF_MSR,
+        LOAD_PAT_MSR = VMX_ENTRY_CTLS_LOAD_PAT_MSR,
+        LOAD_EFER_MSR = VMX_ENTRY_CTLS_LOAD_EFER_MSR,
+        LOAD_BNDCFGS_MSR = VMX_ENTRY_CTLS_LOAD_BNDCFGS_MSR,
+        CONCEAL_VMX_FROM_PT = VMX_ENTRY_CTLS_CONCEAL_VMX_FROM_PT,
+        LOAD_RTIT_CTL_MSR = VMX_ENTRY_CTLS_LOAD_RTIT_CTL_MSR,
+        LOAD_CET_STATE = VMX_ENTRY_CTLS_LOAD_CET_STATE,
+        LOAD_PKRS_MSR = VMX_ENTRY_CTLS_LOAD_PKRS_MSR,
+    }
+}
+
+control_flags! {
+    // vm-exit controls
+    ExitControls,
+    EXIT_CONTROL_NAMES,
+    {
+        SAVE_DEBUG = VMX_EXIT_CTLS_SAVE_DEBUG,
+        HOST_ADDR_SPACE_SIZE = VMX_EXIT_CTLS_HOST_ADDR_SPACE_SIZE,
+        LOAD_PERF_MSR = VMX_EXIT_CTLS_LOAD_PERF_MSR,
+        ACK_EXT_INT = VMX_EXIT_CTLS_ACK_EXT_INT,
+        SAVE_PAT_MSR = VMX_EXIT_CTLS_SAVE_PAT_MSR,
+        LOAD_PAT_MSR = VMX_EXIT_CTLS_LOAD_PAT_MSR,
+        SAVE_EFER_MSR = VMX_EXIT_CTLS_SAVE_EFER_MSR,
+        LOAD_EFER_MSR = VMX_EXIT_CTLS_LOAD_EFER_MSR,
+        SAVE_PREEMPT_TIMER = VMX_EXIT_CTLS_SAVE_PREEMPT_TIMER,
+        CLEAR_BNDCFGS_MSR = VMX_EXIT_CTLS_CLEAR_BNDCFGS_MSR,
+        CONCEAL_VMX_FROM_PT = VMX_EXIT_CTLS_CONCEAL_VMX_FROM_PT,
+        CLEAR_RTIT_CTL_MSR = VMX_EXIT_CTLS_CLEAR_RTIT_CTL_MSR,
+        LOAD_CET_STATE = VMX_EXIT_CTLS_LOAD_CET_STATE,
+        LOAD_PKRS_MSR = VMX_EXIT_CTLS_LOAD_PKRS_MSR,
+        SAVE_PERF_MSR = VMX_EXIT_CTLS_SAVE_PERF_MSR,
+        USE_SECONDARY_CTLS = VMX_EXIT_CTLS_USE_SECONDARY_CTLS,
+    }
+}
+// a control request,tagged with the vmcs field it belongs to
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VmxControl {
+    PinControls(PinControls),
+    PrimaryControls(PrimaryControls),
+    SecondaryControls(SecondaryControls),
+    EntryControls(EntryControls),
+    ExitControls(ExitControls),
+}
+
+impl VmxControl {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            VmxControl::PinControls(flags) => flags.name(),
+            VmxControl::PrimaryControls(flags) => flags.name(),
+            VmxControl::SecondaryControls(flags) => flags.name(),
+            VmxControl::EntryControls(flags) => flags.name(),
+            VmxControl::ExitControls(flags) => flags.name(),
+        }
+    }
+}
+
+// values to write into the vmcs control fields
+#[derive(Debug, Default, Clone)]
+pub struct ResolvedControls {
+    pub pin: PinControls,
+    pub primary: PrimaryControls,
+    pub secondary: SecondaryControls,
+    pub entry: EntryControls,
+    pub exit: ExitControls,
+    // requested but not allowed by the cpu,one entry per bit
+    pub dropped: Vec<VmxControl>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct ControlSet {
+    pin: PinControls,
+    primary: PrimaryControls,
+    secondary: SecondaryControls,
+    entry: EntryControls,
+    exit: ExitControls,
+}
+
+impl ControlSet {
+    fn add(&mut self, control: VmxControl) {
+        match control {
+            VmxControl::PinControls(flags) => self.pin |= flags,
+            VmxControl::PrimaryControls(flags) => self.primary |= flags,
+            VmxControl::SecondaryControls(flags) => self.secondary |= flags,
+            VmxControl::EntryControls(flags) => self.entry |= flags,
+            VmxControl::ExitControls(flags) => self.exit |= flags,
+        }
+    }
+
+    fn select(&self, mask: &ControlSet, inside: bool) -> Self {
+        ControlSet {
+            pin: self.pin.select(mask.pin, inside),
+            primary: self.primary.select(mask.primary, inside),
+            secondary: self.secondary.select(mask.secondary, inside),
+            entry: self.entry.select(mask.entry, inside),
+            exit: self.exit.select(mask.exit, inside),
+        }
+    }
+
+    // every set bit as its own control
+    fn split(&self, out: &mut Vec<VmxControl>) {
+        out.extend(self.pin.iter().map(VmxControl::from));
+        out.extend(self.primary.iter().map(VmxControl::from));
+        out.extend(self.secondary.iter().map(VmxControl::from));
+        out.extend(self.entry.iter().map(VmxControl::from));
+        out.extend(self.exit.iter().map(VmxControl::from));
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct VmcsControls {
+    requested: ControlSet,
+    mandatory: ControlSet,
+}
+
+impl VmcsControls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // enable the control if the cpu allows it,otherwise report it as dropped
+    pub fn request(mut self, control: impl Into<VmxControl>) -> Self {
+        self.requested.add(control.into());
+        self
+    }
+
+    // the vmm can not run without this control
+    pub fn require(mut self, control: impl Into<VmxControl>) -> Self {
+        let control = control.into();
+        self.requested.add(control);
+        self.mandatory.add(control);
+        self
+    }
+
+    pub fn request_if(self, condition: bool, control: impl Into<VmxControl>) -> Self {
+        if condition {
+            self.request(control)
+        } else {
+            self
+        }
+    }
+
+    pub fn require_if(self, condition: bool, control: impl Into<VmxControl>) -> Self {
+        if condition {
+            self.require(control)
+        } else {
+            self
+        }
+    }
+
+    // resolve against the capability msrs,err holds every unsupported mandatory control
+    pub fn resolve(&self, caps: &VmxCapabilities) -> Result<ResolvedControls, Vec<VmxControl>> {
+        let mut requested = self.requested;
+        let mut mandatory = self.mandatory;
+
+        // any secondary control needs the primary activate bit
+        if !requested.secondary.is_empty() {
+            requested.primary |= PrimaryControls::USE_SECONDARY_CTLS;
+            if !mandatory.secondary.is_empty() {
+                mandatory.primary |= PrimaryControls::USE_SECONDARY_CTLS;
+            }
+        }
+
+        let (pin, pin_refused) = requested.pin.resolve(caps.pin_controls);
+        let (primary, primary_refused) = requested.primary.resolve(caps.proc_controls);
+
+        // without the activate bit,secondary controls are all off
+        let (secondary, secondary_refused) =
+            if primary.contains(PrimaryControls::USE_SECONDARY_CTLS) {
+                requested.secondary.resolve(caps.proc2_controls)
+            } else {
+                (SecondaryControls::empty(), requested.secondary)
+            };
+
+        let (entry, entry_refused) = requested.entry.resolve(caps.entry_controls);
+        let (exit, exit_refused) = requested.exit.resolve(caps.exit_controls);
+
+        let refused = ControlSet {
+            pin: pin_refused,
+            primary: primary_refused,
+            secondary: secondary_refused,
+            entry: entry_refused,
+            exit: exit_refused,
+        };
+
+        let mut missing = Vec::new();
+        refused.select(&mandatory, true).split(&mut missing);
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+
+        let mut dropped = Vec::new();
+        refused.select(&mandatory, false).split(&mut dropped);
+
+        Ok(ResolvedControls {
+            pin,
+            primary,
+            secondary,
+            entry,
+            exit,
+            dropped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ia32_vmx_true_*_ctls and procbased_ctls2 of a skylake client
+    fn skylake() -> VmxCapabilities {
+        VmxCapabilities {
+            pin_controls: 0x0000_007F_0000_0016,
+            proc_controls: 0xFFF9_FFFE_0400_6172,
+            proc2_controls: 0x001F_FCFF_0000_0000,
+            exit_controls: 0x01FF_FFFF_0003_6DFB,
+            entry_controls: 0x0003_FFFF_0000_11FB,
+            ..Default::default()
+        }
+    }
+
+    // the driver's request list with every feature switched on
+    fn driver_controls() -> VmcsControls {
+        VmcsControls::new()
+            .require(PrimaryControls::USE_MSR_BITMAPS)
+            .request(PrimaryControls::USE_TSC_OFFSETTING)
+            .require(SecondaryControls::EPT)
+            .request(SecondaryControls::VPID)
+            .request(SecondaryControls::RDTSCP)
+            .request(SecondaryControls::INVPCID)
+            .request(SecondaryControls::XSAVES_XRSTORS)
+            .request(EntryControls::LOAD_DEBUG)
+            .require(EntryControls::IA32E_MODE_GUEST)
+            .require(ExitControls::HOST_ADDR_SPACE_SIZE)
+            .request(PinControls::NMI_EXIT | PinControls::VIRT_NMI)
+            .request(PinControls::PREEMPT_TIMER)
+            .request(ExitControls::SAVE_PREEMPT_TIMER)
+    }
+
+    #[test]
+    fn skylake_resolves_the_driver_controls() {
+        let controls = driver_controls().resolve(&skylake()).unwrap();
+
+        // allowed0 bits come along
+        assert_eq!(controls.pin.bits(), 0x7E);
+        assert!(controls.primary.contains(
+            PrimaryControls::USE_MSR_BITMAPS
+                | PrimaryControls::USE_TSC_OFFSETTING
+                | PrimaryControls::USE_SECONDARY_CTLS
+        ));
+        assert_eq!(controls.primary.bits() & 0x0400_6172, 0x0400_6172);
+        assert_eq!(
+            controls.secondary,
+            SecondaryControls::EPT
+                | SecondaryControls::VPID
+                | SecondaryControls::RDTSCP
+                | SecondaryControls::INVPCID
+                | SecondaryControls::XSAVES_XRSTORS
+        );
+        assert!(controls.entry.contains(EntryControls::IA32E_MODE_GUEST));
+        assert!(controls.exit.contains(ExitControls::SAVE_PREEMPT_TIMER));
+        assert!(controls.dropped.is_empty());
+    }
+
+    #[test]
+    fn unsupported_requests_are_dropped() {
+        let controls = driver_controls()
+            .request(SecondaryControls::MODE_BASED_EPT_PERM | SecondaryControls::SPP_EPT)
+            .resolve(&skylake())
+            .unwrap();
+
+        assert!(!controls
+            .secondary
+            .contains(SecondaryControls::MODE_BASED_EPT_PERM));
+        assert_eq!(
+            controls.dropped,
+            [
+                VmxControl::from(SecondaryControls::MODE_BASED_EPT_PERM),
+                VmxControl::from(SecondaryControls::SPP_EPT)
+            ]
+            .to_vec()
+        );
+        assert_eq!(controls.dropped[0].name(), "MODE_BASED_EPT_PERM");
+    }
+
+    #[test]
+    fn unsupported_requirements_fail() {
+        let missing = driver_controls()
+            .require(SecondaryControls::MODE_BASED_EPT_PERM)
+            .resolve(&skylake())
+            .unwrap_err();
+
+        assert_eq!(
+            missing,
+            [VmxControl::from(SecondaryControls::MODE_BASED_EPT_PERM)].to_vec()
+        );
+    }
+
+    #[test]
+    fn secondary_controls_need_the_activate_bit() {
+        // a nested hypervisor that can not activate the secondary controls
+        let caps = VmxCapabilities {
+            proc_controls: skylake().proc_controls
+                & !((VMX_PROC_CTLS_USE_SECONDARY_CTLS as u64) << 32),
+            ..skylake()
+        };
+
+        let missing = driver_controls().resolve(&caps).unwrap_err();
+        assert_eq!(
+            missing,
+            [
+                VmxControl::from(PrimaryControls::USE_SECONDARY_CTLS),
+                VmxControl::from(SecondaryControls::EPT)
+            ]
+            .to_vec()
+        );
+
+        let controls = VmcsControls::new()
+            .request(SecondaryControls::RDTSCP)
+            .resolve(&caps)
+            .unwrap();
+        assert!(controls.secondary.is_empty());
+        assert_eq!(
+            controls.dropped,
+            [
+                VmxControl::from(PrimaryControls::USE_SECONDARY_CTLS),
+                VmxControl::from(SecondaryControls::RDTSCP)
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let controls = VmcsControls::new()
+            .request_if(false, PinControls::PREEMPT_TIMER)
+            .require_if(false, SecondaryControls::MODE_BASED_EPT_PERM)
+            .resolve(&skylake())
+            .unwrap();
+
+        assert_eq!(controls.pin.bits(), 0x16);
+        assert!(controls.secondary.is_empty());
+        assert!(controls.dropped.is_empty());
+    }
+
+    #[test]
+    fn flag_names() {
+        assert_eq!(PinControls::VIRT_NMI.name(), "VIRT_NMI");
+        assert_eq!(ExitControls::ACK_EXT_INT.name(), "ACK_EXT_INT");
+        assert_eq!(
+            (PinControls::VIRT_NMI | PinControls::NMI_EXIT).name(),
+            "UNKNOWN"
+        );
+        assert_eq!(
+            (PinControls::VIRT_NMI | PinControls::NMI_EXIT)
+                .iter()
+                .collect::<Vec<_>>(),
+            [PinControls::NMI_EXIT, PinControls::VIRT_NMI].to_vec()
+        );
+    }
+}
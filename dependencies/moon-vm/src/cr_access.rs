@@ -0,0 +1,147 @@
+use crate::entry_check::VmxCapabilities;
+
+// control register bits the guest writes are checked against
+const X86_CR0_PE: u64 = 1 << 0;
+const X86_CR0_MP: u64 = 1 << 1;
+const X86_CR0_EM: u64 = 1 << 2;
+const X86_CR0_TS: u64 = 1 << 3;
+const X86_CR0_NW: u64 = 1 << 29;
+const X86_CR0_CD: u64 = 1 << 30;
+const X86_CR0_PG: u64 = 1 << 31;
+const X86_CR4_VMXE: u64 = 1 << 13;
+const X86_CR4_PCIDE: u64 = 1 << 17;
+
+// with cr4.pcide,a mov to cr3 with bit 63 set keeps the tlb entries of the new pcid
+pub const CR3_PCID_NO_FLUSH: u64 = 1 << 63;
+
+// called in vmx root on every guest address space switch
+pub type Cr3SwitchCallback = fn(cpu_index: usize, old_cr3: u64, new_cr3: u64);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cr3Load {
+    pub cr3: u64,
+    // non global translations must be invalidated
+    pub flush: bool,
+}
+
+// cr4 is the value the guest sees
+pub fn cr3_load(value: u64, cr4: u64) -> Result<Cr3Load, &'static str> {
+    if (value & CR3_PCID_NO_FLUSH) == 0 {
+        return Ok(Cr3Load {
+            cr3: value,
+            flush: true,
+        });
+    }
+
+    if (cr4 & X86_CR4_PCIDE) == 0 {
+        return Err("cr3 bit 63 set without cr4.pcide");
+    }
+
+    Ok(Cr3Load {
+        cr3: value & !CR3_PCID_NO_FLUSH,
+        flush: false,
+    })
+}
+
+// what to load into the vmcs after a guest write
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ControlRegisterWrite {
+    // real value,satisfies the fixed bits
+    pub guest: u64,
+    // value the guest reads back for host owned bits
+    pub shadow: u64,
+}
+
+// guest/host masks derived from IA32_VMX_CR{0,4}_FIXED*
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ControlRegisterMasks {
+    cr0_fixed0: u64,
+    cr0_fixed1: u64,
+    cr4_fixed0: u64,
+    cr4_fixed1: u64,
+}
+
+impl ControlRegisterMasks {
+    pub fn new(caps: &VmxCapabilities, unrestricted_guest: bool) -> Self {
+        let mut cr0_fixed0 = caps.cr0_fixed0;
+        // unrestricted guest may run with paging or protection off
+        if unrestricted_guest {
+            cr0_fixed0 &= !(X86_CR0_PE | X86_CR0_PG);
+        }
+
+        Self {
+            cr0_fixed0,
+            cr0_fixed1: caps.cr0_fixed1,
+            cr4_fixed0: caps.cr4_fixed0,
+            cr4_fixed1: caps.cr4_fixed1,
+        }
+    }
+
+    // bits forced to 1 or to 0 are host owned,a guest write that changes them exits.
+    // ts is owned too,clts only exits while the shadow has it set
+    pub fn cr0_guest_host_mask(&self) -> u64 {
+        self.cr0_fixed0 | !self.cr0_fixed1 | X86_CR0_TS
+    }
+
+    pub fn cr4_guest_host_mask(&self) -> u64 {
+        self.cr4_fixed0 | !self.cr4_fixed1 | X86_CR4_VMXE
+    }
+
+    pub fn cr0_read_shadow(&self, cr0: u64) -> u64 {
+        cr0
+    }
+
+    // the guest never sees vmx enabled
+    pub fn cr4_read_shadow(&self, cr4: u64) -> u64 {
+        cr4 & !X86_CR4_VMXE
+    }
+
+    // err means the guest gets #gp(0)
+    pub fn write_cr0(&self, value: u64) -> Result<ControlRegisterWrite, &'static str> {
+        if (value >> 32) != 0 {
+            return Err("cr0 reserved bits set");
+        }
+        if (value & X86_CR0_PG) != 0 && (value & X86_CR0_PE) == 0 {
+            return Err("cr0.pg set without cr0.pe");
+        }
+        if (value & X86_CR0_NW) != 0 && (value & X86_CR0_CD) == 0 {
+            return Err("cr0.nw set without cr0.cd");
+        }
+
+        Ok(ControlRegisterWrite {
+            guest: (value | self.cr0_fixed0) & self.cr0_fixed1,
+            shadow: self.cr0_read_shadow(value),
+        })
+    }
+
+    pub fn write_cr4(&self, value: u64) -> Result<ControlRegisterWrite, &'static str> {
+        // nested vmx is not supported,vmx instructions already #ud
+        if (value & X86_CR4_VMXE) != 0 {
+            return Err("cr4.vmxe is not available to the guest");
+        }
+
+        Ok(ControlRegisterWrite {
+            guest: (value | self.cr4_fixed0) & self.cr4_fixed1,
+            shadow: self.cr4_read_shadow(value),
+        })
+    }
+
+    // clts only clears ts
+    pub fn clts(&self, guest: u64, shadow: u64) -> ControlRegisterWrite {
+        ControlRegisterWrite {
+            guest: guest & !X86_CR0_TS,
+            shadow: shadow & !X86_CR0_TS,
+        }
+    }
+}
+
+// the cr0 lmsw produces from the value the guest sees,it loads pe,mp,em,ts but never clears pe
+pub fn lmsw_value(cr0: u64, source: u16) -> u64 {
+    let source = source as u64 & (X86_CR0_PE | X86_CR0_MP | X86_CR0_EM | X86_CR0_TS);
+    (cr0 & !(X86_CR0_MP | X86_CR0_EM | X86_CR0_TS)) | source
+}
+
+// the value a mov from cr returns,host owned bits come from the shadow
+pub fn guest_visible(guest: u64, shadow: u64, mask: u64) -> u64 {
+    (guest & !mask) | (shadow & mask)
+}
@@ -0,0 +1,228 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+
+use crate::access_dirty::EPT_PAGE_SIZE;
+
+// one page of guest physical addresses,the cpu fills it from the last slot down
+pub const PML_ENTRY_COUNT: usize = 512;
+pub const PML_INDEX_START: u64 = (PML_ENTRY_COUNT - 1) as u64;
+
+// exit qualification of a pml full exit
+pub const PML_FULL_NMI_UNBLOCKING: u64 = 1 << 12;
+// guest interruptibility state
+pub const BLOCKING_BY_NMI: u64 = 1 << 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DirtyLogCommand {
+    Disable = 0,
+    Enable = 1,
+    // drain a log that is not full yet
+    Flush = 2,
+}
+
+impl DirtyLogCommand {
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(DirtyLogCommand::Disable),
+            1 => Some(DirtyLogCommand::Enable),
+            2 => Some(DirtyLogCommand::Flush),
+            _ => None,
+        }
+    }
+}
+
+// page aligned guest physical range
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DirtyRange {
+    pub start: u64,
+    pub pages: u64,
+}
+
+impl DirtyRange {
+    pub fn new(start: u64, size: u64) -> Result<Self, &'static str> {
+        if (start & (EPT_PAGE_SIZE - 1)) != 0 {
+            return Err("dirty range is not page aligned");
+        }
+        if size == 0 {
+            return Err("dirty range is empty");
+        }
+
+        Ok(Self {
+            start,
+            pages: size.div_ceil(EPT_PAGE_SIZE),
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.start + self.pages * EPT_PAGE_SIZE
+    }
+
+    pub fn contains(&self, physical: u64) -> bool {
+        physical >= self.start && physical < self.end()
+    }
+
+    pub fn page_addresses(&self) -> impl Iterator<Item = u64> {
+        let start = self.start;
+        (0..self.pages).map(move |page| start + page * EPT_PAGE_SIZE)
+    }
+}
+
+// one bit per tracked page,set from vmx root and taken at passive level
+pub struct DirtyBitmap {
+    ranges: Vec<DirtyRange>,
+    bits: Vec<AtomicU64>,
+}
+
+impl DirtyBitmap {
+    pub fn new(ranges: &[DirtyRange]) -> Self {
+        let pages: u64 = ranges.iter().map(|range| range.pages).sum();
+        let words = pages.div_ceil(64) as usize;
+
+        Self {
+            ranges: ranges.to_vec(),
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn ranges(&self) -> &[DirtyRange] {
+        &self.ranges
+    }
+
+    pub fn words(&self) -> usize {
+        self.bits.len()
+    }
+
+    // ranges are laid out one after another
+    fn page_index(&self, physical: u64) -> Option<usize> {
+        let mut base = 0;
+        for range in self.ranges.iter() {
+            if range.contains(physical) {
+                return Some((base + (physical - range.start) / EPT_PAGE_SIZE) as usize);
+            }
+            base += range.pages;
+        }
+        None
+    }
+
+    // false when the page is not tracked
+    pub fn set(&self, physical: u64) -> bool {
+        match self.page_index(physical) {
+            Some(index) => {
+                self.bits[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // or the dirty bits into words and clear them
+    pub fn take_into(&self, words: &mut [u64]) {
+        for (word, bits) in words.iter_mut().zip(self.bits.iter()) {
+            *word |= bits.swap(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// entries above the guest pml index were written,the index wraps to 0xFFFF once slot 0 is
+// used. returns the number of tracked pages found
+pub fn drain_pml_log(entries: &[u64; PML_ENTRY_COUNT], index: u64, dirty: &DirtyBitmap) -> usize {
+    let first = if index >= PML_ENTRY_COUNT as u64 {
+        0
+    } else {
+        index as usize + 1
+    };
+
+    entries[first..]
+        .iter()
+        .filter(|&&physical| dirty.set(physical & !(EPT_PAGE_SIZE - 1)))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 0x10_0000;
+
+    // every slot logs its own tracked page
+    fn full_log() -> [u64; PML_ENTRY_COUNT] {
+        core::array::from_fn(|slot| START + slot as u64 * EPT_PAGE_SIZE)
+    }
+
+    fn taken(dirty: &DirtyBitmap) -> Vec<u64> {
+        let mut words = alloc::vec![0; dirty.words()];
+        dirty.take_into(&mut words);
+        words
+    }
+
+    #[test]
+    fn range_rounds_up_to_pages() {
+        let range = DirtyRange::new(START, 0x1001).unwrap();
+        assert_eq!(range.pages, 2);
+        assert_eq!(range.end(), START + 0x2000);
+        assert!(range.contains(START + 0x1FFF));
+        assert!(!range.contains(START + 0x2000));
+        assert!(DirtyRange::new(START + 1, 0x1000).is_err());
+        assert!(DirtyRange::new(START, 0).is_err());
+    }
+
+    #[test]
+    fn ranges_are_laid_out_one_after_another() {
+        let dirty = DirtyBitmap::new(&[
+            DirtyRange::new(START, 0x3000).unwrap(),
+            DirtyRange::new(0x80_0000, 0x1000).unwrap(),
+        ]);
+
+        assert!(dirty.set(START + 0x2000));
+        assert!(dirty.set(0x80_0000));
+        assert!(!dirty.set(START + 0x3000));
+        assert_eq!(taken(&dirty), [0b1100]);
+        // taking clears the bits
+        assert_eq!(taken(&dirty), [0]);
+    }
+
+    #[test]
+    fn full_log_drains_every_slot() {
+        let dirty = DirtyBitmap::new(&[DirtyRange::new(START, 0x20_0000).unwrap()]);
+
+        assert_eq!(drain_pml_log(&full_log(), 0xFFFF, &dirty), PML_ENTRY_COUNT);
+        assert_eq!(taken(&dirty), [u64::MAX; 8]);
+    }
+
+    #[test]
+    fn empty_log_drains_nothing() {
+        let dirty = DirtyBitmap::new(&[DirtyRange::new(START, 0x20_0000).unwrap()]);
+
+        assert_eq!(drain_pml_log(&full_log(), PML_INDEX_START, &dirty), 0);
+        assert_eq!(taken(&dirty), [0; 8]);
+    }
+
+    #[test]
+    fn partial_log_drains_the_slots_above_the_index() {
+        let dirty = DirtyBitmap::new(&[DirtyRange::new(START, 0x20_0000).unwrap()]);
+
+        // two writes,slots 511 and 510
+        assert_eq!(drain_pml_log(&full_log(), 509, &dirty), 2);
+        let words = taken(&dirty);
+        assert_eq!(words[7], 0b11 << 62);
+        assert!(words[..7].iter().all(|&word| word == 0));
+
+        // only slot 0 left
+        assert_eq!(drain_pml_log(&full_log(), 0, &dirty), 511);
+        assert_eq!(taken(&dirty)[0], !1);
+    }
+
+    #[test]
+    fn untracked_and_offset_entries() {
+        let dirty = DirtyBitmap::new(&[DirtyRange::new(START, 0x2000).unwrap()]);
+        let mut entries = [0; PML_ENTRY_COUNT];
+        entries[509] = 0x20_0000;
+        entries[510] = START + 0x1000;
+        // the low bits are not part of the page
+        entries[511] = START + 0xFFF;
+
+        assert_eq!(drain_pml_log(&entries, 508, &dirty), 2);
+        assert_eq!(taken(&dirty), [0b11]);
+    }
+}
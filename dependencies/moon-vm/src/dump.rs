@@ -0,0 +1,733 @@
+use core::fmt::Write;
+
+use alloc::string::String;
+
+use crate::data::{
+    vmcs_encoding::*, vmx_cpu_based_controls::*, vmx_pin_based_controls::*,
+    vmx_secondary_cpu_based_controls::*, vmx_vm_enter_controls::*, vmx_vm_exit_controls::*,
+    VM_INSTRUCTION_ERROR_MAP,
+};
+
+// encoding bits 13-14
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VmcsFieldWidth {
+    Word16,
+    Qword64,
+    Dword32,
+    Natural,
+}
+
+// encoding bits 10-11
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VmcsFieldType {
+    Control,
+    ReadOnlyData,
+    GuestState,
+    HostState,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VmcsField {
+    pub name: &'static str,
+    pub encoding: u64,
+}
+
+impl VmcsField {
+    pub const fn new(name: &'static str, encoding: u64) -> Self {
+        Self { name, encoding }
+    }
+
+    pub const fn width(&self) -> VmcsFieldWidth {
+        match (self.encoding >> 13) & 0x3 {
+            0 => VmcsFieldWidth::Word16,
+            1 => VmcsFieldWidth::Qword64,
+            2 => VmcsFieldWidth::Dword32,
+            _ => VmcsFieldWidth::Natural,
+        }
+    }
+
+    pub const fn field_type(&self) -> VmcsFieldType {
+        match (self.encoding >> 10) & 0x3 {
+            0 => VmcsFieldType::Control,
+            1 => VmcsFieldType::ReadOnlyData,
+            2 => VmcsFieldType::GuestState,
+            _ => VmcsFieldType::HostState,
+        }
+    }
+
+    // access type bit,high 32 bits of a 64-bit field
+    pub const fn is_high(&self) -> bool {
+        (self.encoding & 1) != 0
+    }
+
+    pub const fn value_mask(&self) -> u64 {
+        match self.width() {
+            VmcsFieldWidth::Word16 => 0xFFFF,
+            VmcsFieldWidth::Dword32 => 0xFFFF_FFFF,
+            VmcsFieldWidth::Qword64 => {
+                if self.is_high() {
+                    0xFFFF_FFFF
+                } else {
+                    u64::MAX
+                }
+            }
+            VmcsFieldWidth::Natural => u64::MAX,
+        }
+    }
+}
+
+macro_rules! vmcs_field {
+    ($name:ident) => {
+        VmcsField::new(stringify!($name), $name)
+    };
+}
+
+pub const VMCS_FIELDS: [VmcsField; 188] = [
+    vmcs_field!(VIRTUAL_PROCESSOR_ID),
+    vmcs_field!(POSTED_INTERRUPT_NOTIFICATION),
+    vmcs_field!(EPTP_INDEX),
+    vmcs_field!(GUEST_ES_SELECTOR),
+    vmcs_field!(GUEST_CS_SELECTOR),
+    vmcs_field!(GUEST_SS_SELECTOR),
+    vmcs_field!(GUEST_DS_SELECTOR),
+    vmcs_field!(GUEST_FS_SELECTOR),
+    vmcs_field!(GUEST_GS_SELECTOR),
+    vmcs_field!(GUEST_LDTR_SELECTOR),
+    vmcs_field!(GUEST_TR_SELECTOR),
+    vmcs_field!(GUEST_INTERRUPT_STATUS),
+    vmcs_field!(GUEST_PML_INDEX),
+    vmcs_field!(HOST_ES_SELECTOR),
+    vmcs_field!(HOST_CS_SELECTOR),
+    vmcs_field!(HOST_SS_SELECTOR),
+    vmcs_field!(HOST_DS_SELECTOR),
+    vmcs_field!(HOST_FS_SELECTOR),
+    vmcs_field!(HOST_GS_SELECTOR),
+    vmcs_field!(HOST_TR_SELECTOR),
+    vmcs_field!(IO_BITMAP_A),
+    vmcs_field!(IO_BITMAP_A_HIGH),
+    vmcs_field!(IO_BITMAP_B),
+    vmcs_field!(IO_BITMAP_B_HIGH),
+    vmcs_field!(MSR_BITMAP),
+    vmcs_field!(MSR_BITMAP_HIGH),
+    vmcs_field!(VM_EXIT_MSR_STORE_ADDR),
+    vmcs_field!(VM_EXIT_MSR_STORE_ADDR_HIGH),
+    vmcs_field!(VM_EXIT_MSR_LOAD_ADDR),
+    vmcs_field!(VM_EXIT_MSR_LOAD_ADDR_HIGH),
+    vmcs_field!(VM_ENTRY_MSR_LOAD_ADDR),
+    vmcs_field!(VM_ENTRY_MSR_LOAD_ADDR_HIGH),
+    vmcs_field!(EXECUTIVE_VMCS_POINTER),
+    vmcs_field!(EXECUTIVE_VMCS_POINTER_HIGH),
+    vmcs_field!(TSC_OFFSET),
+    vmcs_field!(TSC_OFFSET_HIGH),
+    vmcs_field!(VIRTUAL_APIC_PAGE_ADDR),
+    vmcs_field!(VIRTUAL_APIC_PAGE_ADDR_HIGH),
+    vmcs_field!(APIC_ACCESS_ADDR),
+    vmcs_field!(APIC_ACCESS_ADDR_HIGH),
+    vmcs_field!(PML_ADDRESS),
+    vmcs_field!(PML_ADDRESS_HIGH),
+    vmcs_field!(VM_FUNCTION_CONTROLS),
+    vmcs_field!(VM_FUNCTION_CONTROLS_HIGH),
+    vmcs_field!(EPT_POINTER),
+    vmcs_field!(EPT_POINTER_HIGH),
+    vmcs_field!(EOI_EXIT_BITMAP_0),
+    vmcs_field!(EOI_EXIT_BITMAP_0_HIGH),
+    vmcs_field!(EOI_EXIT_BITMAP_1),
+    vmcs_field!(EOI_EXIT_BITMAP_1_HIGH),
+    vmcs_field!(EOI_EXIT_BITMAP_2),
+    vmcs_field!(EOI_EXIT_BITMAP_2_HIGH),
+    vmcs_field!(EOI_EXIT_BITMAP_3),
+    vmcs_field!(EOI_EXIT_BITMAP_3_HIGH),
+    vmcs_field!(EPTP_LIST_ADDRESS),
+    vmcs_field!(EPTP_LIST_ADDRESS_HIGH),
+    vmcs_field!(VMREAD_BITMAP_ADDRESS),
+    vmcs_field!(VMREAD_BITMAP_ADDRESS_HIGH),
+    vmcs_field!(VMWRITE_BITMAP_ADDRESS),
+    vmcs_field!(VMWRITE_BITMAP_ADDRESS_HIGH),
+    vmcs_field!(VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS),
+    vmcs_field!(VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS_HIGH),
+    vmcs_field!(XSS_EXITING_BITMAP),
+    vmcs_field!(XSS_EXITING_BITMAP_HIGH),
+    vmcs_field!(SPPT_POINTER),
+    vmcs_field!(SPPT_POINTER_HIGH),
+    vmcs_field!(GUEST_PHYSICAL_ADDRESS),
+    vmcs_field!(GUEST_PHYSICAL_ADDRESS_HIGH),
+    vmcs_field!(VMCS_LINK_POINTER),
+    vmcs_field!(VMCS_LINK_POINTER_HIGH),
+    vmcs_field!(GUEST_IA32_DEBUGCTL),
+    vmcs_field!(GUEST_IA32_DEBUGCTL_HIGH),
+    vmcs_field!(GUEST_IA32_PAT),
+    vmcs_field!(GUEST_IA32_PAT_HIGH),
+    vmcs_field!(GUEST_IA32_EFER),
+    vmcs_field!(GUEST_IA32_EFER_HIGH),
+    vmcs_field!(GUEST_IA32_PERF_GLOBAL_CTRL),
+    vmcs_field!(GUEST_IA32_PERF_GLOBAL_CTRL_HIGH),
+    vmcs_field!(GUEST_PDPTR0),
+    vmcs_field!(GUEST_PDPTR0_HIGH),
+    vmcs_field!(GUEST_PDPTR1),
+    vmcs_field!(GUEST_PDPTR1_HIGH),
+    vmcs_field!(GUEST_PDPTR2),
+    vmcs_field!(GUEST_PDPTR2_HIGH),
+    vmcs_field!(GUEST_PDPTR3),
+    vmcs_field!(GUEST_PDPTR3_HIGH),
+    vmcs_field!(HOST_IA32_PAT),
+    vmcs_field!(HOST_IA32_PAT_HIGH),
+    vmcs_field!(HOST_IA32_EFER),
+    vmcs_field!(HOST_IA32_EFER_HIGH),
+    vmcs_field!(HOST_IA32_PERF_GLOBAL_CTRL),
+    vmcs_field!(HOST_IA32_PERF_GLOBAL_CTRL_HIGH),
+    vmcs_field!(PIN_BASED_VM_EXEC_CONTROL),
+    vmcs_field!(CPU_BASED_VM_EXEC_CONTROL),
+    vmcs_field!(EXCEPTION_BITMAP),
+    vmcs_field!(PAGE_FAULT_ERROR_CODE_MASK),
+    vmcs_field!(PAGE_FAULT_ERROR_CODE_MATCH),
+    vmcs_field!(CR3_TARGET_COUNT),
+    vmcs_field!(VM_EXIT_CONTROLS),
+    vmcs_field!(VM_EXIT_MSR_STORE_COUNT),
+    vmcs_field!(VM_EXIT_MSR_LOAD_COUNT),
+    vmcs_field!(VM_ENTRY_CONTROLS),
+    vmcs_field!(VM_ENTRY_MSR_LOAD_COUNT),
+    vmcs_field!(VM_ENTRY_INTR_INFO_FIELD),
+    vmcs_field!(VM_ENTRY_EXCEPTION_ERROR_CODE),
+    vmcs_field!(VM_ENTRY_INSTRUCTION_LEN),
+    vmcs_field!(TPR_THRESHOLD),
+    vmcs_field!(SECONDARY_VM_EXEC_CONTROL),
+    vmcs_field!(PLE_GAP),
+    vmcs_field!(PLE_WINDOW),
+    vmcs_field!(VM_INSTRUCTION_ERROR),
+    vmcs_field!(VM_EXIT_REASON),
+    vmcs_field!(VM_EXIT_INTR_INFO),
+    vmcs_field!(VM_EXIT_INTR_ERROR_CODE),
+    vmcs_field!(IDT_VECTORING_INFO_FIELD),
+    vmcs_field!(IDT_VECTORING_ERROR_CODE),
+    vmcs_field!(VM_EXIT_INSTRUCTION_LEN),
+    vmcs_field!(VMX_INSTRUCTION_INFO),
+    vmcs_field!(GUEST_ES_LIMIT),
+    vmcs_field!(GUEST_CS_LIMIT),
+    vmcs_field!(GUEST_SS_LIMIT),
+    vmcs_field!(GUEST_DS_LIMIT),
+    vmcs_field!(GUEST_FS_LIMIT),
+    vmcs_field!(GUEST_GS_LIMIT),
+    vmcs_field!(GUEST_LDTR_LIMIT),
+    vmcs_field!(GUEST_TR_LIMIT),
+    vmcs_field!(GUEST_GDTR_LIMIT),
+    vmcs_field!(GUEST_IDTR_LIMIT),
+    vmcs_field!(GUEST_ES_AR_BYTES),
+    vmcs_field!(GUEST_CS_AR_BYTES),
+    vmcs_field!(GUEST_SS_AR_BYTES),
+    vmcs_field!(GUEST_DS_AR_BYTES),
+    vmcs_field!(GUEST_FS_AR_BYTES),
+    vmcs_field!(GUEST_GS_AR_BYTES),
+    vmcs_field!(GUEST_LDTR_AR_BYTES),
+    vmcs_field!(GUEST_TR_AR_BYTES),
+    vmcs_field!(GUEST_INTERRUPTIBILITY_INFO),
+    vmcs_field!(GUEST_ACTIVITY_STATE),
+    vmcs_field!(GUEST_SMBASE),
+    vmcs_field!(GUEST_SYSENTER_CS),
+    vmcs_field!(VMX_PREEMPTION_TIMER_VALUE),
+    vmcs_field!(HOST_IA32_SYSENTER_CS),
+    vmcs_field!(CR0_GUEST_HOST_MASK),
+    vmcs_field!(CR4_GUEST_HOST_MASK),
+    vmcs_field!(CR0_READ_SHADOW),
+    vmcs_field!(CR4_READ_SHADOW),
+    vmcs_field!(CR3_TARGET_VALUE0),
+    vmcs_field!(CR3_TARGET_VALUE1),
+    vmcs_field!(CR3_TARGET_VALUE2),
+    vmcs_field!(CR3_TARGET_VALUE3),
+    vmcs_field!(EXIT_QUALIFICATION),
+    vmcs_field!(IO_RCX),
+    vmcs_field!(IO_RSI),
+    vmcs_field!(IO_RDI),
+    vmcs_field!(IO_RIP),
+    vmcs_field!(GUEST_LINEAR_ADDRESS),
+    vmcs_field!(GUEST_CR0),
+    vmcs_field!(GUEST_CR3),
+    vmcs_field!(GUEST_CR4),
+    vmcs_field!(GUEST_ES_BASE),
+    vmcs_field!(GUEST_CS_BASE),
+    vmcs_field!(GUEST_SS_BASE),
+    vmcs_field!(GUEST_DS_BASE),
+    vmcs_field!(GUEST_FS_BASE),
+    vmcs_field!(GUEST_GS_BASE),
+    vmcs_field!(GUEST_LDTR_BASE),
+    vmcs_field!(GUEST_TR_BASE),
+    vmcs_field!(GUEST_GDTR_BASE),
+    vmcs_field!(GUEST_IDTR_BASE),
+    vmcs_field!(GUEST_DR7),
+    vmcs_field!(GUEST_RSP),
+    vmcs_field!(GUEST_RIP),
+    vmcs_field!(GUEST_RFLAGS),
+    vmcs_field!(GUEST_PENDING_DBG_EXCEPTIONS),
+    vmcs_field!(GUEST_SYSENTER_ESP),
+    vmcs_field!(GUEST_SYSENTER_EIP),
+    vmcs_field!(HOST_CR0),
+    vmcs_field!(HOST_CR3),
+    vmcs_field!(HOST_CR4),
+    vmcs_field!(HOST_FS_BASE),
+    vmcs_field!(HOST_GS_BASE),
+    vmcs_field!(HOST_TR_BASE),
+    vmcs_field!(HOST_GDTR_BASE),
+    vmcs_field!(HOST_IDTR_BASE),
+    vmcs_field!(HOST_IA32_SYSENTER_ESP),
+    vmcs_field!(HOST_IA32_SYSENTER_EIP),
+    vmcs_field!(HOST_RSP),
+    vmcs_field!(HOST_RIP),
+];
+
+pub const PIN_BASED_CONTROL_NAMES: [(u32, &str); 5] = [
+    (VMX_PIN_CTLS_EXT_INT_EXIT, "EXT_INT_EXIT"),
+    (VMX_PIN_CTLS_NMI_EXIT, "NMI_EXIT"),
+    (VMX_PIN_CTLS_VIRT_NMI, "VIRT_NMI"),
+    (VMX_PIN_CTLS_PREEMPT_TIMER, "PREEMPT_TIMER"),
+    (VMX_PIN_CTLS_POSTED_INT, "POSTED_INT"),
+];
+
+pub const CPU_BASED_CONTROL_NAMES: [(u32, &str); 22] = [
+    (VMX_PROC_CTLS_INT_WINDOW_EXIT, "INT_WINDOW_EXIT"),
+    (VMX_PROC_CTLS_USE_TSC_OFFSETTING, "USE_TSC_OFFSETTING"),
+    (VMX_PROC_CTLS_HLT_EXIT, "HLT_EXIT"),
+    (VMX_PROC_CTLS_INVLPG_EXIT, "INVLPG_EXIT"),
+    (VMX_PROC_CTLS_MWAIT_EXIT, "MWAIT_EXIT"),
+    (VMX_PROC_CTLS_RDPMC_EXIT, "RDPMC_EXIT"),
+    (VMX_PROC_CTLS_RDTSC_EXIT, "RDTSC_EXIT"),
+    (VMX_PROC_CTLS_CR3_LOAD_EXIT, "CR3_LOAD_EXIT"),
+    (VMX_PROC_CTLS_CR3_STORE_EXIT, "CR3_STORE_EXIT"),
+    (VMX_PROC_CTLS_USE_TERTIARY_CTLS, "USE_TERTIARY_CTLS"),
+    (VMX_PROC_CTLS_CR8_LOAD_EXIT, "CR8_LOAD_EXIT"),
+    (VMX_PROC_CTLS_CR8_STORE_EXIT, "CR8_STORE_EXIT"),
+    (VMX_PROC_CTLS_USE_TPR_SHADOW, "USE_TPR_SHADOW"),
+    (VMX_PROC_CTLS_NMI_WINDOW_EXIT, "NMI_WINDOW_EXIT"),
+    (VMX_PROC_CTLS_MOV_DR_EXIT, "MOV_DR_EXIT"),
+    (VMX_PROC_CTLS_UNCOND_IO_EXIT, "UNCOND_IO_EXIT"),
+    (VMX_PROC_CTLS_USE_IO_BITMAPS, "USE_IO_BITMAPS"),
+    (VMX_PROC_CTLS_MONITOR_TRAP_FLAG, "MONITOR_TRAP_FLAG"),
+    (VMX_PROC_CTLS_USE_MSR_BITMAPS, "USE_MSR_BITMAPS"),
+    (VMX_PROC_CTLS_MONITOR_EXIT, "MONITOR_EXIT"),
+    (VMX_PROC_CTLS_PAUSE_EXIT, "PAUSE_EXIT"),
+    (VMX_PROC_CTLS_USE_SECONDARY_CTLS, "USE_SECONDARY_CTLS"),
+];
+
+pub const SECONDARY_CONTROL_NAMES: [(u32, &str); 27] = [
+    (VMX_PROC_CTLS2_VIRT_APIC_ACCESS, "VIRT_APIC_ACCESS"),
+    (VMX_PROC_CTLS2_EPT, "EPT"),
+    (VMX_PROC_CTLS2_DESC_TABLE_EXIT, "DESC_TABLE_EXIT"),
+    (VMX_PROC_CTLS2_RDTSCP, "RDTSCP"),
+    (VMX_PROC_CTLS2_VIRT_X2APIC_MODE, "VIRT_X2APIC_MODE"),
+    (VMX_PROC_CTLS2_VPID, "VPID"),
+    (VMX_PROC_CTLS2_WBINVD_EXIT, "WBINVD_EXIT"),
+    (VMX_PROC_CTLS2_UNRESTRICTED_GUEST, "UNRESTRICTED_GUEST"),
+    (VMX_PROC_CTLS2_APIC_REG_VIRT, "APIC_REG_VIRT"),
+    (VMX_PROC_CTLS2_VIRT_INT_DELIVERY, "VIRT_INT_DELIVERY"),
+    (VMX_PROC_CTLS2_PAUSE_LOOP_EXIT, "PAUSE_LOOP_EXIT"),
+    (VMX_PROC_CTLS2_RDRAND_EXIT, "RDRAND_EXIT"),
+    (VMX_PROC_CTLS2_INVPCID, "INVPCID"),
+    (VMX_PROC_CTLS2_VMFUNC, "VMFUNC"),
+    (VMX_PROC_CTLS2_VMCS_SHADOWING, "VMCS_SHADOWING"),
+    (VMX_PROC_CTLS2_ENCLS_EXIT, "ENCLS_EXIT"),
+    (VMX_PROC_CTLS2_RDSEED_EXIT, "RDSEED_EXIT"),
+    (VMX_PROC_CTLS2_PML, "PML"),
+    (VMX_PROC_CTLS2_EPT_XCPT_VE, "EPT_XCPT_VE"),
+    (VMX_PROC_CTLS2_CONCEAL_VMX_FROM_PT, "CONCEAL_VMX_FROM_PT"),
+    (VMX_PROC_CTLS2_XSAVES_XRSTORS, "XSAVES_XRSTORS"),
+    (VMX_PROC_CTLS2_MODE_BASED_EPT_PERM, "MODE_BASED_EPT_PERM"),
+    (VMX_PROC_CTLS2_SPP_EPT, "SPP_EPT"),
+    (VMX_PROC_CTLS2_PT_EPT, "PT_EPT"),
+    (VMX_PROC_CTLS2_TSC_SCALING, "TSC_SCALING"),
+    (VMX_PROC_CTLS2_USER_WAIT_PAUSE, "USER_WAIT_PAUSE"),
+    (VMX_PROC_CTLS2_ENCLV_EXIT, "ENCLV_EXIT"),
+];
+
+pub const ENTRY_CONTROL_NAMES: [(u32, &str); 12] = [
+    (VMX_ENTRY_CTLS_LOAD_DEBUG, "LOAD_DEBUG"),
+    (VMX_ENTRY_CTLS_IA32E_MODE_GUEST, "IA32E_MODE_GUEST"),
+    (VMX_ENTRY_CTLS_ENTRY_TO_SMM, "ENTRY_TO_SMM"),
+    (VMX_ENTRY_CTLS_DEACTIVATE_DUAL_MON, "DEACTIVATE_DUAL_MON"),
+    (VMX_ENTRY_CTLS_LOAD_PERF_MSR, "LOAD_PERF_MSR"),
+    (VMX_ENTRY_CTLS_LOAD_PAT_MSR, "LOAD_PAT_MSR"),
+    (VMX_ENTRY_CTLS_LOAD_EFER_MSR, "LOAD_EFER_MSR"),
+    (VMX_ENTRY_CTLS_LOAD_BNDCFGS_MSR, "LOAD_BNDCFGS_MSR"),
+    (VMX_ENTRY_CTLS_CONCEAL_VMX_FROM_PT, "CONCEAL_VMX_FROM_PT"),
+    (VMX_ENTRY_CTLS_LOAD_RTIT_CTL_MSR, "LOAD_RTIT_CTL_MSR"),
+    (VMX_ENTRY_CTLS_LOAD_CET_STATE, "LOAD_CET_STATE"),
+    (VMX_ENTRY_CTLS_LOAD_PKRS_MSR, "LOAD_PKRS_MSR"),
+];
+
+pub const EXIT_CONTROL_NAMES: [(u32, &str); 16] = [
+    (VMX_EXIT_CTLS_SAVE_DEBUG, "SAVE_DEBUG"),
+    (VMX_EXIT_CTLS_HOST_ADDR_SPACE_SIZE, "HOST_ADDR_SPACE_SIZE"),
+    (VMX_EXIT_CTLS_LOAD_PERF_MSR, "LOAD_PERF_MSR"),
+    (VMX_EXIT_CTLS_ACK_EXT_INT, "ACK_EXT_INT"),
+    (VMX_EXIT_CTLS_SAVE_PAT_MSR, "SAVE_PAT_MSR"),
+    (VMX_EXIT_CTLS_LOAD_PAT_MSR, "LOAD_PAT_MSR"),
+    (VMX_EXIT_CTLS_SAVE_EFER_MSR, "SAVE_EFER_MSR"),
+    (VMX_EXIT_CTLS_LOAD_EFER_MSR, "LOAD_EFER_MSR"),
+    (VMX_EXIT_CTLS_SAVE_PREEMPT_TIMER, "SAVE_PREEMPT_TIMER"),
+    (VMX_EXIT_CTLS_CLEAR_BNDCFGS_MSR, "CLEAR_BNDCFGS_MSR"),
+    (VMX_EXIT_CTLS_CONCEAL_VMX_FROM_PT, "CONCEAL_VMX_FROM_PT"),
+    (VMX_EXIT_CTLS_CLEAR_RTIT_CTL_MSR, "CLEAR_RTIT_CTL_MSR"),
+    (VMX_EXIT_CTLS_LOAD_CET_STATE, "LOAD_CET_STATE"),
+    (VMX_EXIT_CTLS_LOAD_PKRS_MSR, "LOAD_PKRS_MSR"),
+    (VMX_EXIT_CTLS_SAVE_PERF_MSR, "SAVE_PERF_MSR"),
+    (VMX_EXIT_CTLS_USE_SECONDARY_CTLS, "USE_SECONDARY_CTLS"),
+];
+
+pub const EXIT_REASON_NAMES: [&str; 67] = [
+    "EXCEPTION_NMI",
+    "EXTERNAL_INTERRUPT",
+    "TRIPLE_FAULT",
+    "INIT",
+    "SIPI",
+    "IO_SMI",
+    "OTHER_SMI",
+    "PENDING_INTERRUPT",
+    "NMI_WINDOW",
+    "TASK_SWITCH",
+    "CPUID",
+    "GETSEC",
+    "HLT",
+    "INVD",
+    "INVLPG",
+    "RDPMC",
+    "RDTSC",
+    "RSM",
+    "VMCALL",
+    "VMCLEAR",
+    "VMLAUNCH",
+    "VMPTRLD",
+    "VMPTRST",
+    "VMREAD",
+    "VMRESUME",
+    "VMWRITE",
+    "VMXOFF",
+    "VMXON",
+    "CR_ACCESS",
+    "DR_ACCESS",
+    "IO_INSTRUCTION",
+    "MSR_READ",
+    "MSR_WRITE",
+    "INVALID_GUEST_STATE",
+    "MSR_LOADING",
+    "RESERVED_35",
+    "MWAIT_INSTRUCTION",
+    "MTF",
+    "RESERVED_38",
+    "MONITOR_INSTRUCTION",
+    "PAUSE_INSTRUCTION",
+    "MACHINE_CHECK",
+    "RESERVED_42",
+    "TPR_BELOW_THRESHOLD",
+    "APIC_ACCESS",
+    "VIRTUALIZED_EIO",
+    "XDTR_ACCESS",
+    "TR_ACCESS",
+    "EPT_VIOLATION",
+    "EPT_MISCONFIG",
+    "INVEPT",
+    "RDTSCP",
+    "PREEMPT_TIMER",
+    "INVVPID",
+    "WBINVD",
+    "XSETBV",
+    "APIC_WRITE",
+    "RDRAND",
+    "INVPCID",
+    "VMFUNC",
+    "RESERVED_60",
+    "RDSEED",
+    "PML_FULL",
+    "XSAVES",
+    "XRSTORS",
+    "PCONFIG",
+    "SPP_EVENT",
+];
+
+pub fn exit_reason_name(exit_reason: u64) -> &'static str {
+    EXIT_REASON_NAMES
+        .get((exit_reason & 0xFFFF) as usize)
+        .copied()
+        .unwrap_or("UNKNOWN")
+}
+
+fn write_control_names(out: &mut String, value: u64, names: &[(u32, &str)]) {
+    let _ = write!(out, " [");
+    let mut first = true;
+    for (bit, name) in names {
+        if (value as u32 & bit) != 0 {
+            let _ = write!(out, "{}{}", if first { "" } else { " " }, name);
+            first = false;
+        }
+    }
+    let _ = write!(out, "]");
+}
+
+// segment access rights as stored in the vmcs
+fn write_access_rights(out: &mut String, value: u64) {
+    let _ = write!(
+        out,
+        " [type={:X} s={} dpl={} p={} avl={} l={} db={} g={} unusable={}]",
+        value & 0xF,
+        (value >> 4) & 1,
+        (value >> 5) & 3,
+        (value >> 7) & 1,
+        (value >> 12) & 1,
+        (value >> 13) & 1,
+        (value >> 14) & 1,
+        (value >> 15) & 1,
+        (value >> 16) & 1,
+    );
+}
+
+// vm-exit/vm-entry interruption information and idt-vectoring information
+fn write_interruption_info(out: &mut String, value: u64) {
+    let _ = write!(
+        out,
+        " [vector={} type={} error_code={} nmi_unblocking={} valid={}]",
+        value & 0xFF,
+        (value >> 8) & 0x7,
+        (value >> 11) & 1,
+        (value >> 12) & 1,
+        (value >> 31) & 1,
+    );
+}
+
+fn write_decoded(out: &mut String, encoding: u64, value: u64) {
+    match encoding {
+        PIN_BASED_VM_EXEC_CONTROL => write_control_names(out, value, &PIN_BASED_CONTROL_NAMES),
+        CPU_BASED_VM_EXEC_CONTROL => write_control_names(out, value, &CPU_BASED_CONTROL_NAMES),
+        SECONDARY_VM_EXEC_CONTROL => write_control_names(out, value, &SECONDARY_CONTROL_NAMES),
+        VM_ENTRY_CONTROLS => write_control_names(out, value, &ENTRY_CONTROL_NAMES),
+        VM_EXIT_CONTROLS => write_control_names(out, value, &EXIT_CONTROL_NAMES),
+        GUEST_ES_AR_BYTES | GUEST_CS_AR_BYTES | GUEST_SS_AR_BYTES | GUEST_DS_AR_BYTES
+        | GUEST_FS_AR_BYTES | GUEST_GS_AR_BYTES | GUEST_LDTR_AR_BYTES | GUEST_TR_AR_BYTES => {
+            write_access_rights(out, value)
+        }
+        VM_EXIT_INTR_INFO | IDT_VECTORING_INFO_FIELD | VM_ENTRY_INTR_INFO_FIELD => {
+            write_interruption_info(out, value)
+        }
+        VM_EXIT_REASON => {
+            let _ = write!(
+                out,
+                " [{} entry_failure={}]",
+                exit_reason_name(value),
+                (value >> 31) & 1
+            );
+        }
+        VM_INSTRUCTION_ERROR => {
+            let _ = write!(
+                out,
+                " [{}]",
+                VM_INSTRUCTION_ERROR_MAP
+                    .get(value as usize)
+                    .copied()
+                    .unwrap_or("Unknown")
+            );
+        }
+        _ => {}
+    }
+}
+
+// render every known field,read returns none for fields the cpu does not support
+pub fn render_vmcs_dump<F>(mut read: F) -> String
+where
+    F: FnMut(u64) -> Option<u64>,
+{
+    let mut out = String::new();
+    let _ = writeln!(out, "VMCS dump");
+
+    for field in VMCS_FIELDS.iter() {
+        // the full 64-bit read already covers the high half
+        if field.is_high() {
+            continue;
+        }
+
+        let _ = write!(
+            out,
+            "{:?} {:?} {}({:#06X}) = ",
+            field.field_type(),
+            field.width(),
+            field.name,
+            field.encoding
+        );
+
+        match read(field.encoding) {
+            Some(value) => {
+                let value = value & field.value_mask();
+                let _ = write!(out, "{:#X}", value);
+                write_decoded(&mut out, field.encoding, value);
+            }
+            None => {
+                let _ = write!(out, "unsupported");
+            }
+        }
+
+        let _ = writeln!(out);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{collections::BTreeMap, vec::Vec};
+
+    // fields whose names do not say their type
+    const READ_ONLY_FIELDS: [u64; 16] = [
+        GUEST_PHYSICAL_ADDRESS,
+        GUEST_PHYSICAL_ADDRESS_HIGH,
+        VM_INSTRUCTION_ERROR,
+        VM_EXIT_REASON,
+        VM_EXIT_INTR_INFO,
+        VM_EXIT_INTR_ERROR_CODE,
+        IDT_VECTORING_INFO_FIELD,
+        IDT_VECTORING_ERROR_CODE,
+        VM_EXIT_INSTRUCTION_LEN,
+        VMX_INSTRUCTION_INFO,
+        EXIT_QUALIFICATION,
+        IO_RCX,
+        IO_RSI,
+        IO_RDI,
+        IO_RIP,
+        GUEST_LINEAR_ADDRESS,
+    ];
+
+    #[test]
+    fn encodings_are_well_formed_and_unique() {
+        let mut seen = BTreeMap::new();
+        for field in VMCS_FIELDS.iter() {
+            // bit 12 and everything above bit 14 are reserved
+            assert_eq!(field.encoding & !0x6FFF, 0, "{}", field.name);
+            assert!(
+                seen.insert(field.encoding, field.name).is_none(),
+                "{}",
+                field.name
+            );
+
+            // only 64-bit fields have a high access
+            if field.width() != VmcsFieldWidth::Qword64 {
+                assert!(!field.is_high(), "{}", field.name);
+            }
+        }
+    }
+
+    #[test]
+    fn high_fields_follow_their_full_field() {
+        for pair in VMCS_FIELDS.windows(2) {
+            let (full, high) = (pair[0], pair[1]);
+            if !high.is_high() {
+                continue;
+            }
+
+            assert_eq!(high.width(), VmcsFieldWidth::Qword64, "{}", high.name);
+            assert_eq!(high.encoding, full.encoding + 1, "{}", high.name);
+            assert_eq!(high.name.strip_suffix("_HIGH"), Some(full.name));
+            assert_eq!(high.value_mask(), 0xFFFF_FFFF);
+            assert_eq!(full.value_mask(), u64::MAX);
+        }
+
+        for field in VMCS_FIELDS.iter() {
+            assert_eq!(
+                field.is_high(),
+                field.name.ends_with("_HIGH"),
+                "{}",
+                field.name
+            );
+        }
+    }
+
+    #[test]
+    fn types_match_field_names() {
+        for field in VMCS_FIELDS.iter() {
+            let expected = if READ_ONLY_FIELDS.contains(&field.encoding) {
+                VmcsFieldType::ReadOnlyData
+            } else if field.name.starts_with("HOST_") {
+                VmcsFieldType::HostState
+            } else if field.name.starts_with("GUEST_")
+                || field.encoding == VMCS_LINK_POINTER
+                || field.encoding == VMCS_LINK_POINTER_HIGH
+                || field.encoding == VMX_PREEMPTION_TIMER_VALUE
+            {
+                VmcsFieldType::GuestState
+            } else {
+                VmcsFieldType::Control
+            };
+            assert_eq!(field.field_type(), expected, "{}", field.name);
+        }
+    }
+
+    #[test]
+    fn widths_match_field_names() {
+        for field in VMCS_FIELDS.iter() {
+            let name = field.name;
+            let expected = if name.ends_with("_SELECTOR") {
+                Some(VmcsFieldWidth::Word16)
+            } else if name.ends_with("_LIMIT") || name.ends_with("_AR_BYTES") {
+                Some(VmcsFieldWidth::Dword32)
+            } else if name.ends_with("_BASE") || name.starts_with("GUEST_CR") {
+                Some(VmcsFieldWidth::Natural)
+            } else if name.ends_with("_CONTROL") || name.ends_with("_CONTROLS") {
+                // vm function controls are the one 64-bit control word
+                Some(match name.starts_with("VM_FUNCTION") {
+                    true => VmcsFieldWidth::Qword64,
+                    false => VmcsFieldWidth::Dword32,
+                })
+            } else {
+                None
+            };
+
+            if let Some(expected) = expected {
+                assert_eq!(field.width(), expected, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn render_vmcs_dump_golden() {
+        let vmcs: BTreeMap<u64, u64> = [
+            // the cpu zero extends,bits above the width are masked anyway
+            (VIRTUAL_PROCESSOR_ID, 0x1_0001),
+            (EPT_POINTER, 0x1234_5000_001E),
+            (PIN_BASED_VM_EXEC_CONTROL, 0x29),
+            (VM_INSTRUCTION_ERROR, 7),
+            (VM_EXIT_REASON, 0x8000_0021),
+            (GUEST_CS_AR_BYTES, 0xA09B),
+            (GUEST_RIP, 0xFFFF_F800_1234_5678),
+        ]
+        .into_iter()
+        .collect();
+
+        let dump = render_vmcs_dump(|encoding| vmcs.get(&encoding).copied());
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(lines[0], "VMCS dump");
+        assert_eq!(
+            lines.len(),
+            1 + VMCS_FIELDS.iter().filter(|field| !field.is_high()).count()
+        );
+
+        for golden in [
+            "Control Word16 VIRTUAL_PROCESSOR_ID(0x0000) = 0x1",
+            "Control Word16 POSTED_INTERRUPT_NOTIFICATION(0x0002) = unsupported",
+            "Control Qword64 EPT_POINTER(0x201A) = 0x12345000001E",
+            "Control Dword32 PIN_BASED_VM_EXEC_CONTROL(0x4000) = 0x29 \
+             [EXT_INT_EXIT NMI_EXIT VIRT_NMI]",
+            "ReadOnlyData Dword32 VM_INSTRUCTION_ERROR(0x4400) = 0x7 \
+             [VM entry with invalid control field(s)]",
+            "ReadOnlyData Dword32 VM_EXIT_REASON(0x4402) = 0x80000021 \
+             [INVALID_GUEST_STATE entry_failure=1]",
+            "GuestState Dword32 GUEST_CS_AR_BYTES(0x4816) = 0xA09B \
+             [type=B s=1 dpl=0 p=1 avl=0 l=1 db=0 g=1 unusable=0]",
+            "GuestState Natural GUEST_RIP(0x681E) = 0xFFFFF80012345678",
+        ] {
+            assert!(lines.contains(&golden), "missing:{}", golden);
+        }
+
+        assert!(!dump.contains("EPT_POINTER_HIGH"));
+    }
+
+    #[test]
+    fn exit_reason_names() {
+        assert_eq!(exit_reason_name(10), "CPUID");
+        assert_eq!(exit_reason_name(0x8000_0021), "INVALID_GUEST_STATE");
+        assert_eq!(exit_reason_name(0xFFFF), "UNKNOWN");
+    }
+}
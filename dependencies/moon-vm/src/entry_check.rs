@@ -0,0 +1,984 @@
+use alloc::vec::Vec;
+
+use crate::data::{
+    guest_interruptibility::{BLOCKING_BY_MOV_SS, BLOCKING_BY_STI},
+    vmx_cpu_based_controls::*,
+    vmx_pin_based_controls::*,
+    vmx_secondary_cpu_based_controls::*,
+    vmx_vm_enter_controls::*,
+    vmx_vm_exit_controls::*,
+};
+
+// control register and rflags bits the checks look at
+const X86_CR0_PE: u64 = 1 << 0;
+const X86_CR0_PG: u64 = 1 << 31;
+const X86_CR4_PAE: u64 = 1 << 5;
+const RFLAGS_RESERVED1: u64 = 1 << 1;
+const RFLAGS_RESERVED_ZERO: u64 = 0xFFC0_8028;
+const RFLAGS_IF: u64 = 1 << 9;
+const RFLAGS_VM: u64 = 1 << 17;
+
+// activity state
+const ACTIVITY_STATE_HLT: u32 = 1;
+const ACTIVITY_STATE_WAIT_FOR_SIPI: u32 = 3;
+
+// interruptibility state
+const INTERRUPTIBILITY_RESERVED: u32 = !0x1F;
+
+// segment access rights
+const AR_TYPE_MASK: u32 = 0xF;
+const AR_S: u32 = 1 << 4;
+const AR_P: u32 = 1 << 7;
+const AR_L: u32 = 1 << 13;
+const AR_DB: u32 = 1 << 14;
+const AR_G: u32 = 1 << 15;
+const AR_UNUSABLE: u32 = 1 << 16;
+const AR_RESERVED: u32 = 0xF00 | 0xFFFE_0000;
+
+const SEGMENT_TYPE_LDT: u32 = 2;
+const SEGMENT_TYPE_BUSY_TSS64: u32 = 11;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SegmentState {
+    pub selector: u16,
+    pub base: u64,
+    pub limit: u32,
+    pub access_rights: u32,
+}
+
+impl SegmentState {
+    fn usable(&self) -> bool {
+        (self.access_rights & AR_UNUSABLE) == 0
+    }
+
+    fn segment_type(&self) -> u32 {
+        self.access_rights & AR_TYPE_MASK
+    }
+
+    fn dpl(&self) -> u32 {
+        (self.access_rights >> 5) & 3
+    }
+
+    fn rpl(&self) -> u32 {
+        (self.selector & 3) as u32
+    }
+
+    // g must be 0 if limit[11:0] is not all ones,and 1 if any bit of limit[31:20] is set
+    fn granularity_ok(&self) -> bool {
+        let g = (self.access_rights & AR_G) != 0;
+        ((self.limit & 0xFFF) == 0xFFF || !g) && ((self.limit & 0xFFF0_0000) == 0 || g)
+    }
+}
+
+// allowed settings reported by the vmx capability msrs
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VmxCapabilities {
+    pub pin_controls: u64,
+    pub proc_controls: u64,
+    pub proc2_controls: u64,
+    pub exit_controls: u64,
+    pub entry_controls: u64,
+    pub cr0_fixed0: u64,
+    pub cr0_fixed1: u64,
+    pub cr4_fixed0: u64,
+    pub cr4_fixed1: u64,
+    pub physical_address_width: u8,
+}
+
+// everything vm-entry looks at,filled from the current vmcs or by hand
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VmEntryState {
+    pub capabilities: VmxCapabilities,
+
+    pub pin_controls: u32,
+    pub proc_controls: u32,
+    pub proc2_controls: u32,
+    pub exit_controls: u32,
+    pub entry_controls: u32,
+    pub cr3_target_count: u32,
+    pub msr_bitmap: u64,
+    pub ept_pointer: u64,
+    pub vpid: u16,
+
+    pub host_cr0: u64,
+    pub host_cr3: u64,
+    pub host_cr4: u64,
+    pub host_cs_selector: u16,
+    pub host_ss_selector: u16,
+    pub host_ds_selector: u16,
+    pub host_es_selector: u16,
+    pub host_fs_selector: u16,
+    pub host_gs_selector: u16,
+    pub host_tr_selector: u16,
+    pub host_fs_base: u64,
+    pub host_gs_base: u64,
+    pub host_tr_base: u64,
+    pub host_gdtr_base: u64,
+    pub host_idtr_base: u64,
+    pub host_rip: u64,
+
+    pub guest_cr0: u64,
+    pub guest_cr3: u64,
+    pub guest_cr4: u64,
+    pub guest_dr7: u64,
+    pub guest_rflags: u64,
+    pub guest_rip: u64,
+    pub guest_es: SegmentState,
+    pub guest_cs: SegmentState,
+    pub guest_ss: SegmentState,
+    pub guest_ds: SegmentState,
+    pub guest_fs: SegmentState,
+    pub guest_gs: SegmentState,
+    pub guest_tr: SegmentState,
+    pub guest_ldtr: SegmentState,
+    pub guest_gdtr_base: u64,
+    pub guest_gdtr_limit: u32,
+    pub guest_idtr_base: u64,
+    pub guest_idtr_limit: u32,
+    pub guest_activity_state: u32,
+    pub guest_interruptibility: u32,
+    pub vmcs_link_pointer: u64,
+}
+
+fn is_canonical(address: u64) -> bool {
+    // 48-bit linear address,bits 63:47 must be all equal
+    let upper = address >> 47;
+    upper == 0 || upper == 0x1FFFF
+}
+
+fn exceeds_physical_width(address: u64, width: u8) -> bool {
+    width < 64 && (address >> width) != 0
+}
+
+// bits set in allowed0 must be 1,bits clear in allowed1 must be 0
+fn control_reserved_ok(value: u32, capability: u64) -> bool {
+    let allowed0 = capability as u32;
+    let allowed1 = (capability >> 32) as u32;
+    (value & allowed0) == allowed0 && (value & !allowed1) == 0
+}
+
+fn fixed_bits_ok(value: u64, fixed0: u64, fixed1: u64) -> bool {
+    (value & fixed0) == fixed0 && (value & !fixed1) == 0
+}
+
+fn check_controls(state: &VmEntryState, failures: &mut Vec<&'static str>) {
+    let caps = &state.capabilities;
+
+    if !control_reserved_ok(state.pin_controls, caps.pin_controls) {
+        failures.push("pin-based controls:reserved bits");
+    }
+
+    if !control_reserved_ok(state.proc_controls, caps.proc_controls) {
+        failures.push("primary processor-based controls:reserved bits");
+    }
+
+    let secondary = if (state.proc_controls & VMX_PROC_CTLS_USE_SECONDARY_CTLS) != 0 {
+        if !control_reserved_ok(state.proc2_controls, caps.proc2_controls) {
+            failures.push("secondary processor-based controls:reserved bits");
+        }
+        state.proc2_controls
+    } else {
+        0
+    };
+
+    if !control_reserved_ok(state.exit_controls, caps.exit_controls) {
+        failures.push("vm-exit controls:reserved bits");
+    }
+
+    if !control_reserved_ok(state.entry_controls, caps.entry_controls) {
+        failures.push("vm-entry controls:reserved bits");
+    }
+
+    if state.cr3_target_count > 4 {
+        failures.push("cr3-target count greater than 4");
+    }
+
+    if (state.proc_controls & VMX_PROC_CTLS_USE_MSR_BITMAPS) != 0
+        && ((state.msr_bitmap & 0xFFF) != 0
+            || exceeds_physical_width(state.msr_bitmap, caps.physical_address_width))
+    {
+        failures.push("msr bitmap address");
+    }
+
+    if (state.pin_controls & VMX_PIN_CTLS_NMI_EXIT) == 0
+        && (state.pin_controls & VMX_PIN_CTLS_VIRT_NMI) != 0
+    {
+        failures.push("virtual nmis without nmi exiting");
+    }
+
+    if (state.pin_controls & VMX_PIN_CTLS_VIRT_NMI) == 0
+        && (state.proc_controls & VMX_PROC_CTLS_NMI_WINDOW_EXIT) != 0
+    {
+        failures.push("nmi-window exiting without virtual nmis");
+    }
+
+    if (secondary & VMX_PROC_CTLS2_EPT) != 0 {
+        let memory_type = state.ept_pointer & 0x7;
+        let walk_length = (state.ept_pointer >> 3) & 0x7;
+
+        if memory_type != 0 && memory_type != 6 {
+            failures.push("eptp:memory type");
+        }
+        if walk_length != 3 {
+            failures.push("eptp:page-walk length");
+        }
+        if (state.ept_pointer & 0xF80) != 0
+            || exceeds_physical_width(state.ept_pointer, caps.physical_address_width)
+        {
+            failures.push("eptp:reserved bits");
+        }
+    }
+
+    if (secondary & VMX_PROC_CTLS2_VPID) != 0 && state.vpid == 0 {
+        failures.push("vpid is 0");
+    }
+
+    if (secondary & VMX_PROC_CTLS2_UNRESTRICTED_GUEST) != 0 && (secondary & VMX_PROC_CTLS2_EPT) == 0
+    {
+        failures.push("unrestricted guest without ept");
+    }
+
+    if (state.pin_controls & VMX_PIN_CTLS_PREEMPT_TIMER) == 0
+        && (state.exit_controls & VMX_EXIT_CTLS_SAVE_PREEMPT_TIMER) != 0
+    {
+        failures.push("save preemption timer without activating it");
+    }
+
+    if (state.entry_controls & VMX_ENTRY_CTLS_ENTRY_TO_SMM) != 0 {
+        failures.push("entry to smm outside smm");
+    }
+
+    if (state.entry_controls & VMX_ENTRY_CTLS_DEACTIVATE_DUAL_MON) != 0 {
+        failures.push("deactivate dual-monitor outside smm");
+    }
+}
+
+fn check_host_state(state: &VmEntryState, failures: &mut Vec<&'static str>) {
+    let caps = &state.capabilities;
+
+    if !fixed_bits_ok(state.host_cr0, caps.cr0_fixed0, caps.cr0_fixed1) {
+        failures.push("host cr0:fixed bits");
+    }
+
+    if !fixed_bits_ok(state.host_cr4, caps.cr4_fixed0, caps.cr4_fixed1) {
+        failures.push("host cr4:fixed bits");
+    }
+
+    if exceeds_physical_width(state.host_cr3, caps.physical_address_width) {
+        failures.push("host cr3:bits beyond physical-address width");
+    }
+
+    // only a 64-bit host is supported
+    if (state.exit_controls & VMX_EXIT_CTLS_HOST_ADDR_SPACE_SIZE) == 0 {
+        failures.push("host address-space size is 0");
+    }
+
+    if (state.host_cr4 & X86_CR4_PAE) == 0 {
+        failures.push("host cr4.pae is 0");
+    }
+
+    let selectors = [
+        state.host_cs_selector,
+        state.host_ss_selector,
+        state.host_ds_selector,
+        state.host_es_selector,
+        state.host_fs_selector,
+        state.host_gs_selector,
+        state.host_tr_selector,
+    ];
+    if selectors.iter().any(|selector| (selector & 7) != 0) {
+        failures.push("host selector:rpl or ti set");
+    }
+
+    if state.host_cs_selector == 0 {
+        failures.push("host cs selector is 0");
+    }
+
+    if state.host_tr_selector == 0 {
+        failures.push("host tr selector is 0");
+    }
+
+    if !is_canonical(state.host_fs_base) || !is_canonical(state.host_gs_base) {
+        failures.push("host fs/gs base not canonical");
+    }
+
+    if !is_canonical(state.host_tr_base) {
+        failures.push("host tr base not canonical");
+    }
+
+    if !is_canonical(state.host_gdtr_base) || !is_canonical(state.host_idtr_base) {
+        failures.push("host gdtr/idtr base not canonical");
+    }
+
+    if !is_canonical(state.host_rip) {
+        failures.push("host rip not canonical");
+    }
+}
+
+fn check_guest_registers(state: &VmEntryState, failures: &mut Vec<&'static str>) {
+    let caps = &state.capabilities;
+    let unrestricted = (state.proc_controls & VMX_PROC_CTLS_USE_SECONDARY_CTLS) != 0
+        && (state.proc2_controls & VMX_PROC_CTLS2_UNRESTRICTED_GUEST) != 0;
+    let ia32e_guest = (state.entry_controls & VMX_ENTRY_CTLS_IA32E_MODE_GUEST) != 0;
+
+    // unrestricted guest may run with paging or protection off
+    let mut cr0_fixed0 = caps.cr0_fixed0;
+    if unrestricted {
+        cr0_fixed0 &= !(X86_CR0_PE | X86_CR0_PG);
+    }
+
+    if !fixed_bits_ok(state.guest_cr0, cr0_fixed0, caps.cr0_fixed1) {
+        failures.push("guest cr0:fixed bits");
+    }
+
+    if (state.guest_cr0 & X86_CR0_PG) != 0 && (state.guest_cr0 & X86_CR0_PE) == 0 {
+        failures.push("guest cr0.pg set without cr0.pe");
+    }
+
+    if !fixed_bits_ok(state.guest_cr4, caps.cr4_fixed0, caps.cr4_fixed1) {
+        failures.push("guest cr4:fixed bits");
+    }
+
+    if ia32e_guest {
+        if (state.guest_cr0 & X86_CR0_PG) == 0 {
+            failures.push("ia-32e mode guest:cr0.pg is 0");
+        }
+        if (state.guest_cr4 & X86_CR4_PAE) == 0 {
+            failures.push("ia-32e mode guest:cr4.pae is 0");
+        }
+    }
+
+    if exceeds_physical_width(state.guest_cr3, caps.physical_address_width) {
+        failures.push("guest cr3:bits beyond physical-address width");
+    }
+
+    if (state.entry_controls & VMX_ENTRY_CTLS_LOAD_DEBUG) != 0 && (state.guest_dr7 >> 32) != 0 {
+        failures.push("guest dr7:bits 63:32 set");
+    }
+
+    let rflags = state.guest_rflags;
+    if (rflags >> 32) != 0 || (rflags & RFLAGS_RESERVED_ZERO) != 0 {
+        failures.push("guest rflags:reserved bits set");
+    }
+
+    if (rflags & RFLAGS_RESERVED1) == 0 {
+        failures.push("guest rflags:bit 1 is 0");
+    }
+
+    if (ia32e_guest || (state.guest_cr0 & X86_CR0_PE) == 0) && (rflags & RFLAGS_VM) != 0 {
+        failures.push("guest rflags.vm set");
+    }
+
+    if !(ia32e_guest && (state.guest_cs.access_rights & AR_L) != 0) && (state.guest_rip >> 32) != 0
+    {
+        failures.push("guest rip:bits 63:32 set outside 64-bit mode");
+    }
+}
+
+// names reported for ds,es,fs and gs
+struct DataSegmentChecks {
+    segment_type: &'static str,
+    s: &'static str,
+    dpl: &'static str,
+    present: &'static str,
+    reserved: &'static str,
+    granularity: &'static str,
+    base: Option<&'static str>,
+}
+
+macro_rules! data_segment_checks {
+    ($name:literal, $base:expr) => {
+        DataSegmentChecks {
+            segment_type: concat!("guest ", $name, ":type"),
+            s: concat!("guest ", $name, ":s is 0"),
+            dpl: concat!("guest ", $name, ":dpl less than rpl"),
+            present: concat!("guest ", $name, ":not present"),
+            reserved: concat!("guest ", $name, ":reserved bits"),
+            granularity: concat!("guest ", $name, ":granularity"),
+            base: $base,
+        }
+    };
+}
+
+// ds,es,fs,gs in that order,fs and gs bases are only checked for being canonical
+const DATA_SEGMENT_CHECKS: [DataSegmentChecks; 4] = [
+    data_segment_checks!("ds", Some("guest ds base:bits 63:32 set")),
+    data_segment_checks!("es", Some("guest es base:bits 63:32 set")),
+    data_segment_checks!("fs", None),
+    data_segment_checks!("gs", None),
+];
+
+fn check_data_segment(
+    segment: &SegmentState,
+    unrestricted: bool,
+    checks: &DataSegmentChecks,
+    failures: &mut Vec<&'static str>,
+) {
+    if !segment.usable() {
+        return;
+    }
+
+    let segment_type = segment.segment_type();
+
+    // accessed,and code segments must be readable
+    if (segment_type & 1) == 0 || ((segment_type & 8) != 0 && (segment_type & 2) == 0) {
+        failures.push(checks.segment_type);
+    }
+
+    if (segment.access_rights & AR_S) == 0 {
+        failures.push(checks.s);
+    }
+
+    // data and non-conforming code
+    if !unrestricted && segment_type <= 11 && segment.dpl() < segment.rpl() {
+        failures.push(checks.dpl);
+    }
+
+    if (segment.access_rights & AR_P) == 0 {
+        failures.push(checks.present);
+    }
+
+    if (segment.access_rights & AR_RESERVED) != 0 {
+        failures.push(checks.reserved);
+    }
+
+    if !segment.granularity_ok() {
+        failures.push(checks.granularity);
+    }
+
+    if let Some(base) = checks.base {
+        if (segment.base >> 32) != 0 {
+            failures.push(base);
+        }
+    }
+}
+
+fn check_guest_segments(state: &VmEntryState, failures: &mut Vec<&'static str>) {
+    let unrestricted = (state.proc_controls & VMX_PROC_CTLS_USE_SECONDARY_CTLS) != 0
+        && (state.proc2_controls & VMX_PROC_CTLS2_UNRESTRICTED_GUEST) != 0;
+    let ia32e_guest = (state.entry_controls & VMX_ENTRY_CTLS_IA32E_MODE_GUEST) != 0;
+
+    let cs = &state.guest_cs;
+    let ss = &state.guest_ss;
+    let tr = &state.guest_tr;
+    let ldtr = &state.guest_ldtr;
+
+    // selectors
+    if (tr.selector & 4) != 0 {
+        failures.push("guest tr selector:ti set");
+    }
+
+    if ldtr.usable() && (ldtr.selector & 4) != 0 {
+        failures.push("guest ldtr selector:ti set");
+    }
+
+    if !unrestricted && ss.rpl() != cs.rpl() {
+        failures.push("guest ss.rpl differs from cs.rpl");
+    }
+
+    // bases
+    if !is_canonical(tr.base) {
+        failures.push("guest tr base not canonical");
+    }
+
+    if !is_canonical(state.guest_fs.base) || !is_canonical(state.guest_gs.base) {
+        failures.push("guest fs/gs base not canonical");
+    }
+
+    if ldtr.usable() && !is_canonical(ldtr.base) {
+        failures.push("guest ldtr base not canonical");
+    }
+
+    if (cs.base >> 32) != 0 {
+        failures.push("guest cs base:bits 63:32 set");
+    }
+
+    // cs
+    let cs_type = cs.segment_type();
+    let cs_type_ok = matches!(cs_type, 9 | 11 | 13 | 15) || (unrestricted && cs_type == 3);
+    if !cs_type_ok {
+        failures.push("guest cs:type");
+    }
+
+    if (cs.access_rights & AR_S) == 0 {
+        failures.push("guest cs:s is 0");
+    }
+
+    match cs_type {
+        3 if cs.dpl() != 0 => failures.push("guest cs:dpl is not 0 for type 3"),
+        9 | 11 if cs.dpl() != ss.dpl() => failures.push("guest cs:dpl differs from ss.dpl"),
+        13 | 15 if cs.dpl() > ss.dpl() => failures.push("guest cs:dpl greater than ss.dpl"),
+        _ => {}
+    }
+
+    if (cs.access_rights & AR_P) == 0 {
+        failures.push("guest cs:not present");
+    }
+
+    if (cs.access_rights & AR_RESERVED) != 0 {
+        failures.push("guest cs:reserved bits");
+    }
+
+    if ia32e_guest && (cs.access_rights & AR_L) != 0 && (cs.access_rights & AR_DB) != 0 {
+        failures.push("guest cs:l and d/b both set");
+    }
+
+    if !cs.granularity_ok() {
+        failures.push("guest cs:granularity");
+    }
+
+    if (cs.access_rights & AR_UNUSABLE) != 0 {
+        failures.push("guest cs:unusable");
+    }
+
+    // ss
+    if ss.usable() {
+        let ss_type = ss.segment_type();
+        if ss_type != 3 && ss_type != 7 {
+            failures.push("guest ss:type");
+        }
+        if (ss.access_rights & AR_S) == 0 {
+            failures.push("guest ss:s is 0");
+        }
+        if (ss.access_rights & AR_P) == 0 {
+            failures.push("guest ss:not present");
+        }
+        if (ss.access_rights & AR_RESERVED) != 0 {
+            failures.push("guest ss:reserved bits");
+        }
+        if !ss.granularity_ok() {
+            failures.push("guest ss:granularity");
+        }
+        if (ss.base >> 32) != 0 {
+            failures.push("guest ss base:bits 63:32 set");
+        }
+    }
+
+    if !unrestricted && ss.dpl() != ss.rpl() {
+        failures.push("guest ss:dpl differs from rpl");
+    }
+
+    if (cs_type == 3 || (state.guest_cr0 & X86_CR0_PE) == 0) && ss.dpl() != 0 {
+        failures.push("guest ss:dpl is not 0");
+    }
+
+    let data_segments = [
+        &state.guest_ds,
+        &state.guest_es,
+        &state.guest_fs,
+        &state.guest_gs,
+    ];
+    for (segment, checks) in data_segments.iter().zip(DATA_SEGMENT_CHECKS.iter()) {
+        check_data_segment(segment, unrestricted, checks, failures);
+    }
+
+    // tr
+    let tr_type = tr.segment_type();
+    if ia32e_guest && tr_type != SEGMENT_TYPE_BUSY_TSS64 {
+        failures.push("guest tr:type is not busy 64-bit tss");
+    } else if !ia32e_guest && tr_type != 3 && tr_type != SEGMENT_TYPE_BUSY_TSS64 {
+        failures.push("guest tr:type");
+    }
+    if (tr.access_rights & AR_S) != 0 {
+        failures.push("guest tr:s is 1");
+    }
+    if (tr.access_rights & AR_P) == 0 {
+        failures.push("guest tr:not present");
+    }
+    if (tr.access_rights & AR_RESERVED) != 0 {
+        failures.push("guest tr:reserved bits");
+    }
+    if !tr.granularity_ok() {
+        failures.push("guest tr:granularity");
+    }
+    if !tr.usable() {
+        failures.push("guest tr:unusable");
+    }
+
+    // ldtr
+    if ldtr.usable() {
+        if ldtr.segment_type() != SEGMENT_TYPE_LDT {
+            failures.push("guest ldtr:type");
+        }
+        if (ldtr.access_rights & AR_S) != 0 {
+            failures.push("guest ldtr:s is 1");
+        }
+        if (ldtr.access_rights & AR_P) == 0 {
+            failures.push("guest ldtr:not present");
+        }
+        if (ldtr.access_rights & AR_RESERVED) != 0 {
+            failures.push("guest ldtr:reserved bits");
+        }
+        if !ldtr.granularity_ok() {
+            failures.push("guest ldtr:granularity");
+        }
+    }
+
+    // gdtr and idtr
+    if !is_canonical(state.guest_gdtr_base) || !is_canonical(state.guest_idtr_base) {
+        failures.push("guest gdtr/idtr base not canonical");
+    }
+
+    if (state.guest_gdtr_limit >> 16) != 0 || (state.guest_idtr_limit >> 16) != 0 {
+        failures.push("guest gdtr/idtr limit:bits 31:16 set");
+    }
+}
+
+fn check_guest_non_register_state(state: &VmEntryState, failures: &mut Vec<&'static str>) {
+    let caps = &state.capabilities;
+    let interruptibility = state.guest_interruptibility;
+
+    if state.guest_activity_state > ACTIVITY_STATE_WAIT_FOR_SIPI {
+        failures.push("guest activity state:out of range");
+    }
+
+    if state.guest_activity_state == ACTIVITY_STATE_HLT && state.guest_ss.dpl() != 0 {
+        failures.push("guest activity state:hlt with ss.dpl not 0");
+    }
+
+    if (interruptibility & INTERRUPTIBILITY_RESERVED) != 0 {
+        failures.push("guest interruptibility state:reserved bits");
+    }
+
+    if (interruptibility & BLOCKING_BY_STI) != 0 && (interruptibility & BLOCKING_BY_MOV_SS) != 0 {
+        failures.push("guest interruptibility state:sti and mov ss blocking both set");
+    }
+
+    if (state.guest_rflags & RFLAGS_IF) == 0 && (interruptibility & BLOCKING_BY_STI) != 0 {
+        failures.push("guest interruptibility state:sti blocking with rflags.if 0");
+    }
+
+    if state.vmcs_link_pointer != u64::MAX
+        && ((state.vmcs_link_pointer & 0xFFF) != 0
+            || exceeds_physical_width(state.vmcs_link_pointer, caps.physical_address_width))
+    {
+        failures.push("vmcs link pointer");
+    }
+}
+
+// apply the sdm vm-entry checks,every violated check is reported
+pub fn check_vm_entry_state(state: &VmEntryState) -> Result<(), Vec<&'static str>> {
+    let mut failures = Vec::new();
+
+    check_controls(state, &mut failures);
+    check_host_state(state, &mut failures);
+    check_guest_registers(state, &mut failures);
+    check_guest_segments(state, &mut failures);
+    check_guest_non_register_state(state, &mut failures);
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 64-bit windows guest as set_vmcs_data builds it
+    fn windows_guest() -> VmEntryState {
+        let kernel_base = 0xFFFF_F800_0000_0000;
+        let data = SegmentState {
+            selector: 0x2B,
+            base: 0,
+            limit: 0xFFFF_FFFF,
+            access_rights: 0xC0F3,
+        };
+
+        VmEntryState {
+            capabilities: VmxCapabilities {
+                pin_controls: 0xFFFF_FFFF_0000_0000,
+                proc_controls: 0xFFFF_FFFF_0000_0000,
+                proc2_controls: 0xFFFF_FFFF_0000_0000,
+                exit_controls: 0xFFFF_FFFF_0000_0000,
+                entry_controls: 0xFFFF_FFFF_0000_0000,
+                cr0_fixed0: 0x8000_0021,
+                cr0_fixed1: 0xFFFF_FFFF,
+                cr4_fixed0: 0x2000,
+                cr4_fixed1: 0x3767FF,
+                physical_address_width: 39,
+            },
+
+            proc_controls: VMX_PROC_CTLS_USE_SECONDARY_CTLS | VMX_PROC_CTLS_USE_MSR_BITMAPS,
+            proc2_controls: VMX_PROC_CTLS2_EPT | VMX_PROC_CTLS2_VPID,
+            exit_controls: VMX_EXIT_CTLS_HOST_ADDR_SPACE_SIZE,
+            entry_controls: VMX_ENTRY_CTLS_IA32E_MODE_GUEST,
+            msr_bitmap: 0x1000,
+            ept_pointer: 0x2000 | (3 << 3) | 6,
+            vpid: 1,
+
+            host_cr0: 0x8005_0033,
+            host_cr3: 0x1AA000,
+            host_cr4: 0x2426F8,
+            host_cs_selector: 0x10,
+            host_ss_selector: 0x18,
+            host_ds_selector: 0x28,
+            host_es_selector: 0x28,
+            host_fs_selector: 0x50,
+            host_gs_selector: 0x28,
+            host_tr_selector: 0x40,
+            host_fs_base: 0,
+            host_gs_base: kernel_base,
+            host_tr_base: kernel_base + 0x1000,
+            host_gdtr_base: kernel_base + 0x2000,
+            host_idtr_base: kernel_base + 0x3000,
+            host_rip: kernel_base + 0x4000,
+
+            guest_cr0: 0x8005_0033,
+            guest_cr3: 0x1AA000,
+            guest_cr4: 0x2426F8,
+            guest_dr7: 0x400,
+            guest_rflags: 0x2,
+            guest_rip: kernel_base + 0x5000,
+            guest_es: data,
+            guest_cs: SegmentState {
+                selector: 0x10,
+                base: 0,
+                limit: 0,
+                access_rights: 0x209B,
+            },
+            guest_ss: SegmentState {
+                selector: 0x18,
+                base: 0,
+                limit: 0xFFFF_FFFF,
+                access_rights: 0xC093,
+            },
+            guest_ds: data,
+            guest_fs: SegmentState {
+                selector: 0x53,
+                base: 0,
+                limit: 0x3C00,
+                access_rights: 0x40F3,
+            },
+            guest_gs: SegmentState {
+                base: kernel_base,
+                ..data
+            },
+            guest_tr: SegmentState {
+                selector: 0x40,
+                base: kernel_base + 0x1000,
+                limit: 0x67,
+                access_rights: 0x8B,
+            },
+            guest_ldtr: SegmentState {
+                access_rights: AR_UNUSABLE,
+                ..Default::default()
+            },
+            guest_gdtr_base: kernel_base + 0x2000,
+            guest_gdtr_limit: 0x57,
+            guest_idtr_base: kernel_base + 0x3000,
+            guest_idtr_limit: 0xFFF,
+            vmcs_link_pointer: u64::MAX,
+            ..Default::default()
+        }
+    }
+
+    fn failures(state: &VmEntryState) -> Vec<&'static str> {
+        check_vm_entry_state(state).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn windows_guest_passes() {
+        assert_eq!(failures(&windows_guest()), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn control_reserved_bits() {
+        let mut state = windows_guest();
+        // allowed0 forces bit 1,allowed1 forbids bit 7
+        state.capabilities.pin_controls = (0x7Fu64 << 32) | 0x2;
+        state.pin_controls = 0x80;
+
+        assert_eq!(
+            failures(&state),
+            ["pin-based controls:reserved bits"].to_vec()
+        );
+    }
+
+    #[test]
+    fn secondary_controls_ignored_when_not_activated() {
+        let mut state = windows_guest();
+        state.capabilities.proc2_controls = 0;
+        state.proc_controls &= !VMX_PROC_CTLS_USE_SECONDARY_CTLS;
+
+        assert_eq!(failures(&state), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn ept_pointer_checks() {
+        let mut state = windows_guest();
+        state.ept_pointer = 0x2000 | (4 << 3) | 1 | 0x80;
+
+        assert_eq!(
+            failures(&state),
+            [
+                "eptp:memory type",
+                "eptp:page-walk length",
+                "eptp:reserved bits"
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn nmi_controls() {
+        let mut state = windows_guest();
+        state.pin_controls = VMX_PIN_CTLS_VIRT_NMI;
+        assert_eq!(
+            failures(&state),
+            ["virtual nmis without nmi exiting"].to_vec()
+        );
+
+        state.pin_controls = VMX_PIN_CTLS_NMI_EXIT | VMX_PIN_CTLS_VIRT_NMI;
+        state.proc_controls |= VMX_PROC_CTLS_NMI_WINDOW_EXIT;
+        assert_eq!(failures(&state), Vec::<&str>::new());
+
+        state.pin_controls = VMX_PIN_CTLS_NMI_EXIT;
+        assert_eq!(
+            failures(&state),
+            ["nmi-window exiting without virtual nmis"].to_vec()
+        );
+    }
+
+    #[test]
+    fn host_state() {
+        let mut state = windows_guest();
+        state.host_ss_selector = 0x1B;
+        state.host_tr_selector = 0;
+        state.host_rip = 0x0000_8000_0000_0000;
+
+        assert_eq!(
+            failures(&state),
+            [
+                "host selector:rpl or ti set",
+                "host tr selector is 0",
+                "host rip not canonical"
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn guest_cr0_fixed_bits_relaxed_for_unrestricted_guest() {
+        let mut state = windows_guest();
+        state.entry_controls = 0;
+        state.guest_cr0 &= !(X86_CR0_PE | X86_CR0_PG);
+        state.guest_rip = 0x7C00;
+        state.guest_cs.access_rights = 0x9B;
+        state.guest_tr.access_rights = 0x8B;
+
+        assert!(failures(&state).contains(&"guest cr0:fixed bits"));
+
+        state.proc2_controls |= VMX_PROC_CTLS2_UNRESTRICTED_GUEST;
+        assert!(!failures(&state).contains(&"guest cr0:fixed bits"));
+    }
+
+    #[test]
+    fn guest_rflags() {
+        let mut state = windows_guest();
+        state.guest_rflags = 0x8;
+        state.guest_interruptibility = BLOCKING_BY_STI;
+
+        assert_eq!(
+            failures(&state),
+            [
+                "guest rflags:reserved bits set",
+                "guest rflags:bit 1 is 0",
+                "guest interruptibility state:sti blocking with rflags.if 0"
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn guest_cs_long_mode() {
+        let mut state = windows_guest();
+        state.guest_cs.access_rights |= AR_DB;
+
+        assert_eq!(failures(&state), ["guest cs:l and d/b both set"].to_vec());
+
+        // a 32-bit cs can not run a 64-bit rip
+        state.guest_cs.access_rights &= !AR_L;
+        assert_eq!(
+            failures(&state),
+            ["guest rip:bits 63:32 set outside 64-bit mode"].to_vec()
+        );
+    }
+
+    #[test]
+    fn data_segments_report_their_own_names() {
+        let mut state = windows_guest();
+        state.guest_ds.access_rights &= !AR_P;
+        state.guest_es.base = 1 << 32;
+        state.guest_fs.base = 1 << 32;
+        state.guest_gs.limit = 0xFFFF_F000;
+
+        assert_eq!(
+            failures(&state),
+            [
+                "guest ds:not present",
+                "guest es base:bits 63:32 set",
+                "guest gs:granularity"
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn unusable_data_segments_are_skipped() {
+        let mut state = windows_guest();
+        state.guest_ds = SegmentState {
+            access_rights: AR_UNUSABLE,
+            ..Default::default()
+        };
+
+        assert_eq!(failures(&state), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn guest_tr_and_ldtr() {
+        let mut state = windows_guest();
+        state.guest_tr.access_rights = 0x89;
+        state.guest_ldtr = SegmentState {
+            selector: 0x4,
+            base: 0,
+            limit: 0xFFFF,
+            access_rights: 0x82,
+        };
+
+        assert_eq!(
+            failures(&state),
+            [
+                "guest ldtr selector:ti set",
+                "guest tr:type is not busy 64-bit tss"
+            ]
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn guest_non_register_state() {
+        let mut state = windows_guest();
+        state.guest_activity_state = ACTIVITY_STATE_HLT;
+        state.guest_ss.access_rights |= 3 << 5;
+        state.guest_ss.selector |= 3;
+        state.guest_cs.selector |= 3;
+        state.guest_cs.access_rights |= 3 << 5;
+        state.guest_interruptibility = BLOCKING_BY_STI | BLOCKING_BY_MOV_SS | 0x20;
+        state.guest_rflags |= RFLAGS_IF;
+        state.vmcs_link_pointer = 0x1234;
+
+        assert_eq!(
+            failures(&state),
+            [
+                "guest activity state:hlt with ss.dpl not 0",
+                "guest interruptibility state:reserved bits",
+                "guest interruptibility state:sti and mov ss blocking both set",
+                "vmcs link pointer"
+            ]
+            .to_vec()
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::data::{
+    ept_violation_qualification::*,
+    page_hook_attrib::{
+        PAGE_ATTRIBE_EXECUTE, PAGE_ATTRIBE_READ, PAGE_ATTRIBE_USER_EXECUTE, PAGE_ATTRIBE_WRITE,
+    },
+    ptee,
+};
+
+pub const EPT_ACCESS_MASK: u64 =
+    ptee::READ_ACCESS | ptee::WRITE_ACCESS | ptee::EXECUTE_ACCESS | ptee::EXECUTE_USER_ACCESS;
+
+// rights of a leaf entry,execute_user is ignored by the cpu without mode based execute control
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct EptPermissions {
+    pub read: bool,
+    pub write: bool,
+    // supervisor linear addresses with mode based execute control,all of them otherwise
+    pub execute: bool,
+    pub execute_user: bool,
+}
+
+impl EptPermissions {
+    pub fn from_entry(entry: u64) -> Self {
+        Self {
+            read: (entry & ptee::READ_ACCESS) != 0,
+            write: (entry & ptee::WRITE_ACCESS) != 0,
+            execute: (entry & ptee::EXECUTE_ACCESS) != 0,
+            execute_user: (entry & ptee::EXECUTE_USER_ACCESS) != 0,
+        }
+    }
+
+    pub fn from_page_attribe(page_attribe: u64) -> Self {
+        Self {
+            read: (page_attribe & PAGE_ATTRIBE_READ) != 0,
+            write: (page_attribe & PAGE_ATTRIBE_WRITE) != 0,
+            execute: (page_attribe & PAGE_ATTRIBE_EXECUTE) != 0,
+            execute_user: (page_attribe & PAGE_ATTRIBE_USER_EXECUTE) != 0,
+        }
+    }
+
+    pub fn entry_bits(&self) -> u64 {
+        let mut bits = 0;
+        if self.read {
+            bits |= ptee::READ_ACCESS;
+        }
+        if self.write {
+            bits |= ptee::WRITE_ACCESS;
+        }
+        if self.execute {
+            bits |= ptee::EXECUTE_ACCESS;
+        }
+        if self.execute_user {
+            bits |= ptee::EXECUTE_USER_ACCESS;
+        }
+        bits
+    }
+
+    // same entry with these rights
+    pub fn apply(&self, entry: u64) -> u64 {
+        (entry & !EPT_ACCESS_MASK) | self.entry_bits()
+    }
+}
+
+// exit qualification of an ept violation
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct EptViolation {
+    pub read: bool,
+    pub write: bool,
+    pub fetch: bool,
+    // rights of the guest physical page when the access happened
+    pub allowed: EptPermissions,
+    pub linear_address_valid: bool,
+    // the access was to the final translation,not to a guest paging structure
+    pub translation: bool,
+    // only reported with advanced vm-exit information for ept violations
+    pub user_mode_address: Option<bool>,
+    // a write to a page under sub-page permissions hit a protected sub-page
+    pub sub_page_write: bool,
+}
+
+impl EptViolation {
+    // mode_based_execute says whether bit 6 is reported,advanced_info whether bit 9 is
+    pub fn decode(qualification: u64, mode_based_execute: bool, advanced_info: bool) -> Self {
+        let bit = |mask: u64| (qualification & mask) != 0;
+        let executable = bit(EXECUTABLE);
+        let linear_address_valid = bit(LINEAR_ADDRESS_VALID);
+        let translation = linear_address_valid && bit(LINEAR_ADDRESS_TRANSLATION);
+
+        Self {
+            read: bit(DATA_READ),
+            write: bit(DATA_WRITE),
+            fetch: bit(INSTRUCTION_FETCH),
+            allowed: EptPermissions {
+                read: bit(READABLE),
+                write: bit(WRITEABLE),
+                execute: executable,
+                execute_user: if mode_based_execute {
+                    bit(EXECUTABLE_USER)
+                } else {
+                    executable
+                },
+            },
+            linear_address_valid,
+            translation,
+            user_mode_address: (advanced_info && translation).then(|| bit(USER_MODE_ADDRESS)),
+            sub_page_write: bit(SUB_PAGE_WRITE),
+        }
+    }
+
+    // the fetch needs the user execute right,none when the mode is not known
+    fn user_fetch(&self, mode_based_execute: bool) -> Option<bool> {
+        if mode_based_execute {
+            self.user_mode_address
+        } else {
+            Some(false)
+        }
+    }
+
+    // the rights in permissions cover the access,so the violation came from a stale translation
+    pub fn allowed_by(&self, permissions: EptPermissions, mode_based_execute: bool) -> bool {
+        if self.read && !permissions.read {
+            return false;
+        }
+        if self.write && !permissions.write {
+            return false;
+        }
+        if self.fetch {
+            let executable = match self.user_fetch(mode_based_execute) {
+                Some(true) => permissions.execute_user,
+                Some(false) => permissions.execute,
+                None => permissions.execute && permissions.execute_user,
+            };
+            if !executable {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RW: EptPermissions = EptPermissions {
+        read: true,
+        write: true,
+        execute: false,
+        execute_user: false,
+    };
+    const SUPERVISOR_EXECUTE: EptPermissions = EptPermissions {
+        read: true,
+        write: false,
+        execute: true,
+        execute_user: false,
+    };
+    const USER_EXECUTE: EptPermissions = EptPermissions {
+        read: true,
+        write: false,
+        execute: false,
+        execute_user: true,
+    };
+
+    #[test]
+    fn permissions_round_trip_through_entries() {
+        let entry = 0x1234_5000 | ptee::READ_ACCESS | ptee::EXECUTE_USER_ACCESS;
+        let permissions = EptPermissions::from_entry(entry);
+
+        assert_eq!(permissions, USER_EXECUTE);
+        assert_eq!(permissions.entry_bits(), entry & EPT_ACCESS_MASK);
+        assert_eq!(
+            RW.apply(entry),
+            0x1234_5000 | ptee::READ_ACCESS | ptee::WRITE_ACCESS
+        );
+    }
+
+    #[test]
+    fn decode_reports_the_access_and_the_rights() {
+        // write to a read only page through a final translation
+        let qualification =
+            DATA_WRITE | READABLE | LINEAR_ADDRESS_VALID | LINEAR_ADDRESS_TRANSLATION;
+        let violation = EptViolation::decode(qualification, false, false);
+
+        assert!(violation.write && !violation.read && !violation.fetch);
+        assert_eq!(
+            violation.allowed,
+            EptPermissions {
+                read: true,
+                ..Default::default()
+            }
+        );
+        assert!(violation.translation);
+        assert_eq!(violation.user_mode_address, None);
+        assert!(!violation.sub_page_write);
+    }
+
+    #[test]
+    fn decode_follows_mode_based_execute() {
+        let qualification = INSTRUCTION_FETCH | EXECUTABLE_USER;
+
+        // bit 6 is only reported with the control,otherwise user rights follow bit 5
+        let violation = EptViolation::decode(qualification, true, false);
+        assert!(!violation.allowed.execute && violation.allowed.execute_user);
+        let violation = EptViolation::decode(qualification, false, false);
+        assert!(!violation.allowed.execute && !violation.allowed.execute_user);
+        let violation = EptViolation::decode(INSTRUCTION_FETCH | EXECUTABLE, false, false);
+        assert!(violation.allowed.execute && violation.allowed.execute_user);
+    }
+
+    #[test]
+    fn decode_reports_the_address_mode_for_final_translations_only() {
+        let user = LINEAR_ADDRESS_VALID | LINEAR_ADDRESS_TRANSLATION | USER_MODE_ADDRESS;
+
+        assert_eq!(
+            EptViolation::decode(user, true, true).user_mode_address,
+            Some(true)
+        );
+        assert_eq!(
+            EptViolation::decode(user, true, false).user_mode_address,
+            None
+        );
+        // a guest paging structure access
+        assert_eq!(
+            EptViolation::decode(LINEAR_ADDRESS_VALID | USER_MODE_ADDRESS, true, true)
+                .user_mode_address,
+            None
+        );
+        assert!(EptViolation::decode(SUB_PAGE_WRITE | DATA_WRITE, false, false).sub_page_write);
+    }
+
+    #[test]
+    fn data_accesses_need_their_rights() {
+        let read = EptViolation::decode(DATA_READ, false, false);
+        let write = EptViolation::decode(DATA_WRITE, false, false);
+
+        assert!(read.allowed_by(RW, false));
+        assert!(write.allowed_by(RW, false));
+        assert!(read.allowed_by(SUPERVISOR_EXECUTE, false));
+        assert!(!write.allowed_by(SUPERVISOR_EXECUTE, false));
+        assert!(!read.allowed_by(EptPermissions::default(), false));
+    }
+
+    #[test]
+    fn fetches_without_mode_based_execute_use_the_execute_right() {
+        let fetch = EptViolation::decode(INSTRUCTION_FETCH, false, false);
+
+        assert!(fetch.allowed_by(SUPERVISOR_EXECUTE, false));
+        assert!(!fetch.allowed_by(USER_EXECUTE, false));
+        assert!(!fetch.allowed_by(RW, false));
+    }
+
+    #[test]
+    fn fetches_with_mode_based_execute_use_the_right_of_the_mode() {
+        let translation = INSTRUCTION_FETCH | LINEAR_ADDRESS_VALID | LINEAR_ADDRESS_TRANSLATION;
+        let user = EptViolation::decode(translation | USER_MODE_ADDRESS, true, true);
+        let supervisor = EptViolation::decode(translation, true, true);
+
+        assert!(user.allowed_by(USER_EXECUTE, true));
+        assert!(!user.allowed_by(SUPERVISOR_EXECUTE, true));
+        assert!(supervisor.allowed_by(SUPERVISOR_EXECUTE, true));
+        assert!(!supervisor.allowed_by(USER_EXECUTE, true));
+
+        // without advanced information the mode is not known,both rights are needed
+        let unknown = EptViolation::decode(translation, true, false);
+        assert!(!unknown.allowed_by(USER_EXECUTE, true));
+        assert!(!unknown.allowed_by(SUPERVISOR_EXECUTE, true));
+        assert!(unknown.allowed_by(
+            EptPermissions {
+                execute: true,
+                execute_user: true,
+                ..RW
+            },
+            true
+        ));
+    }
+}
@@ -0,0 +1,863 @@
+use crate::{
+    cr_access::{cr3_load, guest_visible, lmsw_value, ControlRegisterMasks, Cr3Load},
+    data::{
+        exit_reason::{
+            EXIT_REASON_CPUID, EXIT_REASON_CR_ACCESS, EXIT_REASON_MSR_READ, EXIT_REASON_MSR_WRITE,
+        },
+        interrupt_inject_info::{DELIVER_ERROR_CODE, TYPE_START, VALID, VECTOR_START},
+        interrupt_type::INTERRUPT_HARDWARE_EXCEPTION,
+        mov_cr_qualification,
+        vector_exception::{VECTOR_GENERAL_PROTECTION_EXCEPTION, VECTOR_INVALID_OPCODE_EXCEPTION},
+        vmcs_encoding::{
+            CR0_READ_SHADOW, CR4_READ_SHADOW, EXIT_QUALIFICATION, GUEST_CR0, GUEST_CR3, GUEST_CR4,
+            GUEST_FS_BASE, GUEST_GS_BASE, GUEST_IA32_DEBUGCTL, GUEST_IA32_EFER, GUEST_IA32_PAT,
+            GUEST_RIP, GUEST_RSP, GUEST_SYSENTER_CS, GUEST_SYSENTER_EIP, GUEST_SYSENTER_ESP,
+            VM_ENTRY_CONTROLS, VM_ENTRY_EXCEPTION_ERROR_CODE, VM_ENTRY_INTR_INFO_FIELD,
+            VM_EXIT_INSTRUCTION_LEN, VM_EXIT_REASON,
+        },
+        vmx_vm_enter_controls::{
+            VMX_ENTRY_CTLS_LOAD_DEBUG, VMX_ENTRY_CTLS_LOAD_EFER_MSR, VMX_ENTRY_CTLS_LOAD_PAT_MSR,
+        },
+        TYPE_CLTS, TYPE_CR_READ, TYPE_CR_WRITE, TYPE_LMSW,
+    },
+    msr_shadow::{
+        check_efer_write, check_pat_write, MsrBacking, MsrShadowTable, MSR_EFER, MSR_FS_BASE,
+        MSR_GS_BASE, MSR_IA32_DEBUGCTL, MSR_IA32_PAT, MSR_IA32_SYSENTER_CS, MSR_IA32_SYSENTER_EIP,
+        MSR_IA32_SYSENTER_ESP, MSR_LSTAR,
+    },
+};
+
+// general purpose registers in the order the vmm entry stub pushes them,r15 first and the
+// unused rsp slot last
+pub const GUEST_REGISTER_COUNT: usize = 16;
+pub type GuestRegisterSlots = [u64; GUEST_REGISTER_COUNT];
+
+const SLOT_RAX: usize = 14;
+const SLOT_RCX: usize = 13;
+const SLOT_RDX: usize = 12;
+const SLOT_RBX: usize = 11;
+
+const CPUID_FEATURES: u32 = 1;
+const CPUID_EXT_FEATURES: u32 = 0x8000_0001;
+// nested virtualization is not offered
+const CPUID_FEATURE_ECX_VMX: u32 = 1 << 5;
+const CPUID_EXT_FEATURE_ECX_SVM: u32 = 1 << 2;
+
+const X86_CR0_PG: u64 = 1 << 31;
+
+const MSR_IA32_VMX_BASIC: u32 = 0x480;
+const MSR_IA32_VMX_VMFUNC: u32 = 0x491;
+// hyper-v synthetic msrs
+const MSR_STIMER0_CONFIG: u32 = 0x4000_00B0;
+const MSR_STIMER0_COUNT: u32 = 0x4000_00B1;
+const MSR_CRASH_P0: u32 = 0x4000_0100;
+const MSR_RESERVED_MIN: u32 = 0x4000_0000;
+const MSR_RESERVED_MAX: u32 = 0xC000_0079;
+const MSR_UNKNOWN: u32 = 0xC000_2FFF;
+const MSR_UNKNOWN2: u32 = 0x0000_2FFF;
+
+// the current vmcs,vmread and vmwrite on the hardware and a field map on a build machine
+pub trait VmcsAccess {
+    // 0 for a field the vmcs does not have
+    fn read(&mut self, field: u64) -> u64;
+    fn write(&mut self, field: u64, value: u64);
+}
+
+// cpuid of the processor,recorded results on replay
+pub trait CpuidSource {
+    // eax,ebx,ecx,edx
+    fn cpuid(&mut self, leaf: u32, sub_leaf: u32) -> [u32; 4];
+}
+
+// rdmsr and wrmsr of the processor for msrs the guest state does not hold
+pub trait MsrSource {
+    fn read_msr(&mut self, msr: u32) -> u64;
+    fn write_msr(&mut self, msr: u32, value: u64);
+}
+
+// the vcpu that exited,shared by vmx and svm
+pub trait ExitVmm {
+    fn cpu_index(&mut self) -> usize;
+    fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable;
+    // after a wrmsr lstar the guest got
+    fn lstar_written(&mut self, _lstar: u64) {}
+    // unknown msr writes go to the cpu under vmware
+    fn in_vmware(&mut self) -> bool {
+        false
+    }
+}
+
+// what a mov to or from a control register needs besides the vmcs
+pub trait CrAccessVmm {
+    fn cr_masks(&mut self) -> ControlRegisterMasks;
+    // GUEST_CR3 holds the new value already,flush what the instruction would
+    fn guest_cr3_loaded(&mut self, old_cr3: u64, cr3: Cr3Load);
+    // vm-exit does not switch the tpr,the cr8 of the host is the one of the guest
+    fn read_cr8(&mut self) -> u64;
+    fn write_cr8(&mut self, value: u64);
+}
+
+// how a handled exit ends,the reasons are only logged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitResult {
+    // rip moves past the instruction
+    Advance,
+    // the instruction did nothing,rip moves past it
+    Ignored(&'static str),
+    // rip stays on the instruction
+    Fault {
+        vector: u8,
+        error_code: Option<u32>,
+        reason: &'static str,
+    },
+}
+
+impl ExitResult {
+    pub fn general_protection(reason: &'static str) -> Self {
+        ExitResult::Fault {
+            vector: VECTOR_GENERAL_PROTECTION_EXCEPTION,
+            error_code: Some(0),
+            reason,
+        }
+    }
+
+    pub fn invalid_opcode(reason: &'static str) -> Self {
+        ExitResult::Fault {
+            vector: VECTOR_INVALID_OPCODE_EXCEPTION,
+            error_code: None,
+            reason,
+        }
+    }
+}
+
+// slot of a register number of an exit qualification,4 is the unused rsp slot
+fn register_slot(index: u8) -> usize {
+    match index & 0xF {
+        index @ 0..=3 => SLOT_RAX - index as usize,
+        index => GUEST_REGISTER_COUNT - 1 - index as usize,
+    }
+}
+
+// the pushed rsp is a placeholder,the guest value lives in the vmcs
+pub fn read_register<V: VmcsAccess>(
+    vmcs: &mut V,
+    registers: &GuestRegisterSlots,
+    index: u8,
+) -> u64 {
+    match index & 0xF {
+        4 => vmcs.read(GUEST_RSP),
+        index => registers[register_slot(index)],
+    }
+}
+
+pub fn write_register<V: VmcsAccess>(
+    vmcs: &mut V,
+    registers: &mut GuestRegisterSlots,
+    index: u8,
+    value: u64,
+) {
+    match index & 0xF {
+        4 => vmcs.write(GUEST_RSP, value),
+        index => registers[register_slot(index)] = value,
+    }
+}
+
+pub fn advance_rip<V: VmcsAccess>(vmcs: &mut V) {
+    let rip = vmcs.read(GUEST_RIP) + vmcs.read(VM_EXIT_INSTRUCTION_LEN);
+    vmcs.write(GUEST_RIP, rip);
+}
+
+// hardware exception,rip is left on the faulting instruction
+pub fn inject_exception<V: VmcsAccess>(vmcs: &mut V, vector: u8, error_code: Option<u32>) {
+    let mut event =
+        ((vector as u32) << VECTOR_START) | (INTERRUPT_HARDWARE_EXCEPTION << TYPE_START) | VALID;
+    if let Some(error_code) = error_code {
+        event |= DELIVER_ERROR_CODE;
+        vmcs.write(VM_ENTRY_EXCEPTION_ERROR_CODE, error_code as _);
+    }
+    vmcs.write(VM_ENTRY_INTR_INFO_FIELD, event as _);
+}
+
+pub fn end_exit<V: VmcsAccess>(vmcs: &mut V, result: ExitResult) {
+    match result {
+        ExitResult::Advance | ExitResult::Ignored(_) => advance_rip(vmcs),
+        ExitResult::Fault {
+            vector, error_code, ..
+        } => inject_exception(vmcs, vector, error_code),
+    }
+}
+
+// what cpuid reports to the guest
+pub fn guest_cpuid<C: CpuidSource>(cpuid: &mut C, leaf: u32, sub_leaf: u32) -> [u32; 4] {
+    let mut result = cpuid.cpuid(leaf, sub_leaf);
+    match leaf {
+        // cr4.vmxe writes #gp,so vmx must not be reported either
+        CPUID_FEATURES => result[2] &= !CPUID_FEATURE_ECX_VMX,
+        // vmrun and the other svm instructions #ud
+        CPUID_EXT_FEATURES => result[2] &= !CPUID_EXT_FEATURE_ECX_SVM,
+        _ => {}
+    }
+    result
+}
+
+pub fn exit_cpuid<C: CpuidSource>(registers: &mut GuestRegisterSlots, cpuid: &mut C) -> ExitResult {
+    let [eax, ebx, ecx, edx] =
+        guest_cpuid(cpuid, registers[SLOT_RAX] as _, registers[SLOT_RCX] as _);
+
+    registers[SLOT_RAX] = eax as _;
+    registers[SLOT_RBX] = ebx as _;
+    registers[SLOT_RCX] = ecx as _;
+    registers[SLOT_RDX] = edx as _;
+    ExitResult::Advance
+}
+
+fn is_vmx_msr(msr: u32) -> bool {
+    (MSR_IA32_VMX_BASIC..=MSR_IA32_VMX_VMFUNC).contains(&msr)
+}
+
+// backing is where the guest value of a msr without a policy lives as well
+pub fn exit_msr_read<B: MsrBacking, H: ExitVmm>(
+    registers: &mut GuestRegisterSlots,
+    vmm: &mut H,
+    backing: &mut B,
+) -> ExitResult {
+    let msr = registers[SLOT_RCX] as u32;
+    let cpu_index = vmm.cpu_index();
+
+    let (value, result) = match vmm.msr_shadow_mut().read(cpu_index, msr, backing) {
+        Some(Ok(value)) => (value, ExitResult::Advance),
+        Some(Err(reason)) => return ExitResult::general_protection(reason),
+        // todo vmx msr
+        None if is_vmx_msr(msr) => (0, ExitResult::Ignored("vmx msr read")),
+        None => (backing.read(msr), ExitResult::Advance),
+    };
+
+    registers[SLOT_RAX] = value & 0xFFFF_FFFF;
+    registers[SLOT_RDX] = value >> 32;
+    result
+}
+
+pub fn exit_msr_write<B: MsrBacking, H: ExitVmm>(
+    registers: &mut GuestRegisterSlots,
+    vmm: &mut H,
+    backing: &mut B,
+) -> ExitResult {
+    let msr = registers[SLOT_RCX] as u32;
+    let value = (registers[SLOT_RDX] << 32) | (registers[SLOT_RAX] & 0xFFFF_FFFF);
+    let cpu_index = vmm.cpu_index();
+
+    match vmm.msr_shadow_mut().write(cpu_index, msr, value, backing) {
+        Some(Ok(())) => {
+            if msr == MSR_LSTAR {
+                vmm.lstar_written(value);
+            }
+            ExitResult::Advance
+        }
+        Some(Err(reason)) => ExitResult::general_protection(reason),
+        // todo vmx msr
+        None if is_vmx_msr(msr) => ExitResult::Ignored("vmx msr write"),
+        None if matches!(msr, MSR_STIMER0_CONFIG | MSR_STIMER0_COUNT | MSR_CRASH_P0)
+            || vmm.in_vmware() =>
+        {
+            match backing.write(msr, value) {
+                Ok(()) => ExitResult::Advance,
+                Err(reason) => ExitResult::general_protection(reason),
+            }
+        }
+        None if (MSR_RESERVED_MIN..=MSR_RESERVED_MAX).contains(&msr) => {
+            ExitResult::invalid_opcode("reserved msr write")
+        }
+        None if msr == MSR_UNKNOWN || msr == MSR_UNKNOWN2 => {
+            ExitResult::invalid_opcode("unknown msr write")
+        }
+        None => ExitResult::Advance,
+    }
+}
+
+// msrs loaded from the guest-state area on vm-entry must be accessed in the vmcs,the rest
+// on the processor
+pub struct VmcsMsrBacking<'a, V: VmcsAccess, M: MsrSource> {
+    vmcs: &'a mut V,
+    msrs: &'a mut M,
+}
+
+impl<'a, V: VmcsAccess, M: MsrSource> VmcsMsrBacking<'a, V, M> {
+    pub fn new(vmcs: &'a mut V, msrs: &'a mut M) -> Self {
+        Self { vmcs, msrs }
+    }
+
+    fn vmcs_field(&mut self, msr: u32) -> Option<u64> {
+        let entry_controls = self.vmcs.read(VM_ENTRY_CONTROLS) as u32;
+
+        match msr {
+            MSR_FS_BASE => Some(GUEST_FS_BASE),
+            MSR_GS_BASE => Some(GUEST_GS_BASE),
+            MSR_IA32_SYSENTER_CS => Some(GUEST_SYSENTER_CS),
+            MSR_IA32_SYSENTER_ESP => Some(GUEST_SYSENTER_ESP),
+            MSR_IA32_SYSENTER_EIP => Some(GUEST_SYSENTER_EIP),
+            MSR_IA32_DEBUGCTL if (entry_controls & VMX_ENTRY_CTLS_LOAD_DEBUG) != 0 => {
+                Some(GUEST_IA32_DEBUGCTL)
+            }
+            MSR_EFER if (entry_controls & VMX_ENTRY_CTLS_LOAD_EFER_MSR) != 0 => {
+                Some(GUEST_IA32_EFER)
+            }
+            MSR_IA32_PAT if (entry_controls & VMX_ENTRY_CTLS_LOAD_PAT_MSR) != 0 => {
+                Some(GUEST_IA32_PAT)
+            }
+            // kernel gs base is not switched by vm-entry/vm-exit
+            _ => None,
+        }
+    }
+
+    // a bad value written in root #gp the host instead of the guest
+    fn check_write(
+        &mut self,
+        msr: u32,
+        current: u64,
+        value: u64,
+        shared: bool,
+    ) -> Result<u64, &'static str> {
+        match msr {
+            MSR_EFER => {
+                let paging = (self.vmcs.read(GUEST_CR0) & X86_CR0_PG) != 0;
+                check_efer_write(current, value, paging, shared)
+            }
+            MSR_IA32_PAT => check_pat_write(value).map(|_| value),
+            _ => Ok(value),
+        }
+    }
+}
+
+impl<V: VmcsAccess, M: MsrSource> MsrBacking for VmcsMsrBacking<'_, V, M> {
+    fn read(&mut self, msr: u32) -> u64 {
+        match self.vmcs_field(msr) {
+            Some(field) => self.vmcs.read(field),
+            None => self.msrs.read_msr(msr),
+        }
+    }
+
+    fn write(&mut self, msr: u32, value: u64) -> Result<(), &'static str> {
+        match self.vmcs_field(msr) {
+            Some(field) => {
+                let current = self.vmcs.read(field);
+                let value = self.check_write(msr, current, value, false)?;
+                self.vmcs.write(field, value);
+            }
+            None => {
+                let current = self.msrs.read_msr(msr);
+                let value = self.check_write(msr, current, value, true)?;
+                self.msrs.write_msr(msr, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_cr0<V: VmcsAccess>(
+    vmcs: &mut V,
+    masks: &ControlRegisterMasks,
+    value: u64,
+) -> Result<(), &'static str> {
+    let cr0 = masks.write_cr0(value)?;
+    vmcs.write(GUEST_CR0, cr0.guest);
+    vmcs.write(CR0_READ_SHADOW, cr0.shadow);
+    Ok(())
+}
+
+// only the task priority class is defined,bits 63:4 are reserved
+fn cr8_load(value: u64) -> Result<u64, &'static str> {
+    if (value & !0xF) != 0 {
+        return Err("cr8 reserved bits set");
+    }
+    Ok(value)
+}
+
+pub fn exit_cr_access<V: VmcsAccess, H: CrAccessVmm>(
+    vmcs: &mut V,
+    registers: &mut GuestRegisterSlots,
+    vmm: &mut H,
+) -> ExitResult {
+    let data = vmcs.read(EXIT_QUALIFICATION); // MOV_CR_QUALIFICATION
+    let access_type = ((data & mov_cr_qualification::ACCESS_TYPE_MASK as u64) >> 4) as u32;
+    let control_register = data & mov_cr_qualification::CONTROL_REGISTER_MASK as u64;
+    let register = ((data & mov_cr_qualification::REGISTER_MASK as u64) >> 8) as u8;
+
+    let cr_masks = vmm.cr_masks();
+    let cr0 = guest_visible(
+        vmcs.read(GUEST_CR0),
+        vmcs.read(CR0_READ_SHADOW),
+        cr_masks.cr0_guest_host_mask(),
+    );
+    let cr4 = guest_visible(
+        vmcs.read(GUEST_CR4),
+        vmcs.read(CR4_READ_SHADOW),
+        cr_masks.cr4_guest_host_mask(),
+    );
+
+    let result = match access_type {
+        TYPE_CR_WRITE => {
+            let value = read_register(vmcs, registers, register);
+            match control_register {
+                0 => write_cr0(vmcs, &cr_masks, value),
+                3 => cr3_load(value, cr4).map(|cr3| {
+                    let old_cr3 = vmcs.read(GUEST_CR3);
+                    vmcs.write(GUEST_CR3, cr3.cr3);
+                    vmm.guest_cr3_loaded(old_cr3, cr3);
+                }),
+                4 => cr_masks.write_cr4(value).map(|cr4| {
+                    vmcs.write(GUEST_CR4, cr4.guest);
+                    vmcs.write(CR4_READ_SHADOW, cr4.shadow);
+                }),
+                8 => cr8_load(value).map(|tpr| vmm.write_cr8(tpr)),
+                _ => return ExitResult::Ignored("unknown cr write"),
+            }
+        }
+        TYPE_CR_READ => {
+            let value = match control_register {
+                0 => cr0,
+                3 => vmcs.read(GUEST_CR3),
+                4 => cr4,
+                8 => vmm.read_cr8(),
+                _ => return ExitResult::Ignored("unknown cr read"),
+            };
+            write_register(vmcs, registers, register, value);
+            Ok(())
+        }
+        TYPE_CLTS => {
+            let cr0 = cr_masks.clts(vmcs.read(GUEST_CR0), vmcs.read(CR0_READ_SHADOW));
+            vmcs.write(GUEST_CR0, cr0.guest);
+            vmcs.write(CR0_READ_SHADOW, cr0.shadow);
+            Ok(())
+        }
+        TYPE_LMSW => {
+            // a memory operand was already read by the cpu,only the source data matters
+            let source = ((data & mov_cr_qualification::LMSW_SOURCE_DATA_MASK as u64) >> 16) as u16;
+            write_cr0(vmcs, &cr_masks, lmsw_value(cr0, source))
+        }
+        _ => return ExitResult::Ignored("error cr access type"),
+    };
+
+    match result {
+        Ok(()) => ExitResult::Advance,
+        Err(reason) => ExitResult::general_protection(reason),
+    }
+}
+
+// the exits handled here,none for the others. the vmcs is left as vm-entry should find it
+pub fn vmx_exit<V, C, M, H>(
+    vmcs: &mut V,
+    registers: &mut GuestRegisterSlots,
+    cpuid: &mut C,
+    msrs: &mut M,
+    vmm: &mut H,
+) -> Option<ExitResult>
+where
+    V: VmcsAccess,
+    C: CpuidSource,
+    M: MsrSource,
+    H: ExitVmm + CrAccessVmm,
+{
+    let result = match vmcs.read(VM_EXIT_REASON) as u16 {
+        EXIT_REASON_CPUID => exit_cpuid(registers, cpuid),
+        EXIT_REASON_CR_ACCESS => exit_cr_access(vmcs, registers, vmm),
+        EXIT_REASON_MSR_READ => exit_msr_read(registers, vmm, &mut VmcsMsrBacking::new(vmcs, msrs)),
+        EXIT_REASON_MSR_WRITE => {
+            exit_msr_write(registers, vmm, &mut VmcsMsrBacking::new(vmcs, msrs))
+        }
+        _ => return None,
+    };
+
+    end_exit(vmcs, result);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::vmcs_encoding::VM_ENTRY_INTR_INFO_FIELD, entry_check::VmxCapabilities,
+        msr_shadow::MsrPolicy, soft_vmcs::SoftVmcs,
+    };
+    use alloc::{collections::BTreeMap, vec::Vec};
+
+    const RIP: u64 = 0x1000;
+    const X86_CR4_VMXE: u64 = 1 << 13;
+    const X86_CR4_PCIDE: u64 = 1 << 17;
+
+    struct TestCpuid;
+
+    impl CpuidSource for TestCpuid {
+        fn cpuid(&mut self, leaf: u32, sub_leaf: u32) -> [u32; 4] {
+            [leaf, sub_leaf, u32::MAX, 0x1234]
+        }
+    }
+
+    #[derive(Default)]
+    struct TestMsrs {
+        msrs: BTreeMap<u32, u64>,
+    }
+
+    impl MsrSource for TestMsrs {
+        fn read_msr(&mut self, msr: u32) -> u64 {
+            self.msrs.get(&msr).copied().unwrap_or(0)
+        }
+
+        fn write_msr(&mut self, msr: u32, value: u64) {
+            self.msrs.insert(msr, value);
+        }
+    }
+
+    struct TestVmm {
+        msr_shadow: MsrShadowTable,
+        cr_masks: ControlRegisterMasks,
+        cr3_loads: Vec<(u64, Cr3Load)>,
+        cr8: u64,
+    }
+
+    impl TestVmm {
+        fn new() -> Self {
+            // cr0.pe,cr0.ne and cr0.pg fixed to 1,cr4.vmxe fixed to 1
+            let caps = VmxCapabilities {
+                cr0_fixed0: 0x8000_0021,
+                cr0_fixed1: 0xFFFF_FFFF,
+                cr4_fixed0: X86_CR4_VMXE,
+                cr4_fixed1: 0x3F_FFFF,
+                ..Default::default()
+            };
+            Self {
+                msr_shadow: MsrShadowTable::default(),
+                cr_masks: ControlRegisterMasks::new(&caps, false),
+                cr3_loads: Vec::new(),
+                cr8: 0,
+            }
+        }
+    }
+
+    impl ExitVmm for TestVmm {
+        fn cpu_index(&mut self) -> usize {
+            0
+        }
+
+        fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+            &mut self.msr_shadow
+        }
+    }
+
+    impl CrAccessVmm for TestVmm {
+        fn cr_masks(&mut self) -> ControlRegisterMasks {
+            self.cr_masks
+        }
+
+        fn guest_cr3_loaded(&mut self, old_cr3: u64, cr3: Cr3Load) {
+            self.cr3_loads.push((old_cr3, cr3));
+        }
+
+        fn read_cr8(&mut self) -> u64 {
+            self.cr8
+        }
+
+        fn write_cr8(&mut self, value: u64) {
+            self.cr8 = value;
+        }
+    }
+
+    fn exit(reason: u16, qualification: u64, length: u64) -> SoftVmcs {
+        let mut vmcs = SoftVmcs::new();
+        vmcs.set(VM_EXIT_REASON, reason as _).unwrap();
+        vmcs.set(EXIT_QUALIFICATION, qualification).unwrap();
+        vmcs.set(VM_EXIT_INSTRUCTION_LEN, length).unwrap();
+        vmcs.set(GUEST_RIP, RIP).unwrap();
+        vmcs
+    }
+
+    fn run(
+        vmcs: &mut SoftVmcs,
+        registers: &mut GuestRegisterSlots,
+        vmm: &mut TestVmm,
+    ) -> ExitResult {
+        vmx_exit(
+            vmcs,
+            registers,
+            &mut TestCpuid,
+            &mut TestMsrs::default(),
+            vmm,
+        )
+        .unwrap()
+    }
+
+    fn injected_vector(vmcs: &SoftVmcs) -> Option<u8> {
+        let event = vmcs.get(VM_ENTRY_INTR_INFO_FIELD) as u32;
+        ((event & VALID) != 0).then_some(event as u8)
+    }
+
+    #[test]
+    fn register_numbers_map_to_push_order() {
+        let mut vmcs = SoftVmcs::new();
+        vmcs.set(GUEST_RSP, 0x8000).unwrap();
+        let mut registers: GuestRegisterSlots = core::array::from_fn(|slot| slot as u64);
+
+        // rax,rcx,rdx,rbx,rbp,rsi,rdi,r8 and r15
+        for (index, slot) in [
+            (0, 14),
+            (1, 13),
+            (2, 12),
+            (3, 11),
+            (5, 10),
+            (6, 9),
+            (7, 8),
+            (8, 7),
+            (15, 0),
+        ] {
+            assert_eq!(read_register(&mut vmcs, &registers, index), slot);
+        }
+        assert_eq!(read_register(&mut vmcs, &registers, 4), 0x8000);
+
+        write_register(&mut vmcs, &mut registers, 4, 0x9000);
+        assert_eq!(vmcs.get(GUEST_RSP), 0x9000);
+        assert_eq!(registers[15], 15);
+    }
+
+    #[test]
+    fn cpuid_hides_vmx_and_svm() {
+        let mut vmcs = exit(EXIT_REASON_CPUID, 0, 2);
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+        registers[SLOT_RAX] = CPUID_FEATURES as _;
+        registers[SLOT_RCX] = 7;
+
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut TestVmm::new()),
+            ExitResult::Advance
+        );
+        assert_eq!(registers[SLOT_RAX], CPUID_FEATURES as u64);
+        assert_eq!(registers[SLOT_RBX], 7);
+        assert_eq!(registers[SLOT_RCX], !CPUID_FEATURE_ECX_VMX as u64);
+        assert_eq!(registers[SLOT_RDX], 0x1234);
+        assert_eq!(vmcs.get(GUEST_RIP), RIP + 2);
+
+        let mut vmcs = exit(EXIT_REASON_CPUID, 0, 2);
+        registers[SLOT_RAX] = CPUID_EXT_FEATURES as _;
+        run(&mut vmcs, &mut registers, &mut TestVmm::new());
+        assert_eq!(registers[SLOT_RCX], !CPUID_EXT_FEATURE_ECX_SVM as u64);
+    }
+
+    #[test]
+    fn cr_write_keeps_fixed_bits_and_shadows_the_guest_value() {
+        // mov cr0,rbx
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            (3 << 8) | (TYPE_CR_WRITE as u64) << 4,
+            3,
+        );
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+        registers[SLOT_RBX] = 0x8000_0011;
+
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut TestVmm::new()),
+            ExitResult::Advance
+        );
+        assert_eq!(vmcs.get(GUEST_CR0), 0x8000_0031);
+        assert_eq!(vmcs.get(CR0_READ_SHADOW), 0x8000_0011);
+        assert_eq!(vmcs.get(GUEST_RIP), RIP + 3);
+    }
+
+    #[test]
+    fn cr_write_of_a_bad_value_injects_gp() {
+        // mov cr4,rax with vmxe
+        let mut vmcs = exit(EXIT_REASON_CR_ACCESS, 4 | (TYPE_CR_WRITE as u64) << 4, 3);
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+        registers[SLOT_RAX] = X86_CR4_VMXE;
+
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut TestVmm::new()),
+            ExitResult::general_protection("cr4.vmxe is not available to the guest")
+        );
+        assert_eq!(
+            injected_vector(&vmcs),
+            Some(VECTOR_GENERAL_PROTECTION_EXCEPTION)
+        );
+        assert_eq!(vmcs.get(GUEST_RIP), RIP);
+        assert_eq!(vmcs.get(GUEST_CR4), 0);
+    }
+
+    #[test]
+    fn cr3_write_reaches_the_vmm() {
+        // mov cr3,rsp with bit 63 set,pcid on
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            3 | (4 << 8) | (TYPE_CR_WRITE as u64) << 4,
+            3,
+        );
+        vmcs.set(GUEST_CR3, 0x1000).unwrap();
+        vmcs.set(GUEST_CR4, X86_CR4_PCIDE | X86_CR4_VMXE).unwrap();
+        vmcs.set(CR4_READ_SHADOW, X86_CR4_PCIDE).unwrap();
+        vmcs.set(GUEST_RSP, (1 << 63) | 0x5001).unwrap();
+        let mut vmm = TestVmm::new();
+
+        assert_eq!(
+            run(&mut vmcs, &mut [0; GUEST_REGISTER_COUNT], &mut vmm),
+            ExitResult::Advance
+        );
+        assert_eq!(vmcs.get(GUEST_CR3), 0x5001);
+        assert_eq!(
+            vmm.cr3_loads,
+            [(
+                0x1000,
+                Cr3Load {
+                    cr3: 0x5001,
+                    flush: false
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn cr_read_returns_the_shadowed_bits() {
+        // mov r9,cr4
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            4 | (9 << 8) | (TYPE_CR_READ as u64) << 4,
+            3,
+        );
+        vmcs.set(GUEST_CR4, X86_CR4_VMXE | 0x20).unwrap();
+        vmcs.set(CR4_READ_SHADOW, 0).unwrap();
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut TestVmm::new()),
+            ExitResult::Advance
+        );
+        assert_eq!(registers[register_slot(9)], 0x20);
+    }
+
+    #[test]
+    fn cr8_goes_to_the_tpr() {
+        let mut vmm = TestVmm::new();
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+
+        // mov cr8,rcx
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            8 | (1 << 8) | (TYPE_CR_WRITE as u64) << 4,
+            4,
+        );
+        registers[SLOT_RCX] = 0xD;
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut vmm),
+            ExitResult::Advance
+        );
+        assert_eq!(vmm.cr8, 0xD);
+        assert_eq!(vmcs.get(GUEST_RIP), RIP + 4);
+
+        // mov rdx,cr8
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            8 | (2 << 8) | (TYPE_CR_READ as u64) << 4,
+            4,
+        );
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut vmm),
+            ExitResult::Advance
+        );
+        assert_eq!(registers[SLOT_RDX], 0xD);
+
+        // reserved bits leave the tpr alone
+        let mut vmcs = exit(
+            EXIT_REASON_CR_ACCESS,
+            8 | (1 << 8) | (TYPE_CR_WRITE as u64) << 4,
+            4,
+        );
+        registers[SLOT_RCX] = 0x10;
+        assert_eq!(
+            run(&mut vmcs, &mut registers, &mut vmm),
+            ExitResult::general_protection("cr8 reserved bits set")
+        );
+        assert_eq!(vmm.cr8, 0xD);
+        assert_eq!(vmcs.get(GUEST_RIP), RIP);
+    }
+
+    #[test]
+    fn msr_read_follows_the_policy() {
+        let mut vmm = TestVmm::new();
+        let mut msrs = TestMsrs::default();
+        msrs.write_msr(0x1B, 0x1234_5678_FEE0_0900);
+
+        // efer loaded by vm-entry comes from the vmcs
+        let mut vmcs = exit(EXIT_REASON_MSR_READ, 0, 2);
+        vmcs.set(VM_ENTRY_CONTROLS, VMX_ENTRY_CTLS_LOAD_EFER_MSR as _)
+            .unwrap();
+        vmcs.set(GUEST_IA32_EFER, 0xD01).unwrap();
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+        registers[SLOT_RCX] = MSR_EFER as _;
+        assert_eq!(
+            vmx_exit(
+                &mut vmcs,
+                &mut registers,
+                &mut TestCpuid,
+                &mut msrs,
+                &mut vmm
+            ),
+            Some(ExitResult::Advance)
+        );
+        assert_eq!((registers[SLOT_RAX], registers[SLOT_RDX]), (0xD01, 0));
+        assert_eq!(vmcs.get(GUEST_RIP), RIP + 2);
+
+        // no policy,the processor value split over edx:eax
+        let mut vmcs = exit(EXIT_REASON_MSR_READ, 0, 2);
+        registers[SLOT_RCX] = 0x1B;
+        vmx_exit(
+            &mut vmcs,
+            &mut registers,
+            &mut TestCpuid,
+            &mut msrs,
+            &mut vmm,
+        );
+        assert_eq!(
+            (registers[SLOT_RAX], registers[SLOT_RDX]),
+            (0xFEE0_0900, 0x1234_5678)
+        );
+
+        // denied,rax and rdx untouched
+        vmm.msr_shadow.set_policy(0x1B, MsrPolicy::DenyGp);
+        let mut vmcs = exit(EXIT_REASON_MSR_READ, 0, 2);
+        registers[SLOT_RAX] = 0;
+        vmx_exit(
+            &mut vmcs,
+            &mut registers,
+            &mut TestCpuid,
+            &mut msrs,
+            &mut vmm,
+        );
+        assert_eq!(
+            injected_vector(&vmcs),
+            Some(VECTOR_GENERAL_PROTECTION_EXCEPTION)
+        );
+        assert_eq!(registers[SLOT_RAX], 0);
+        assert_eq!(vmcs.get(GUEST_RIP), RIP);
+    }
+
+    #[test]
+    fn msr_write_validates_before_the_vmcs() {
+        let mut vmm = TestVmm::new();
+        let mut vmcs = exit(EXIT_REASON_MSR_WRITE, 0, 2);
+        vmcs.set(VM_ENTRY_CONTROLS, VMX_ENTRY_CTLS_LOAD_PAT_MSR as _)
+            .unwrap();
+        vmcs.set(GUEST_IA32_PAT, 0x0007_0406_0007_0406).unwrap();
+        let mut registers = [0; GUEST_REGISTER_COUNT];
+        registers[SLOT_RCX] = MSR_IA32_PAT as _;
+        // memory type 2 is reserved
+        registers[SLOT_RAX] = 0x0007_0402;
+
+        vmx_exit(
+            &mut vmcs,
+            &mut registers,
+            &mut TestCpuid,
+            &mut TestMsrs::default(),
+            &mut vmm,
+        );
+        assert_eq!(
+            injected_vector(&vmcs),
+            Some(VECTOR_GENERAL_PROTECTION_EXCEPTION)
+        );
+        assert_eq!(vmcs.get(GUEST_IA32_PAT), 0x0007_0406_0007_0406);
+    }
+}
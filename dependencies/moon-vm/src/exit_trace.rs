@@ -0,0 +1,632 @@
+use core::mem::{size_of, size_of_val};
+
+use alloc::vec::Vec;
+
+use crate::{
+    cr_access::{ControlRegisterMasks, Cr3Load},
+    data::vmcs_encoding::{
+        CPU_BASED_VM_EXEC_CONTROL, CR0_GUEST_HOST_MASK, CR0_READ_SHADOW, CR4_GUEST_HOST_MASK,
+        CR4_READ_SHADOW, EPTP_INDEX, EPTP_LIST_ADDRESS, EPT_POINTER, EXCEPTION_BITMAP,
+        EXIT_QUALIFICATION, GUEST_ACTIVITY_STATE, GUEST_CR0, GUEST_CR3, GUEST_CR4,
+        GUEST_CS_AR_BYTES, GUEST_CS_BASE, GUEST_CS_SELECTOR, GUEST_DR7, GUEST_DS_BASE,
+        GUEST_ES_BASE, GUEST_FS_BASE, GUEST_GDTR_BASE, GUEST_GDTR_LIMIT, GUEST_GS_BASE,
+        GUEST_IA32_DEBUGCTL, GUEST_IA32_EFER, GUEST_IA32_PAT, GUEST_IDTR_BASE, GUEST_IDTR_LIMIT,
+        GUEST_INTERRUPTIBILITY_INFO, GUEST_LDTR_AR_BYTES, GUEST_LDTR_BASE, GUEST_LDTR_LIMIT,
+        GUEST_LDTR_SELECTOR, GUEST_LINEAR_ADDRESS, GUEST_PENDING_DBG_EXCEPTIONS,
+        GUEST_PHYSICAL_ADDRESS, GUEST_PML_INDEX, GUEST_RFLAGS, GUEST_RIP, GUEST_RSP,
+        GUEST_SS_AR_BYTES, GUEST_SS_BASE, GUEST_SYSENTER_CS, GUEST_SYSENTER_EIP,
+        GUEST_SYSENTER_ESP, GUEST_TR_AR_BYTES, GUEST_TR_BASE, GUEST_TR_LIMIT, GUEST_TR_SELECTOR,
+        IDT_VECTORING_ERROR_CODE, IDT_VECTORING_INFO_FIELD, PIN_BASED_VM_EXEC_CONTROL, PML_ADDRESS,
+        SECONDARY_VM_EXEC_CONTROL, SPPT_POINTER, VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS,
+        VMX_INSTRUCTION_INFO, VM_ENTRY_CONTROLS, VM_ENTRY_EXCEPTION_ERROR_CODE,
+        VM_ENTRY_INSTRUCTION_LEN, VM_ENTRY_INTR_INFO_FIELD, VM_EXIT_INSTRUCTION_LEN,
+        VM_EXIT_INTR_ERROR_CODE, VM_EXIT_INTR_INFO, VM_EXIT_REASON, VM_FUNCTION_CONTROLS,
+    },
+    exit_handler::{
+        vmx_exit, CpuidSource, CrAccessVmm, ExitVmm, MsrSource, VmcsAccess, GUEST_REGISTER_COUNT,
+    },
+    msr_shadow::MsrShadowTable,
+    soft_vmcs::SoftVmcs,
+};
+
+// general purpose registers in the order the vmm entry stub pushes them,r15 first and the
+// unused rsp slot last
+pub const EXIT_TRACE_REGISTER_COUNT: usize = GUEST_REGISTER_COUNT;
+
+// every field the exit handlers read or write. the preemption timer value is left out,it
+// changes on its own. fields this cpu does not support are recorded as 0
+pub const EXIT_TRACE_FIELDS: [u64; 67] = [
+    VM_EXIT_REASON,
+    EXIT_QUALIFICATION,
+    GUEST_LINEAR_ADDRESS,
+    GUEST_PHYSICAL_ADDRESS,
+    VM_EXIT_INSTRUCTION_LEN,
+    VMX_INSTRUCTION_INFO,
+    VM_EXIT_INTR_INFO,
+    VM_EXIT_INTR_ERROR_CODE,
+    IDT_VECTORING_INFO_FIELD,
+    IDT_VECTORING_ERROR_CODE,
+    GUEST_RIP,
+    GUEST_RSP,
+    GUEST_RFLAGS,
+    GUEST_CR0,
+    GUEST_CR3,
+    GUEST_CR4,
+    CR0_READ_SHADOW,
+    CR4_READ_SHADOW,
+    CR0_GUEST_HOST_MASK,
+    CR4_GUEST_HOST_MASK,
+    GUEST_DR7,
+    GUEST_IA32_EFER,
+    GUEST_IA32_PAT,
+    GUEST_IA32_DEBUGCTL,
+    GUEST_SYSENTER_CS,
+    GUEST_SYSENTER_ESP,
+    GUEST_SYSENTER_EIP,
+    GUEST_CS_SELECTOR,
+    GUEST_CS_BASE,
+    GUEST_CS_AR_BYTES,
+    GUEST_SS_BASE,
+    GUEST_SS_AR_BYTES,
+    GUEST_DS_BASE,
+    GUEST_ES_BASE,
+    GUEST_FS_BASE,
+    GUEST_GS_BASE,
+    GUEST_LDTR_SELECTOR,
+    GUEST_LDTR_BASE,
+    GUEST_LDTR_LIMIT,
+    GUEST_LDTR_AR_BYTES,
+    GUEST_TR_SELECTOR,
+    GUEST_TR_BASE,
+    GUEST_TR_LIMIT,
+    GUEST_TR_AR_BYTES,
+    GUEST_GDTR_BASE,
+    GUEST_GDTR_LIMIT,
+    GUEST_IDTR_BASE,
+    GUEST_IDTR_LIMIT,
+    GUEST_INTERRUPTIBILITY_INFO,
+    GUEST_ACTIVITY_STATE,
+    GUEST_PENDING_DBG_EXCEPTIONS,
+    PIN_BASED_VM_EXEC_CONTROL,
+    CPU_BASED_VM_EXEC_CONTROL,
+    SECONDARY_VM_EXEC_CONTROL,
+    EXCEPTION_BITMAP,
+    VM_FUNCTION_CONTROLS,
+    VM_ENTRY_CONTROLS,
+    VM_ENTRY_INTR_INFO_FIELD,
+    VM_ENTRY_EXCEPTION_ERROR_CODE,
+    VM_ENTRY_INSTRUCTION_LEN,
+    EPT_POINTER,
+    EPTP_INDEX,
+    EPTP_LIST_ADDRESS,
+    GUEST_PML_INDEX,
+    PML_ADDRESS,
+    SPPT_POINTER,
+    VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS,
+];
+
+const EXIT_TRACE_FIELD_COUNT: usize = EXIT_TRACE_FIELDS.len();
+
+// "VTXT"
+pub const EXIT_TRACE_MAGIC: u32 = 0x5458_5456;
+// 2 added the cpuid and msr results and the msr fields of the guest-state area
+pub const EXIT_TRACE_VERSION: u32 = 2;
+
+// one exit as the handlers saw it and left it,layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitRecord {
+    pub cpu_index: u32,
+    pub exit_reason: u16,
+    pub guest_irql: u8,
+    pub reserved: u8,
+    pub exit_qualification: u64,
+    // what the processor returned to the handler,replay answers cpuid and rdmsr with these
+    pub cpuid: [u32; 4],
+    pub msr_value: u64,
+    pub registers_before: [u64; EXIT_TRACE_REGISTER_COUNT],
+    pub registers_after: [u64; EXIT_TRACE_REGISTER_COUNT],
+    // values of EXIT_TRACE_FIELDS
+    pub fields_before: [u64; EXIT_TRACE_FIELD_COUNT],
+    pub fields_after: [u64; EXIT_TRACE_FIELD_COUNT],
+}
+
+impl Default for ExitRecord {
+    fn default() -> Self {
+        Self {
+            cpu_index: 0,
+            exit_reason: 0,
+            guest_irql: 0,
+            reserved: 0,
+            exit_qualification: 0,
+            cpuid: [0; 4],
+            msr_value: 0,
+            registers_before: [0; EXIT_TRACE_REGISTER_COUNT],
+            registers_after: [0; EXIT_TRACE_REGISTER_COUNT],
+            fields_before: [0; EXIT_TRACE_FIELD_COUNT],
+            fields_after: [0; EXIT_TRACE_FIELD_COUNT],
+        }
+    }
+}
+
+// a field or register the replayed handlers left different from the traced system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayMismatch {
+    // index in the entry stub push order
+    Register {
+        index: usize,
+        recorded: u64,
+        replayed: u64,
+    },
+    Field {
+        field: u64,
+        recorded: u64,
+        replayed: u64,
+    },
+}
+
+fn read_trace_fields<V: VmcsAccess>(vmcs: &mut V) -> [u64; EXIT_TRACE_FIELD_COUNT] {
+    let mut fields = [0; EXIT_TRACE_FIELD_COUNT];
+    for (value, field) in fields.iter_mut().zip(EXIT_TRACE_FIELDS.iter()) {
+        *value = vmcs.read(*field);
+    }
+    fields
+}
+
+impl ExitRecord {
+    // vmx root,before the exit handler runs
+    pub fn capture<V: VmcsAccess>(
+        vmcs: &mut V,
+        cpu_index: usize,
+        guest_irql: u8,
+        registers: [u64; EXIT_TRACE_REGISTER_COUNT],
+    ) -> Self {
+        Self {
+            cpu_index: cpu_index as _,
+            exit_reason: vmcs.read(VM_EXIT_REASON) as _,
+            guest_irql,
+            exit_qualification: vmcs.read(EXIT_QUALIFICATION),
+            registers_before: registers,
+            fields_before: read_trace_fields(vmcs),
+            ..Default::default()
+        }
+    }
+
+    // vmx root,right after the exit handler returned
+    pub fn capture_after<V: VmcsAccess>(
+        &mut self,
+        vmcs: &mut V,
+        registers: [u64; EXIT_TRACE_REGISTER_COUNT],
+    ) {
+        self.registers_after = registers;
+        self.fields_after = read_trace_fields(vmcs);
+    }
+
+    // the vmcs as the handler found it
+    pub fn load(&self, vmcs: &mut SoftVmcs) -> Result<(), &'static str> {
+        for (field, value) in EXIT_TRACE_FIELDS.iter().zip(self.fields_before.iter()) {
+            vmcs.set(*field, *value)?;
+        }
+        Ok(())
+    }
+
+    pub fn diff(
+        &self,
+        registers: &[u64; EXIT_TRACE_REGISTER_COUNT],
+        vmcs: &SoftVmcs,
+    ) -> Vec<ReplayMismatch> {
+        let registers = self
+            .registers_after
+            .iter()
+            .zip(registers.iter())
+            .enumerate()
+            .filter(|(_, (recorded, replayed))| recorded != replayed)
+            .map(|(index, (recorded, replayed))| ReplayMismatch::Register {
+                index,
+                recorded: *recorded,
+                replayed: *replayed,
+            });
+
+        let fields = EXIT_TRACE_FIELDS
+            .iter()
+            .zip(self.fields_after.iter())
+            .filter(|(field, recorded)| vmcs.get(**field) != **recorded)
+            .map(|(field, recorded)| ReplayMismatch::Field {
+                field: *field,
+                recorded: *recorded,
+                replayed: vmcs.get(*field),
+            });
+
+        registers.chain(fields).collect()
+    }
+}
+
+// passes cpuid and rdmsr through to source and keeps the last result for the record
+pub struct RecordingSource<S> {
+    source: S,
+    pub cpuid: [u32; 4],
+    pub msr_value: u64,
+}
+
+impl<S> RecordingSource<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            cpuid: [0; 4],
+            msr_value: 0,
+        }
+    }
+}
+
+impl<S: CpuidSource> CpuidSource for RecordingSource<S> {
+    fn cpuid(&mut self, leaf: u32, sub_leaf: u32) -> [u32; 4] {
+        self.cpuid = self.source.cpuid(leaf, sub_leaf);
+        self.cpuid
+    }
+}
+
+impl<S: MsrSource> MsrSource for RecordingSource<S> {
+    fn read_msr(&mut self, msr: u32) -> u64 {
+        self.msr_value = self.source.read_msr(msr);
+        self.msr_value
+    }
+
+    fn write_msr(&mut self, msr: u32, value: u64) {
+        self.source.write_msr(msr, value);
+    }
+}
+
+// cpuid and rdmsr answered from a record,wrmsr is dropped
+#[derive(Clone, Copy)]
+struct RecordedSource {
+    cpuid: [u32; 4],
+    msr_value: u64,
+}
+
+impl CpuidSource for RecordedSource {
+    fn cpuid(&mut self, _leaf: u32, _sub_leaf: u32) -> [u32; 4] {
+        self.cpuid
+    }
+}
+
+impl MsrSource for RecordedSource {
+    fn read_msr(&mut self, _msr: u32) -> u64 {
+        self.msr_value
+    }
+
+    fn write_msr(&mut self, _msr: u32, _value: u64) {}
+}
+
+// the vmm of a replayed exit. msrs follow the default policies,address space switches are
+// only noted
+pub struct ReplayVmm {
+    cpu_index: usize,
+    cr_masks: ControlRegisterMasks,
+    msr_shadow: MsrShadowTable,
+    pub cr3_loads: Vec<(u64, Cr3Load)>,
+    pub cr8: u64,
+}
+
+impl ReplayVmm {
+    pub fn new(cpu_index: usize, cr_masks: ControlRegisterMasks) -> Self {
+        Self {
+            cpu_index,
+            cr_masks,
+            msr_shadow: MsrShadowTable::default(),
+            cr3_loads: Vec::new(),
+            cr8: 0,
+        }
+    }
+}
+
+impl ExitVmm for ReplayVmm {
+    fn cpu_index(&mut self) -> usize {
+        self.cpu_index
+    }
+
+    fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+        &mut self.msr_shadow
+    }
+}
+
+impl CrAccessVmm for ReplayVmm {
+    fn cr_masks(&mut self) -> ControlRegisterMasks {
+        self.cr_masks
+    }
+
+    fn guest_cr3_loaded(&mut self, old_cr3: u64, cr3: Cr3Load) {
+        self.cr3_loads.push((old_cr3, cr3));
+    }
+
+    fn read_cr8(&mut self) -> u64 {
+        self.cr8
+    }
+
+    fn write_cr8(&mut self, value: u64) {
+        self.cr8 = value;
+    }
+}
+
+// runs a recorded exit through the handlers on a software vmcs and returns where they left a
+// traced field or register different. cr_masks are the ones of the traced system
+pub fn replay_exit(
+    record: &ExitRecord,
+    cr_masks: ControlRegisterMasks,
+) -> Result<Vec<ReplayMismatch>, &'static str> {
+    let mut vmcs = SoftVmcs::new();
+    record.load(&mut vmcs)?;
+
+    let mut registers = record.registers_before;
+    let recorded = RecordedSource {
+        cpuid: record.cpuid,
+        msr_value: record.msr_value,
+    };
+    let (mut cpuid, mut msrs) = (recorded, recorded);
+    let mut vmm = ReplayVmm::new(record.cpu_index as _, cr_masks);
+    vmx_exit(&mut vmcs, &mut registers, &mut cpuid, &mut msrs, &mut vmm)
+        .ok_or("exit reason is not replayed")?;
+
+    Ok(record.diff(&registers, &vmcs))
+}
+
+// every record of a trace file,mismatches with the record index
+pub fn replay_trace(
+    trace: &[u8],
+    cr_masks: ControlRegisterMasks,
+) -> Result<Vec<(usize, ReplayMismatch)>, &'static str> {
+    let mut mismatches = Vec::new();
+
+    for (index, record) in decode_trace(trace)?.iter().enumerate() {
+        for mismatch in replay_exit(record, cr_masks)? {
+            mismatches.push((index, mismatch));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+// start of a trace file,followed by the field encodings and then the records. a trace is
+// only replayed against the field list it was recorded with
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ExitTraceHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub record_size: u32,
+    pub field_count: u32,
+}
+
+impl Default for ExitTraceHeader {
+    fn default() -> Self {
+        Self {
+            magic: EXIT_TRACE_MAGIC,
+            version: EXIT_TRACE_VERSION,
+            record_size: size_of::<ExitRecord>() as _,
+            field_count: EXIT_TRACE_FIELD_COUNT as _,
+        }
+    }
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn from_bytes<T: Copy>(bytes: &[u8]) -> Option<T> {
+    if bytes.len() < size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+pub fn encode_trace(records: &[ExitRecord]) -> Vec<u8> {
+    let mut trace = Vec::with_capacity(
+        size_of::<ExitTraceHeader>()
+            + size_of::<[u64; EXIT_TRACE_FIELD_COUNT]>()
+            + size_of_val(records),
+    );
+
+    trace.extend_from_slice(as_bytes(&ExitTraceHeader::default()));
+    trace.extend_from_slice(as_bytes(&EXIT_TRACE_FIELDS));
+    for record in records {
+        trace.extend_from_slice(as_bytes(record));
+    }
+    trace
+}
+
+pub fn decode_trace(trace: &[u8]) -> Result<Vec<ExitRecord>, &'static str> {
+    let header: ExitTraceHeader = from_bytes(trace).ok_or("exit trace is truncated")?;
+    let expected = ExitTraceHeader::default();
+    if header.magic != expected.magic {
+        return Err("not an exit trace");
+    }
+    if header.version != expected.version || header.record_size != expected.record_size {
+        return Err("exit trace version is not supported");
+    }
+
+    let fields_start = size_of::<ExitTraceHeader>();
+    let fields: [u64; EXIT_TRACE_FIELD_COUNT] =
+        from_bytes(&trace[fields_start..]).ok_or("exit trace is truncated")?;
+    if header.field_count != expected.field_count || fields != EXIT_TRACE_FIELDS {
+        return Err("exit trace was recorded with other vmcs fields");
+    }
+
+    let records = &trace[fields_start + size_of::<[u64; EXIT_TRACE_FIELD_COUNT]>()..];
+    if !records.len().is_multiple_of(size_of::<ExitRecord>()) {
+        return Err("exit trace is truncated");
+    }
+
+    Ok(records
+        .chunks_exact(size_of::<ExitRecord>())
+        .filter_map(from_bytes)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::exit_reason::{EXIT_REASON_CPUID, EXIT_REASON_MSR_READ};
+
+    const RIP: u64 = 0x1000;
+    const SLOT_RAX: usize = 14;
+    const SLOT_RCX: usize = 13;
+    const SLOT_RDX: usize = 12;
+    const SLOT_RBX: usize = 11;
+
+    fn set_field(fields: &mut [u64; EXIT_TRACE_FIELD_COUNT], field: u64, value: u64) {
+        let index = EXIT_TRACE_FIELDS.iter().position(|f| *f == field).unwrap();
+        fields[index] = value;
+    }
+
+    // an exit of a two byte instruction,the handler advanced rip and changed nothing else
+    fn record(reason: u16) -> ExitRecord {
+        let mut record = ExitRecord {
+            cpu_index: 1,
+            exit_reason: reason,
+            ..Default::default()
+        };
+        set_field(&mut record.fields_before, VM_EXIT_REASON, reason as _);
+        set_field(&mut record.fields_before, VM_EXIT_INSTRUCTION_LEN, 2);
+        set_field(&mut record.fields_before, GUEST_RIP, RIP);
+        record.fields_after = record.fields_before;
+        set_field(&mut record.fields_after, GUEST_RIP, RIP + 2);
+        record
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let mut first = record(EXIT_REASON_CPUID);
+        first.cpuid = [1, 2, 3, 4];
+        first.registers_before[SLOT_RAX] = 1;
+        let mut second = record(EXIT_REASON_MSR_READ);
+        second.msr_value = 0xFEE0_0900;
+        second.guest_irql = 2;
+
+        let trace = encode_trace(&[first, second]);
+        assert_eq!(decode_trace(&trace), Ok(alloc::vec![first, second]));
+        assert_eq!(decode_trace(&encode_trace(&[])), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn decode_rejects_other_traces() {
+        let trace = encode_trace(&[record(EXIT_REASON_CPUID)]);
+
+        assert_eq!(
+            decode_trace(&trace[..trace.len() - 1]),
+            Err("exit trace is truncated")
+        );
+        assert_eq!(decode_trace(&trace[..8]), Err("exit trace is truncated"));
+
+        let mut other = trace.clone();
+        other[0] ^= 0xFF;
+        assert_eq!(decode_trace(&other), Err("not an exit trace"));
+
+        let mut other = trace.clone();
+        other[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(
+            decode_trace(&other),
+            Err("exit trace version is not supported")
+        );
+
+        // first field of the list
+        let mut other = trace;
+        other[size_of::<ExitTraceHeader>()] ^= 0xFF;
+        assert_eq!(
+            decode_trace(&other),
+            Err("exit trace was recorded with other vmcs fields")
+        );
+    }
+
+    #[test]
+    fn diff_reports_registers_and_fields() {
+        let mut record = record(EXIT_REASON_CPUID);
+        record.registers_after[SLOT_RAX] = 5;
+
+        let mut vmcs = SoftVmcs::new();
+        record.load(&mut vmcs).unwrap();
+        let registers = record.registers_after;
+        assert_eq!(
+            record.diff(&registers, &vmcs),
+            alloc::vec![ReplayMismatch::Field {
+                field: GUEST_RIP,
+                recorded: RIP + 2,
+                replayed: RIP,
+            }]
+        );
+
+        vmcs.set(GUEST_RIP, RIP + 2).unwrap();
+        let mut registers = record.registers_after;
+        registers[SLOT_RAX] = 6;
+        assert_eq!(
+            record.diff(&registers, &vmcs),
+            alloc::vec![ReplayMismatch::Register {
+                index: SLOT_RAX,
+                recorded: 5,
+                replayed: 6,
+            }]
+        );
+    }
+
+    #[test]
+    fn replay_answers_cpuid_from_the_record() {
+        let mut record = record(EXIT_REASON_CPUID);
+        record.registers_before[SLOT_RAX] = 1;
+        // vmx reported by the traced processor,hidden by the handler
+        record.cpuid = [0x906EA, 0x100800, 0x7FFA_FBFF, 0xBFEB_FBFF];
+        record.registers_after[SLOT_RAX] = 0x906EA;
+        record.registers_after[SLOT_RBX] = 0x100800;
+        record.registers_after[SLOT_RCX] = 0x7FFA_FBDF;
+        record.registers_after[SLOT_RDX] = 0xBFEB_FBFF;
+
+        let masks = ControlRegisterMasks::default();
+        assert_eq!(replay_exit(&record, masks), Ok(Vec::new()));
+
+        // a handler that stopped hiding vmx
+        record.registers_after[SLOT_RCX] = 0x7FFA_FBFF;
+        assert_eq!(
+            replay_exit(&record, masks),
+            Ok(alloc::vec![ReplayMismatch::Register {
+                index: SLOT_RCX,
+                recorded: 0x7FFA_FBFF,
+                replayed: 0x7FFA_FBDF,
+            }])
+        );
+    }
+
+    #[test]
+    fn replay_answers_rdmsr_from_the_record() {
+        // ia32_apic_base,no policy and not in the vmcs
+        let mut rdmsr = record(EXIT_REASON_MSR_READ);
+        rdmsr.registers_before[SLOT_RCX] = 0x1B;
+        rdmsr.msr_value = 0x1_FEE0_0900;
+        rdmsr.registers_after[SLOT_RCX] = 0x1B;
+        rdmsr.registers_after[SLOT_RAX] = 0xFEE0_0900;
+        rdmsr.registers_after[SLOT_RDX] = 1;
+
+        let masks = ControlRegisterMasks::default();
+        assert_eq!(replay_exit(&rdmsr, masks), Ok(Vec::new()));
+
+        // only the first record of the trace went wrong
+        let mut stale = record(EXIT_REASON_CPUID);
+        stale.registers_after[SLOT_RAX] = 7;
+        let trace = encode_trace(&[stale, rdmsr]);
+        assert_eq!(
+            replay_trace(&trace, masks),
+            Ok(alloc::vec![(
+                0,
+                ReplayMismatch::Register {
+                    index: SLOT_RAX,
+                    recorded: 7,
+                    replayed: 0,
+                }
+            )])
+        );
+    }
+
+    #[test]
+    fn replay_rejects_other_exits() {
+        assert_eq!(
+            replay_exit(&record(0), ControlRegisterMasks::default()),
+            Err("exit reason is not replayed")
+        );
+    }
+}
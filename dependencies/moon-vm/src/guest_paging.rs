@@ -0,0 +1,281 @@
+// guest paging entry bits
+const PAGE_PRESENT: u64 = 1 << 0;
+const PAGE_WRITE: u64 = 1 << 1;
+const PAGE_USER: u64 = 1 << 2;
+const PAGE_LARGE: u64 = 1 << 7;
+const PAGE_NO_EXECUTE: u64 = 1 << 63;
+pub const PAGE_FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// #pf error code bits
+pub const PF_PRESENT: u32 = 1 << 0;
+pub const PF_WRITE: u32 = 1 << 1;
+pub const PF_USER: u32 = 1 << 2;
+pub const PF_INSTRUCTION: u32 = 1 << 4;
+
+pub const RFLAGS_AC: u64 = 1 << 18;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GuestAccess {
+    pub write: bool,
+    // instruction fetch
+    pub execute: bool,
+    // cpl 3
+    pub user: bool,
+    // cr0.wp,supervisor writes honour read only pages
+    pub write_protect: bool,
+    // cr4.smap with rflags.ac clear,supervisor data accesses to user pages fault
+    pub smap: bool,
+    // cr4.smep,supervisor fetches from user pages fault
+    pub smep: bool,
+    // efer.nxe,bit 63 of any level forbids fetches.
+    // off by default so a lookup without checks sees every present page
+    pub no_execute: bool,
+}
+
+// reported to the guest as #pf with cr2 = address
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GuestPageFault {
+    pub address: u64,
+    pub error_code: u32,
+}
+
+fn page_fault(address: u64, access: GuestAccess, present: bool) -> GuestPageFault {
+    let mut error_code = 0;
+    if present {
+        error_code |= PF_PRESENT;
+    }
+    if access.write {
+        error_code |= PF_WRITE;
+    }
+    if access.user {
+        error_code |= PF_USER;
+    }
+    if access.execute && (access.no_execute || access.smep) {
+        error_code |= PF_INSTRUCTION;
+    }
+
+    GuestPageFault {
+        address,
+        error_code,
+    }
+}
+
+// rights of the leaf against the access,every level was already combined
+fn check_rights(
+    access: GuestAccess,
+    writable: bool,
+    user: bool,
+    executable: bool,
+) -> Result<(), ()> {
+    if access.user {
+        if !user || (access.write && !writable) {
+            return Err(());
+        }
+    } else if user {
+        if access.execute && access.smep {
+            return Err(());
+        }
+        if !access.execute && access.smap {
+            return Err(());
+        }
+    }
+
+    if access.write && !writable && !access.user && access.write_protect {
+        return Err(());
+    }
+    if access.execute && access.no_execute && !executable {
+        return Err(());
+    }
+
+    Ok(())
+}
+
+// 4-level walk of the guest tables,read_entry returns the u64 at a guest physical address
+pub fn translate<F: FnMut(u64) -> Option<u64>>(
+    cr3: u64,
+    linear: u64,
+    access: GuestAccess,
+    mut read_entry: F,
+) -> Result<u64, GuestPageFault> {
+    let mut table = cr3 & PAGE_FRAME_MASK;
+    let mut writable = true;
+    let mut user = true;
+    let mut executable = true;
+
+    for (level, shift) in [39u64, 30, 21, 12].into_iter().enumerate() {
+        let index = (linear >> shift) & 0x1FF;
+        let entry = read_entry(table + index * 8).unwrap_or(0);
+
+        if (entry & PAGE_PRESENT) == 0 {
+            return Err(page_fault(linear, access, false));
+        }
+
+        writable &= (entry & PAGE_WRITE) != 0;
+        user &= (entry & PAGE_USER) != 0;
+        executable &= (entry & PAGE_NO_EXECUTE) == 0;
+
+        // 1gb and 2mb pages end the walk early
+        let leaf = shift == 12 || (level > 0 && (entry & PAGE_LARGE) != 0);
+        if leaf {
+            check_rights(access, writable, user, executable)
+                .map_err(|_| page_fault(linear, access, true))?;
+
+            let page_mask = (1u64 << shift) - 1;
+            return Ok((entry & PAGE_FRAME_MASK & !page_mask) | (linear & page_mask));
+        }
+
+        table = entry & PAGE_FRAME_MASK;
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    const PML4: u64 = 0x1000;
+    const PDPT: u64 = 0x2000;
+    const PD: u64 = 0x3000;
+    const PT: u64 = 0x4000;
+
+    const USER_PAGE: u64 = 0x0000_0000_0040_1000;
+    const LARGE_PAGE: u64 = 0x0000_0000_0060_0000;
+
+    // one user 4kb page at USER_PAGE and a supervisor 2mb page at LARGE_PAGE
+    fn tables(leaf: u64) -> BTreeMap<u64, u64> {
+        let table = PAGE_PRESENT | PAGE_WRITE | PAGE_USER;
+        [
+            (PML4, PDPT | table),
+            (PDPT, PD | table),
+            (PD + 2 * 8, PT | table),
+            (
+                PD + 3 * 8,
+                0x20_0000 | PAGE_PRESENT | PAGE_WRITE | PAGE_LARGE,
+            ),
+            (PT + 8, 0x7000 | leaf),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn walk(memory: &BTreeMap<u64, u64>, linear: u64, access: GuestAccess) -> Result<u64, u32> {
+        translate(PML4, linear, access, |physical| {
+            memory.get(&physical).copied()
+        })
+        .map_err(|fault| fault.error_code)
+    }
+
+    fn supervisor() -> GuestAccess {
+        GuestAccess {
+            write_protect: true,
+            smap: true,
+            smep: true,
+            no_execute: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn translates_small_and_large_pages() {
+        let memory = tables(PAGE_PRESENT | PAGE_WRITE | PAGE_USER);
+        let user = GuestAccess {
+            user: true,
+            ..supervisor()
+        };
+
+        assert_eq!(walk(&memory, USER_PAGE + 0x123, user), Ok(0x7123));
+        assert_eq!(
+            walk(&memory, LARGE_PAGE + 0x1_2345, supervisor()),
+            Ok(0x21_2345)
+        );
+    }
+
+    #[test]
+    fn user_access_to_supervisor_page_faults() {
+        let memory = tables(PAGE_PRESENT | PAGE_WRITE | PAGE_USER);
+        let user = GuestAccess {
+            user: true,
+            ..supervisor()
+        };
+
+        assert_eq!(walk(&memory, LARGE_PAGE, user), Err(PF_PRESENT | PF_USER));
+        assert_eq!(walk(&memory, 0x8000_0000, user), Err(PF_USER));
+    }
+
+    #[test]
+    fn write_protect_applies_to_supervisor_writes() {
+        let memory = tables(PAGE_PRESENT | PAGE_USER);
+        let write = GuestAccess {
+            write: true,
+            user: true,
+            ..supervisor()
+        };
+        assert_eq!(
+            walk(&memory, USER_PAGE, write),
+            Err(PF_PRESENT | PF_WRITE | PF_USER)
+        );
+
+        let memory = tables(PAGE_PRESENT | PAGE_USER);
+        let kernel_write = GuestAccess {
+            write: true,
+            smap: false,
+            ..supervisor()
+        };
+        assert_eq!(
+            walk(&memory, USER_PAGE, kernel_write),
+            Err(PF_PRESENT | PF_WRITE)
+        );
+        let no_wp = GuestAccess {
+            write_protect: false,
+            ..kernel_write
+        };
+        assert_eq!(walk(&memory, USER_PAGE, no_wp), Ok(0x7000));
+    }
+
+    #[test]
+    fn smap_blocks_supervisor_data_access_to_user_pages() {
+        let memory = tables(PAGE_PRESENT | PAGE_WRITE | PAGE_USER);
+
+        assert_eq!(walk(&memory, USER_PAGE, supervisor()), Err(PF_PRESENT));
+
+        // rflags.ac set
+        let ac = GuestAccess {
+            smap: false,
+            ..supervisor()
+        };
+        assert_eq!(walk(&memory, USER_PAGE, ac), Ok(0x7000));
+    }
+
+    #[test]
+    fn smep_and_nx_block_fetches() {
+        let memory = tables(PAGE_PRESENT | PAGE_WRITE | PAGE_USER);
+        let fetch = GuestAccess {
+            execute: true,
+            ..supervisor()
+        };
+        assert_eq!(
+            walk(&memory, USER_PAGE, fetch),
+            Err(PF_PRESENT | PF_INSTRUCTION)
+        );
+        assert_eq!(walk(&memory, LARGE_PAGE, fetch), Ok(0x20_0000));
+
+        let memory = tables(PAGE_PRESENT | PAGE_WRITE | PAGE_USER | PAGE_NO_EXECUTE);
+        let user_fetch = GuestAccess {
+            user: true,
+            ..fetch
+        };
+        assert_eq!(
+            walk(&memory, USER_PAGE, user_fetch),
+            Err(PF_PRESENT | PF_USER | PF_INSTRUCTION)
+        );
+
+        // without efer.nxe bit 63 is not looked at
+        let no_nx = GuestAccess {
+            no_execute: false,
+            ..user_fetch
+        };
+        assert_eq!(walk(&memory, USER_PAGE, no_nx), Ok(0x7000));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::data::{
+    ept_memory_type::{MEMORY_TYPE_UNCACHEABLE, MEMORY_TYPE_WRITE_BACK, MEMORY_TYPE_WRITE_THROUGH},
+    pml2e_2mb, pml4e,
+};
+
+// the first 512gb of physical memory in 2mb pages,one pml4 entry. ept and npt walk the same
+// tables,only the entry formats differ. nothing here touches the kernel,so a build machine can
+// fill the tables of both formats and compare them
+pub const IDENTITY_MAP_ENTRIES: usize = 512;
+pub const IDENTITY_MAP_LARGE_PAGE_SIZE: u64 = 0x20_0000;
+
+const PAGE_SIZE: u64 = 0x1000;
+const FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const LARGE_FRAME_MASK: u64 = 0x000F_FFFF_FFE0_0000;
+
+// a variable mtrr range,end is inclusive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub base: u64,
+    pub end: u64,
+    pub memory_type: u8,
+}
+
+// value placed in a field of len bits at start
+fn bits(value: u64, start: u64, len: u64) -> u64 {
+    (value & ((1 << len) - 1)) << start
+}
+
+// type of the 2mb page,the first one is always uncacheable and uncacheable wins an overlap
+pub fn large_page_memory_type(large_page: u64, ranges: &[MemoryRange]) -> u8 {
+    if large_page == 0 {
+        return MEMORY_TYPE_UNCACHEABLE;
+    }
+
+    let start = large_page * IDENTITY_MAP_LARGE_PAGE_SIZE;
+    let end = start + IDENTITY_MAP_LARGE_PAGE_SIZE - 1;
+
+    let mut memory_type = MEMORY_TYPE_WRITE_BACK;
+    for range in ranges
+        .iter()
+        .filter(|range| start <= range.end && end >= range.base)
+    {
+        memory_type = range.memory_type;
+        if memory_type == MEMORY_TYPE_UNCACHEABLE {
+            break;
+        }
+    }
+    memory_type
+}
+
+// how one nested paging format spells the entries of the identity map
+pub trait PagingFormat {
+    // pml4 and pml3 entry,full rights to the next table
+    fn table_entry(table_physical: u64) -> u64;
+    // pml2 entry mapping a 2mb page with full rights
+    fn large_entry(physical: u64, memory_type: u8) -> u64;
+}
+
+pub struct EptFormat;
+
+impl PagingFormat for EptFormat {
+    fn table_entry(table_physical: u64) -> u64 {
+        let entry = pml4e::READ_ACCESS
+            | pml4e::WRITE_ACCESS
+            | pml4e::EXECUTE_ACCESS
+            | pml4e::EXECUTE_USER_ACCESS;
+        entry
+            | bits(
+                table_physical / PAGE_SIZE,
+                pml4e::PAGE_FRAME_NUMBER_START,
+                pml4e::PAGE_FRAME_NUMBER_LEN,
+            )
+    }
+
+    fn large_entry(physical: u64, memory_type: u8) -> u64 {
+        let mut entry = pml2e_2mb::READ_ACCESS
+            | pml2e_2mb::WRITE_ACCESS
+            | pml2e_2mb::EXECUTE_ACCESS
+            | pml2e_2mb::EXECUTE_USER_ACCESS
+            | pml2e_2mb::LARGET_PAGE;
+        // no page raises #ve until it is selected
+        entry |= pml2e_2mb::SUPPRESS_VE;
+        entry
+            | bits(
+                memory_type as u64,
+                pml2e_2mb::MEMORY_TYPE_START,
+                pml2e_2mb::MEMORY_TYPE_LEN,
+            )
+            | bits(
+                physical / IDENTITY_MAP_LARGE_PAGE_SIZE,
+                pml2e_2mb::PAGE_FRAME_NUMBER_START,
+                pml2e_2mb::PAGE_FRAME_NUMBER_LEN,
+            )
+    }
+}
+
+// amd nested page tables are long mode page tables,guest physical accesses count as user
+// accesses so every level needs the user bit
+pub mod npte {
+    use crate::RT_BIT_64;
+
+    pub const PRESENT: u64 = RT_BIT_64!(0);
+    pub const WRITE: u64 = RT_BIT_64!(1);
+    pub const USER: u64 = RT_BIT_64!(2);
+    pub const WRITE_THROUGH: u64 = RT_BIT_64!(3);
+    pub const CACHE_DISABLE: u64 = RT_BIT_64!(4);
+    pub const ACCESSED: u64 = RT_BIT_64!(5);
+    pub const DIRTY: u64 = RT_BIT_64!(6);
+    pub const LARGE_PAGE: u64 = RT_BIT_64!(7);
+    pub const NO_EXECUTE: u64 = RT_BIT_64!(63);
+}
+
+pub struct NptFormat;
+
+impl PagingFormat for NptFormat {
+    fn table_entry(table_physical: u64) -> u64 {
+        npte::PRESENT | npte::WRITE | npte::USER | (table_physical & FRAME_MASK)
+    }
+
+    // the mtrrs still apply to npt walks,the pat index only has to keep uncacheable pages
+    // uncacheable. index 3 is uc in the reset pat and in the one windows programs
+    fn large_entry(physical: u64, memory_type: u8) -> u64 {
+        let cache = match memory_type {
+            MEMORY_TYPE_UNCACHEABLE => npte::CACHE_DISABLE | npte::WRITE_THROUGH,
+            MEMORY_TYPE_WRITE_THROUGH => npte::WRITE_THROUGH,
+            _ => 0,
+        };
+        npte::PRESENT
+            | npte::WRITE
+            | npte::USER
+            | npte::LARGE_PAGE
+            | cache
+            | (physical & LARGE_FRAME_MASK)
+    }
+}
+
+// identity maps 512gb. physical gives the physical address of a table entry,the tables are
+// never moved after this
+pub fn fill_identity_map<F: PagingFormat>(
+    pml4: &mut [u64; IDENTITY_MAP_ENTRIES],
+    pml3: &mut [u64; IDENTITY_MAP_ENTRIES],
+    pml2: &mut [[u64; IDENTITY_MAP_ENTRIES]; IDENTITY_MAP_ENTRIES],
+    physical: impl Fn(*const u64) -> u64,
+    ranges: &[MemoryRange],
+) {
+    pml4.fill(0);
+    pml4[0] = F::table_entry(physical(pml3.as_ptr()));
+
+    for (i, entry) in pml3.iter_mut().enumerate() {
+        *entry = F::table_entry(physical(pml2[i].as_ptr()));
+    }
+
+    for (i, table) in pml2.iter_mut().enumerate() {
+        for (j, entry) in table.iter_mut().enumerate() {
+            let large_page = (i * IDENTITY_MAP_ENTRIES + j) as u64;
+            *entry = F::large_entry(
+                large_page * IDENTITY_MAP_LARGE_PAGE_SIZE,
+                large_page_memory_type(large_page, ranges),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::ept_memory_type::MEMORY_TYPE_WRITE_COMBINING;
+    use alloc::{alloc::Layout, boxed::Box, vec, vec::Vec};
+
+    // page aligned like the real tables,entries only hold page frames
+    #[repr(C, align(4096))]
+    struct Tables {
+        pml4: [u64; IDENTITY_MAP_ENTRIES],
+        pml3: [u64; IDENTITY_MAP_ENTRIES],
+        pml2: [[u64; IDENTITY_MAP_ENTRIES]; IDENTITY_MAP_ENTRIES],
+    }
+
+    // the host address stands in for the physical one
+    fn fill<F: PagingFormat>(ranges: &[MemoryRange]) -> Box<Tables> {
+        // too large for the test thread stack
+        let mut tables: Box<Tables> =
+            unsafe { Box::from_raw(alloc::alloc::alloc_zeroed(Layout::new::<Tables>()) as _) };
+        fill_identity_map::<F>(
+            &mut tables.pml4,
+            &mut tables.pml3,
+            &mut tables.pml2,
+            |entry| entry as u64,
+            ranges,
+        );
+        tables
+    }
+
+    fn ept_frame(entry: u64) -> u64 {
+        entry & FRAME_MASK
+    }
+
+    fn ept_memory_type(entry: u64) -> u8 {
+        ((entry >> pml2e_2mb::MEMORY_TYPE_START) & 0x7) as u8
+    }
+
+    // uncacheable through pcd and pwt,write through through pwt
+    fn npt_memory_type(entry: u64) -> u8 {
+        match entry & (npte::CACHE_DISABLE | npte::WRITE_THROUGH) {
+            0 => MEMORY_TYPE_WRITE_BACK,
+            npte::WRITE_THROUGH => MEMORY_TYPE_WRITE_THROUGH,
+            _ => MEMORY_TYPE_UNCACHEABLE,
+        }
+    }
+
+    fn large_page(address: u64) -> (usize, usize) {
+        let page = (address / IDENTITY_MAP_LARGE_PAGE_SIZE) as usize;
+        (page / IDENTITY_MAP_ENTRIES, page % IDENTITY_MAP_ENTRIES)
+    }
+
+    fn mtrr_ranges() -> Vec<MemoryRange> {
+        vec![
+            // pci hole
+            MemoryRange {
+                base: 0xC000_0000,
+                end: 0xFFFF_FFFF,
+                memory_type: MEMORY_TYPE_UNCACHEABLE,
+            },
+            MemoryRange {
+                base: 0x1_0000_0000,
+                end: 0x1_003F_FFFF,
+                memory_type: MEMORY_TYPE_WRITE_THROUGH,
+            },
+            // the second half of a 2mb page,the whole page takes the type
+            MemoryRange {
+                base: 0x1_0050_0000,
+                end: 0x1_005F_FFFF,
+                memory_type: MEMORY_TYPE_UNCACHEABLE,
+            },
+        ]
+    }
+
+    #[test]
+    fn uncacheable_wins_an_overlap() {
+        let ranges = [
+            MemoryRange {
+                base: 0x4000_0000,
+                end: 0x4FFF_FFFF,
+                memory_type: MEMORY_TYPE_UNCACHEABLE,
+            },
+            MemoryRange {
+                base: 0x4000_0000,
+                end: 0x4FFF_FFFF,
+                memory_type: MEMORY_TYPE_WRITE_COMBINING,
+            },
+        ];
+
+        assert_eq!(large_page_memory_type(0, &[]), MEMORY_TYPE_UNCACHEABLE);
+        assert_eq!(large_page_memory_type(1, &ranges), MEMORY_TYPE_WRITE_BACK);
+        assert_eq!(
+            large_page_memory_type(0x4000_0000 / IDENTITY_MAP_LARGE_PAGE_SIZE, &ranges),
+            MEMORY_TYPE_UNCACHEABLE
+        );
+        assert_eq!(
+            large_page_memory_type(0x4000_0000 / IDENTITY_MAP_LARGE_PAGE_SIZE, &ranges[1..]),
+            MEMORY_TYPE_WRITE_COMBINING
+        );
+    }
+
+    #[test]
+    fn ept_and_npt_map_the_same_frames_with_the_same_types() {
+        let ranges = mtrr_ranges();
+        let ept = fill::<EptFormat>(&ranges);
+        let npt = fill::<NptFormat>(&ranges);
+
+        // one pml4 entry,every pml3 entry to its own pml2 table
+        assert_eq!(ept_frame(ept.pml4[0]), ept.pml3.as_ptr() as u64);
+        assert_eq!(npt.pml4[0] & FRAME_MASK, npt.pml3.as_ptr() as u64);
+        assert!(ept.pml4[1..].iter().all(|entry| *entry == 0));
+        assert!(npt.pml4[1..].iter().all(|entry| *entry == 0));
+        for i in 0..IDENTITY_MAP_ENTRIES {
+            assert_eq!(ept_frame(ept.pml3[i]), ept.pml2[i].as_ptr() as u64);
+            assert_eq!(npt.pml3[i] & FRAME_MASK, npt.pml2[i].as_ptr() as u64);
+        }
+
+        for i in 0..IDENTITY_MAP_ENTRIES {
+            for j in 0..IDENTITY_MAP_ENTRIES {
+                let (ept_entry, npt_entry) = (ept.pml2[i][j], npt.pml2[i][j]);
+                let physical = (i * IDENTITY_MAP_ENTRIES + j) as u64 * IDENTITY_MAP_LARGE_PAGE_SIZE;
+
+                assert_eq!(ept_frame(ept_entry), physical);
+                assert_eq!(npt_entry & LARGE_FRAME_MASK, physical);
+                assert_ne!(ept_entry & pml2e_2mb::LARGET_PAGE, 0);
+                assert_ne!(npt_entry & npte::LARGE_PAGE, 0);
+                assert_eq!(ept_memory_type(ept_entry), npt_memory_type(npt_entry));
+            }
+        }
+
+        let expected = [
+            (0, MEMORY_TYPE_UNCACHEABLE),
+            (0x8000_0000, MEMORY_TYPE_WRITE_BACK),
+            (0xC000_0000, MEMORY_TYPE_UNCACHEABLE),
+            (0xFFE0_0000, MEMORY_TYPE_UNCACHEABLE),
+            (0x1_0000_0000, MEMORY_TYPE_WRITE_THROUGH),
+            (0x1_0020_0000, MEMORY_TYPE_WRITE_THROUGH),
+            (0x1_0040_0000, MEMORY_TYPE_UNCACHEABLE),
+            (0x1_0060_0000, MEMORY_TYPE_WRITE_BACK),
+        ];
+        for (address, memory_type) in expected {
+            let (i, j) = large_page(address);
+            assert_eq!(ept_memory_type(ept.pml2[i][j]), memory_type);
+            assert_eq!(npt_memory_type(npt.pml2[i][j]), memory_type);
+        }
+    }
+
+    #[test]
+    fn npt_entries_carry_the_user_bit_and_ept_entries_suppress_ve() {
+        let npt = fill::<NptFormat>(&[]);
+        let ept = fill::<EptFormat>(&[]);
+
+        let table_rights = npte::PRESENT | npte::WRITE | npte::USER;
+        assert_eq!(npt.pml4[0] & table_rights, table_rights);
+        assert_eq!(npt.pml2[3][7] & table_rights, table_rights);
+        assert_eq!(npt.pml2[3][7] & npte::NO_EXECUTE, 0);
+        assert_ne!(ept.pml2[3][7] & pml2e_2mb::SUPPRESS_VE, 0);
+        assert_eq!(ept.pml4[0] & pml2e_2mb::SUPPRESS_VE, 0);
+    }
+}
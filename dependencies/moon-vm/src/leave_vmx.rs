@@ -0,0 +1,130 @@
+use core::arch::global_asm;
+
+use crate::exit_handler::GuestRegisterSlots;
+
+// the end of the exit that turned vmx off. every register comes back from the slots the
+// entry stub pushed,rsp from the rsp slot,and the cpu continues at guest_rip. the target
+// goes through r11,so the vmcall asking to leave must treat r11 as clobbered while rax
+// still carries its status
+global_asm!(
+    r#"
+.section .text
+.global leave_vmx_to_guest
+leave_vmx_to_guest:
+    mov     r11, rdx        // guest_rip
+    mov     rsp, rcx        // slots
+
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    add     rsp, 8          // r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdi
+    pop     rsi
+    pop     rbp
+    pop     rbx
+    pop     rdx
+    pop     rcx
+    pop     rax
+    pop     rsp
+
+    jmp     r11
+"#
+);
+
+extern "win64" {
+    // host,after vmxoff with the guest cr3,gdt and idt loaded
+    pub fn leave_vmx_to_guest(registers: *const GuestRegisterSlots, guest_rip: u64) -> !;
+}
+
+#[cfg(test)]
+mod tests {
+    use core::arch::global_asm;
+
+    use super::*;
+
+    static mut LANDED: GuestRegisterSlots = [0; 16];
+    static mut TEST_RSP: u64 = 0;
+
+    // stands in for the guest,calls leave_vmx_to_guest and comes back with what the target
+    // saw in LANDED
+    global_asm!(
+        r#"
+.section .text
+leave_vmx_test:
+    push    rbx
+    push    rbp
+    push    rdi
+    push    rsi
+    push    r12
+    push    r13
+    push    r14
+    push    r15
+    mov     [rip + {test_rsp}], rsp
+    jmp     leave_vmx_to_guest
+
+.global leave_vmx_test_target
+leave_vmx_test_target:
+    mov     [rip + {landed}], r15
+    mov     [rip + {landed} + 0x08], r14
+    mov     [rip + {landed} + 0x10], r13
+    mov     [rip + {landed} + 0x18], r12
+    mov     [rip + {landed} + 0x20], r11
+    mov     [rip + {landed} + 0x28], r10
+    mov     [rip + {landed} + 0x30], r9
+    mov     [rip + {landed} + 0x38], r8
+    mov     [rip + {landed} + 0x40], rdi
+    mov     [rip + {landed} + 0x48], rsi
+    mov     [rip + {landed} + 0x50], rbp
+    mov     [rip + {landed} + 0x58], rbx
+    mov     [rip + {landed} + 0x60], rdx
+    mov     [rip + {landed} + 0x68], rcx
+    mov     [rip + {landed} + 0x70], rax
+    mov     [rip + {landed} + 0x78], rsp
+
+    mov     rsp, [rip + {test_rsp}]
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    pop     rsi
+    pop     rdi
+    pop     rbp
+    pop     rbx
+    ret
+"#,
+        landed = sym LANDED,
+        test_rsp = sym TEST_RSP,
+    );
+
+    extern "win64" {
+        fn leave_vmx_test(registers: *const GuestRegisterSlots, guest_rip: u64);
+        fn leave_vmx_test_target();
+    }
+
+    #[test]
+    fn leave_restores_every_register_but_r11() {
+        let guest_stack = [0u64; 64];
+        let guest_rsp = guest_stack.as_ptr_range().end as u64 - 0x10;
+        let target = leave_vmx_test_target as *const () as u64;
+
+        let mut registers: GuestRegisterSlots = core::array::from_fn(|i| 0x1000 + i as u64);
+        // the vmcall status the handler left,what __vmx_vmcall checks after the exit
+        registers[14] = 0;
+        registers[15] = guest_rsp;
+
+        unsafe { leave_vmx_test(&registers, target) };
+        let landed = unsafe { *core::ptr::addr_of!(LANDED) };
+
+        for (slot, (&expected, &seen)) in registers.iter().zip(landed.iter()).enumerate() {
+            if slot == 4 {
+                assert_eq!(seen, target);
+            } else {
+                assert_eq!(seen, expected, "slot {}", slot);
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod access_dirty;
+pub mod controls;
+pub mod cr_access;
+pub mod data;
+pub mod dirty_log;
+pub mod dump;
+pub mod entry_check;
+pub mod ept_violation;
+pub mod exit_handler;
+pub mod exit_trace;
+pub mod guest_paging;
+pub mod identity_map;
+pub mod leave_vmx;
+pub mod msr_bitmap;
+pub mod msr_permission;
+pub mod msr_shadow;
+pub mod soft_vmcs;
+pub mod spp;
+pub mod vmcb;
+pub mod watch_range;
+
+#[macro_export]
+macro_rules! RT_BIT_32 {
+    ($bit:expr) => {
+        1u32 << ($bit)
+    };
+}
+
+#[macro_export]
+macro_rules! RT_BIT_64 {
+    ($bit:expr) => {
+        1u64 << ($bit)
+    };
+}
@@ -0,0 +1,245 @@
+// msr bitmap page layout,each region is 1KB and holds one bit per msr
+// 0x000 read low    00000000 - 00001FFF
+// 0x400 read high   C0000000 - C0001FFF
+// 0x800 write low   00000000 - 00001FFF
+// 0xC00 write high  C0000000 - C0001FFF
+pub const MSR_BITMAP_SIZE: usize = 4096;
+
+const REGION_SIZE: usize = 1024;
+const READ_LOW_OFFSET: usize = 0;
+const READ_HIGH_OFFSET: usize = REGION_SIZE;
+const WRITE_LOW_OFFSET: usize = REGION_SIZE * 2;
+const WRITE_HIGH_OFFSET: usize = REGION_SIZE * 3;
+
+pub const MSR_LOW_START: u32 = 0x00000000;
+pub const MSR_LOW_END: u32 = 0x00001FFF;
+pub const MSR_HIGH_START: u32 = 0xC0000000;
+pub const MSR_HIGH_END: u32 = 0xC0001FFF;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MsrAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl MsrAccess {
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(MsrAccess::Read),
+            1 => Some(MsrAccess::Write),
+            2 => Some(MsrAccess::ReadWrite),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            MsrAccess::Read => 0,
+            MsrAccess::Write => 1,
+            MsrAccess::ReadWrite => 2,
+        }
+    }
+
+    pub fn read(&self) -> bool {
+        *self != MsrAccess::Write
+    }
+
+    pub fn write(&self) -> bool {
+        *self != MsrAccess::Read
+    }
+}
+
+// vmx enablement and capability msrs,the hypervisor answers them itself.
+// exposing them would show the guest the real vmx configuration
+const MSR_IA32_FEATURE_CONTROL: u32 = 0x3A;
+const MSR_IA32_VMX_FIRST: u32 = 0x480;
+const MSR_IA32_VMX_LAST: u32 = 0x493;
+
+// msrs whose intercept can be changed at runtime
+pub fn check_msr_intercept(msr: u32) -> Result<(), &'static str> {
+    match msr {
+        MSR_IA32_FEATURE_CONTROL | MSR_IA32_VMX_FIRST..=MSR_IA32_VMX_LAST => {
+            Err("msr intercept is owned by the hypervisor")
+        }
+        _ => msr_bit_position(msr, false).map(|_| ()),
+    }
+}
+
+// byte offset in the page and bit in that byte,msrs outside both ranges always exit
+pub fn msr_bit_position(msr: u32, write: bool) -> Result<(usize, u8), &'static str> {
+    let (region, index) = match msr {
+        MSR_LOW_START..=MSR_LOW_END => (
+            if write {
+                WRITE_LOW_OFFSET
+            } else {
+                READ_LOW_OFFSET
+            },
+            msr - MSR_LOW_START,
+        ),
+        MSR_HIGH_START..=MSR_HIGH_END => (
+            if write {
+                WRITE_HIGH_OFFSET
+            } else {
+                READ_HIGH_OFFSET
+            },
+            msr - MSR_HIGH_START,
+        ),
+        _ => return Err("msr is not covered by the msr bitmap"),
+    };
+
+    Ok((region + (index / 8) as usize, (index % 8) as u8))
+}
+
+pub struct MsrBitmap<'a> {
+    page: &'a mut [u8; MSR_BITMAP_SIZE],
+}
+
+impl<'a> MsrBitmap<'a> {
+    pub fn new(page: &'a mut [u8; MSR_BITMAP_SIZE]) -> Self {
+        Self { page }
+    }
+
+    fn update(&mut self, msr: u32, write: bool, intercept: bool) -> Result<(), &'static str> {
+        let (offset, bit) = msr_bit_position(msr, write)?;
+        if intercept {
+            self.page[offset] |= 1 << bit;
+        } else {
+            self.page[offset] &= !(1 << bit);
+        }
+        Ok(())
+    }
+
+    pub fn set(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        // validate once so a bad msr never leaves a half applied change
+        msr_bit_position(msr, false)?;
+
+        if access.read() {
+            self.update(msr, false, intercept)?;
+        }
+        if access.write() {
+            self.update(msr, true, intercept)?;
+        }
+        Ok(())
+    }
+
+    pub fn intercept_read(&mut self, msr: u32) -> Result<(), &'static str> {
+        self.set(msr, MsrAccess::Read, true)
+    }
+
+    pub fn intercept_write(&mut self, msr: u32) -> Result<(), &'static str> {
+        self.set(msr, MsrAccess::Write, true)
+    }
+
+    // let the guest access the msr directly again
+    pub fn clear(&mut self, msr: u32, access: MsrAccess) -> Result<(), &'static str> {
+        self.set(msr, access, false)
+    }
+
+    pub fn is_intercepted(&self, msr: u32, access: MsrAccess) -> Result<bool, &'static str> {
+        let read = if access.read() {
+            let (offset, bit) = msr_bit_position(msr, false)?;
+            (self.page[offset] & (1 << bit)) != 0
+        } else {
+            false
+        };
+
+        let write = if access.write() {
+            let (offset, bit) = msr_bit_position(msr, true)?;
+            (self.page[offset] & (1 << bit)) != 0
+        } else {
+            false
+        };
+
+        Ok(read || write)
+    }
+
+    pub fn clear_all(&mut self) {
+        self.page.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_positions() {
+        assert_eq!(msr_bit_position(0, false), Ok((0, 0)));
+        assert_eq!(msr_bit_position(0, true), Ok((0x800, 0)));
+        // ia32_sysenter_cs
+        assert_eq!(msr_bit_position(0x174, false), Ok((0x2E, 4)));
+        assert_eq!(msr_bit_position(MSR_LOW_END, true), Ok((0xBFF, 7)));
+        // ia32_lstar
+        assert_eq!(msr_bit_position(0xC000_0082, false), Ok((0x410, 2)));
+        assert_eq!(msr_bit_position(0xC000_0082, true), Ok((0xC10, 2)));
+        assert_eq!(msr_bit_position(MSR_HIGH_END, false), Ok((0x7FF, 7)));
+        assert_eq!(msr_bit_position(MSR_HIGH_END, true), Ok((0xFFF, 7)));
+    }
+
+    #[test]
+    fn msrs_outside_the_bitmap() {
+        assert!(msr_bit_position(MSR_LOW_END + 1, false).is_err());
+        assert!(msr_bit_position(MSR_HIGH_START - 1, true).is_err());
+        assert!(msr_bit_position(MSR_HIGH_END + 1, false).is_err());
+        // amd range,never covered
+        assert!(msr_bit_position(0xC001_0114, false).is_err());
+    }
+
+    #[test]
+    fn set_and_clear() {
+        let mut page = [0u8; MSR_BITMAP_SIZE];
+        let mut bitmap = MsrBitmap::new(&mut page);
+
+        bitmap.set(0xC000_0082, MsrAccess::ReadWrite, true).unwrap();
+        bitmap.intercept_write(0x174).unwrap();
+        assert_eq!(
+            bitmap.is_intercepted(0xC000_0082, MsrAccess::Read),
+            Ok(true)
+        );
+        assert_eq!(bitmap.is_intercepted(0x174, MsrAccess::Read), Ok(false));
+        assert_eq!(bitmap.is_intercepted(0x174, MsrAccess::ReadWrite), Ok(true));
+
+        bitmap.clear(0xC000_0082, MsrAccess::Read).unwrap();
+        assert_eq!(
+            bitmap.is_intercepted(0xC000_0082, MsrAccess::Read),
+            Ok(false)
+        );
+        assert_eq!(
+            bitmap.is_intercepted(0xC000_0082, MsrAccess::Write),
+            Ok(true)
+        );
+
+        // a rejected msr leaves the page alone
+        assert!(bitmap.set(0x4000_0000, MsrAccess::ReadWrite, true).is_err());
+
+        assert_eq!(page[0xC10], 1 << 2);
+        assert_eq!(page[0x82E], 1 << 4);
+        assert_eq!(page.iter().filter(|byte| **byte != 0).count(), 2);
+    }
+
+    #[test]
+    fn hypervisor_owned_msrs_are_rejected() {
+        assert!(check_msr_intercept(0x3A).is_err());
+        assert!(check_msr_intercept(0x480).is_err());
+        assert!(check_msr_intercept(0x48D).is_err());
+        assert!(check_msr_intercept(0x493).is_err());
+        assert!(check_msr_intercept(0x0FFF_FFFF).is_err());
+        assert_eq!(check_msr_intercept(0x479), Ok(()));
+        assert_eq!(check_msr_intercept(0x494), Ok(()));
+        assert_eq!(check_msr_intercept(0xC000_0082), Ok(()));
+    }
+
+    #[test]
+    fn access_encoding_round_trips() {
+        for access in [MsrAccess::Read, MsrAccess::Write, MsrAccess::ReadWrite] {
+            assert_eq!(MsrAccess::from_u64(access.as_u64()), Some(access));
+        }
+        assert_eq!(MsrAccess::from_u64(3), None);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::msr_bitmap::MsrAccess;
+
+// svm msr permission map,two bits per msr,the even one intercepts rdmsr and the odd one wrmsr
+// 0x0000 00000000 - 00001FFF
+// 0x0800 C0000000 - C0001FFF
+// 0x1000 C0010000 - C0011FFF
+// 0x1800 reserved,msrs outside the three ranges always exit
+pub const MSR_PERMISSION_MAP_SIZE: usize = 0x2000;
+
+const RANGE_SIZE: usize = 0x800;
+const MSR_PER_RANGE: u32 = 0x2000;
+
+pub const MSR_AMD_LOW_START: u32 = 0x00000000;
+pub const MSR_AMD_HIGH_START: u32 = 0xC0000000;
+pub const MSR_AMD_SVM_START: u32 = 0xC0010000;
+
+// byte offset in the map and bit in that byte
+pub fn msr_permission_position(msr: u32, write: bool) -> Result<(usize, u8), &'static str> {
+    let (range, index) = [MSR_AMD_LOW_START, MSR_AMD_HIGH_START, MSR_AMD_SVM_START]
+        .iter()
+        .enumerate()
+        .find(|(_, start)| (**start..*start + MSR_PER_RANGE).contains(&msr))
+        .map(|(range, start)| (range, msr - *start))
+        .ok_or("msr is not covered by the msr permission map")?;
+
+    let bit = index as usize * 2 + write as usize;
+    Ok((range * RANGE_SIZE + bit / 8, (bit % 8) as u8))
+}
+
+pub struct MsrPermissionMap<'a> {
+    map: &'a mut [u8; MSR_PERMISSION_MAP_SIZE],
+}
+
+impl<'a> MsrPermissionMap<'a> {
+    pub fn new(map: &'a mut [u8; MSR_PERMISSION_MAP_SIZE]) -> Self {
+        Self { map }
+    }
+
+    fn update(&mut self, msr: u32, write: bool, intercept: bool) -> Result<(), &'static str> {
+        let (offset, bit) = msr_permission_position(msr, write)?;
+        if intercept {
+            self.map[offset] |= 1 << bit;
+        } else {
+            self.map[offset] &= !(1 << bit);
+        }
+        Ok(())
+    }
+
+    pub fn set(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        // validate once so a bad msr never leaves a half applied change
+        msr_permission_position(msr, false)?;
+
+        if access.read() {
+            self.update(msr, false, intercept)?;
+        }
+        if access.write() {
+            self.update(msr, true, intercept)?;
+        }
+        Ok(())
+    }
+
+    pub fn intercept_read(&mut self, msr: u32) -> Result<(), &'static str> {
+        self.set(msr, MsrAccess::Read, true)
+    }
+
+    pub fn intercept_write(&mut self, msr: u32) -> Result<(), &'static str> {
+        self.set(msr, MsrAccess::Write, true)
+    }
+
+    // let the guest access the msr directly again
+    pub fn clear(&mut self, msr: u32, access: MsrAccess) -> Result<(), &'static str> {
+        self.set(msr, access, false)
+    }
+
+    pub fn is_intercepted(&self, msr: u32, access: MsrAccess) -> Result<bool, &'static str> {
+        let read = if access.read() {
+            let (offset, bit) = msr_permission_position(msr, false)?;
+            (self.map[offset] & (1 << bit)) != 0
+        } else {
+            false
+        };
+
+        let write = if access.write() {
+            let (offset, bit) = msr_permission_position(msr, true)?;
+            (self.map[offset] & (1 << bit)) != 0
+        } else {
+            false
+        };
+
+        Ok(read || write)
+    }
+
+    pub fn clear_all(&mut self) {
+        self.map.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_start_at_their_offsets() {
+        assert_eq!(msr_permission_position(0x0, false), Ok((0, 0)));
+        assert_eq!(msr_permission_position(0x0, true), Ok((0, 1)));
+        assert_eq!(msr_permission_position(0xC0000000, false), Ok((0x800, 0)));
+        assert_eq!(msr_permission_position(0xC0010000, true), Ok((0x1000, 1)));
+    }
+
+    #[test]
+    fn ranges_end_in_their_last_byte() {
+        assert_eq!(msr_permission_position(0x1FFF, false), Ok((0x7FF, 6)));
+        assert_eq!(msr_permission_position(0x1FFF, true), Ok((0x7FF, 7)));
+        assert_eq!(msr_permission_position(0xC0001FFF, true), Ok((0xFFF, 7)));
+        assert_eq!(msr_permission_position(0xC0011FFF, true), Ok((0x17FF, 7)));
+    }
+
+    #[test]
+    fn msrs_outside_the_ranges_are_refused() {
+        for msr in [
+            0x2000,
+            0xBFFFFFFF,
+            0xC0002000,
+            0xC000FFFF,
+            0xC0012000,
+            u32::MAX,
+        ] {
+            assert!(msr_permission_position(msr, false).is_err(), "{:x}", msr);
+        }
+    }
+
+    #[test]
+    fn map_sets_and_clears_each_direction() {
+        let mut raw = [0u8; MSR_PERMISSION_MAP_SIZE];
+        let mut map = MsrPermissionMap::new(&mut raw);
+
+        map.intercept_read(0x10).unwrap();
+        map.clear_all();
+        assert!(!map.is_intercepted(0x10, MsrAccess::ReadWrite).unwrap());
+
+        map.intercept_write(0xC0000082).unwrap();
+        assert!(map.is_intercepted(0xC0000082, MsrAccess::Write).unwrap());
+        assert!(!map.is_intercepted(0xC0000082, MsrAccess::Read).unwrap());
+        assert!(map
+            .is_intercepted(0xC0000082, MsrAccess::ReadWrite)
+            .unwrap());
+
+        map.set(0x1FFF, MsrAccess::ReadWrite, true).unwrap();
+        map.clear(0x1FFF, MsrAccess::Read).unwrap();
+        assert!(!map.is_intercepted(0x1FFF, MsrAccess::Read).unwrap());
+        assert!(map.is_intercepted(0x1FFF, MsrAccess::Write).unwrap());
+
+        // nothing changes for a msr the map does not cover
+        assert!(map.set(0x2000, MsrAccess::ReadWrite, true).is_err());
+    }
+
+    #[test]
+    fn map_bits_land_where_the_apm_puts_them() {
+        let mut raw = [0u8; MSR_PERMISSION_MAP_SIZE];
+        {
+            let mut map = MsrPermissionMap::new(&mut raw);
+            // efer,rdmsr bit of msr 0x80 in the second range
+            map.intercept_read(0xC0000080).unwrap();
+            map.intercept_write(0xC0010000).unwrap();
+        }
+
+        assert_eq!(raw[0x820], 1);
+        assert_eq!(raw[0x1000], 2);
+        assert_eq!(raw.iter().filter(|&&byte| byte != 0).count(), 2);
+    }
+}
@@ -0,0 +1,446 @@
+use alloc::vec::Vec;
+
+use crate::msr_bitmap::MsrAccess;
+
+pub const MSR_IA32_FEATURE_CONTROL: u32 = 0x3A;
+pub const MSR_IA32_SYSENTER_CS: u32 = 0x174;
+pub const MSR_IA32_SYSENTER_ESP: u32 = 0x175;
+pub const MSR_IA32_SYSENTER_EIP: u32 = 0x176;
+pub const MSR_IA32_DEBUGCTL: u32 = 0x1D9;
+pub const MSR_IA32_PAT: u32 = 0x277;
+pub const MSR_EFER: u32 = 0xC000_0080;
+pub const MSR_LSTAR: u32 = 0xC000_0082;
+pub const MSR_FS_BASE: u32 = 0xC000_0100;
+pub const MSR_GS_BASE: u32 = 0xC000_0101;
+pub const MSR_SHADOW_GS_BASE: u32 = 0xC000_0102;
+
+const FEATURE_CONTROL_LOCK: u64 = 1 << 0;
+const FEATURE_CONTROL_VMXON: u64 = 1 << 2;
+
+pub const EFER_SCE: u64 = 1 << 0;
+pub const EFER_LME: u64 = 1 << 8;
+pub const EFER_LMA: u64 = 1 << 10;
+pub const EFER_NXE: u64 = 1 << 11;
+pub const EFER_SVME: u64 = 1 << 12;
+
+// false makes the access #gp,value can be changed for both reads and writes
+pub type MsrCallback = fn(cpu_index: usize, msr: u32, access: MsrAccess, value: &mut u64) -> bool;
+
+#[derive(Clone, Copy)]
+pub enum MsrPolicy {
+    // guest reads and writes the real value
+    PassThrough,
+    // guest reads its own last write,the real value stays ours
+    Shadow,
+    DenyGp,
+    Callback(MsrCallback),
+}
+
+// where the guest value of a msr really lives,a write the backing can not take is #gp
+pub trait MsrBacking {
+    fn read(&mut self, msr: u32) -> u64;
+    fn write(&mut self, msr: u32, value: u64) -> Result<(), &'static str>;
+}
+
+struct MsrShadowEntry {
+    msr: u32,
+    policy: MsrPolicy,
+    shadow: Option<u64>,
+}
+
+pub struct MsrShadowTable {
+    entries: Vec<MsrShadowEntry>,
+}
+
+fn is_canonical(address: u64) -> bool {
+    let upper = address >> 47;
+    upper == 0 || upper == 0x1FFFF
+}
+
+// wrmsr of a non canonical address #gp,and the vmcs would fail the next vm-entry
+fn requires_canonical(msr: u32) -> bool {
+    matches!(
+        msr,
+        MSR_FS_BASE
+            | MSR_GS_BASE
+            | MSR_SHADOW_GS_BASE
+            | MSR_LSTAR
+            | MSR_IA32_SYSENTER_ESP
+            | MSR_IA32_SYSENTER_EIP
+    )
+}
+
+// the value wrmsr efer really leaves behind,lma only follows lme and paging.
+// shared is set when root runs on the guest efer,nxe then also covers the host page tables
+pub fn check_efer_write(
+    current: u64,
+    value: u64,
+    paging: bool,
+    shared: bool,
+) -> Result<u64, &'static str> {
+    if (value & !(EFER_SCE | EFER_LME | EFER_LMA | EFER_NXE)) != 0 {
+        return Err("efer reserved bits");
+    }
+
+    let changed = value ^ current;
+    if paging && (changed & EFER_LME) != 0 {
+        return Err("efer.lme changed with paging on");
+    }
+
+    if shared && (changed & EFER_NXE) != 0 {
+        return Err("efer.nxe is shared with the host");
+    }
+
+    Ok((value & !EFER_LMA) | (current & EFER_LMA))
+}
+
+// efer of an svm guest. svme belongs to the host,the guest reads it clear,may not set it and
+// every write keeps it or the next vmrun fails
+pub fn check_svm_efer_write(current: u64, value: u64, paging: bool) -> Result<u64, &'static str> {
+    check_efer_write(current & !EFER_SVME, value, paging, false).map(|efer| efer | EFER_SVME)
+}
+
+// every entry must name a memory type,2 and 3 are reserved
+pub fn check_pat_write(value: u64) -> Result<(), &'static str> {
+    let valid = value
+        .to_le_bytes()
+        .iter()
+        .all(|memory_type| matches!(memory_type, 0 | 1 | 4 | 5 | 6 | 7));
+
+    if valid {
+        Ok(())
+    } else {
+        Err("pat entry with a reserved memory type")
+    }
+}
+
+// report vmx as enabled and locked,a locked msr can not be written
+fn feature_control_callback(
+    _cpu_index: usize,
+    _msr: u32,
+    access: MsrAccess,
+    value: &mut u64,
+) -> bool {
+    match access {
+        MsrAccess::Read => {
+            *value |= FEATURE_CONTROL_VMXON | FEATURE_CONTROL_LOCK;
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Default for MsrShadowTable {
+    fn default() -> Self {
+        let mut table = Self::new();
+
+        // vmcs backed,handled through the backing. writes always exit to be validated
+        for msr in [
+            MSR_FS_BASE,
+            MSR_GS_BASE,
+            MSR_SHADOW_GS_BASE,
+            MSR_IA32_SYSENTER_CS,
+            MSR_IA32_SYSENTER_ESP,
+            MSR_IA32_SYSENTER_EIP,
+            MSR_EFER,
+            MSR_IA32_PAT,
+            MSR_IA32_DEBUGCTL,
+            MSR_LSTAR,
+        ] {
+            table.set_policy(msr, MsrPolicy::PassThrough);
+        }
+
+        table.set_policy(
+            MSR_IA32_FEATURE_CONTROL,
+            MsrPolicy::Callback(feature_control_callback),
+        );
+
+        table
+    }
+}
+
+impl MsrShadowTable {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, msr: u32, policy: MsrPolicy) {
+        match self.entries.iter_mut().find(|entry| entry.msr == msr) {
+            Some(entry) => {
+                entry.policy = policy;
+                entry.shadow = None;
+            }
+            None => self.entries.push(MsrShadowEntry {
+                msr,
+                policy,
+                shadow: None,
+            }),
+        }
+    }
+
+    pub fn policy(&self, msr: u32) -> Option<MsrPolicy> {
+        self.entries
+            .iter()
+            .find(|entry| entry.msr == msr)
+            .map(|entry| entry.policy)
+    }
+
+    // value the guest reads back under the shadow policy
+    pub fn set_shadow(&mut self, msr: u32, value: u64) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.msr == msr) {
+            entry.shadow = Some(value);
+        }
+    }
+
+    pub fn shadow(&self, msr: u32) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|entry| entry.msr == msr)
+            .and_then(|entry| entry.shadow)
+    }
+
+    // bitmap exits each policy needs,pass through reads go to the cpu directly
+    pub fn intercepted_msrs(&self) -> impl Iterator<Item = (u32, MsrAccess)> + '_ {
+        self.entries.iter().map(|entry| match entry.policy {
+            MsrPolicy::PassThrough => (entry.msr, MsrAccess::Write),
+            _ => (entry.msr, MsrAccess::ReadWrite),
+        })
+    }
+
+    // none when the msr has no policy
+    pub fn read<B: MsrBacking>(
+        &mut self,
+        cpu_index: usize,
+        msr: u32,
+        backing: &mut B,
+    ) -> Option<Result<u64, &'static str>> {
+        let entry = self.entries.iter_mut().find(|entry| entry.msr == msr)?;
+
+        Some(match entry.policy {
+            MsrPolicy::PassThrough => Ok(backing.read(msr)),
+            MsrPolicy::Shadow => Ok(entry.shadow.unwrap_or_else(|| backing.read(msr))),
+            MsrPolicy::DenyGp => Err("msr read denied"),
+            MsrPolicy::Callback(callback) => {
+                let mut value = backing.read(msr);
+                if callback(cpu_index, msr, MsrAccess::Read, &mut value) {
+                    Ok(value)
+                } else {
+                    Err("msr read rejected by callback")
+                }
+            }
+        })
+    }
+
+    pub fn write<B: MsrBacking>(
+        &mut self,
+        cpu_index: usize,
+        msr: u32,
+        value: u64,
+        backing: &mut B,
+    ) -> Option<Result<(), &'static str>> {
+        let entry = self.entries.iter_mut().find(|entry| entry.msr == msr)?;
+
+        if requires_canonical(msr) && !is_canonical(value) {
+            return Some(Err("non canonical msr value"));
+        }
+
+        Some(match entry.policy {
+            MsrPolicy::PassThrough => backing.write(msr, value),
+            MsrPolicy::Shadow => {
+                entry.shadow = Some(value);
+                Ok(())
+            }
+            MsrPolicy::DenyGp => Err("msr write denied"),
+            MsrPolicy::Callback(callback) => {
+                let mut value = value;
+                if callback(cpu_index, msr, MsrAccess::Write, &mut value) {
+                    backing.write(msr, value)
+                } else {
+                    Err("msr write rejected by callback")
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    // a 64-bit windows cpu,efer validated the way VmcsMsrBacking does without load efer
+    struct TestBacking {
+        msrs: BTreeMap<u32, u64>,
+    }
+
+    impl TestBacking {
+        fn new() -> Self {
+            Self {
+                msrs: [
+                    (MSR_EFER, EFER_SCE | EFER_LME | EFER_LMA | EFER_NXE),
+                    (MSR_IA32_PAT, 0x0007_0106_0007_0106),
+                    (MSR_IA32_FEATURE_CONTROL, 0x1),
+                ]
+                .into_iter()
+                .collect(),
+            }
+        }
+    }
+
+    impl MsrBacking for TestBacking {
+        fn read(&mut self, msr: u32) -> u64 {
+            self.msrs.get(&msr).copied().unwrap_or(0)
+        }
+
+        fn write(&mut self, msr: u32, value: u64) -> Result<(), &'static str> {
+            let value = match msr {
+                MSR_EFER => check_efer_write(self.read(msr), value, true, true)?,
+                MSR_IA32_PAT => check_pat_write(value).map(|_| value)?,
+                _ => value,
+            };
+            self.msrs.insert(msr, value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn default_policies_intercept_writes() {
+        let table = MsrShadowTable::default();
+        let intercepted: Vec<(u32, MsrAccess)> = table.intercepted_msrs().collect();
+
+        for msr in [MSR_FS_BASE, MSR_EFER, MSR_IA32_PAT, MSR_IA32_SYSENTER_EIP] {
+            assert!(intercepted.contains(&(msr, MsrAccess::Write)));
+        }
+        assert!(intercepted.contains(&(MSR_IA32_FEATURE_CONTROL, MsrAccess::ReadWrite)));
+    }
+
+    #[test]
+    fn efer_writes() {
+        let mut table = MsrShadowTable::default();
+        let mut backing = TestBacking::new();
+        let efer = backing.read(MSR_EFER);
+
+        // sce can change,lma writes are ignored
+        assert_eq!(
+            table.write(0, MSR_EFER, efer & !(EFER_SCE | EFER_LMA), &mut backing),
+            Some(Ok(()))
+        );
+        assert_eq!(backing.read(MSR_EFER), efer & !EFER_SCE);
+
+        for value in [efer | (1 << 12), efer & !EFER_LME, efer & !EFER_NXE] {
+            assert!(matches!(
+                table.write(0, MSR_EFER, value, &mut backing),
+                Some(Err(_))
+            ));
+        }
+        assert_eq!(backing.read(MSR_EFER), efer & !EFER_SCE);
+    }
+
+    #[test]
+    fn efer_checks() {
+        let efer = EFER_SCE | EFER_LME | EFER_LMA | EFER_NXE;
+
+        assert_eq!(check_efer_write(efer, efer, true, true), Ok(efer));
+        assert!(check_efer_write(efer, efer | (1 << 63), true, false).is_err());
+        assert!(check_efer_write(efer, efer & !EFER_LME, true, false).is_err());
+        // a guest efer in the vmcs may drop nxe,and lme with paging off
+        assert_eq!(
+            check_efer_write(efer, efer & !EFER_NXE, true, false),
+            Ok(efer & !EFER_NXE)
+        );
+        assert_eq!(check_efer_write(0, EFER_LME, false, false), Ok(EFER_LME));
+    }
+
+    #[test]
+    fn svm_efer_checks() {
+        let efer = EFER_SCE | EFER_LME | EFER_LMA | EFER_NXE | EFER_SVME;
+        let guest = efer & !EFER_SVME;
+
+        assert_eq!(check_svm_efer_write(efer, guest, true), Ok(efer));
+        assert_eq!(
+            check_svm_efer_write(efer, guest & !EFER_SCE, true),
+            Ok(efer & !EFER_SCE)
+        );
+        // the guest has no svm
+        assert!(check_svm_efer_write(efer, efer, true).is_err());
+        assert!(check_svm_efer_write(efer, guest | (1 << 63), true).is_err());
+        assert!(check_svm_efer_write(efer, guest & !EFER_LME, true).is_err());
+    }
+
+    #[test]
+    fn pat_checks() {
+        assert_eq!(check_pat_write(0x0007_0406_0007_0406), Ok(()));
+        assert_eq!(check_pat_write(0), Ok(()));
+        assert!(check_pat_write(0x0007_0406_0007_0402).is_err());
+        assert!(check_pat_write(0x0007_0406_0003_0406).is_err());
+        assert!(check_pat_write(0x0807_0406_0007_0406).is_err());
+    }
+
+    #[test]
+    fn canonical_bases() {
+        let mut table = MsrShadowTable::default();
+        let mut backing = TestBacking::new();
+
+        assert_eq!(
+            table.write(0, MSR_GS_BASE, 0xFFFF_F800_0000_0000, &mut backing),
+            Some(Ok(()))
+        );
+        assert!(matches!(
+            table.write(0, MSR_GS_BASE, 0x0000_8000_0000_0000, &mut backing),
+            Some(Err(_))
+        ));
+        assert_eq!(backing.read(MSR_GS_BASE), 0xFFFF_F800_0000_0000);
+    }
+
+    #[test]
+    fn shadow_keeps_the_real_value() {
+        let mut table = MsrShadowTable::default();
+        let mut backing = TestBacking::new();
+        backing.msrs.insert(MSR_LSTAR, 0xFFFF_F800_1000_0000);
+
+        table.set_policy(MSR_LSTAR, MsrPolicy::Shadow);
+        table.set_shadow(MSR_LSTAR, 0xFFFF_F800_2000_0000);
+        assert_eq!(
+            table.read(0, MSR_LSTAR, &mut backing),
+            Some(Ok(0xFFFF_F800_2000_0000))
+        );
+
+        assert_eq!(
+            table.write(0, MSR_LSTAR, 0xFFFF_F800_3000_0000, &mut backing),
+            Some(Ok(()))
+        );
+        assert_eq!(
+            table.read(0, MSR_LSTAR, &mut backing),
+            Some(Ok(0xFFFF_F800_3000_0000))
+        );
+        assert_eq!(backing.read(MSR_LSTAR), 0xFFFF_F800_1000_0000);
+    }
+
+    #[test]
+    fn feature_control_reads_locked_and_rejects_writes() {
+        let mut table = MsrShadowTable::default();
+        let mut backing = TestBacking::new();
+
+        assert_eq!(
+            table.read(0, MSR_IA32_FEATURE_CONTROL, &mut backing),
+            Some(Ok(FEATURE_CONTROL_LOCK | FEATURE_CONTROL_VMXON))
+        );
+        assert!(matches!(
+            table.write(0, MSR_IA32_FEATURE_CONTROL, 0x5, &mut backing),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_msrs_have_no_policy() {
+        let mut table = MsrShadowTable::default();
+        let mut backing = TestBacking::new();
+
+        table.set_policy(0x10, MsrPolicy::DenyGp);
+        assert!(matches!(table.read(0, 0x10, &mut backing), Some(Err(_))));
+        assert_eq!(table.read(0, 0x11, &mut backing), None);
+        assert_eq!(table.write(0, 0x11, 0, &mut backing), None);
+    }
+}
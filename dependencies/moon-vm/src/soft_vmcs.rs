@@ -0,0 +1,173 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{data::vmcs_encoding::VM_INSTRUCTION_ERROR, exit_handler::VmcsAccess};
+
+// field encoding,bit 0 reads or writes bits 63:32 of a 64 bit field
+const ENCODING_ACCESS_HIGH: u64 = 1 << 0;
+const ENCODING_TYPE_START: u64 = 10;
+const ENCODING_TYPE_MASK: u64 = 0x3;
+const ENCODING_TYPE_EXIT_INFORMATION: u64 = 1;
+const ENCODING_WIDTH_START: u64 = 13;
+const ENCODING_WIDTH_MASK: u64 = 0x3;
+const ENCODING_WIDTH_16: u64 = 0;
+const ENCODING_WIDTH_64: u64 = 1;
+const ENCODING_WIDTH_32: u64 = 2;
+// bit 12 and everything above bit 14
+const ENCODING_RESERVED: u64 = !0x6FFF;
+
+// vm-instruction error numbers
+const ERROR_UNSUPPORTED_COMPONENT: u64 = 12;
+const ERROR_WRITE_READ_ONLY: u64 = 13;
+
+// a vmcs kept in a field map,for running exit handlers without vmx. fields never written read
+// as 0 like a cleared vmcs,values are cut to the width of the field
+#[derive(Default)]
+pub struct SoftVmcs {
+    fields: BTreeMap<u64, u64>,
+    // ia32_vmx_misc bit 29,vmwrite may change vm-exit information fields
+    writable_exit_information: bool,
+    // every successful vmwrite of the handlers in order
+    writes: Vec<(u64, u64)>,
+}
+
+impl SoftVmcs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_writable_exit_information(&mut self, writable: bool) {
+        self.writable_exit_information = writable;
+    }
+
+    fn width_mask(field: u64) -> u64 {
+        match (field >> ENCODING_WIDTH_START) & ENCODING_WIDTH_MASK {
+            ENCODING_WIDTH_16 => 0xFFFF,
+            ENCODING_WIDTH_32 => 0xFFFF_FFFF,
+            _ => u64::MAX,
+        }
+    }
+
+    fn is_supported(field: u64) -> bool {
+        if (field & ENCODING_RESERVED) != 0 {
+            return false;
+        }
+        // only 64 bit fields have a high half
+        (field & ENCODING_ACCESS_HIGH) == 0
+            || ((field >> ENCODING_WIDTH_START) & ENCODING_WIDTH_MASK) == ENCODING_WIDTH_64
+    }
+
+    fn is_read_only(field: u64) -> bool {
+        ((field >> ENCODING_TYPE_START) & ENCODING_TYPE_MASK) == ENCODING_TYPE_EXIT_INFORMATION
+    }
+
+    fn fail(&mut self, error: u64) {
+        self.fields.insert(VM_INSTRUCTION_ERROR, error);
+    }
+
+    // value of a field as vmread returns it,0 for an unsupported encoding
+    pub fn get(&self, field: u64) -> u64 {
+        if !Self::is_supported(field) {
+            return 0;
+        }
+
+        let full = self
+            .fields
+            .get(&(field & !ENCODING_ACCESS_HIGH))
+            .copied()
+            .unwrap_or(0);
+        if (field & ENCODING_ACCESS_HIGH) != 0 {
+            full >> 32
+        } else {
+            full & Self::width_mask(field)
+        }
+    }
+
+    // for scripting an exit,read only fields included. not logged as a write
+    pub fn set(&mut self, field: u64, value: u64) -> Result<(), &'static str> {
+        if !Self::is_supported(field) {
+            return Err("unsupported vmcs field encoding");
+        }
+
+        let full = field & !ENCODING_ACCESS_HIGH;
+        let value = if (field & ENCODING_ACCESS_HIGH) != 0 {
+            let low = self.fields.get(&full).copied().unwrap_or(0) & 0xFFFF_FFFF;
+            low | ((value & 0xFFFF_FFFF) << 32)
+        } else {
+            value & Self::width_mask(field)
+        };
+        self.fields.insert(full, value);
+        Ok(())
+    }
+
+    pub fn writes(&self) -> &[(u64, u64)] {
+        &self.writes
+    }
+
+    pub fn take_writes(&mut self) -> Vec<(u64, u64)> {
+        core::mem::take(&mut self.writes)
+    }
+}
+
+impl VmcsAccess for SoftVmcs {
+    fn read(&mut self, field: u64) -> u64 {
+        if !Self::is_supported(field) {
+            self.fail(ERROR_UNSUPPORTED_COMPONENT);
+        }
+        self.get(field)
+    }
+
+    fn write(&mut self, field: u64, value: u64) {
+        if !Self::is_supported(field) {
+            return self.fail(ERROR_UNSUPPORTED_COMPONENT);
+        }
+        if Self::is_read_only(field) && !self.writable_exit_information {
+            return self.fail(ERROR_WRITE_READ_ONLY);
+        }
+
+        if self.set(field, value).is_err() {
+            return self.fail(ERROR_UNSUPPORTED_COMPONENT);
+        }
+        self.writes.push((field, self.get(field)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::vmcs_encoding::{
+        EPT_POINTER, EPT_POINTER_HIGH, EXIT_QUALIFICATION, GUEST_CS_SELECTOR, VM_EXIT_REASON,
+    };
+
+    #[test]
+    fn values_are_cut_to_the_field_width() {
+        let mut vmcs = SoftVmcs::new();
+
+        vmcs.write(GUEST_CS_SELECTOR, 0x1_0010);
+        vmcs.write(EPT_POINTER, 0x1234_5678_9ABC_D000);
+
+        assert_eq!(vmcs.read(GUEST_CS_SELECTOR), 0x10);
+        assert_eq!(vmcs.read(EPT_POINTER_HIGH), 0x1234_5678);
+        assert_eq!(
+            vmcs.writes(),
+            &[
+                (GUEST_CS_SELECTOR, 0x10),
+                (EPT_POINTER, 0x1234_5678_9ABC_D000)
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_information_is_read_only() {
+        let mut vmcs = SoftVmcs::new();
+        vmcs.set(EXIT_QUALIFICATION, 0x33).unwrap();
+
+        vmcs.write(EXIT_QUALIFICATION, 0x44);
+        assert_eq!(vmcs.read(EXIT_QUALIFICATION), 0x33);
+        assert_eq!(vmcs.read(VM_INSTRUCTION_ERROR), ERROR_WRITE_READ_ONLY);
+        assert!(vmcs.writes().is_empty());
+
+        vmcs.set_writable_exit_information(true);
+        vmcs.write(VM_EXIT_REASON, 10);
+        assert_eq!(vmcs.read(VM_EXIT_REASON), 10);
+    }
+}
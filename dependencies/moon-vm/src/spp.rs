@@ -0,0 +1,113 @@
+use crate::access_dirty::EPT_PAGE_SIZE;
+
+// 32 sub-pages of 128 bytes per 4kb page
+pub const SUB_PAGE_SIZE: u64 = 128;
+pub const SUB_PAGE_COUNT: u32 = 32;
+
+// write permission of sub-page i is bit 2i of a leaf,odd bits are reserved
+const SPPT_LEAF_WRITE_MASK: u64 = 0x5555_5555_5555_5555;
+
+// bit i set,sub-page i accepts writes
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct SubPageMask(pub u32);
+
+impl SubPageMask {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+
+    // sub-pages touched by [offset,offset + size) of the page
+    pub fn covering(offset: u64, size: u64) -> Self {
+        let end = offset.saturating_add(size).min(EPT_PAGE_SIZE);
+        if size == 0 || offset >= end {
+            return Self::NONE;
+        }
+
+        let first = offset / SUB_PAGE_SIZE;
+        let last = (end - 1) / SUB_PAGE_SIZE;
+        let count = last - first + 1;
+        let bits = if count >= SUB_PAGE_COUNT as u64 {
+            u32::MAX
+        } else {
+            ((1u32 << count) - 1) << first
+        };
+        Self(bits)
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn is_writable(self, offset: u64) -> bool {
+        let index = (offset % EPT_PAGE_SIZE) / SUB_PAGE_SIZE;
+        (self.0 & (1 << index)) != 0
+    }
+
+    // spread bit i to bit 2i
+    pub fn to_leaf(self) -> u64 {
+        (0..SUB_PAGE_COUNT)
+            .filter(|i| (self.0 & (1 << i)) != 0)
+            .fold(0, |leaf, i| leaf | (1 << (2 * i)))
+    }
+
+    pub fn from_leaf(leaf: u64) -> Self {
+        let leaf = leaf & SPPT_LEAF_WRITE_MASK;
+        Self(
+            (0..SUB_PAGE_COUNT)
+                .filter(|i| (leaf & (1 << (2 * i))) != 0)
+                .fold(0, |bits, i| bits | (1 << i)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_bytes_select_their_sub_page() {
+        assert_eq!(SubPageMask::covering(0, 1), SubPageMask(1));
+        assert_eq!(SubPageMask::covering(127, 1), SubPageMask(1));
+        assert_eq!(SubPageMask::covering(128, 1), SubPageMask(1 << 1));
+        assert_eq!(SubPageMask::covering(4095, 1), SubPageMask(1 << 31));
+    }
+
+    #[test]
+    fn spans_select_every_sub_page_they_touch() {
+        // the last byte of sub-page 0 and the first of sub-page 1
+        assert_eq!(SubPageMask::covering(127, 2), SubPageMask(0b11));
+        assert_eq!(SubPageMask::covering(128, 128), SubPageMask(0b10));
+        assert_eq!(SubPageMask::covering(100, 300), SubPageMask(0b1111));
+        assert_eq!(SubPageMask::covering(0, 4096), SubPageMask::ALL);
+        // cut at the end of the page
+        assert_eq!(SubPageMask::covering(3967, 0x1000), SubPageMask(0b11 << 30));
+        assert_eq!(SubPageMask::covering(u64::MAX, 2), SubPageMask::NONE);
+        assert_eq!(SubPageMask::covering(128, 0), SubPageMask::NONE);
+    }
+
+    #[test]
+    fn writable_follows_the_offset_in_the_page() {
+        let mask = SubPageMask::covering(128, 128).union(SubPageMask::covering(4095, 1));
+
+        assert!(!mask.is_writable(0));
+        assert!(!mask.is_writable(127));
+        assert!(mask.is_writable(128));
+        assert!(mask.is_writable(255));
+        assert!(!mask.is_writable(256));
+        assert!(mask.is_writable(4095));
+        // only the offset in the page matters
+        assert!(mask.is_writable(0x7000 + 128));
+    }
+
+    #[test]
+    fn leaf_spreads_to_even_bits() {
+        assert_eq!(SubPageMask(0b101).to_leaf(), 0b1_0001);
+        assert_eq!(SubPageMask::ALL.to_leaf(), SPPT_LEAF_WRITE_MASK);
+        assert_eq!(SubPageMask(1 << 31).to_leaf(), 1 << 62);
+
+        // odd bits are reserved and dropped
+        assert_eq!(SubPageMask::from_leaf(0b1_0011), SubPageMask(0b101));
+        assert_eq!(SubPageMask::from_leaf(u64::MAX), SubPageMask::ALL);
+        let mask = SubPageMask(0x8001_2345);
+        assert_eq!(SubPageMask::from_leaf(mask.to_leaf()), mask);
+    }
+}
@@ -0,0 +1,29 @@
+// attrib of a segment from its raw gdt descriptor,0 for a null selector
+pub fn segment_attributes(descriptor: u64) -> u16 {
+    (((descriptor >> 40) & 0xFF) | (((descriptor >> 52) & 0xF) << 8)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_of_the_windows_gdt() {
+        // kernel code,l set
+        assert_eq!(segment_attributes(0x0020_9B00_0000_0000), 0x29B);
+        // kernel data,flat 4gb
+        assert_eq!(segment_attributes(0x00CF_9300_0000_FFFF), 0xC93);
+        // user code 32 and 64 bit
+        assert_eq!(segment_attributes(0x00CF_FB00_0000_FFFF), 0xCFB);
+        assert_eq!(segment_attributes(0x0020_FB00_0000_0000), 0x2FB);
+        // busy tss,the base bytes around the access byte do not leak in
+        assert_eq!(segment_attributes(0x5E00_8B8B_5000_0067), 0x08B);
+        assert_eq!(segment_attributes(0), 0);
+    }
+
+    #[test]
+    fn limit_and_base_are_dropped() {
+        assert_eq!(segment_attributes(0xFF0F_00FF_FFFF_FFFF), 0);
+        assert_eq!(segment_attributes(0x00F0_FF00_0000_0000), 0xFFF);
+    }
+}
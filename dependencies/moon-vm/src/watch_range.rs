@@ -0,0 +1,78 @@
+use crate::access_dirty::EPT_PAGE_SIZE;
+
+// the linear address of the access at physical when it is one of the watched bytes
+// [address,address + size). pages holds the frame of every virtual page of the range,a frame
+// mapped at several of them hits through the one that puts the access inside the range
+pub fn watched_linear_address(
+    address: u64,
+    size: u64,
+    pages: &[u64],
+    physical: u64,
+) -> Option<u64> {
+    let page = physical & !(EPT_PAGE_SIZE - 1);
+    let offset = physical & (EPT_PAGE_SIZE - 1);
+    let first_page = address & !(EPT_PAGE_SIZE - 1);
+    let end = address.saturating_add(size);
+
+    pages
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| **frame == page)
+        .map(|(index, _)| first_page + index as u64 * EPT_PAGE_SIZE + offset)
+        .find(|linear| *linear >= address && *linear < end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_watched_bytes_hit() {
+        let pages = [0x5000];
+
+        assert_eq!(watched_linear_address(0x1010, 0x10, &pages, 0x500F), None);
+        assert_eq!(
+            watched_linear_address(0x1010, 0x10, &pages, 0x5010),
+            Some(0x1010)
+        );
+        assert_eq!(
+            watched_linear_address(0x1010, 0x10, &pages, 0x501F),
+            Some(0x101F)
+        );
+        assert_eq!(watched_linear_address(0x1010, 0x10, &pages, 0x5020), None);
+        assert_eq!(watched_linear_address(0x1010, 0, &pages, 0x5010), None);
+        // a frame the range does not map
+        assert_eq!(watched_linear_address(0x1010, 0x10, &pages, 0x6010), None);
+    }
+
+    #[test]
+    fn ranges_crossing_a_page_follow_each_frame() {
+        let pages = [0x5000, 0x9000];
+
+        assert_eq!(watched_linear_address(0x1FF8, 0x10, &pages, 0x5FF7), None);
+        assert_eq!(
+            watched_linear_address(0x1FF8, 0x10, &pages, 0x5FF8),
+            Some(0x1FF8)
+        );
+        assert_eq!(
+            watched_linear_address(0x1FF8, 0x10, &pages, 0x9007),
+            Some(0x2007)
+        );
+        assert_eq!(watched_linear_address(0x1FF8, 0x10, &pages, 0x9008), None);
+    }
+
+    #[test]
+    fn a_frame_mapped_twice_hits_through_either_page() {
+        let pages = [0x5000, 0x5000];
+
+        assert_eq!(
+            watched_linear_address(0x1F00, 0x200, &pages, 0x5F80),
+            Some(0x1F80)
+        );
+        assert_eq!(
+            watched_linear_address(0x1F00, 0x200, &pages, 0x5050),
+            Some(0x2050)
+        );
+        assert_eq!(watched_linear_address(0x1F00, 0x200, &pages, 0x5100), None);
+    }
+}
@@ -1,6 +1,6 @@
 use cty::c_void;
 use wdk_sys::{
-    ntddk::IofCompleteRequest, IO_NO_INCREMENT, IO_STACK_LOCATION, IRP, STATUS_SUCCESS,
+    ntddk::IofCompleteRequest, IO_NO_INCREMENT, IO_STACK_LOCATION, IRP, NTSTATUS, STATUS_SUCCESS,
     STATUS_UNSUCCESSFUL,
 };
 
@@ -12,18 +12,18 @@ pub struct IoRequest {
 
 impl IoRequest {
     pub fn complete(&mut self, value: Result<usize, &'static str>) {
-        let irp = self.as_raw_mut();
-
         match value {
-            Ok(value) => {
-                irp.IoStatus.Information = value as _;
-                irp.IoStatus.__bindgen_anon_1.Status = STATUS_SUCCESS;
-            }
-            Err(_) => {
-                irp.IoStatus.Information = 0;
-                irp.IoStatus.__bindgen_anon_1.Status = STATUS_UNSUCCESSFUL;
-            }
+            Ok(value) => self.complete_status(STATUS_SUCCESS, value),
+            Err(_) => self.complete_status(STATUS_UNSUCCESSFUL, 0),
         }
+    }
+
+    // information is the number of bytes written to the output buffer
+    pub fn complete_status(&mut self, status: NTSTATUS, information: usize) {
+        let irp = self.as_raw_mut();
+
+        irp.IoStatus.Information = information as _;
+        irp.IoStatus.__bindgen_anon_1.Status = status;
 
         unsafe {
             IofCompleteRequest(irp, IO_NO_INCREMENT as _);
@@ -1,4 +1,19 @@
 use moon_log::info;
+use wdk_sys::{NTSTATUS, STATUS_BUFFER_TOO_SMALL, STATUS_INVALID_PARAMETER, STATUS_UNSUCCESSFUL};
+
+use crate::{
+    vm::{
+        code_integrity::{CodeIntegrityPolicy, CodeIntegrityRequest, CodeWriteEvent},
+        dump::LAST_VMCS_DUMP,
+        exec_breakpoint::ExecBreakpointHit,
+        exit_trace::ExitRecord,
+        spp::{SubPageMask, SubPagePermissionRequest, SubPageWrite},
+        syscall_trace::SyscallEvent,
+        vmx::{Vmm, VMM_LOCK},
+        watchpoint::{WatchEvent, WatchpointRequest},
+    },
+    __GD,
+};
 
 use super::{io_request::IoRequest, Device, DeviceOperations};
 
@@ -21,9 +36,89 @@ const METHOD_BUFFERED: u32 = 0;
 
 const IOCTL_DEVICE_IO_CONTROL_TEST: u32 =
     CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2000, METHOD_BUFFERED, 0);
+// text of the last vmcs dump taken after a vmlaunch/vm-exit failure
+const IOCTL_GET_VMCS_DUMP: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2001, METHOD_BUFFERED, 0);
+// input u32,non zero starts the lstar syscall trace
+const IOCTL_SET_SYSCALL_TRACE: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2002, METHOD_BUFFERED, 0);
+// output array of SyscallEvent
+const IOCTL_READ_SYSCALL_EVENTS: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2003, METHOD_BUFFERED, 0);
+// input CodeIntegrityRequest,output u32 number of protected pages
+const IOCTL_PROTECT_MODULE_CODE: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2004, METHOD_BUFFERED, 0);
+// input CodeIntegrityRequest,an empty name releases every module
+const IOCTL_UNPROTECT_MODULE_CODE: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2005, METHOD_BUFFERED, 0);
+// output array of CodeWriteEvent
+const IOCTL_READ_CODE_WRITE_EVENTS: u32 =
+    CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2006, METHOD_BUFFERED, 0);
+// input u64 address in the caller process or the kernel
+const IOCTL_SET_EXEC_BREAKPOINT: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2007, METHOD_BUFFERED, 0);
+// input u64 address
+const IOCTL_CLEAR_EXEC_BREAKPOINT: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2008, METHOD_BUFFERED, 0);
+// output array of ExecBreakpointHit
+const IOCTL_READ_EXEC_BREAKPOINT_HITS: u32 =
+    CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2009, METHOD_BUFFERED, 0);
+// input WatchpointRequest in the caller process or the kernel,output u32 id
+const IOCTL_ADD_WATCHPOINT: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200A, METHOD_BUFFERED, 0);
+// input u32 id
+const IOCTL_REMOVE_WATCHPOINT: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200B, METHOD_BUFFERED, 0);
+// output array of WatchEvent
+const IOCTL_READ_WATCH_EVENTS: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200C, METHOD_BUFFERED, 0);
+// input u32,non zero records every vm-exit
+const IOCTL_SET_EXIT_TRACE: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200D, METHOD_BUFFERED, 0);
+// output array of ExitRecord,a trace file is ExitTraceHeader,the field list and the records
+const IOCTL_READ_EXIT_RECORDS: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200E, METHOD_BUFFERED, 0);
+// input u32,non zero logs writes to protected sub-pages
+const IOCTL_SET_SUB_PAGE_WRITE: u32 = CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x200F, METHOD_BUFFERED, 0);
+// input SubPagePermissionRequest
+const IOCTL_SET_SUB_PAGE_PERMISSIONS: u32 =
+    CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2010, METHOD_BUFFERED, 0);
+// input u64 guest physical address
+const IOCTL_CLEAR_SUB_PAGE_PERMISSIONS: u32 =
+    CTL_CODE!(FILE_DEVICE_UNKNOWN, 0x2011, METHOD_BUFFERED, 0);
+
+// requests failing with these complete with their own status instead of unsuccessful
+const INVALID_BUFFER: &str = "invalid buffer";
+const BUFFER_TOO_SMALL: &str = "buffer too small";
+
+fn error_status(e: &'static str) -> NTSTATUS {
+    match e {
+        INVALID_BUFFER => STATUS_INVALID_PARAMETER,
+        BUFFER_TOO_SMALL => STATUS_BUFFER_TOO_SMALL,
+        _ => STATUS_UNSUCCESSFUL,
+    }
+}
+
+// the output buffer as an array of whole records
+fn output_array<'a, T>(buff: *mut T, output_data_length: u32) -> Result<&'a mut [T], &'static str> {
+    if buff.is_null() {
+        return Err(INVALID_BUFFER);
+    }
+
+    let capacity = output_data_length as usize / core::mem::size_of::<T>();
+    if capacity == 0 {
+        return Err(BUFFER_TOO_SMALL);
+    }
+
+    Ok(unsafe { core::slice::from_raw_parts_mut(buff, capacity) })
+}
 
 pub struct IoControl {}
 
+// the ioctls below the test ones drive vmx only features
+// vmx root,the write is let through once it is logged
+fn log_sub_page_write(write: &SubPageWrite) {
+    info!(
+        "CPU:{} sub-page write {:x} rip {:x}",
+        write.cpu_index, write.physical_address, write.rip
+    );
+}
+
+fn vmx_backend() -> Result<&'static mut Vmm, &'static str> {
+    let backend = unsafe { __GD.as_mut() }
+        .and_then(|gd| gd.backend())
+        .ok_or("vmm is not running")?;
+    backend.vmx().ok_or("unsupported on svm")
+}
+
 impl DeviceOperations for IoControl {
     fn create(&self, _device: &Device, request: &mut IoRequest) -> Result<(), &'static str> {
         info!("create dispatch");
@@ -48,10 +143,13 @@ impl DeviceOperations for IoControl {
 
         let code = request.control_code();
         let buff = request.system_buffer();
-        let _input_data_length = request.input_buffer_length();
-        let _output_data_length = request.output_buffer_length();
+        let input_data_length = request.input_buffer_length();
+        let output_data_length = request.output_buffer_length();
+
+        let mut ret = Ok(0);
 
-        let mut ret = 0;
+        // the device is not exclusive,requests from several threads reach the vmm one by one
+        let _lock = VMM_LOCK.write();
 
         if code == IOCTL_DEVICE_IO_CONTROL_TEST {
             info!("Test DeviceControl");
@@ -64,10 +162,179 @@ impl DeviceOperations for IoControl {
             (unsafe { &mut *out_p }).length = 1;
             (unsafe { &mut *out_p }).maximum_length = 2;
 
-            ret = core::mem::size_of::<DeviceIoTestOut>();
+            ret = Ok(core::mem::size_of::<DeviceIoTestOut>());
+        } else if code == IOCTL_GET_VMCS_DUMP {
+            ret = if buff.is_null() {
+                Err(INVALID_BUFFER)
+            } else if output_data_length == 0 {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                // a dump longer than the buffer is cut,information says how much was copied
+                Ok(LAST_VMCS_DUMP.read().as_ref().map_or(0, |dump| {
+                    let len = dump.len().min(output_data_length as usize);
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(dump.as_ptr(), buff as *mut u8, len);
+                    }
+                    len
+                }))
+            };
+        } else if code == IOCTL_SET_SYSCALL_TRACE {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u32>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let enable = unsafe { *(buff as *const u32) } != 0;
+                vmx_backend().and_then(|vmm| vmm.set_syscall_trace(enable).map(|_| 0))
+            };
+        } else if code == IOCTL_READ_SYSCALL_EVENTS {
+            ret = output_array(buff as *mut SyscallEvent, output_data_length).and_then(|events| {
+                vmx_backend().map(|vmm| {
+                    vmm.read_syscall_events(events) * core::mem::size_of::<SyscallEvent>()
+                })
+            });
+        } else if code == IOCTL_PROTECT_MODULE_CODE {
+            ret = if (input_data_length as usize) < core::mem::size_of::<CodeIntegrityRequest>()
+                || (output_data_length as usize) < core::mem::size_of::<u32>()
+            {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let input = unsafe { &*(buff as *const CodeIntegrityRequest) };
+                let policy = CodeIntegrityPolicy::from_u32(input.policy);
+                match vmx_backend() {
+                    Ok(vmm) => match (input.name(), policy) {
+                        (Some(name), Some(policy)) if !name.is_empty() => {
+                            vmm.protect_module_code(name, policy).map(|pages| {
+                                unsafe { *(buff as *mut u32) = pages as _ };
+                                core::mem::size_of::<u32>()
+                            })
+                        }
+                        _ => Err("invalid code integrity request"),
+                    },
+                    Err(e) => Err(e),
+                }
+            };
+        } else if code == IOCTL_UNPROTECT_MODULE_CODE {
+            ret = if (input_data_length as usize) < core::mem::size_of::<CodeIntegrityRequest>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let input = unsafe { &*(buff as *const CodeIntegrityRequest) };
+                match vmx_backend() {
+                    Ok(vmm) => match input.name() {
+                        Some(name) => vmm
+                            .unprotect_module_code((!name.is_empty()).then_some(name))
+                            .map(|_| 0),
+                        None => Err("invalid code integrity request"),
+                    },
+                    Err(e) => Err(e),
+                }
+            };
+        } else if code == IOCTL_READ_CODE_WRITE_EVENTS {
+            ret =
+                output_array(buff as *mut CodeWriteEvent, output_data_length).and_then(|events| {
+                    vmx_backend().map(|vmm| {
+                        vmm.read_code_write_events(events) * core::mem::size_of::<CodeWriteEvent>()
+                    })
+                });
+        } else if code == IOCTL_SET_EXEC_BREAKPOINT || code == IOCTL_CLEAR_EXEC_BREAKPOINT {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u64>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let address = unsafe { *(buff as *const u64) };
+                match vmx_backend() {
+                    // hits are only recorded,user mode reads them back
+                    Ok(vmm) if code == IOCTL_SET_EXEC_BREAKPOINT => {
+                        vmm.set_exec_breakpoint(address, None).map(|_| 0)
+                    }
+                    Ok(vmm) => vmm.clear_exec_breakpoint(address).map(|_| 0),
+                    Err(e) => Err(e),
+                }
+            };
+        } else if code == IOCTL_READ_EXEC_BREAKPOINT_HITS {
+            ret =
+                output_array(buff as *mut ExecBreakpointHit, output_data_length).and_then(|hits| {
+                    vmx_backend().map(|vmm| {
+                        vmm.read_exec_breakpoint_hits(hits)
+                            * core::mem::size_of::<ExecBreakpointHit>()
+                    })
+                });
+        } else if code == IOCTL_ADD_WATCHPOINT {
+            ret = if (input_data_length as usize) < core::mem::size_of::<WatchpointRequest>()
+                || (output_data_length as usize) < core::mem::size_of::<u32>()
+            {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let input = unsafe { *(buff as *const WatchpointRequest) };
+                match vmx_backend() {
+                    Ok(vmm) => vmm
+                        .add_watchpoint(input.address, input.size, input.access)
+                        .map(|id| {
+                            unsafe { *(buff as *mut u32) = id };
+                            core::mem::size_of::<u32>()
+                        }),
+                    Err(e) => Err(e),
+                }
+            };
+        } else if code == IOCTL_REMOVE_WATCHPOINT {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u32>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let id = unsafe { *(buff as *const u32) };
+                vmx_backend().and_then(|vmm| vmm.remove_watchpoint(id).map(|_| 0))
+            };
+        } else if code == IOCTL_READ_WATCH_EVENTS {
+            ret = output_array(buff as *mut WatchEvent, output_data_length).and_then(|events| {
+                vmx_backend()
+                    .map(|vmm| vmm.read_watch_events(events) * core::mem::size_of::<WatchEvent>())
+            });
+        } else if code == IOCTL_SET_EXIT_TRACE {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u32>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let enable = unsafe { *(buff as *const u32) } != 0;
+                vmx_backend().and_then(|vmm| vmm.set_exit_trace(enable).map(|_| 0))
+            };
+        } else if code == IOCTL_READ_EXIT_RECORDS {
+            ret = output_array(buff as *mut ExitRecord, output_data_length).and_then(|records| {
+                vmx_backend()
+                    .map(|vmm| vmm.read_exit_records(records) * core::mem::size_of::<ExitRecord>())
+            });
+        } else if code == IOCTL_SET_SUB_PAGE_WRITE {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u32>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let enable = unsafe { *(buff as *const u32) } != 0;
+                match vmx_backend() {
+                    Ok(vmm) if enable => vmm.enable_sub_page_write(log_sub_page_write).map(|_| 0),
+                    Ok(vmm) => vmm.disable_sub_page_write().map(|_| 0),
+                    Err(e) => Err(e),
+                }
+            };
+        } else if code == IOCTL_SET_SUB_PAGE_PERMISSIONS {
+            ret = if (input_data_length as usize) < core::mem::size_of::<SubPagePermissionRequest>()
+            {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let input = unsafe { *(buff as *const SubPagePermissionRequest) };
+                vmx_backend().and_then(|vmm| {
+                    vmm.set_sub_page_permissions(input.physical, SubPageMask(input.writable))
+                        .map(|_| 0)
+                })
+            };
+        } else if code == IOCTL_CLEAR_SUB_PAGE_PERMISSIONS {
+            ret = if (input_data_length as usize) < core::mem::size_of::<u64>() {
+                Err(BUFFER_TOO_SMALL)
+            } else {
+                let physical = unsafe { *(buff as *const u64) };
+                vmx_backend().and_then(|vmm| vmm.clear_sub_page_permissions(physical).map(|_| 0))
+            };
         }
 
-        request.complete(Ok(ret));
+        match ret {
+            Ok(len) => request.complete(Ok(len)),
+            Err(e) => {
+                info!("{}", e);
+                request.complete_status(error_status(e), 0);
+            }
+        }
         Ok(())
     }
 }
@@ -2,7 +2,7 @@ use moon_log::info;
 
 use crate::{
     device::{symbolic_link::SymbolicLink, Device},
-    vm::vmx::Vmm,
+    vm::{backend::HypervisorBackend, svm::Svm, vmx::Vmm},
 };
 
 #[derive(Default)]
@@ -10,6 +10,19 @@ pub struct GD {
     pub symbolic_link: Option<SymbolicLink>,
     pub device: Option<Device>,
     pub vmm: Option<Vmm>,
+    // amd cpus run this instead of vmm
+    pub svm: Option<Svm>,
+}
+
+impl GD {
+    // the running hypervisor,whichever vendor it is
+    pub fn backend(&mut self) -> Option<&mut dyn HypervisorBackend> {
+        match (self.vmm.as_mut(), self.svm.as_mut()) {
+            (Some(vmm), _) => Some(vmm),
+            (None, Some(svm)) => Some(svm),
+            (None, None) => None,
+        }
+    }
 }
 
 impl Drop for GD {
@@ -2,7 +2,7 @@ pub mod data_struct;
 
 use moon_struct::inner::PKPROCESSOR_STATE;
 // kernel inner function
-use wdk_sys::{LIST_ENTRY, PCONTEXT, PIO_STACK_LOCATION, PIRP, _EXCEPTION_RECORD};
+use wdk_sys::{LIST_ENTRY, NTSTATUS, PCONTEXT, PIO_STACK_LOCATION, PIRP, PVOID, _EXCEPTION_RECORD};
 
 /// # Safety
 ///
@@ -26,4 +26,10 @@ pub fn initialize_list_head(list_head: &mut LIST_ENTRY) {
 extern "C" {
     pub fn KeSaveStateForHibernate(state: PKPROCESSOR_STATE);
     pub fn RtlRestoreContext(ContextRecord: PCONTEXT, ExceptionRecord: *mut _EXCEPTION_RECORD);
+    pub fn ZwQuerySystemInformation(
+        SystemInformationClass: u32,
+        SystemInformation: PVOID,
+        SystemInformationLength: u32,
+        ReturnLength: *mut u32,
+    ) -> NTSTATUS;
 }
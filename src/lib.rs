@@ -31,7 +31,7 @@ use mem::global_alloc::WDKAllocator;
 #[global_allocator]
 static GLOBAL_ALLOCATOR: WDKAllocator = WDKAllocator;
 
-use vm::vmx::Vmm;
+use vm::{backend::BackendKind, config::VmmConfig, svm::Svm, vmx::Vmm};
 use wdk_sys::{
     ACCESS_MASK, DRIVER_OBJECT, IRP_MJ_MAXIMUM_FUNCTION, NTSTATUS, PCLIENT_ID, PCUNICODE_STRING,
     PDRIVER_OBJECT, PHANDLE64, POBJECT_ATTRIBUTES, STATUS_SUCCESS, STATUS_UNSUCCESSFUL,
@@ -43,7 +43,7 @@ use crate::{
     gd::GD,
     hook::inline_hook::{NtOpenProcessFn, HOOK_LIST},
     symbol::{generic::OS_INFO, get_ssdt_function_by_name},
-    vm::check::check_vmx_cpu_support,
+    vm::check::check_cpu_support,
 };
 
 static mut __GD: Option<NPP<GD>> = Option::None;
@@ -125,10 +125,21 @@ pub unsafe fn init_ioctl(driver_object: *mut _DRIVER_OBJECT) -> Result<(), InitE
                     }
                 }
 
-                gd.vmm = Some(Vmm::new());
-                match gd.vmm.as_mut().unwrap().start() {
+                match BackendKind::current() {
+                    Some(BackendKind::Svm) => gd.svm = Some(Svm::new()),
+                    _ => {
+                        let mut vmm = Vmm::new();
+                        if let Err(e) = VmmConfig::default().apply(&mut vmm) {
+                            error!("{}", e);
+                            return Err(InitError {});
+                        }
+                        gd.vmm = Some(vmm);
+                    }
+                }
+                match gd.backend().unwrap().start() {
                     Ok(_) => {}
-                    Err(_) => {
+                    Err(e) => {
+                        error!("{}", e);
                         return Err(InitError {});
                     }
                 }
@@ -155,7 +166,7 @@ pub unsafe fn init_ioctl(driver_object: *mut _DRIVER_OBJECT) -> Result<(), InitE
 /// init will change global var
 pub unsafe fn init() -> Result<(), InitError> {
     // check cpu support
-    if let Err(e) = check_vmx_cpu_support() {
+    if let Err(e) = check_cpu_support() {
         error!("{}", e);
         return Err(InitError {});
     }
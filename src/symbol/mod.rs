@@ -5,19 +5,26 @@ use alloc::ffi::CString;
 use moon_driver_utils::string::{cstr_to_rust_str, str_to_unicode_string};
 use moon_instructions::read_msr;
 use moon_struct::{
+    inner::{RTL_PROCESS_MODULES, SYSTEM_MODULE_INFORMATION},
     msr::msr_index::MSR_LSTAR,
-    pe::{ImageDosHeader, ImageExportDirectory, ImageFileHeader, ImageOptionalHeader64},
+    pe::{
+        ImageDosHeader, ImageExportDirectory, ImageFileHeader, ImageOptionalHeader64,
+        ImageSectionHeader,
+    },
 };
 use wdk_sys::{
     ntddk::{
         memcpy, strcmp, IoGetCurrentProcess, KeStackAttachProcess, KeUnstackDetachProcess,
-        MmIsAddressValid, PsLookupProcessByProcessId, RtlCompareUnicodeString,
+        MmIsAddressValid, ObfDereferenceObject, PsLookupProcessByProcessId,
+        RtlCompareUnicodeString,
     },
     KAPC_STATE, LIST_ENTRY64, NT_SUCCESS, PEPROCESS, UCHAR, _KPROCESS,
 };
 
 use generic::OS_INFO;
 
+use crate::inner::ZwQuerySystemInformation;
+
 extern "C" {
     fn PsGetProcessImageFileName(Process: PEPROCESS) -> *mut UCHAR;
 }
@@ -112,6 +119,65 @@ pub fn get_ldr_module_base_by_name(name: &str) -> *mut c_void {
     core::ptr::null_mut()
 }
 
+pub struct KernelModule {
+    pub base: u64,
+    pub size: u32,
+}
+
+// loaded driver by file name,the first module is the kernel whatever it is called
+pub fn get_kernel_module(name: &str) -> Option<KernelModule> {
+    let mut length = 0u32;
+    unsafe {
+        ZwQuerySystemInformation(
+            SYSTEM_MODULE_INFORMATION,
+            core::ptr::null_mut(),
+            0,
+            &mut length,
+        )
+    };
+    if length == 0 {
+        return None;
+    }
+
+    // the list can grow between the two calls
+    let mut buffer = alloc::vec![0u8; length as usize + 0x1000];
+    let status = unsafe {
+        ZwQuerySystemInformation(
+            SYSTEM_MODULE_INFORMATION,
+            buffer.as_mut_ptr() as _,
+            buffer.len() as _,
+            &mut length,
+        )
+    };
+    if !NT_SUCCESS(status) {
+        return None;
+    }
+
+    let modules = buffer.as_ptr() as *const RTL_PROCESS_MODULES;
+    let count = unsafe { (*modules).NumberOfModules };
+    let first = unsafe { (*modules).Modules.as_ptr() };
+
+    for i in 0..count {
+        let module = unsafe { &*first.add(i as _) };
+        let file_name = &module.FullPathName[module.OffsetToFileName as usize..];
+        let len = file_name
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(file_name.len());
+
+        if file_name[..len].eq_ignore_ascii_case(name.as_bytes())
+            || (i == 0 && name.eq_ignore_ascii_case("ntoskrnl.exe"))
+        {
+            return Some(KernelModule {
+                base: module.ImageBase,
+                size: module.ImageSize,
+            });
+        }
+    }
+
+    None
+}
+
 pub fn get_process_by_name(name: &str) -> *mut _KPROCESS {
     for i in (4..=262144).step_by(4) {
         let process = lookup_process(i as _);
@@ -124,6 +190,8 @@ pub fn get_process_by_name(name: &str) -> *mut _KPROCESS {
             if cstr_to_rust_str(cname) == name {
                 return process;
             }
+            // only the returned process keeps the reference of its lookup
+            ObfDereferenceObject(process as _);
         }
     }
 
@@ -209,6 +277,28 @@ pub unsafe fn get_module_export_address(module: *mut c_void, name: &str) -> *mut
     }
 }
 
+/// # Safety
+///
+/// dereference module point
+pub unsafe fn get_module_sections<'a>(module: *mut c_void) -> &'a [ImageSectionHeader] {
+    let dos_header = module as *mut ImageDosHeader;
+    if dos_header.is_null() {
+        return &[];
+    }
+    unsafe {
+        let file_header: *mut ImageFileHeader =
+            (module as u64 + (*dos_header).e_lfanew as u64 + core::mem::size_of::<u32>() as u64)
+                as _;
+
+        let first_section: *const ImageSectionHeader = (file_header as u64
+            + core::mem::size_of::<ImageFileHeader>() as u64
+            + (*file_header).size_of_optional_header as u64)
+            as _;
+
+        core::slice::from_raw_parts(first_section, (*file_header).number_of_sections as _)
+    }
+}
+
 pub fn get_ntdll_function_id(name: &str) -> u32 {
     let process = get_process_by_name("smss.exe");
     if process.is_null() {
@@ -3,9 +3,9 @@ use core::ffi::c_void;
 use wdk_sys::{
     ntddk::{
         IoAllocateMdl, IoFreeMdl, KeGetCurrentProcessorNumberEx, MmBuildMdlForNonPagedPool,
-        MmGetPhysicalAddress, MmProtectMdlSystemAddress,
+        MmGetPhysicalAddress, MmProbeAndLockPages, MmProtectMdlSystemAddress, MmUnlockPages,
     },
-    MDL_MAPPED_TO_SYSTEM_VA, NT_SUCCESS,
+    _LOCK_OPERATION, _MODE, MDL_MAPPED_TO_SYSTEM_VA, NT_SUCCESS, PAGE_SIZE, PMDL,
 };
 
 pub fn get_current_processor_idx() -> u32 {
@@ -49,3 +49,64 @@ pub unsafe fn protect_non_paged_memory(
 pub fn virtual_address_to_physical_address(virtual_address: *mut c_void) -> u64 {
     unsafe { MmGetPhysicalAddress(virtual_address as _).QuadPart as u64 }
 }
+
+// pages of a virtual range locked resident through an mdl,the frames stay until drop.
+// user pages keep their process alive as a zombie until then
+pub struct LockedPages {
+    mdl: PMDL,
+}
+
+impl LockedPages {
+    /// # Safety
+    ///
+    /// passive level in the process that owns a user range. MmProbeAndLockPages raises on a
+    /// range that is not mapped,the caller checks it is
+    pub unsafe fn lock(address: u64, size: u64) -> Result<Self, &'static str> {
+        let mdl = unsafe {
+            IoAllocateMdl(
+                address as _,
+                size as _,
+                false as _,
+                false as _,
+                core::ptr::null_mut(),
+            )
+        };
+        if mdl.is_null() {
+            return Err("IoAllocateMdl error");
+        }
+
+        let mode = if (address as i64) < 0 {
+            _MODE::KernelMode
+        } else {
+            _MODE::UserMode
+        };
+        unsafe { MmProbeAndLockPages(mdl, mode as _, _LOCK_OPERATION::IoReadAccess) };
+        Ok(Self { mdl })
+    }
+
+    fn page_count(&self) -> usize {
+        let offset = unsafe { (*self.mdl).ByteOffset } as u64;
+        let size = unsafe { (*self.mdl).ByteCount } as u64;
+        (offset + size).div_ceil(PAGE_SIZE as u64) as usize
+    }
+
+    // physical address of the nth page of the range
+    pub fn physical_page(&self, index: usize) -> Option<u64> {
+        if index >= self.page_count() {
+            return None;
+        }
+
+        // the pfn array follows the mdl header
+        let pfns = unsafe { self.mdl.add(1) } as *const u64;
+        Some(unsafe { *pfns.add(index) } * PAGE_SIZE as u64)
+    }
+}
+
+impl Drop for LockedPages {
+    fn drop(&mut self) {
+        unsafe {
+            MmUnlockPages(self.mdl);
+            IoFreeMdl(self.mdl);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use moon_feature::{cpu_manufacturer, CpuManufacturer};
+use moon_log::{error, info};
+use wdk_sys::ntddk::{KeRevertToUserAffinityThread, KeSetSystemAffinityThread};
+
+use super::{
+    data::vm_call::EXIT_VT,
+    ins::{VmxInstructionResult, __vmx_vmcall},
+    msr_bitmap::MsrAccess,
+    svm::{Svm, SvmVcpu},
+    svm_exit::svm_vcpu_exit,
+    svm_ins::__svm_vmmcall,
+    vmm::{vmx_vcpu_exit, Context},
+    vmx::{Vcpu, VcpuVmxState, Vmm},
+};
+
+// the hypervisor the cpu vendor decides on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Vmx,
+    Svm,
+}
+
+impl BackendKind {
+    pub fn current() -> Option<Self> {
+        match cpu_manufacturer() {
+            CpuManufacturer::INTEL => Some(BackendKind::Vmx),
+            CpuManufacturer::AMD => Some(BackendKind::Svm),
+            CpuManufacturer::UNKNOWN => None,
+        }
+    }
+}
+
+// one cpu of either vendor
+pub trait BackendVcpu {
+    fn cpu_index(&self) -> usize;
+
+    fn is_running(&self) -> bool;
+
+    // passive level on the cpu itself,continues in the guest once the launch worked
+    fn launch(&mut self, cpu_index: usize);
+
+    // on the cpu itself,back in the os with virtualization off after Ok
+    fn leave(&mut self) -> Result<(), &'static str>;
+
+    // host,called by the exit stub with the guest registers it saved. true leaves the
+    // hypervisor on this cpu
+    fn dispatch_exit(&mut self, context: &mut Context) -> bool;
+}
+
+// what the driver needs from either vendor,lifecycle,nested paging and msr interception.
+// vmx only features stay on Vmm
+pub trait HypervisorBackend {
+    fn kind(&self) -> BackendKind;
+
+    fn cpu_count(&self) -> u32;
+
+    fn vcpu(&self, index: usize) -> &dyn BackendVcpu;
+
+    fn vcpu_mut(&mut self, index: usize) -> &mut dyn BackendVcpu;
+
+    // passive level,what every cpu shares. runs before the first launch
+    fn prepare(&mut self) -> Result<(), &'static str>;
+
+    // every cpu runs the guest
+    fn started(&mut self) {}
+
+    // passive level,every cpu runs the guest after Ok
+    fn start(&mut self) -> Result<(), &'static str> {
+        self.prepare()?;
+
+        for i in 0..self.cpu_count() as usize {
+            unsafe { KeSetSystemAffinityThread(1 << i) };
+            self.vcpu_mut(i).launch(i);
+            unsafe { KeRevertToUserAffinityThread() };
+        }
+
+        if (0..self.cpu_count() as usize).any(|i| !self.vcpu(i).is_running()) {
+            return Err("hypervisor did not start on every cpu");
+        }
+
+        self.started();
+        Ok(())
+    }
+
+    // passive level,every running cpu goes back to the os
+    fn stop(&mut self) {
+        for i in 0..self.cpu_count() as usize {
+            if !self.vcpu(i).is_running() {
+                continue;
+            }
+
+            let cpu_index = self.vcpu(i).cpu_index();
+            unsafe { KeSetSystemAffinityThread(1 << cpu_index) };
+            let result = self.vcpu_mut(i).leave();
+            unsafe { KeRevertToUserAffinityThread() };
+
+            match result {
+                Ok(_) => info!("CPU:{} left the hypervisor", cpu_index),
+                Err(e) => error!("CPU:{} {}", cpu_index, e),
+            }
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        (0..self.cpu_count() as usize).any(|i| self.vcpu(i).is_running())
+    }
+
+    // eptp or n_cr3 of the identity map
+    fn nested_paging_root(&mut self) -> Option<u64>;
+
+    // drop cached guest physical translations on every running cpu
+    fn flush_nested_paging(&mut self) -> Result<(), &'static str>;
+
+    fn set_msr_intercept(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str>;
+
+    // the vmx only features,none on svm
+    fn vmx(&mut self) -> Option<&mut Vmm> {
+        None
+    }
+}
+
+impl BackendVcpu for Vcpu {
+    fn cpu_index(&self) -> usize {
+        Vcpu::cpu_index(self)
+    }
+
+    fn is_running(&self) -> bool {
+        Vcpu::is_running(self)
+    }
+
+    fn launch(&mut self, cpu_index: usize) {
+        self.set_cpu_index(cpu_index);
+        self.start_vt();
+    }
+
+    fn leave(&mut self) -> Result<(), &'static str> {
+        match __vmx_vmcall(EXIT_VT, 0, 0, 0) {
+            VmxInstructionResult::VmxSuccess => {
+                self.set_vmx_state(VcpuVmxState::VmxStateOff);
+                Ok(())
+            }
+            _ => Err("vmcall failed"),
+        }
+    }
+
+    fn dispatch_exit(&mut self, context: &mut Context) -> bool {
+        vmx_vcpu_exit(self, context)
+    }
+}
+
+impl BackendVcpu for SvmVcpu {
+    fn cpu_index(&self) -> usize {
+        SvmVcpu::cpu_index(self)
+    }
+
+    fn is_running(&self) -> bool {
+        SvmVcpu::is_running(self)
+    }
+
+    fn launch(&mut self, cpu_index: usize) {
+        self.set_cpu_index(cpu_index);
+        self.start_svm();
+    }
+
+    // the exit handler turns svm off before it returns to the os
+    fn leave(&mut self) -> Result<(), &'static str> {
+        __svm_vmmcall(EXIT_VT, 0, 0, 0);
+        if SvmVcpu::is_running(self) {
+            return Err("vmmcall did not leave svm");
+        }
+        Ok(())
+    }
+
+    fn dispatch_exit(&mut self, context: &mut Context) -> bool {
+        svm_vcpu_exit(self, context)
+    }
+}
+
+impl HypervisorBackend for Vmm {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Vmx
+    }
+
+    fn cpu_count(&self) -> u32 {
+        self.cpu_count
+    }
+
+    fn vcpu(&self, index: usize) -> &dyn BackendVcpu {
+        self.vcpu[index].as_ref()
+    }
+
+    fn vcpu_mut(&mut self, index: usize) -> &mut dyn BackendVcpu {
+        self.vcpu[index].as_mut()
+    }
+
+    fn prepare(&mut self) -> Result<(), &'static str> {
+        Vmm::prepare(self);
+        Ok(())
+    }
+
+    fn started(&mut self) {
+        Vmm::started(self)
+    }
+
+    fn nested_paging_root(&mut self) -> Option<u64> {
+        self.ept_state
+            .as_mut()
+            .map(|ept_state| ept_state.get_ept_pointer())
+    }
+
+    fn flush_nested_paging(&mut self) -> Result<(), &'static str> {
+        self.invept_all_cpus()
+    }
+
+    fn set_msr_intercept(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        Vmm::set_msr_intercept(self, msr, access, intercept)
+    }
+
+    fn vmx(&mut self) -> Option<&mut Vmm> {
+        Some(self)
+    }
+}
+
+impl HypervisorBackend for Svm {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Svm
+    }
+
+    fn cpu_count(&self) -> u32 {
+        self.cpu_count
+    }
+
+    fn vcpu(&self, index: usize) -> &dyn BackendVcpu {
+        self.vcpu[index].as_ref()
+    }
+
+    fn vcpu_mut(&mut self, index: usize) -> &mut dyn BackendVcpu {
+        self.vcpu[index].as_mut()
+    }
+
+    fn prepare(&mut self) -> Result<(), &'static str> {
+        Svm::prepare(self)
+    }
+
+    fn nested_paging_root(&mut self) -> Option<u64> {
+        self.npt_state.as_ref().map(|npt_state| npt_state.n_cr3())
+    }
+
+    fn flush_nested_paging(&mut self) -> Result<(), &'static str> {
+        self.flush_npt();
+        Ok(())
+    }
+
+    fn set_msr_intercept(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        Svm::set_msr_intercept(self, msr, access, intercept)
+    }
+}
@@ -1,9 +1,22 @@
 extern crate alloc;
 
-use moon_feature::{cpu_manufacturer, vmx_support, CpuManufacturer};
+use moon_feature::{
+    cpu_manufacturer, svm_nested_paging_support, svm_support, vmx_support, CpuManufacturer,
+};
 use moon_instructions::{read_msr, write_msr};
 use moon_log::info;
-use moon_struct::msr::{self, ia32_feature_control_msr, ia32_mtrr_def_type_msr};
+use moon_struct::msr::{self, amd_vm_cr_msr, ia32_feature_control_msr, ia32_mtrr_def_type_msr};
+
+use super::backend::BackendKind;
+
+// the backend for this cpu,each one checks its own vendor
+pub fn check_cpu_support() -> Result<BackendKind, &'static str> {
+    match BackendKind::current() {
+        Some(BackendKind::Vmx) => check_vmx_cpu_support().map(|_| BackendKind::Vmx),
+        Some(BackendKind::Svm) => check_svm_cpu_support().map(|_| BackendKind::Svm),
+        None => Err("Only support intel and amd cpu"),
+    }
+}
 
 pub fn check_vmx_cpu_support() -> Result<(), &'static str> {
     if cpu_manufacturer() != CpuManufacturer::INTEL {
@@ -44,3 +57,36 @@ pub fn check_vmx_cpu_support() -> Result<(), &'static str> {
 
     Ok(())
 }
+
+pub fn check_svm_cpu_support() -> Result<(), &'static str> {
+    if cpu_manufacturer() != CpuManufacturer::AMD {
+        return Err("Only support amd cpu");
+    }
+
+    if !svm_support() {
+        return Err("CPU dont support svm");
+    }
+
+    // check bios switch,efer.svme will gp with svmdis set
+    let vm_cr_msr = read_msr(msr::msr_index::MSR_VM_CR);
+
+    if (vm_cr_msr & amd_vm_cr_msr::VM_CR_SVMDIS) != 0 {
+        return Err("BIOS dont enable virtualazation");
+    }
+
+    // the guest runs on the identity map,no shadow paging
+    if !svm_nested_paging_support() {
+        return Err("CPU dont support nested paging");
+    }
+
+    // check mttr
+    let mtrr_def_type_msr = read_msr(msr::msr_index::MSR_IA32_MTRR_DEF_TYPE);
+
+    if (mtrr_def_type_msr & ia32_mtrr_def_type_msr::MTRR_ENABLE_MASK) == 0 {
+        return Err("Mtrr dynamic ranges not supported");
+    }
+
+    info!("SVM cpu check success");
+
+    Ok(())
+}
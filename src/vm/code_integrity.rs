@@ -0,0 +1,271 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::{string::String, vec::Vec};
+use moon_struct::pe::{ImageSectionHeader, IMAGE_SCN_MEM_DISCARDABLE, IMAGE_SCN_MEM_EXECUTE};
+use wdk_sys::{
+    ntddk::{MmAllocateContiguousMemory, MmFreeContiguousMemory, MmIsAddressValid},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::{
+    symbol::{get_kernel_module, get_module_sections},
+    utils::virtual_address_to_physical_address,
+};
+
+use super::{
+    data::ptee,
+    ept::{EptState, EPT_ENTRY_CODE_INTEGRITY, EPT_OVERLAY_PAGES},
+    event_ring::EventRing,
+    guest_memory::GuestMapping,
+};
+
+pub const CODE_WRITE_RING_CAPACITY: usize = 1024;
+const CODE_INTEGRITY_NAME_LEN: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CodeIntegrityPolicy {
+    // the write goes through
+    Log = 0,
+    // the write lands in a scratch page and is thrown away
+    Block = 1,
+}
+
+impl CodeIntegrityPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(CodeIntegrityPolicy::Log),
+            1 => Some(CodeIntegrityPolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+// layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CodeWriteEvent {
+    pub rip: u64,
+    pub cr3: u64,
+    // 0 when the cpu did not report one
+    pub linear_address: u64,
+    pub physical_address: u64,
+    pub cpu_index: u32,
+    pub blocked: u32,
+}
+
+// layout shared with user mode,an empty name means every protected module
+#[repr(C)]
+pub struct CodeIntegrityRequest {
+    pub policy: u32,
+    pub name: [u8; CODE_INTEGRITY_NAME_LEN],
+}
+
+impl CodeIntegrityRequest {
+    pub fn name(&self) -> Option<&str> {
+        let len = self
+            .name
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).ok()
+    }
+}
+
+pub struct ProtectedModule {
+    pub name: String,
+    pub base: u64,
+    // physical pages made read+execute only
+    pub pages: Vec<u64>,
+}
+
+// per cpu,only touched by vmx root on that cpu once created
+pub struct CodeIntegrityCpu {
+    pub ring: EventRing<CodeWriteEvent>,
+    // one page for each page a blocked write can cross
+    scratch: *mut u8,
+    scratch_physical: u64,
+}
+
+pub struct CodeIntegrityMonitor {
+    policy: AtomicU32,
+    modules: Vec<ProtectedModule>,
+    cpus: Vec<CodeIntegrityCpu>,
+}
+
+// resident executable sections,discardable and pageable ones get their frames reused
+fn is_protected_section(section: &ImageSectionHeader) -> bool {
+    (section.characteristics & IMAGE_SCN_MEM_EXECUTE) != 0
+        && (section.characteristics & IMAGE_SCN_MEM_DISCARDABLE) == 0
+        && !section.name.starts_with(b"PAGE")
+}
+
+impl CodeIntegrityMonitor {
+    // passive level
+    pub fn new(cpu_count: usize, policy: CodeIntegrityPolicy) -> Result<Self, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let mut monitor = Self {
+            policy: AtomicU32::new(policy as _),
+            modules: Vec::new(),
+            cpus: Vec::with_capacity(cpu_count),
+        };
+
+        for _ in 0..cpu_count {
+            let scratch: *mut u8 = unsafe {
+                MmAllocateContiguousMemory((EPT_OVERLAY_PAGES * PAGE_SIZE as usize) as _, max_size)
+            } as _;
+            if scratch.is_null() {
+                return Err("error to allocate scratch page");
+            }
+
+            monitor.cpus.push(CodeIntegrityCpu {
+                ring: EventRing::new(CODE_WRITE_RING_CAPACITY),
+                scratch,
+                scratch_physical: virtual_address_to_physical_address(scratch as _),
+            });
+        }
+
+        Ok(monitor)
+    }
+
+    pub fn policy(&self) -> CodeIntegrityPolicy {
+        CodeIntegrityPolicy::from_u32(self.policy.load(Ordering::Relaxed))
+            .unwrap_or(CodeIntegrityPolicy::Log)
+    }
+
+    pub fn set_policy(&self, policy: CodeIntegrityPolicy) {
+        self.policy.store(policy as _, Ordering::Relaxed);
+    }
+
+    pub fn cpus(&self) -> &[CodeIntegrityCpu] {
+        &self.cpus
+    }
+
+    pub fn modules(&self) -> &[ProtectedModule] {
+        &self.modules
+    }
+
+    pub fn is_protected(entry: u64) -> bool {
+        (entry & EPT_ENTRY_CODE_INTEGRITY) != 0
+    }
+
+    // passive level,the caller flushes the ept on every cpu. returns the number of new pages
+    pub fn protect_module(
+        &mut self,
+        ept_state: &mut EptState,
+        name: &str,
+    ) -> Result<usize, &'static str> {
+        if self
+            .modules
+            .iter()
+            .any(|module| module.name.eq_ignore_ascii_case(name))
+        {
+            return Ok(0);
+        }
+
+        let module = get_kernel_module(name).ok_or("module is not loaded")?;
+        let sections = unsafe { get_module_sections(module.base as _) };
+
+        let mut pages = Vec::new();
+        for section in sections
+            .iter()
+            .filter(|section| is_protected_section(section))
+        {
+            let start = module.base + section.virtual_address as u64;
+            let end = start + section.virtual_size as u64;
+
+            for page in (start..end).step_by(PAGE_SIZE as _) {
+                if unsafe { MmIsAddressValid(page as _) } == 0 {
+                    continue;
+                }
+
+                let physical = virtual_address_to_physical_address(page as _);
+                if physical == 0 {
+                    continue;
+                }
+
+                ept_state.split_large_page(physical)?;
+                let entry = ept_state
+                    .page_entry(physical)
+                    .ok_or("page is not split to 4kb")?;
+                ept_state.set_page_entry(
+                    physical,
+                    (entry & !ptee::WRITE_ACCESS) | EPT_ENTRY_CODE_INTEGRITY,
+                )?;
+                pages.push(physical);
+            }
+        }
+
+        let count = pages.len();
+        self.modules.push(ProtectedModule {
+            name: String::from(name),
+            base: module.base,
+            pages,
+        });
+
+        Ok(count)
+    }
+
+    // passive level,none unprotects every module. returns the number of released pages
+    pub fn unprotect_module(
+        &mut self,
+        ept_state: &mut EptState,
+        name: Option<&str>,
+    ) -> Result<usize, &'static str> {
+        let mut count = 0;
+
+        let (released, kept): (Vec<ProtectedModule>, Vec<ProtectedModule>) =
+            self.modules.drain(..).partition(|module| match name {
+                Some(name) => module.name.eq_ignore_ascii_case(name),
+                None => true,
+            });
+        self.modules = kept;
+
+        for module in released {
+            for physical in module.pages {
+                if let Some(entry) = ept_state.page_entry(physical) {
+                    ept_state.set_page_entry(
+                        physical,
+                        (entry | ptee::WRITE_ACCESS) & !EPT_ENTRY_CODE_INTEGRITY,
+                    )?;
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    // vmx root,the entry the write executes against. it only goes into the overlay of this
+    // cpu,slot is the number of pages the overlay already holds
+    pub fn loosen(
+        &self,
+        cpu_index: usize,
+        slot: usize,
+        mapping: &mut GuestMapping,
+        physical: u64,
+        entry: u64,
+        block: bool,
+    ) -> u64 {
+        let entry = (entry | ptee::WRITE_ACCESS) & !EPT_ENTRY_CODE_INTEGRITY;
+        if !block {
+            return entry;
+        }
+
+        let cpu = &self.cpus[cpu_index];
+        let offset = (slot % EPT_OVERLAY_PAGES) * PAGE_SIZE as usize;
+        let source = mapping.map(physical & !0xFFF);
+        unsafe { core::ptr::copy_nonoverlapping(source, cpu.scratch.add(offset), PAGE_SIZE as _) };
+
+        EptState::page_entry_with_frame(entry, cpu.scratch_physical + offset as u64)
+    }
+}
+
+impl Drop for CodeIntegrityMonitor {
+    fn drop(&mut self) {
+        for cpu in self.cpus.iter() {
+            unsafe { MmFreeContiguousMemory(cpu.scratch as _) };
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::vec::Vec;
+
+use super::{
+    cr_access::Cr3SwitchCallback, desc_table::DescTablePolicy, msr_shadow::MsrPolicy,
+    preemption::PreemptionTimerCallback, vmx::Vmm,
+};
+
+// vmx features that can only be chosen before any cpu launches,the driver applies them
+// between Vmm::new and start. the default leaves every one of them off
+#[derive(Default)]
+pub struct VmmConfig {
+    // tsc ticks between callbacks,the same watchdog on every cpu
+    pub preemption_timer: Option<(u64, PreemptionTimerCallback)>,
+    // msrs that do not pass through,the bitmap intercepts them
+    pub msr_policies: Vec<(u32, MsrPolicy)>,
+    // mov to cr3 exits and reports every address space switch
+    pub cr3_switch_callback: Option<Cr3SwitchCallback>,
+    // lgdt,lidt and the other descriptor table instructions exit and are checked
+    pub desc_table_policy: Option<DescTablePolicy>,
+    // the ept keeps accessed and dirty flags for harvest_access_dirty
+    pub ept_access_dirty: bool,
+    // ept entries carry separate execute rights for user and supervisor addresses
+    pub mode_based_execute: bool,
+}
+
+impl VmmConfig {
+    pub fn apply(&self, vmm: &mut Vmm) -> Result<(), &'static str> {
+        if let Some((interval_tsc, callback)) = self.preemption_timer {
+            for cpu_index in 0..vmm.cpu_count as usize {
+                vmm.set_preemption_timer(cpu_index, interval_tsc, Some(callback))?;
+            }
+        }
+
+        for &(msr, policy) in &self.msr_policies {
+            vmm.set_msr_policy(msr, policy)?;
+        }
+
+        if self.cr3_switch_callback.is_some() {
+            vmm.set_cr3_switch_callback(self.cr3_switch_callback)?;
+        }
+
+        if self.desc_table_policy.is_some() {
+            vmm.set_descriptor_table_exiting(self.desc_table_policy)?;
+        }
+
+        if self.ept_access_dirty {
+            vmm.set_ept_access_dirty(true)?;
+        }
+
+        if self.mode_based_execute {
+            vmm.set_mode_based_execute(true)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+use super::{
+    data::exit_reason::{EXIT_REASON_TR_ACCESS, EXIT_REASON_XDTR_ACCESS},
+    guest_memory::GuestPageFault,
+};
+
+// system descriptor types
+pub const DESC_TYPE_LDT: u8 = 0x2;
+pub const DESC_TYPE_TSS_AVAILABLE: u8 = 0x9;
+pub const DESC_TYPE_TSS_BUSY: u8 = 0xB;
+
+// vmcs access rights
+pub const SEGMENT_AR_LONG_MODE: u64 = 1 << 13;
+pub const SEGMENT_AR_UNUSABLE: u64 = 1 << 16;
+
+// what the emulated instruction raises in the guest,selector faults carry the selector error code
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DescTableFault {
+    GeneralProtection(u16),
+    NotPresent(u16),
+    PageFault(GuestPageFault),
+}
+
+impl From<GuestPageFault> for DescTableFault {
+    fn from(fault: GuestPageFault) -> Self {
+        DescTableFault::PageFault(fault)
+    }
+}
+
+fn is_canonical(address: u64) -> bool {
+    let high = (address as i64) >> 47;
+    high == 0 || high == -1
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DescTableInstruction {
+    Sgdt,
+    Sidt,
+    Lgdt,
+    Lidt,
+    Sldt,
+    Str,
+    Lldt,
+    Ltr,
+}
+
+impl DescTableInstruction {
+    pub fn is_load(&self) -> bool {
+        matches!(
+            self,
+            DescTableInstruction::Lgdt
+                | DescTableInstruction::Lidt
+                | DescTableInstruction::Lldt
+                | DescTableInstruction::Ltr
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DescTableInstruction::Sgdt => "SGDT",
+            DescTableInstruction::Sidt => "SIDT",
+            DescTableInstruction::Lgdt => "LGDT",
+            DescTableInstruction::Lidt => "LIDT",
+            DescTableInstruction::Sldt => "SLDT",
+            DescTableInstruction::Str => "STR",
+            DescTableInstruction::Lldt => "LLDT",
+            DescTableInstruction::Ltr => "LTR",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DescTableOperand {
+    Register(u8),
+    Memory {
+        // es,cs,ss,ds,fs,gs
+        segment: u8,
+        base: Option<u8>,
+        index: Option<u8>,
+        scale: u8,
+        // 2,4 or 8 bytes
+        address_size: u8,
+    },
+}
+
+impl DescTableOperand {
+    // displacement comes sign extended in the exit qualification
+    pub fn linear_address<F: FnMut(u8) -> u64>(
+        &self,
+        displacement: u64,
+        segment_base: u64,
+        mut register: F,
+    ) -> Option<u64> {
+        match *self {
+            DescTableOperand::Register(_) => None,
+            DescTableOperand::Memory {
+                base,
+                index,
+                scale,
+                address_size,
+                ..
+            } => {
+                let mut address = displacement;
+                if let Some(base) = base {
+                    address = address.wrapping_add(register(base));
+                }
+                if let Some(index) = index {
+                    address = address.wrapping_add(register(index) << scale);
+                }
+
+                let mask = match address_size {
+                    2 => 0xFFFF,
+                    4 => 0xFFFF_FFFF,
+                    _ => u64::MAX,
+                };
+                address &= mask;
+
+                let linear = segment_base.wrapping_add(address);
+                Some(if address_size == 8 {
+                    linear
+                } else {
+                    linear & 0xFFFF_FFFF
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DescTableAccess {
+    pub instruction: DescTableInstruction,
+    pub operand: DescTableOperand,
+    // lgdt/lidt outside 64-bit mode,a 16-bit operand only loads 24 bits of base
+    pub operand_size_32: bool,
+}
+
+fn bits(value: u32, start: u32, len: u32) -> u32 {
+    (value >> start) & ((1 << len) - 1)
+}
+
+// vm-exit instruction information for exits 46 and 47
+pub fn decode_desc_table_access(
+    exit_reason: u16,
+    info: u32,
+) -> Result<DescTableAccess, &'static str> {
+    let identity = bits(info, 28, 2);
+
+    let instruction = match (exit_reason, identity) {
+        (EXIT_REASON_XDTR_ACCESS, 0) => DescTableInstruction::Sgdt,
+        (EXIT_REASON_XDTR_ACCESS, 1) => DescTableInstruction::Sidt,
+        (EXIT_REASON_XDTR_ACCESS, 2) => DescTableInstruction::Lgdt,
+        (EXIT_REASON_XDTR_ACCESS, 3) => DescTableInstruction::Lidt,
+        (EXIT_REASON_TR_ACCESS, 0) => DescTableInstruction::Sldt,
+        (EXIT_REASON_TR_ACCESS, 1) => DescTableInstruction::Str,
+        (EXIT_REASON_TR_ACCESS, 2) => DescTableInstruction::Lldt,
+        (EXIT_REASON_TR_ACCESS, 3) => DescTableInstruction::Ltr,
+        _ => return Err("not a descriptor table exit"),
+    };
+
+    // ldtr/tr instructions may use a register,gdtr/idtr always use memory
+    let operand = if exit_reason == EXIT_REASON_TR_ACCESS && bits(info, 10, 1) != 0 {
+        DescTableOperand::Register(bits(info, 3, 4) as u8)
+    } else {
+        let address_size = match bits(info, 7, 3) {
+            0 => 2,
+            1 => 4,
+            2 => 8,
+            _ => return Err("invalid address size"),
+        };
+
+        DescTableOperand::Memory {
+            segment: bits(info, 15, 3) as u8,
+            base: (bits(info, 27, 1) == 0).then(|| bits(info, 23, 4) as u8),
+            index: (bits(info, 22, 1) == 0).then(|| bits(info, 18, 4) as u8),
+            scale: bits(info, 0, 2) as u8,
+            address_size,
+        }
+    };
+
+    Ok(DescTableAccess {
+        instruction,
+        operand,
+        operand_size_32: exit_reason == EXIT_REASON_XDTR_ACCESS && bits(info, 11, 1) != 0,
+    })
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct DescriptorTableRegister {
+    pub base: u64,
+    pub limit: u16,
+}
+
+impl DescriptorTableRegister {
+    // memory image of sgdt/sidt,10 bytes in 64-bit mode and 6 otherwise
+    pub fn to_bytes(&self, long_mode: bool) -> ([u8; 10], usize) {
+        let mut bytes = [0u8; 10];
+        bytes[..2].copy_from_slice(&self.limit.to_le_bytes());
+        bytes[2..].copy_from_slice(&self.base.to_le_bytes());
+
+        (bytes, if long_mode { 10 } else { 6 })
+    }
+
+    pub fn from_bytes(bytes: &[u8; 10], long_mode: bool, operand_size_32: bool) -> Self {
+        let limit = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let mut base = u64::from_le_bytes(bytes[2..].try_into().unwrap());
+
+        if !long_mode {
+            base &= if operand_size_32 {
+                0xFFFF_FFFF
+            } else {
+                0x00FF_FFFF
+            };
+        }
+
+        Self { base, limit }
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        is_canonical(self.base)
+    }
+}
+
+// lldt/ltr only take gdt selectors,returns where the 16 byte descriptor lives
+pub fn system_descriptor_address(
+    selector: u16,
+    gdtr: &DescriptorTableRegister,
+) -> Result<u64, DescTableFault> {
+    let error_code = selector & !3;
+
+    if (selector & 4) != 0 {
+        return Err(DescTableFault::GeneralProtection(error_code));
+    }
+
+    let offset = (selector & !7) as u64;
+    if offset + 15 > gdtr.limit as u64 {
+        return Err(DescTableFault::GeneralProtection(error_code));
+    }
+
+    Ok(gdtr.base.wrapping_add(offset))
+}
+
+// 16 byte system descriptor (ldt,tss) in the gdt
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SystemDescriptor {
+    pub base: u64,
+    pub limit: u32,
+    // vmcs access rights format
+    pub access_rights: u32,
+}
+
+impl SystemDescriptor {
+    pub fn descriptor_type(&self) -> u8 {
+        (self.access_rights & 0xF) as u8
+    }
+
+    pub fn is_system(&self) -> bool {
+        (self.access_rights & (1 << 4)) == 0
+    }
+
+    pub fn is_present(&self) -> bool {
+        (self.access_rights & (1 << 7)) != 0
+    }
+
+    // type and presence checks of lldt/ltr
+    pub fn check(
+        &self,
+        instruction: DescTableInstruction,
+        selector: u16,
+    ) -> Result<(), DescTableFault> {
+        let error_code = selector & !3;
+        let expected = match instruction {
+            DescTableInstruction::Lldt => DESC_TYPE_LDT,
+            DescTableInstruction::Ltr => DESC_TYPE_TSS_AVAILABLE,
+            _ => return Err(DescTableFault::GeneralProtection(0)),
+        };
+
+        if !self.is_system() || self.descriptor_type() != expected {
+            return Err(DescTableFault::GeneralProtection(error_code));
+        }
+        if !self.is_present() {
+            return Err(DescTableFault::NotPresent(error_code));
+        }
+        if !is_canonical(self.base) {
+            return Err(DescTableFault::GeneralProtection(error_code));
+        }
+
+        Ok(())
+    }
+
+    pub fn from_raw(low: u64, high: u64) -> Self {
+        let base =
+            ((low >> 16) & 0xFF_FFFF) | (((low >> 56) & 0xFF) << 24) | ((high & 0xFFFF_FFFF) << 32);
+
+        let mut limit = ((low & 0xFFFF) | (((low >> 48) & 0xF) << 16)) as u32;
+        // granularity,limit counts 4kb pages
+        if (low & (1 << 55)) != 0 {
+            limit = (limit << 12) | 0xFFF;
+        }
+
+        Self {
+            base,
+            limit,
+            access_rights: ((low >> 40) & 0xF0FF) as u32,
+        }
+    }
+}
+
+// a load would point the guest at a different table
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DescTablePolicy {
+    // refuse with #gp instead of only reporting
+    pub block_tampering: bool,
+}
+
+pub struct DescTableGuard {
+    pub policy: DescTablePolicy,
+    pub gdtr: DescriptorTableRegister,
+    pub idtr: DescriptorTableRegister,
+    // what sidt reports instead of the real idtr
+    pub shadow_idtr: Option<DescriptorTableRegister>,
+}
+
+impl DescTableGuard {
+    pub fn new(
+        policy: DescTablePolicy,
+        gdtr: DescriptorTableRegister,
+        idtr: DescriptorTableRegister,
+    ) -> Self {
+        Self {
+            policy,
+            gdtr,
+            idtr,
+            shadow_idtr: None,
+        }
+    }
+
+    pub fn stored_idtr(&self, idtr: DescriptorTableRegister) -> DescriptorTableRegister {
+        self.shadow_idtr.unwrap_or(idtr)
+    }
+
+    // ok(true) when the table changed and the change is allowed
+    fn load(
+        policy: DescTablePolicy,
+        current: &mut DescriptorTableRegister,
+        value: DescriptorTableRegister,
+    ) -> Result<bool, &'static str> {
+        if *current == value {
+            return Ok(false);
+        }
+
+        if policy.block_tampering {
+            return Err("descriptor table change blocked");
+        }
+
+        *current = value;
+        Ok(true)
+    }
+
+    pub fn load_gdtr(&mut self, value: DescriptorTableRegister) -> Result<bool, &'static str> {
+        Self::load(self.policy, &mut self.gdtr, value)
+    }
+
+    pub fn load_idtr(&mut self, value: DescriptorTableRegister) -> Result<bool, &'static str> {
+        Self::load(self.policy, &mut self.idtr, value)
+    }
+
+    // lldt/ltr,the selector is the thing that changes
+    pub fn load_selector(&self, current: u16, value: u16) -> Result<bool, &'static str> {
+        if current == value {
+            return Ok(false);
+        }
+
+        if self.policy.block_tampering {
+            return Err("selector change blocked");
+        }
+
+        Ok(true)
+    }
+}
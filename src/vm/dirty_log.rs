@@ -0,0 +1,51 @@
+use alloc::boxed::Box;
+use wdk_sys::{
+    ntddk::{MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::utils::virtual_address_to_physical_address;
+
+pub use moon_vm::dirty_log::*;
+
+// per vcpu,the log page is only written by the cpu and drained in vmx root
+pub struct PmlLog {
+    page: *mut u64,
+    physical: u64,
+    pub dirty: DirtyBitmap,
+}
+
+impl PmlLog {
+    // passive level
+    pub fn new(ranges: &[DirtyRange]) -> Result<Box<Self>, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let page: *mut u64 = unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+        if page.is_null() {
+            return Err("error to allocate pml page");
+        }
+
+        Ok(Box::new(Self {
+            page,
+            physical: virtual_address_to_physical_address(page as _),
+            dirty: DirtyBitmap::new(ranges),
+        }))
+    }
+
+    pub fn physical_address(&self) -> u64 {
+        self.physical
+    }
+
+    // vmx root,the cpu only writes the log while the guest runs
+    pub fn drain(&self, index: u64) -> usize {
+        let entries = unsafe { &*(self.page as *const [u64; PML_ENTRY_COUNT]) };
+        drain_pml_log(entries, index, &self.dirty)
+    }
+}
+
+impl Drop for PmlLog {
+    fn drop(&mut self) {
+        unsafe { MmFreeContiguousMemory(self.page as _) };
+    }
+}
@@ -0,0 +1,29 @@
+use alloc::string::String;
+use moon_driver_utils::rwlock::ReadWriteLock;
+use moon_log::error;
+
+use super::ins::{VmxInstructionResult, __vmx_vmread};
+
+pub use moon_vm::dump::*;
+
+lazy_static! {
+    // last dump taken on a failure,read back through the device
+    pub static ref LAST_VMCS_DUMP: ReadWriteLock<Option<String>> = ReadWriteLock::new(None);
+}
+
+// dump the current vmcs to the log and keep it for user mode
+pub fn dump_current_vmcs() {
+    let dump = render_vmcs_dump(|encoding| {
+        let mut value = 0u64;
+        match __vmx_vmread(encoding, &mut value) {
+            VmxInstructionResult::VmxSuccess => Some(value),
+            _ => None,
+        }
+    });
+
+    for line in dump.lines() {
+        error!("{}", line);
+    }
+
+    *LAST_VMCS_DUMP.write() = Some(dump);
+}
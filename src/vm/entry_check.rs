@@ -0,0 +1,176 @@
+use moon_instructions::{cpuidex, read_msr};
+use moon_log::error;
+use moon_struct::msr::msr_index::{
+    MSR_IA32_VMX_CR0_FIXED0, MSR_IA32_VMX_CR0_FIXED1, MSR_IA32_VMX_CR4_FIXED0,
+    MSR_IA32_VMX_CR4_FIXED1, MSR_IA32_VMX_ENTRY_CTLS, MSR_IA32_VMX_EXIT_CTLS,
+    MSR_IA32_VMX_PINBASED_CTLS, MSR_IA32_VMX_PROCBASED_CTLS, MSR_IA32_VMX_PROCBASED_CTLS2,
+    MSR_IA32_VMX_TRUE_ENTRY_CTLS, MSR_IA32_VMX_TRUE_EXIT_CTLS, MSR_IA32_VMX_TRUE_PINBASED_CTLS,
+    MSR_IA32_VMX_TRUE_PROCBASED_CTLS,
+};
+
+use super::{
+    data::{vmcs_encoding::*, vmx_cpu_based_controls::VMX_PROC_CTLS_USE_SECONDARY_CTLS},
+    ins::vmcs_read,
+};
+
+pub use moon_vm::entry_check::*;
+
+// allowed settings of the running cpu
+pub fn read_vmx_capabilities(true_msrs: bool) -> VmxCapabilities {
+    let (pin, proc, exit, entry) = if true_msrs {
+        (
+            MSR_IA32_VMX_TRUE_PINBASED_CTLS,
+            MSR_IA32_VMX_TRUE_PROCBASED_CTLS,
+            MSR_IA32_VMX_TRUE_EXIT_CTLS,
+            MSR_IA32_VMX_TRUE_ENTRY_CTLS,
+        )
+    } else {
+        (
+            MSR_IA32_VMX_PINBASED_CTLS,
+            MSR_IA32_VMX_PROCBASED_CTLS,
+            MSR_IA32_VMX_EXIT_CTLS,
+            MSR_IA32_VMX_ENTRY_CTLS,
+        )
+    };
+
+    // the secondary msr only exists if the activate bit can be set
+    let proc_controls = read_msr(proc);
+    let proc2_controls = if ((proc_controls >> 32) & VMX_PROC_CTLS_USE_SECONDARY_CTLS as u64) != 0 {
+        read_msr(MSR_IA32_VMX_PROCBASED_CTLS2)
+    } else {
+        0
+    };
+
+    VmxCapabilities {
+        pin_controls: read_msr(pin),
+        proc_controls,
+        proc2_controls,
+        exit_controls: read_msr(exit),
+        entry_controls: read_msr(entry),
+        cr0_fixed0: read_msr(MSR_IA32_VMX_CR0_FIXED0),
+        cr0_fixed1: read_msr(MSR_IA32_VMX_CR0_FIXED1),
+        cr4_fixed0: read_msr(MSR_IA32_VMX_CR4_FIXED0),
+        cr4_fixed1: read_msr(MSR_IA32_VMX_CR4_FIXED1),
+        physical_address_width: cpuidex(0x80000008, 0).eax as u8,
+    }
+}
+
+fn read_guest_segment(selector: u64, base: u64, limit: u64, access_rights: u64) -> SegmentState {
+    SegmentState {
+        selector: vmcs_read(selector) as _,
+        base: vmcs_read(base),
+        limit: vmcs_read(limit) as _,
+        access_rights: vmcs_read(access_rights) as _,
+    }
+}
+
+// read back what set_vmcs_data wrote into the current vmcs
+pub fn read_vm_entry_state(capabilities: VmxCapabilities) -> VmEntryState {
+    VmEntryState {
+        capabilities,
+
+        pin_controls: vmcs_read(PIN_BASED_VM_EXEC_CONTROL) as _,
+        proc_controls: vmcs_read(CPU_BASED_VM_EXEC_CONTROL) as _,
+        proc2_controls: vmcs_read(SECONDARY_VM_EXEC_CONTROL) as _,
+        exit_controls: vmcs_read(VM_EXIT_CONTROLS) as _,
+        entry_controls: vmcs_read(VM_ENTRY_CONTROLS) as _,
+        cr3_target_count: vmcs_read(CR3_TARGET_COUNT) as _,
+        msr_bitmap: vmcs_read(MSR_BITMAP),
+        ept_pointer: vmcs_read(EPT_POINTER),
+        vpid: vmcs_read(VIRTUAL_PROCESSOR_ID) as _,
+
+        host_cr0: vmcs_read(HOST_CR0),
+        host_cr3: vmcs_read(HOST_CR3),
+        host_cr4: vmcs_read(HOST_CR4),
+        host_cs_selector: vmcs_read(HOST_CS_SELECTOR) as _,
+        host_ss_selector: vmcs_read(HOST_SS_SELECTOR) as _,
+        host_ds_selector: vmcs_read(HOST_DS_SELECTOR) as _,
+        host_es_selector: vmcs_read(HOST_ES_SELECTOR) as _,
+        host_fs_selector: vmcs_read(HOST_FS_SELECTOR) as _,
+        host_gs_selector: vmcs_read(HOST_GS_SELECTOR) as _,
+        host_tr_selector: vmcs_read(HOST_TR_SELECTOR) as _,
+        host_fs_base: vmcs_read(HOST_FS_BASE),
+        host_gs_base: vmcs_read(HOST_GS_BASE),
+        host_tr_base: vmcs_read(HOST_TR_BASE),
+        host_gdtr_base: vmcs_read(HOST_GDTR_BASE),
+        host_idtr_base: vmcs_read(HOST_IDTR_BASE),
+        host_rip: vmcs_read(HOST_RIP),
+
+        guest_cr0: vmcs_read(GUEST_CR0),
+        guest_cr3: vmcs_read(GUEST_CR3),
+        guest_cr4: vmcs_read(GUEST_CR4),
+        guest_dr7: vmcs_read(GUEST_DR7),
+        guest_rflags: vmcs_read(GUEST_RFLAGS),
+        guest_rip: vmcs_read(GUEST_RIP),
+        guest_es: read_guest_segment(
+            GUEST_ES_SELECTOR,
+            GUEST_ES_BASE,
+            GUEST_ES_LIMIT,
+            GUEST_ES_AR_BYTES,
+        ),
+        guest_cs: read_guest_segment(
+            GUEST_CS_SELECTOR,
+            GUEST_CS_BASE,
+            GUEST_CS_LIMIT,
+            GUEST_CS_AR_BYTES,
+        ),
+        guest_ss: read_guest_segment(
+            GUEST_SS_SELECTOR,
+            GUEST_SS_BASE,
+            GUEST_SS_LIMIT,
+            GUEST_SS_AR_BYTES,
+        ),
+        guest_ds: read_guest_segment(
+            GUEST_DS_SELECTOR,
+            GUEST_DS_BASE,
+            GUEST_DS_LIMIT,
+            GUEST_DS_AR_BYTES,
+        ),
+        guest_fs: read_guest_segment(
+            GUEST_FS_SELECTOR,
+            GUEST_FS_BASE,
+            GUEST_FS_LIMIT,
+            GUEST_FS_AR_BYTES,
+        ),
+        guest_gs: read_guest_segment(
+            GUEST_GS_SELECTOR,
+            GUEST_GS_BASE,
+            GUEST_GS_LIMIT,
+            GUEST_GS_AR_BYTES,
+        ),
+        guest_tr: read_guest_segment(
+            GUEST_TR_SELECTOR,
+            GUEST_TR_BASE,
+            GUEST_TR_LIMIT,
+            GUEST_TR_AR_BYTES,
+        ),
+        guest_ldtr: read_guest_segment(
+            GUEST_LDTR_SELECTOR,
+            GUEST_LDTR_BASE,
+            GUEST_LDTR_LIMIT,
+            GUEST_LDTR_AR_BYTES,
+        ),
+        guest_gdtr_base: vmcs_read(GUEST_GDTR_BASE),
+        guest_gdtr_limit: vmcs_read(GUEST_GDTR_LIMIT) as _,
+        guest_idtr_base: vmcs_read(GUEST_IDTR_BASE),
+        guest_idtr_limit: vmcs_read(GUEST_IDTR_LIMIT) as _,
+        guest_activity_state: vmcs_read(GUEST_ACTIVITY_STATE) as _,
+        guest_interruptibility: vmcs_read(GUEST_INTERRUPTIBILITY_INFO) as _,
+        vmcs_link_pointer: vmcs_read(VMCS_LINK_POINTER),
+    }
+}
+
+// run the checks on the current vmcs,log every failure
+pub fn validate_current_vmcs(true_msrs: bool) -> Result<(), &'static str> {
+    let state = read_vm_entry_state(read_vmx_capabilities(true_msrs));
+
+    match check_vm_entry_state(&state) {
+        Ok(_) => Ok(()),
+        Err(failures) => {
+            for failure in failures.iter() {
+                error!("vm-entry check failed:{}", failure);
+            }
+            Err("vmcs state violates vm-entry checks")
+        }
+    }
+}
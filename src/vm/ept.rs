@@ -1,27 +1,64 @@
-use core::{ffi::c_void, mem::size_of};
+use core::{
+    ffi::c_void,
+    mem::size_of,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
 
-use alloc::collections::LinkedList;
+use alloc::{collections::LinkedList, vec::Vec};
 use moon_driver_utils::bitfield::{get_bits_value, set_bits_value};
-use moon_instructions::{bit_scan_forward64, read_msr, stosq};
 use moon_log::{error, info};
-use moon_struct::msr::{
-    ia32_mtrr_capabilities_msr, ia32_mtrr_phys_base_msr, ia32_mtrr_phys_mask_msr,
-    msr_index::{MSR_IA32_MTRR_CAPABILITIES, MSR_IA32_MTRR_PHYSBASE0, MSR_IA32_MTRR_PHYSMASK0},
-};
 use wdk_sys::{
-    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
-    LIST_ENTRY, PAGE_SIZE, PHYSICAL_ADDRESS,
+    ntddk::{memset, KeIpiGenericCall, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    LIST_ENTRY, PAGE_SIZE, PHYSICAL_ADDRESS, ULONG_PTR,
 };
 
 use crate::{
     inner::initialize_list_head,
     utils::virtual_address_to_physical_address,
-    vm::data::{
-        ept_memory_type::{MEMORY_TYPE_UNCACHEABLE, MEMORY_TYPE_WRITE_BACK},
-        ept_pointer, pml2e_2mb, pml3e, pml4e,
+    vm::{
+        access_dirty::{EptLeafEntries, EPT_ACCESSED_DIRTY, EPT_LARGE_PAGE_SIZE, EPT_PAGE_SIZE},
+        data::{
+            ept_memory_type::MEMORY_TYPE_WRITE_BACK, ept_pointer, pml2e, pml2e_2mb, ptee,
+            vm_call::INVEPT_ALL_CONTEXT,
+        },
+        identity_map::{fill_identity_map, read_mtrr_ranges, EptFormat},
+        ins::__vmx_vmcall,
     },
+    __GD,
 };
 
+pub use super::ept_violation::{EptPermissions, EPT_ACCESS_MASK};
+
+// ignored by the cpu,marks a 4kb page the code integrity monitor made read+execute only
+pub const EPT_ENTRY_CODE_INTEGRITY: u64 = 1 << 52;
+// ignored by the cpu,marks a 4kb page whose execute rights were taken for breakpoints
+pub const EPT_ENTRY_EXEC_BREAKPOINT: u64 = 1 << 53;
+pub const EPT_EXECUTE_MASK: u64 = ptee::EXECUTE_ACCESS | ptee::EXECUTE_USER_ACCESS;
+// ignored by the cpu,marks a 4kb page whose read or write rights were taken for watchpoints
+pub const EPT_ENTRY_WATCHPOINT: u64 = 1 << 54;
+// ignored by the cpu,marks a 4kb page made non-present because another view owns it
+pub const EPT_ENTRY_PROTECTED: u64 = 1 << 55;
+// ignored by the cpu,marks a 4kb page of hypervisor memory hidden from the guest
+pub const EPT_ENTRY_HIDDEN: u64 = 1 << 56;
+// ignored by the cpu,the page had its write right before sub-page permissions took it
+pub const EPT_ENTRY_SUB_PAGE_WRITABLE: u64 = 1 << 62;
+// a monitor took rights from the page,no other view may hand them back
+pub const EPT_ENTRY_MONITORED: u64 = EPT_ENTRY_CODE_INTEGRITY
+    | EPT_ENTRY_EXEC_BREAKPOINT
+    | EPT_ENTRY_WATCHPOINT
+    | EPT_ENTRY_PROTECTED
+    | EPT_ENTRY_HIDDEN
+    | EPT_ENTRY_SUB_PAGE_WRITABLE
+    | ptee::SUB_PAGE_WRITE;
+
+// split tables are never freed before the ept,the list is preallocated so vmx root can walk it
+const EPT_MAX_SPLITS: usize = 512;
+const EPT_MAX_PENDING_RESTORES: usize = 4;
+// table address bits of a non-leaf entry and of the eptp
+const EPT_TABLE_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+// an access crossing a page boundary loosens two pages
+pub const EPT_OVERLAY_PAGES: usize = 2;
+
 enum PoolAllocationIntention {
     TrackingHookedPages,
     ExecTrampoline,
@@ -55,11 +92,73 @@ pub struct VmmEptPageTable {
     dynamic_split_list: LIST_ENTRY,
 }
 
+// 4kb entries of one split 2mb page
+#[repr(C)]
+#[repr(align(0x1000))]
+pub struct VmmEptDynamicSplit {
+    pml1: [u64; 512],
+}
+
+pub struct EptSplit {
+    // physical address >> 21
+    large_page: u64,
+    table: *mut VmmEptDynamicSplit,
+}
+
+// an entry loosened for one guest instruction
+#[derive(Debug, Clone, Copy)]
+pub struct EptRestore {
+    // view the entry belongs to
+    pub eptp: u64,
+    pub physical: u64,
+    pub entry: u64,
+}
+
+// per vcpu,put back on the next monitor trap flag exit
 #[derive(Default)]
-pub struct MtrrRangeDescriptor {
-    physical_base_address: u64,
-    physical_end_address: u64,
-    memory_type: u8,
+pub struct PendingEptRestore {
+    entries: [Option<EptRestore>; EPT_MAX_PENDING_RESTORES],
+}
+
+impl PendingEptRestore {
+    pub fn push(&mut self, restore: EptRestore) -> Result<(), &'static str> {
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(slot) => {
+                *slot = Some(restore);
+                Ok(())
+            }
+            None => Err("too many pending ept restores"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| entry.is_none())
+    }
+
+    pub fn drain<F: FnMut(EptRestore)>(&mut self, mut f: F) {
+        for entry in self.entries.iter_mut() {
+            if let Some(restore) = entry.take() {
+                f(restore);
+            }
+        }
+    }
+}
+
+// ipi level,a vcpu that is not running has nothing cached
+unsafe extern "C" fn invept_every_cpu_worker(_argument: ULONG_PTR) -> ULONG_PTR {
+    let running = match __GD.as_mut().and_then(|gd| gd.vmm.as_mut()) {
+        Some(vmm) => vmm.get_current_vcpu().is_running(),
+        None => false,
+    };
+    if running {
+        __vmx_vmcall(INVEPT_ALL_CONTEXT, 0, 0, 0);
+    }
+    0
+}
+
+// passive level,every cpu drops the translations of every ept
+fn invept_every_cpu() {
+    unsafe { KeIpiGenericCall(Some(invept_every_cpu_worker), 0) };
 }
 
 #[repr(C)]
@@ -73,10 +172,11 @@ pub struct InveptDescriptor {
 pub struct EptState {
     hooked_pages_list: LIST_ENTRY,
     memory_pool_list: LinkedList<PoolTable>,
-    memory_ranges: [MtrrRangeDescriptor; 9],
-    number_of_enabled_memory_ranges: u32,
     ept_pointer: u64,
     ept_page_table: Option<*mut VmmEptPageTable>,
+    splits: Vec<EptSplit>,
+    // published length of splits,read from vmx root
+    split_count: AtomicUsize,
 }
 
 impl EptState {
@@ -88,98 +188,264 @@ impl EptState {
         self.ept_pointer
     }
 
-    fn ept_build_mtrr_map(&mut self) {
-        let mtrr_cap = read_msr(MSR_IA32_MTRR_CAPABILITIES);
-
-        let variable_range_count = get_bits_value(
-            mtrr_cap,
-            ia32_mtrr_capabilities_msr::VARIABLE_RANGE_COUNT_START,
-            ia32_mtrr_capabilities_msr::VARIABLE_RANGE_COUNT_LEN,
-        );
+    pub fn is_access_dirty_enabled(&self) -> bool {
+        (self.ept_pointer & ept_pointer::ENABLE_ACCESS_AND_DIRTY_FLAGS) != 0
+    }
 
-        for i in 0..variable_range_count {
-            let phys_base = read_msr(MSR_IA32_MTRR_PHYSBASE0 + (i * 2) as u32);
-            let phys_mask = read_msr(MSR_IA32_MTRR_PHYSMASK0 + (i * 2) as u32);
-
-            if (phys_mask & ia32_mtrr_phys_mask_msr::VALID) != 0 {
-                let descriptor =
-                    &mut self.memory_ranges[self.number_of_enabled_memory_ranges as usize];
-                self.number_of_enabled_memory_ranges += 1;
-
-                descriptor.physical_base_address = get_bits_value(
-                    phys_base,
-                    ia32_mtrr_phys_base_msr::PAGE_FRAME_NUMBER_START,
-                    ia32_mtrr_capabilities_msr::PAGE_FRAME_NUMBER_LEN,
-                ) * PAGE_SIZE as u64;
-
-                let mut number_of_bitmask = 0u32;
-
-                bit_scan_forward64(
-                    &mut number_of_bitmask,
-                    get_bits_value(
-                        phys_mask,
-                        ia32_mtrr_phys_mask_msr::PAGE_FRAME_NUMBER_START,
-                        ia32_mtrr_phys_mask_msr::PAGE_FRAME_NUMBER_LEN,
-                    ) * PAGE_SIZE as u64,
-                );
-
-                descriptor.physical_end_address =
-                    descriptor.physical_base_address + ((1u64 << number_of_bitmask) - 1);
-
-                descriptor.memory_type = get_bits_value(
-                    phys_base,
-                    ia32_mtrr_phys_base_msr::TYPE_START,
-                    ia32_mtrr_phys_base_msr::TYPE_LEN,
-                ) as u8;
-
-                if descriptor.memory_type == MEMORY_TYPE_WRITE_BACK {
-                    self.number_of_enabled_memory_ranges -= 1;
-                }
-            }
+    // the new eptp only takes effect once every vcpu loads it
+    pub fn set_access_dirty(&mut self, enable: bool) {
+        if enable {
+            self.ept_pointer |= ept_pointer::ENABLE_ACCESS_AND_DIRTY_FLAGS;
+        } else {
+            self.ept_pointer &= !ept_pointer::ENABLE_ACCESS_AND_DIRTY_FLAGS;
         }
     }
 
-    fn ept_setup_pml2_entry(&mut self, new_entry: &mut u64, pfn: u64) {
-        *new_entry = set_bits_value(
-            *new_entry,
-            pml2e_2mb::PAGE_FRAME_NUMBER_START,
-            pml2e_2mb::PAGE_FRAME_NUMBER_LEN,
-            pfn,
-        );
+    fn pml2_entry(&mut self, physical: u64) -> Option<&mut u64> {
+        let page_table = unsafe { &mut *self.ept_page_table? };
+        let index = (physical >> 21) as usize;
+        page_table.pml2.get_mut(index / 512)?.get_mut(index % 512)
+    }
+
+    // vmx root safe,splits are only appended
+    fn published_splits(&self) -> &[EptSplit] {
+        let count = self.split_count.load(Ordering::Acquire);
+        unsafe { core::slice::from_raw_parts(self.splits.as_ptr(), count) }
+    }
+
+    fn pml1_entry_ptr(&self, physical: u64) -> Option<*mut u64> {
+        let large_page = physical >> 21;
+        let split = self
+            .published_splits()
+            .iter()
+            .find(|split| split.large_page == large_page)?;
+        let index = ((physical >> 12) & 0x1FF) as usize;
+        Some(unsafe { core::ptr::addr_of_mut!((*split.table).pml1[index]) })
+    }
+
+    fn pml1_entry(&mut self, physical: u64) -> Option<&mut u64> {
+        self.pml1_entry_ptr(physical)
+            .map(|entry| unsafe { &mut *entry })
+    }
+
+    // passive level,the 2mb mapping is replaced by 512 4kb entries with the same rights.
+    // a cpu may still hold the 2mb translation,so every cpu flushes before this returns
+    pub fn split_large_page(&mut self, physical: u64) -> Result<(), &'static str> {
+        if self.pml1_entry(physical).is_some() {
+            return Ok(());
+        }
+
+        if self.splits.len() == self.splits.capacity() {
+            return Err("too many ept splits");
+        }
+
+        let large = *self
+            .pml2_entry(physical)
+            .ok_or("physical address is outside the ept")?;
+        if (large & pml2e_2mb::LARGET_PAGE) == 0 {
+            return Err("pml2 entry is not a large page");
+        }
+
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
 
-        let address_of_page = pfn * (512 * PAGE_SIZE as u64);
+        let table: *mut VmmEptDynamicSplit =
+            unsafe { MmAllocateContiguousMemory(size_of::<VmmEptDynamicSplit>() as _, max_size) }
+                as _;
+        if table.is_null() {
+            return Err("error to allocate split table memory");
+        }
 
-        if pfn == 0 {
-            *new_entry = set_bits_value(
-                *new_entry,
-                pml2e_2mb::MEMORY_TYPE_START,
-                pml2e_2mb::MEMORY_TYPE_LEN,
-                MEMORY_TYPE_UNCACHEABLE as u64,
+        let memory_type = get_bits_value(
+            large,
+            pml2e_2mb::MEMORY_TYPE_START,
+            pml2e_2mb::MEMORY_TYPE_LEN,
+        );
+        let large_page = physical >> 21;
+
+        for (i, entry) in unsafe { (*table).pml1.iter_mut() }.enumerate() {
+            let mut pml1 = large & (EPT_ACCESS_MASK | EPT_ACCESSED_DIRTY | ptee::SUPPRESS_VE);
+            pml1 = set_bits_value(
+                pml1,
+                ptee::MEMORY_TYPE_START,
+                ptee::MEMORY_TYPE_LEN,
+                memory_type,
+            );
+            pml1 = set_bits_value(
+                pml1,
+                ptee::PAGE_FRAME_NUMBER_START,
+                ptee::PAGE_FRAME_NUMBER_LEN,
+                large_page * 512 + i as u64,
             );
-            return;
+            *entry = pml1;
         }
 
-        let mut target_memory_type = MEMORY_TYPE_WRITE_BACK;
-
-        for i in 0..self.number_of_enabled_memory_ranges {
-            if address_of_page <= self.memory_ranges[i as usize].physical_end_address
-                && (address_of_page + 512 * PAGE_SIZE as u64 - 1)
-                    >= self.memory_ranges[i as usize].physical_base_address
-            {
-                // address in range
-                target_memory_type = self.memory_ranges[i as usize].memory_type as _;
-                if target_memory_type == MEMORY_TYPE_UNCACHEABLE {
-                    break;
+        self.splits.push(EptSplit { large_page, table });
+        self.split_count.store(self.splits.len(), Ordering::Release);
+
+        // the table is complete before the pde points at it
+        let mut pde = large & EPT_ACCESS_MASK;
+        pde = set_bits_value(
+            pde,
+            pml2e::PAGE_FRAME_NUMBER_START,
+            pml2e::PAGE_FRAME_NUMBER_LEN,
+            virtual_address_to_physical_address(table as _) / PAGE_SIZE as u64,
+        );
+        let pml2 = self.pml2_entry(physical).unwrap();
+        unsafe { core::ptr::write_volatile(pml2, pde) };
+        invept_every_cpu();
+
+        Ok(())
+    }
+
+    // passive level,physical pages of the root table and of every split
+    pub fn table_pages(&self) -> Vec<u64> {
+        let mut pages = Vec::new();
+        if let Some(page_table) = self.ept_page_table {
+            let physical = virtual_address_to_physical_address(page_table as _);
+            let count = size_of::<VmmEptPageTable>().div_ceil(PAGE_SIZE as usize) as u64;
+            pages.extend((0..count).map(|index| physical + index * PAGE_SIZE as u64));
+        }
+        pages.extend(
+            self.splits
+                .iter()
+                .map(|split| virtual_address_to_physical_address(split.table as _)),
+        );
+        pages
+    }
+
+    // 4kb entry of a split page
+    pub fn page_entry(&mut self, physical: u64) -> Option<u64> {
+        self.pml1_entry(physical)
+            .map(|entry| unsafe { core::ptr::read_volatile(entry) })
+    }
+
+    // returns the previous entry,the caller flushes the ept tlb
+    pub fn set_page_entry(&mut self, physical: u64, value: u64) -> Result<u64, &'static str> {
+        let entry = self
+            .pml1_entry(physical)
+            .ok_or("page is not split to 4kb")?;
+        let previous = unsafe { core::ptr::read_volatile(entry) };
+        unsafe { core::ptr::write_volatile(entry, value) };
+        Ok(previous)
+    }
+
+    // the cpu sets accessed and dirty with locked writes,so do we
+    fn atomic_entry(entry: &mut u64) -> &AtomicU64 {
+        unsafe { &*(entry as *mut u64 as *const AtomicU64) }
+    }
+
+    // passive level,every leaf entry looks written so the cpu never logs it
+    pub fn mark_all_dirty(&mut self) {
+        if let Some(page_table) = self.ept_page_table {
+            let page_table = unsafe { &mut *page_table };
+            for entry in page_table.pml2.iter_mut().flatten() {
+                if (*entry & pml2e_2mb::LARGET_PAGE) != 0 {
+                    Self::atomic_entry(entry).fetch_or(EPT_ACCESSED_DIRTY, Ordering::SeqCst);
                 }
             }
         }
 
-        *new_entry = set_bits_value(
-            *new_entry,
-            pml2e_2mb::MEMORY_TYPE_START,
-            pml2e_2mb::MEMORY_TYPE_LEN,
-            target_memory_type as u64,
+        for split in self.splits.iter() {
+            for entry in unsafe { (*split.table).pml1.iter_mut() } {
+                Self::atomic_entry(entry).fetch_or(EPT_ACCESSED_DIRTY, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // returns whether the 4kb page was dirty,the next write is logged again after an invept
+    pub fn clear_page_dirty(&mut self, physical: u64) -> Result<bool, &'static str> {
+        let entry = self
+            .pml1_entry(physical)
+            .ok_or("page is not split to 4kb")?;
+        let previous = Self::atomic_entry(entry).fetch_and(!ptee::DIRTY, Ordering::SeqCst);
+        Ok((previous & ptee::DIRTY) != 0)
+    }
+
+    // flags the cpu set in an overlay copy of the entry
+    pub fn merge_accessed_dirty(&mut self, physical: u64, entry: u64) -> Result<(), &'static str> {
+        let flags = entry & EPT_ACCESSED_DIRTY;
+        if flags != 0 && self.is_access_dirty_enabled() {
+            let entry = self
+                .pml1_entry(physical)
+                .ok_or("page is not split to 4kb")?;
+            Self::atomic_entry(entry).fetch_or(flags, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // returns whether #ve was suppressed before,the caller flushes the ept tlb
+    pub fn set_page_suppress_ve(
+        &mut self,
+        physical: u64,
+        suppress: bool,
+    ) -> Result<bool, &'static str> {
+        let entry = Self::atomic_entry(
+            self.pml1_entry(physical)
+                .ok_or("page is not split to 4kb")?,
+        );
+        let previous = if suppress {
+            entry.fetch_or(ptee::SUPPRESS_VE, Ordering::SeqCst)
+        } else {
+            entry.fetch_and(!ptee::SUPPRESS_VE, Ordering::SeqCst)
+        };
+        Ok((previous & ptee::SUPPRESS_VE) != 0)
+    }
+
+    // returns the previous entry. enabled,the page loses its own write right and the spp table
+    // decides per sub-page. disabled,the page gets back the write right it had before.
+    // the caller flushes the ept tlb
+    pub fn set_page_sub_page_write(
+        &mut self,
+        physical: u64,
+        enable: bool,
+    ) -> Result<u64, &'static str> {
+        let entry = Self::atomic_entry(
+            self.pml1_entry(physical)
+                .ok_or("page is not split to 4kb")?,
+        );
+        let update = |value: u64| {
+            if enable {
+                // selected twice,the right saved the first time stays
+                let writable = if (value & ptee::WRITE_ACCESS) != 0 {
+                    EPT_ENTRY_SUB_PAGE_WRITABLE
+                } else {
+                    value & EPT_ENTRY_SUB_PAGE_WRITABLE
+                };
+                Some((value & !ptee::WRITE_ACCESS) | ptee::SUB_PAGE_WRITE | writable)
+            } else {
+                let write = if (value & EPT_ENTRY_SUB_PAGE_WRITABLE) != 0 {
+                    ptee::WRITE_ACCESS
+                } else {
+                    0
+                };
+                Some((value & !(ptee::SUB_PAGE_WRITE | EPT_ENTRY_SUB_PAGE_WRITABLE)) | write)
+            }
+        };
+        let previous = match entry.fetch_update(Ordering::SeqCst, Ordering::SeqCst, update) {
+            Ok(value) | Err(value) => value,
+        };
+        Ok(previous)
+    }
+
+    // vmx root,the spp table can not be used for the page. it stays write protected as a whole
+    // and keeps the saved write right for set_page_sub_page_write. returns the previous entry
+    pub fn drop_page_sub_page_write(&mut self, physical: u64) -> Result<u64, &'static str> {
+        let entry = Self::atomic_entry(
+            self.pml1_entry(physical)
+                .ok_or("page is not split to 4kb")?,
         );
+        Ok(entry.fetch_and(!ptee::SUB_PAGE_WRITE, Ordering::SeqCst))
+    }
+
+    // same entry pointing at another page frame
+    pub fn page_entry_with_frame(entry: u64, physical: u64) -> u64 {
+        set_bits_value(
+            entry,
+            ptee::PAGE_FRAME_NUMBER_START,
+            ptee::PAGE_FRAME_NUMBER_LEN,
+            physical / PAGE_SIZE as u64,
+        )
     }
 
     fn ept_logical_processor_initialize(&mut self) {
@@ -214,70 +480,22 @@ impl EptState {
         initialize_list_head(unsafe { &mut (*page_table).dynamic_split_list });
 
         let page_table = unsafe { &mut (*page_table) };
-        let pml4 = &mut page_table.pml4;
-        let pml3 = &mut page_table.pml3;
-        let pml2 = &mut page_table.pml2;
-
-        // fill pml4e
-        pml4[0] |= pml4e::READ_ACCESS;
-        pml4[0] |= pml4e::WRITE_ACCESS;
-        pml4[0] |= pml4e::EXECUTE_ACCESS;
-        let pfn = virtual_address_to_physical_address(((&mut pml3[0]) as *mut u64) as *mut c_void)
-            / PAGE_SIZE as u64;
-        pml4[0] = set_bits_value(
-            pml4[0],
-            pml4e::PAGE_FRAME_NUMBER_START,
-            pml4e::PAGE_FRAME_NUMBER_LEN,
-            pfn,
-        );
-
-        // fill pml3e
-        let mut rwx_template: u64 = 0;
-        rwx_template |= pml3e::READ_ACCESS;
-        rwx_template |= pml3e::WRITE_ACCESS;
-        rwx_template |= pml3e::EXECUTE_ACCESS;
-
-        stosq(&mut pml3[0], rwx_template, pml3.len() as u64);
-
-        for i in 0..pml3.len() {
-            let pfn =
-                virtual_address_to_physical_address(((&mut pml2[i][0]) as *mut u64) as *mut c_void)
-                    / PAGE_SIZE as u64;
-            pml3[i] = set_bits_value(
-                pml3[i],
-                pml3e::PAGE_FRAME_NUMBER_START,
-                pml3e::PAGE_FRAME_NUMBER_LEN,
-                pfn,
-            );
-        }
-
-        // fill pml2e
-        let mut pml2e_template: u64 = 0;
-        pml2e_template |= pml2e_2mb::READ_ACCESS;
-        pml2e_template |= pml2e_2mb::WRITE_ACCESS;
-        pml2e_template |= pml2e_2mb::EXECUTE_ACCESS;
-        pml2e_template |= pml2e_2mb::LARGET_PAGE;
-
-        stosq(
-            (&mut pml2[0]) as *mut u64,
-            pml2e_template,
-            (pml2.len() * pml2[0].len()) as _,
+        fill_identity_map::<EptFormat>(
+            &mut page_table.pml4,
+            &mut page_table.pml3,
+            &mut page_table.pml2,
+            |entry| virtual_address_to_physical_address(entry as *mut c_void),
+            &read_mtrr_ranges(),
         );
-
-        for i in 0..pml2.len() {
-            for j in 0..pml2[0].len() {
-                let pfn = i * pml2.len() + j;
-                self.ept_setup_pml2_entry(&mut pml2[i][j], pfn as _);
-            }
-        }
     }
 
-    pub fn new() -> Self {
+    // access_dirty makes the cpu set accessed and dirty flags in the leaf entries
+    pub fn new(access_dirty: bool) -> Self {
         let mut ept_state = EptState::default();
-        ept_state.ept_build_mtrr_map();
         ept_state.ept_logical_processor_initialize();
         initialize_list_head(&mut ept_state.hooked_pages_list);
         ept_state.memory_pool_list = LinkedList::new();
+        ept_state.splits = Vec::with_capacity(EPT_MAX_SPLITS);
 
         // ept_pointer
         ept_state.ept_pointer = 0;
@@ -301,14 +519,211 @@ impl EptState {
             ept_pointer::PHYS_ADDR_LEN,
             phys_addr >> 12,
         );
+        ept_state.set_access_dirty(access_dirty);
 
         ept_state
     }
 }
 
+impl EptLeafEntries for EptState {
+    fn leaf_entry(&self, physical: u64) -> Option<(&AtomicU64, u64)> {
+        let page_table = self.ept_page_table?;
+        let index = (physical >> 21) as usize;
+        if index >= 512 * 512 {
+            return None;
+        }
+
+        let pml2 = unsafe { core::ptr::addr_of_mut!((*page_table).pml2[index / 512][index % 512]) };
+        let (entry, size) = if (unsafe { *pml2 } & pml2e_2mb::LARGET_PAGE) != 0 {
+            (pml2, EPT_LARGE_PAGE_SIZE)
+        } else {
+            (self.pml1_entry_ptr(physical)?, EPT_PAGE_SIZE)
+        };
+
+        Some((unsafe { &*(entry as *const AtomicU64) }, size))
+    }
+}
+
+// the tables on the paths to the loosened 4kb entries
+#[repr(C)]
+#[repr(align(0x1000))]
+struct EptOverlayTables {
+    pml4: [u64; 512],
+    pml3: [u64; 512],
+    pml2: [[u64; 512]; EPT_OVERLAY_PAGES],
+    pml1: [[u64; 512]; EPT_OVERLAY_PAGES],
+}
+
+// per vcpu copy of an ept where a few 4kb entries differ. every other table is shared,so
+// entries can be loosened for one instruction without the other cpus seeing them
+pub struct EptOverlay {
+    tables: *mut EptOverlayTables,
+    physical: u64,
+    // physical address >> 30 of each copied pml2,>> 21 of each copied pml1
+    pml2_slots: [Option<u64>; EPT_OVERLAY_PAGES],
+    pml1_slots: [Option<u64>; EPT_OVERLAY_PAGES],
+    pages: [Option<u64>; EPT_OVERLAY_PAGES],
+    // eptp the vcpu goes back to,set while the overlay is loaded
+    return_eptp: Option<u64>,
+}
+
+impl EptOverlay {
+    // passive level
+    pub fn new() -> Result<Self, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let tables: *mut EptOverlayTables =
+            unsafe { MmAllocateContiguousMemory(size_of::<EptOverlayTables>() as _, max_size) }
+                as _;
+        if tables.is_null() {
+            return Err("error to allocate ept overlay memory");
+        }
+
+        Ok(Self {
+            tables,
+            physical: virtual_address_to_physical_address(tables as _),
+            pml2_slots: [None; EPT_OVERLAY_PAGES],
+            pml1_slots: [None; EPT_OVERLAY_PAGES],
+            pages: [None; EPT_OVERLAY_PAGES],
+            return_eptp: None,
+        })
+    }
+
+    // passive level,hidden with the other vmm pages
+    pub fn pages(&self) -> Vec<u64> {
+        (0..size_of::<EptOverlayTables>() as u64)
+            .step_by(PAGE_SIZE as _)
+            .map(|offset| self.physical + offset)
+            .collect()
+    }
+
+    fn with_table(entry: u64, table: u64) -> u64 {
+        (entry & !EPT_TABLE_MASK) | table
+    }
+
+    fn table_physical(&self, table: *const [u64; 512]) -> u64 {
+        self.physical + (table as u64 - self.tables as u64)
+    }
+
+    // index of the slot holding key,a free slot is claimed and filled by copy
+    fn slot<F: FnOnce(usize)>(
+        slots: &mut [Option<u64>; EPT_OVERLAY_PAGES],
+        key: u64,
+        copy: F,
+    ) -> Result<usize, &'static str> {
+        if let Some(index) = slots.iter().position(|slot| *slot == Some(key)) {
+            return Ok(index);
+        }
+        let index = slots
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or("ept overlay is full")?;
+        slots[index] = Some(key);
+        copy(index);
+        Ok(index)
+    }
+
+    // vmx root,the entry of physical becomes entry in the overlay of ept_state. current is the
+    // eptp loaded now. returns the overlay eptp,the caller flushes it before loading it
+    pub fn load(
+        &mut self,
+        ept_state: &EptState,
+        current: u64,
+        physical: u64,
+        entry: u64,
+    ) -> Result<u64, &'static str> {
+        let page_table = unsafe { &*ept_state.ept_page_table.ok_or("ept is not allocated")? };
+        let large_page = physical >> 21;
+        let split = ept_state
+            .published_splits()
+            .iter()
+            .find(|split| split.large_page == large_page)
+            .ok_or("page is not split to 4kb")?;
+        let page = physical & !0xFFF;
+        if !self.pages.contains(&Some(page)) && !self.pages.contains(&None) {
+            return Err("ept overlay is full");
+        }
+
+        let tables = unsafe { &mut *self.tables };
+        if self.return_eptp.is_none() {
+            tables.pml4 = page_table.pml4;
+            tables.pml3 = page_table.pml3;
+            tables.pml4[0] = Self::with_table(tables.pml4[0], self.physical + PAGE_SIZE as u64);
+        }
+
+        let region = large_page / 512;
+        let pml2 = Self::slot(&mut self.pml2_slots, region, |index| {
+            tables.pml2[index] = page_table.pml2[region as usize]
+        })?;
+        let pml1 = Self::slot(&mut self.pml1_slots, large_page, |index| {
+            tables.pml1[index] = unsafe { (*split.table).pml1 }
+        })?;
+
+        tables.pml3[region as usize] = Self::with_table(
+            tables.pml3[region as usize],
+            self.table_physical(&tables.pml2[pml2]),
+        );
+        let pde = &mut tables.pml2[pml2][(large_page % 512) as usize];
+        *pde = Self::with_table(*pde, self.table_physical(&tables.pml1[pml1]));
+        tables.pml1[pml1][((physical >> 12) & 0x1FF) as usize] = entry;
+
+        if !self.pages.contains(&Some(page)) {
+            if let Some(slot) = self.pages.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(page);
+            }
+        }
+        self.return_eptp.get_or_insert(current);
+        Ok(Self::with_table(ept_state.ept_pointer, self.physical))
+    }
+
+    // vmx root,the eptp the overlay copies while it is loaded
+    pub fn return_eptp(&self) -> Option<u64> {
+        self.return_eptp
+    }
+
+    // vmx root,number of loosened pages while loaded
+    pub fn page_count(&self) -> usize {
+        self.pages.iter().flatten().count()
+    }
+
+    // vmx root,the eptp to load again and the loosened pages with what the cpu did to their
+    // entries. accessed and dirty flags are only set in the overlay while it is loaded
+    pub fn unload(&mut self) -> Option<(u64, [Option<(u64, u64)>; EPT_OVERLAY_PAGES])> {
+        let return_eptp = self.return_eptp.take()?;
+
+        let mut pages = [None; EPT_OVERLAY_PAGES];
+        for (slot, page) in pages.iter_mut().zip(self.pages.iter().flatten()) {
+            let pml1 = self
+                .pml1_slots
+                .iter()
+                .position(|slot| *slot == Some(page >> 21))
+                .unwrap();
+            let index = ((page >> 12) & 0x1FF) as usize;
+            let entry = unsafe { core::ptr::read_volatile(&(*self.tables).pml1[pml1][index]) };
+            *slot = Some((*page, entry));
+        }
+
+        self.pml2_slots = [None; EPT_OVERLAY_PAGES];
+        self.pml1_slots = [None; EPT_OVERLAY_PAGES];
+        self.pages = [None; EPT_OVERLAY_PAGES];
+        Some((return_eptp, pages))
+    }
+}
+
+impl Drop for EptOverlay {
+    fn drop(&mut self) {
+        unsafe { MmFreeContiguousMemory(self.tables as _) };
+    }
+}
+
 impl Drop for EptState {
     fn drop(&mut self) {
         info!("EptState Drop");
+        for split in self.splits.drain(..) {
+            unsafe { MmFreeContiguousMemory(split.table as _) };
+        }
+
         if let Some(ept_table) = self.ept_page_table {
             if !ept_table.is_null() {
                 info!("free ept data");
@@ -0,0 +1,155 @@
+use core::sync::atomic::Ordering;
+
+use alloc::{collections::BTreeSet, vec::Vec};
+use wdk_sys::{
+    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::utils::virtual_address_to_physical_address;
+
+use super::{
+    access_dirty::EptLeafEntries,
+    ept::{EptPermissions, EptState},
+    ept_violation::EptViolation,
+    ins::__vmx_vmfunc,
+};
+
+// the eptp list has 512 slots,every extra view is a full identity ept
+pub const EPT_MAX_VIEWS: usize = 8;
+
+// vm function 0,also the bit in the vm-function controls and ia32_vmx_vmfunc
+pub const VMFUNC_EPTP_SWITCHING: u32 = 0;
+pub const VMFUNC_EPTP_SWITCHING_BIT: u64 = 1 << VMFUNC_EPTP_SWITCHING;
+
+// view 0 is the default ept of the vmm,views 1.. are owned here. every view starts as the
+// same identity map and differs only where a page is overridden
+pub struct EptViews {
+    list: *mut u64,
+    list_physical: u64,
+    views: Vec<EptState>,
+    // (view,page) set through set_view_page,the only pages a violation may switch views for
+    overrides: BTreeSet<(usize, u64)>,
+    // guest code may switch views with vmfunc
+    vmfunc: bool,
+}
+
+impl EptViews {
+    // passive level
+    pub fn new(
+        default_eptp: u64,
+        count: usize,
+        access_dirty: bool,
+        vmfunc: bool,
+    ) -> Result<Self, &'static str> {
+        if !(2..=EPT_MAX_VIEWS).contains(&count) {
+            return Err("ept view count is out of range");
+        }
+
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let list: *mut u64 = unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+        if list.is_null() {
+            return Err("error to allocate eptp list");
+        }
+        unsafe { memset(list as _, 0, PAGE_SIZE as _) };
+
+        let mut ept_views = Self {
+            list,
+            list_physical: virtual_address_to_physical_address(list as _),
+            views: Vec::with_capacity(count - 1),
+            overrides: BTreeSet::new(),
+            vmfunc,
+        };
+
+        ept_views.set_list_entry(0, default_eptp);
+        for index in 1..count {
+            let mut view = EptState::new(access_dirty);
+            ept_views.set_list_entry(index, view.get_ept_pointer());
+            ept_views.views.push(view);
+        }
+
+        Ok(ept_views)
+    }
+
+    fn set_list_entry(&mut self, index: usize, eptp: u64) {
+        unsafe { core::ptr::write_volatile(self.list.add(index), eptp) };
+    }
+
+    pub fn count(&self) -> usize {
+        self.views.len() + 1
+    }
+
+    pub fn is_vmfunc_enabled(&self) -> bool {
+        self.vmfunc
+    }
+
+    pub fn list_physical_address(&self) -> u64 {
+        self.list_physical
+    }
+
+    pub fn eptp(&self, index: usize) -> Option<u64> {
+        if index >= self.count() {
+            return None;
+        }
+        Some(unsafe { core::ptr::read_volatile(self.list.add(index)) })
+    }
+
+    pub fn index_of(&self, eptp: u64) -> Option<usize> {
+        (0..self.count()).find(|index| self.eptp(*index) == Some(eptp))
+    }
+
+    // tables of views 1..,process views included
+    pub fn table_pages(&self) -> Vec<u64> {
+        self.views.iter().flat_map(EptState::table_pages).collect()
+    }
+
+    // views 1..,the default ept is not owned here
+    pub fn view_mut(&mut self, index: usize) -> Option<&mut EptState> {
+        self.views.get_mut(index.checked_sub(1)?)
+    }
+
+    // passive level,the rights of the page in the view were chosen by the caller
+    pub fn add_override(&mut self, index: usize, page: u64) {
+        if index != 0 {
+            self.overrides.insert((index, page));
+        }
+    }
+
+    // vmx root,the first view whose rights for the page cover the access. views 1.. only
+    // count for pages overridden in them,the rest of their identity map grants everything
+    pub fn view_allowing(
+        &self,
+        default: &EptState,
+        physical: u64,
+        violation: &EptViolation,
+        mode_based_execute: bool,
+    ) -> Option<usize> {
+        (0..self.count()).find(|index| {
+            let state = match index {
+                0 => default,
+                _ if self.overrides.contains(&(*index, physical)) => &self.views[index - 1],
+                _ => return false,
+            };
+            match state.leaf_entry(physical) {
+                Some((entry, _)) => violation.allowed_by(
+                    EptPermissions::from_entry(entry.load(Ordering::SeqCst)),
+                    mode_based_execute,
+                ),
+                None => false,
+            }
+        })
+    }
+}
+
+// guest side,views must be enabled with vmfunc. the cpu loads the view without an exit
+pub fn vmfunc_switch_ept_view(index: usize) {
+    __vmx_vmfunc(VMFUNC_EPTP_SWITCHING, index as _);
+}
+
+impl Drop for EptViews {
+    fn drop(&mut self) {
+        unsafe { MmFreeContiguousMemory(self.list as _) };
+    }
+}
@@ -0,0 +1 @@
+pub use moon_vm::ept_violation::*;
@@ -0,0 +1,87 @@
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use alloc::boxed::Box;
+
+// single producer ring,the producer is one cpu running with interrupts off
+pub struct EventRing<T> {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    // one drain at a time
+    draining: AtomicBool,
+    pub dropped: AtomicU64,
+    events: Box<[UnsafeCell<T>]>,
+}
+
+unsafe impl<T: Send> Sync for EventRing<T> {}
+
+impl<T: Copy + Default> EventRing<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two());
+
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            events: (0..capacity)
+                .map(|_| UnsafeCell::new(T::default()))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.head
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // full ring drops the newest event
+    pub fn push(&self, event: T) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) >= self.events.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        unsafe { *self.events[head & (self.events.len() - 1)].get() = event };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    // hand events to f until the ring is empty,an event refused by f stays queued
+    pub fn drain<F: FnMut(T) -> bool>(&self, mut f: F) -> usize {
+        if self
+            .draining
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return 0;
+        }
+
+        let mut count = 0;
+        let head = self.head.load(Ordering::Acquire);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+
+        while tail != head {
+            let event = unsafe { *self.events[tail & (self.events.len() - 1)].get() };
+            if !f(event) {
+                break;
+            }
+            tail = tail.wrapping_add(1);
+            self.tail.store(tail, Ordering::Release);
+            count += 1;
+        }
+
+        self.draining.store(false, Ordering::Release);
+        count
+    }
+}
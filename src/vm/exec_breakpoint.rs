@@ -0,0 +1,157 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+
+use super::event_ring::EventRing;
+
+pub const EXEC_BREAKPOINT_MAX: usize = 32;
+pub const EXEC_HIT_RING_CAPACITY: usize = 256;
+
+// guest general purpose registers at the breakpoint,layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GuestRegisters {
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rbx: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    // read only
+    pub cr3: u64,
+}
+
+// layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExecBreakpointHit {
+    pub address: u64,
+    pub physical_address: u64,
+    pub cpu_index: u32,
+    pub reserved: u32,
+    pub registers: GuestRegisters,
+}
+
+// vmx root,any irql of the guest. register changes are written back,a new rip resumes there
+// instead of running the instruction at the breakpoint
+pub type ExecBreakpointCallback = fn(hit: &mut ExecBreakpointHit);
+
+#[derive(Default)]
+struct ExecBreakpointSlot {
+    // vmx root reads the other fields only while set
+    active: AtomicBool,
+    address: u64,
+    // 4kb page of address
+    page: u64,
+    // execute bits of the page before the first breakpoint on it
+    execute: u64,
+    callback: Option<ExecBreakpointCallback>,
+}
+
+// slots are only reused after every cpu flushed the ept,so vmx root never sees one half written
+pub struct ExecBreakpoints {
+    slots: Vec<ExecBreakpointSlot>,
+    // per cpu,every hit is recorded before the callback runs
+    rings: Vec<EventRing<ExecBreakpointHit>>,
+}
+
+impl ExecBreakpoints {
+    pub fn new(cpu_count: usize) -> Self {
+        Self {
+            slots: (0..EXEC_BREAKPOINT_MAX)
+                .map(|_| ExecBreakpointSlot::default())
+                .collect(),
+            rings: (0..cpu_count)
+                .map(|_| EventRing::new(EXEC_HIT_RING_CAPACITY))
+                .collect(),
+        }
+    }
+
+    fn active(&self) -> impl Iterator<Item = &ExecBreakpointSlot> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.active.load(Ordering::Acquire))
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        self.active().any(|slot| slot.address == address)
+    }
+
+    // execute bits saved by another breakpoint on the page
+    pub fn page_execute(&self, page: u64) -> Option<u64> {
+        self.active()
+            .find(|slot| slot.page == page)
+            .map(|slot| slot.execute)
+    }
+
+    // passive level
+    pub fn insert(
+        &mut self,
+        address: u64,
+        page: u64,
+        execute: u64,
+        callback: Option<ExecBreakpointCallback>,
+    ) -> Result<(), &'static str> {
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| !slot.active.load(Ordering::Acquire))
+            .ok_or("too many exec breakpoints")?;
+
+        slot.address = address;
+        slot.page = page;
+        slot.execute = execute;
+        slot.callback = callback;
+        slot.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // passive level,returns the page and its saved execute bits
+    pub fn remove(&mut self, address: u64) -> Option<(u64, u64)> {
+        let slot = self.active().find(|slot| slot.address == address)?;
+        slot.active.store(false, Ordering::Release);
+        Some((slot.page, slot.execute))
+    }
+
+    // vmx root,the breakpoint at rip when rip is on the page
+    pub fn find(&self, rip: u64, page: u64) -> Option<Option<ExecBreakpointCallback>> {
+        self.active()
+            .find(|slot| slot.address == rip && slot.page == page)
+            .map(|slot| slot.callback)
+    }
+
+    pub fn record(&self, cpu_index: usize, hit: ExecBreakpointHit) {
+        if let Some(ring) = self.rings.get(cpu_index) {
+            ring.push(hit);
+        }
+    }
+
+    pub fn read_hits(&self, out: &mut [ExecBreakpointHit]) -> usize {
+        let mut count = 0;
+
+        for ring in self.rings.iter() {
+            ring.drain(|hit| {
+                if count == out.len() {
+                    return false;
+                }
+                out[count] = hit;
+                count += 1;
+                true
+            });
+        }
+
+        count
+    }
+}
@@ -0,0 +1,39 @@
+use moon_instructions::{cpuidex, read_msr, write_msr};
+
+use super::ins::{__vmx_vmwrite, vmcs_read};
+
+pub use moon_vm::exit_handler::*;
+
+// the current vmcs of this cpu
+pub struct HardwareVmcs;
+
+impl VmcsAccess for HardwareVmcs {
+    fn read(&mut self, field: u64) -> u64 {
+        vmcs_read(field)
+    }
+
+    fn write(&mut self, field: u64, value: u64) {
+        __vmx_vmwrite(field, value);
+    }
+}
+
+pub struct ProcessorCpuid;
+
+impl CpuidSource for ProcessorCpuid {
+    fn cpuid(&mut self, leaf: u32, sub_leaf: u32) -> [u32; 4] {
+        let cpuinfo = cpuidex(leaf, sub_leaf);
+        [cpuinfo.eax, cpuinfo.ebx, cpuinfo.ecx, cpuinfo.edx]
+    }
+}
+
+pub struct ProcessorMsrs;
+
+impl MsrSource for ProcessorMsrs {
+    fn read_msr(&mut self, msr: u32) -> u64 {
+        read_msr(msr)
+    }
+
+    fn write_msr(&mut self, msr: u32, value: u64) {
+        write_msr(msr, value);
+    }
+}
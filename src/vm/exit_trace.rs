@@ -0,0 +1,39 @@
+use super::event_ring::EventRing;
+
+pub use moon_vm::exit_trace::*;
+
+pub const EXIT_TRACE_RING_CAPACITY: usize = 256;
+
+// filled by the exit handler of one cpu
+pub type ExitTraceRing = EventRing<ExitRecord>;
+
+// kept until the vcpu is freed like the syscall trace
+pub struct ExitTraceCpu {
+    pub ring: ExitTraceRing,
+    enabled: bool,
+}
+
+impl ExitTraceCpu {
+    // passive level
+    pub fn new() -> Self {
+        Self {
+            ring: ExitTraceRing::new(EXIT_TRACE_RING_CAPACITY),
+            enabled: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // vmx root,called on the cpu that owns the ring
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+impl Default for ExitTraceCpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
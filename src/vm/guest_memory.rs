@@ -0,0 +1,149 @@
+use alloc::vec::Vec;
+use moon_instructions::invlpg;
+use wdk_sys::{
+    ntddk::{MmAllocateMappingAddress, MmFreeMappingAddress, MmGetVirtualForPhysical},
+    PHYSICAL_ADDRESS,
+};
+
+use crate::mem::PageTableTansform;
+
+pub use moon_vm::guest_paging::*;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+// the window pte,nx like any kernel data mapping
+const WINDOW_PRESENT: u64 = 1 << 0;
+const WINDOW_WRITE: u64 = 1 << 1;
+const WINDOW_NO_EXECUTE: u64 = 1 << 63;
+
+const GUEST_MAPPING_TAG: u32 = u32::from_le_bytes(*b"VmGm");
+
+// one page of system va reserved per cpu,vmx root points its pte at guest frames.
+// host cr3 is the system cr3,so user pages and the tables of other processes
+// are only reachable this way
+pub struct GuestMapping {
+    address: *mut u8,
+    pte: *mut u64,
+}
+
+impl GuestMapping {
+    // passive level,the pte of a reserved mapping address already exists
+    pub fn new() -> Result<Self, &'static str> {
+        let transform = PageTableTansform::new(true).map_err(|_| "page table base is not found")?;
+
+        let address =
+            unsafe { MmAllocateMappingAddress(PAGE_SIZE as _, GUEST_MAPPING_TAG) } as *mut u8;
+        if address.is_null() {
+            return Err("mapping address allocate fault");
+        }
+
+        Ok(Self {
+            address,
+            pte: transform.get_pte_address(address as u64) as *mut u64,
+        })
+    }
+
+    // the previous frame is unmapped,only one page is visible at a time
+    pub fn map(&mut self, physical: u64) -> *mut u8 {
+        unsafe {
+            core::ptr::write_volatile(
+                self.pte,
+                (physical & PAGE_FRAME_MASK) | WINDOW_PRESENT | WINDOW_WRITE | WINDOW_NO_EXECUTE,
+            );
+        }
+        invlpg(self.address as u64);
+
+        unsafe { self.address.add((physical & (PAGE_SIZE - 1)) as usize) }
+    }
+
+    fn read_u64(&mut self, physical: u64) -> Option<u64> {
+        let p = self.map(physical);
+        Some(unsafe { core::ptr::read_volatile(p as *const u64) })
+    }
+}
+
+impl Drop for GuestMapping {
+    fn drop(&mut self) {
+        unsafe { core::ptr::write_volatile(self.pte, 0) };
+        invlpg(self.address as u64);
+        unsafe { MmFreeMappingAddress(self.address as _, GUEST_MAPPING_TAG) };
+    }
+}
+
+// host owned memory only,guest frames go through GuestMapping
+pub fn physical_to_virtual(physical: u64) -> *mut u8 {
+    let address = PHYSICAL_ADDRESS {
+        QuadPart: physical as _,
+    };
+    unsafe { MmGetVirtualForPhysical(address) as _ }
+}
+
+// every page is translated before any byte is copied,so a fault leaves memory untouched
+fn guest_chunks(
+    mapping: &mut GuestMapping,
+    cr3: u64,
+    linear: u64,
+    len: usize,
+    access: GuestAccess,
+) -> Result<Vec<(u64, usize)>, GuestPageFault> {
+    let mut chunks = Vec::new();
+    let mut address = linear;
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let in_page = (PAGE_SIZE - (address & (PAGE_SIZE - 1))) as usize;
+        let size = remaining.min(in_page);
+
+        let physical = translate(cr3, address, access, |entry| mapping.read_u64(entry))?;
+
+        chunks.push((physical, size));
+        address = address.wrapping_add(size as u64);
+        remaining -= size;
+    }
+
+    Ok(chunks)
+}
+
+pub fn read_guest_memory(
+    mapping: &mut GuestMapping,
+    cr3: u64,
+    linear: u64,
+    buffer: &mut [u8],
+    access: GuestAccess,
+) -> Result<(), GuestPageFault> {
+    let access = GuestAccess {
+        write: false,
+        ..access
+    };
+
+    let mut offset = 0;
+    for (physical, size) in guest_chunks(mapping, cr3, linear, buffer.len(), access)? {
+        let p = mapping.map(physical);
+        unsafe { core::ptr::copy_nonoverlapping(p, buffer[offset..].as_mut_ptr(), size) };
+        offset += size;
+    }
+
+    Ok(())
+}
+
+pub fn write_guest_memory(
+    mapping: &mut GuestMapping,
+    cr3: u64,
+    linear: u64,
+    data: &[u8],
+    access: GuestAccess,
+) -> Result<(), GuestPageFault> {
+    let access = GuestAccess {
+        write: true,
+        ..access
+    };
+
+    let mut offset = 0;
+    for (physical, size) in guest_chunks(mapping, cr3, linear, data.len(), access)? {
+        let p = mapping.map(physical);
+        unsafe { core::ptr::copy_nonoverlapping(data[offset..].as_ptr(), p, size) };
+        offset += size;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::vec::Vec;
+use moon_struct::pe::{ImageDosHeader, ImageFileHeader, ImageOptionalHeader64};
+use wdk_sys::{
+    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::utils::virtual_address_to_physical_address;
+
+use super::{
+    data::ptee,
+    ept::{EptState, EPT_ACCESS_MASK, EPT_ENTRY_HIDDEN},
+};
+
+extern "C" {
+    // placed by the linker at the start of our own image
+    static __ImageBase: ImageDosHeader;
+}
+
+// start and end of the image this code runs from,also when it was mapped by hand
+pub fn driver_image() -> (u64, u64) {
+    unsafe {
+        let base = core::ptr::addr_of!(__ImageBase) as u64;
+        let option_header: *const ImageOptionalHeader64 = (base
+            + __ImageBase.e_lfanew as u64
+            + size_of::<u32>() as u64
+            + size_of::<ImageFileHeader>() as u64)
+            as _;
+        (base, base + (*option_header).size_of_image as u64)
+    }
+}
+
+// how the guest sees a hidden page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HiddenMode {
+    // only vmx root touches it,reads see the dummy page without an exit
+    Dummy,
+    // our guest side code still reads and writes it,every access exits
+    NonPresent,
+}
+
+// a hidden page of one view and its entry before
+struct HiddenPage {
+    view: usize,
+    physical: u64,
+    entry: u64,
+}
+
+pub struct HiddenMemory {
+    pages: Vec<HiddenPage>,
+    image_start: u64,
+    image_end: u64,
+    // zeroed,read only behind every dummy mapped page. virtual and physical
+    dummy: (*mut u8, u64),
+    // per cpu,writes of other code land here for one instruction
+    sinks: Vec<(*mut u8, u64)>,
+    denied: AtomicU64,
+}
+
+fn allocate_zeroed_page() -> Result<(*mut u8, u64), &'static str> {
+    let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+    max_size.QuadPart = i64::MAX;
+
+    let page: *mut u8 = unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+    if page.is_null() {
+        return Err("error to allocate hidden memory page");
+    }
+    unsafe { memset(page as _, 0, PAGE_SIZE as _) };
+    Ok((page, virtual_address_to_physical_address(page as _)))
+}
+
+impl HiddenMemory {
+    // passive level
+    pub fn new(cpu_count: usize) -> Result<Self, &'static str> {
+        let (image_start, image_end) = driver_image();
+        let mut hidden_memory = Self {
+            pages: Vec::new(),
+            image_start,
+            image_end,
+            dummy: allocate_zeroed_page()?,
+            sinks: Vec::with_capacity(cpu_count),
+            denied: AtomicU64::new(0),
+        };
+
+        for _ in 0..cpu_count {
+            hidden_memory.sinks.push(allocate_zeroed_page()?);
+        }
+
+        Ok(hidden_memory)
+    }
+
+    pub fn is_hidden(&self, view: usize, physical: u64) -> bool {
+        let page = physical & !(PAGE_SIZE as u64 - 1);
+        self.pages
+            .iter()
+            .any(|hidden| hidden.view == view && hidden.physical == page)
+    }
+
+    // passive level,the 4kb page must be split in the view. the caller flushes the ept
+    pub fn hide(
+        &mut self,
+        view: usize,
+        ept_state: &mut EptState,
+        physical: u64,
+        mode: HiddenMode,
+    ) -> Result<(), &'static str> {
+        let page = physical & !(PAGE_SIZE as u64 - 1);
+        if self.is_hidden(view, page) {
+            return Ok(());
+        }
+
+        let entry = ept_state
+            .page_entry(page)
+            .ok_or("page is not split to 4kb")?;
+        let hidden = match mode {
+            HiddenMode::Dummy => EptState::page_entry_with_frame(
+                (entry & !EPT_ACCESS_MASK) | ptee::READ_ACCESS,
+                self.dummy.1,
+            ),
+            HiddenMode::NonPresent => entry & !EPT_ACCESS_MASK,
+        };
+
+        self.pages.push(HiddenPage {
+            view,
+            physical: page,
+            entry,
+        });
+        ept_state.set_page_entry(page, hidden | EPT_ENTRY_HIDDEN)?;
+        Ok(())
+    }
+
+    // passive level,every page of the view gets its entry back. the caller flushes the ept
+    pub fn reveal(&mut self, view: usize, ept_state: &mut EptState) -> Result<(), &'static str> {
+        for hidden in self.pages.iter().filter(|hidden| hidden.view == view) {
+            ept_state.set_page_entry(hidden.physical, hidden.entry)?;
+        }
+        self.pages.retain(|hidden| hidden.view != view);
+        Ok(())
+    }
+
+    // pages of views that are gone
+    pub fn forget(&mut self) {
+        self.pages.clear();
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    // vmx root,our own code reaches the real page
+    pub fn is_driver_code(&self, rip: u64) -> bool {
+        (self.image_start..self.image_end).contains(&rip)
+    }
+
+    // vmx root,zeroed again for every denied access
+    pub fn sink_page(&self, cpu_index: usize) -> Option<u64> {
+        let (page, physical) = *self.sinks.get(cpu_index)?;
+        unsafe { memset(page as _, 0, PAGE_SIZE as _) };
+        Some(physical)
+    }
+
+    pub fn count_denied(&self) {
+        self.denied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn denied_count(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for HiddenMemory {
+    fn drop(&mut self) {
+        unsafe { MmFreeContiguousMemory(self.dummy.0 as _) };
+        for (page, _) in self.sinks.iter() {
+            unsafe { MmFreeContiguousMemory(*page as _) };
+        }
+    }
+}
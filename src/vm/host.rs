@@ -0,0 +1,545 @@
+use core::{arch::global_asm, mem::size_of};
+
+use alloc::vec::Vec;
+use moon_instructions::{read_cr2, read_cr3};
+use moon_log::error;
+use wdk_sys::{
+    ntddk::{memset, KeBugCheckEx, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    KERNEL_STACK_SIZE, PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::{
+    utils::{get_current_processor_idx, virtual_address_to_physical_address},
+    __GD,
+};
+
+use super::{
+    data::{
+        guest_interruptibility::{BLOCKING_BY_MOV_SS, BLOCKING_BY_NMI, BLOCKING_BY_STI},
+        interrupt_inject_info::VALID,
+        interrupt_type::INTERRUPT_NMI,
+        vector_exception::{
+            VECTOR_DOUBLE_FAULT_EXCEPTION, VECTOR_MACHINE_CHECK_EXCEPTION, VECTOR_NMI_INTERRUPT,
+        },
+        vmcs_encoding::{
+            GUEST_INTERRUPTIBILITY_INFO, GUEST_RIP, PIN_BASED_VM_EXEC_CONTROL,
+            VM_ENTRY_INTR_INFO_FIELD, VM_EXIT_REASON,
+        },
+        vmx_pin_based_controls::VMX_PIN_CTLS_VIRT_NMI,
+    },
+    ins::{__vmx_vmwrite, vmcs_read},
+    vmm::{vmx_inject_event, vmx_set_nmi_window_exiting},
+};
+
+// bugcheck code reserved for hypervisor failures
+const HYPERVISOR_ERROR: u32 = 0x00020001;
+
+// ist index in tss,1-7
+const IST_NMI: u8 = 1;
+const IST_DOUBLE_FAULT: u8 = 2;
+const IST_MACHINE_CHECK: u8 = 3;
+const IST_STACK_COUNT: usize = 3;
+
+// present,dpl 0,64-bit interrupt gate
+const IDT_INTERRUPT_GATE: u8 = 0x8E;
+// present,dpl 0,64-bit available tss
+const GDT_TSS_AVAILABLE: u8 = 0x89;
+
+global_asm!(r#"
+.section .text
+
+.macro host_isr_noerr vector
+host_isr_\vector:
+    push    0       // error code
+    push    \vector
+    jmp     host_isr_common
+.endm
+
+.macro host_isr_err vector
+host_isr_\vector:
+    push    \vector
+    jmp     host_isr_common
+.endm
+
+.macro host_isr_entry vector
+    .quad host_isr_\vector
+.endm
+
+host_isr_noerr 0
+host_isr_noerr 1
+host_isr_noerr 2
+host_isr_noerr 3
+host_isr_noerr 4
+host_isr_noerr 5
+host_isr_noerr 6
+host_isr_noerr 7
+host_isr_err   8
+host_isr_noerr 9
+host_isr_err   10
+host_isr_err   11
+host_isr_err   12
+host_isr_err   13
+host_isr_err   14
+host_isr_noerr 15
+host_isr_noerr 16
+host_isr_err   17
+host_isr_noerr 18
+host_isr_noerr 19
+host_isr_noerr 20
+host_isr_err   21
+host_isr_noerr 22
+host_isr_noerr 23
+host_isr_noerr 24
+host_isr_noerr 25
+host_isr_noerr 26
+host_isr_noerr 27
+host_isr_noerr 28
+host_isr_err   29
+host_isr_err   30
+host_isr_noerr 31
+
+// external interrupts 32-255,one stub each so the handler sees the real vector
+.altmacro
+.set host_isr_vector, 32
+.rept 224
+host_isr_noerr %host_isr_vector
+.set host_isr_vector, host_isr_vector + 1
+.endr
+.noaltmacro
+
+host_isr_common:
+    push    rax
+    push    rcx
+    push    rdx
+    push    rbx
+    push    rbp
+    push    rsi
+    push    rdi
+    push    r8
+    push    r9
+    push    r10
+    push    r11
+    push    r12
+    push    r13
+    push    r14
+    push    r15
+
+    mov rcx, rsp
+    sub rsp, 0x20
+    call {}
+    add rsp, 0x20
+
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdi
+    pop     rsi
+    pop     rbp
+    pop     rbx
+    pop     rdx
+    pop     rcx
+    pop     rax
+
+    add rsp, 0x10   // vector,error code
+    iretq
+
+.section .rdata
+.global host_isr_table
+.p2align 3
+host_isr_table:
+    .quad host_isr_0
+    .quad host_isr_1
+    .quad host_isr_2
+    .quad host_isr_3
+    .quad host_isr_4
+    .quad host_isr_5
+    .quad host_isr_6
+    .quad host_isr_7
+    .quad host_isr_8
+    .quad host_isr_9
+    .quad host_isr_10
+    .quad host_isr_11
+    .quad host_isr_12
+    .quad host_isr_13
+    .quad host_isr_14
+    .quad host_isr_15
+    .quad host_isr_16
+    .quad host_isr_17
+    .quad host_isr_18
+    .quad host_isr_19
+    .quad host_isr_20
+    .quad host_isr_21
+    .quad host_isr_22
+    .quad host_isr_23
+    .quad host_isr_24
+    .quad host_isr_25
+    .quad host_isr_26
+    .quad host_isr_27
+    .quad host_isr_28
+    .quad host_isr_29
+    .quad host_isr_30
+    .quad host_isr_31
+.altmacro
+.set host_isr_vector, 32
+.rept 224
+host_isr_entry %host_isr_vector
+.set host_isr_vector, host_isr_vector + 1
+.endr
+.noaltmacro
+"#, sym host_exception_handler);
+
+#[allow(non_upper_case_globals)]
+extern "C" {
+    // one stub per vector
+    static host_isr_table: [u64; 256];
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct HostTrapFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rcx: u64,
+    rax: u64,
+
+    vector: u64,
+    error_code: u64,
+
+    // pushed by cpu
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostFaultRecord {
+    pub valid: bool,
+    pub cpu_index: u32,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    // the exit which was being handled when the fault happened
+    pub exit_reason: u64,
+    pub guest_rip: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Default)]
+struct TaskStateSegment {
+    reserved0: u32,
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    io_map_base: u16,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    ist: u8,
+    type_attributes: u8,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IdtEntry {
+    fn new(handler: u64, selector: u16, ist: u8) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector,
+            ist: ist & 0x7,
+            type_attributes: IDT_INTERRUPT_GATE,
+            offset_middle: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+// contiguous whole pages,hiding them from the guest hides nothing the os owns
+struct HostPages {
+    address: *mut u8,
+    size: usize,
+}
+
+impl HostPages {
+    // passive level,zeroed
+    fn new(size: usize) -> Result<Self, &'static str> {
+        let size = size.next_multiple_of(PAGE_SIZE as usize);
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let address: *mut u8 = unsafe { MmAllocateContiguousMemory(size as _, max_size) } as _;
+        if address.is_null() {
+            return Err("error to allocate host tables");
+        }
+        unsafe { memset(address as _, 0, size as _) };
+
+        Ok(Self { address, size })
+    }
+
+    fn physical_pages(&self) -> impl Iterator<Item = u64> {
+        let physical = virtual_address_to_physical_address(self.address as _);
+        (0..self.size as u64)
+            .step_by(PAGE_SIZE as _)
+            .map(move |offset| physical + offset)
+    }
+}
+
+impl Drop for HostPages {
+    fn drop(&mut self) {
+        unsafe { MmFreeContiguousMemory(self.address as _) };
+    }
+}
+
+// gdt,idt and tss used while the cpu is in vmx root mode
+pub struct HostTables {
+    gdt: HostPages,
+    idt: HostPages,
+    tss: HostPages,
+    ist_stacks: HostPages,
+    pending_nmi: u32,
+    pub fault_record: HostFaultRecord,
+}
+
+impl HostTables {
+    // passive level,copy the guest gdt so the host selectors keep working,then put our tss on
+    // the tr slot
+    pub fn new(
+        guest_gdt_base: u64,
+        guest_gdt_limit: u16,
+        cs_selector: u16,
+        tr_selector: u16,
+    ) -> Result<Self, &'static str> {
+        let tr_index = (tr_selector >> 3) as usize;
+        let guest_entries = (guest_gdt_limit as usize + 1) / size_of::<u64>();
+        let gdt = HostPages::new(guest_entries.max(tr_index + 2) * size_of::<u64>())?;
+        let gdt_entries = gdt.address as *mut u64;
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                guest_gdt_base as *const u64,
+                gdt_entries,
+                guest_entries,
+            );
+        }
+
+        let ist_stacks = HostPages::new(KERNEL_STACK_SIZE as usize * IST_STACK_COUNT)?;
+
+        let mut ist = [0u64; 7];
+        for (i, top) in ist.iter_mut().take(IST_STACK_COUNT).enumerate() {
+            let stack_top = ist_stacks.address as u64 + (i as u64 + 1) * KERNEL_STACK_SIZE as u64;
+            *top = stack_top & !0xF;
+        }
+
+        let tss = HostPages::new(size_of::<TaskStateSegment>())?;
+        unsafe {
+            (tss.address as *mut TaskStateSegment).write(TaskStateSegment {
+                ist,
+                io_map_base: size_of::<TaskStateSegment>() as _,
+                ..Default::default()
+            })
+        };
+
+        let tss_base = tss.address as u64;
+        let tss_limit = size_of::<TaskStateSegment>() as u64 - 1;
+
+        // 16 byte system descriptor
+        unsafe {
+            gdt_entries.add(tr_index).write(
+                (tss_limit & 0xFFFF)
+                    | ((tss_base & 0xFFFFFF) << 16)
+                    | ((GDT_TSS_AVAILABLE as u64) << 40)
+                    | (((tss_limit >> 16) & 0xF) << 48)
+                    | (((tss_base >> 24) & 0xFF) << 56),
+            );
+            gdt_entries.add(tr_index + 1).write(tss_base >> 32);
+        }
+
+        let cs_selector = cs_selector & !3;
+        let idt = HostPages::new(256 * size_of::<IdtEntry>())?;
+        let idt_entries = idt.address as *mut IdtEntry;
+        for vector in 0..256 {
+            let handler = unsafe { host_isr_table[vector] };
+            let ist = match vector as u8 {
+                VECTOR_NMI_INTERRUPT => IST_NMI,
+                VECTOR_DOUBLE_FAULT_EXCEPTION => IST_DOUBLE_FAULT,
+                VECTOR_MACHINE_CHECK_EXCEPTION => IST_MACHINE_CHECK,
+                _ => 0,
+            };
+            unsafe {
+                idt_entries
+                    .add(vector)
+                    .write(IdtEntry::new(handler, cs_selector, ist))
+            };
+        }
+
+        Ok(Self {
+            gdt,
+            idt,
+            tss,
+            ist_stacks,
+            pending_nmi: 0,
+            fault_record: HostFaultRecord::default(),
+        })
+    }
+
+    pub fn gdt_base(&self) -> u64 {
+        self.gdt.address as _
+    }
+
+    pub fn idt_base(&self) -> u64 {
+        self.idt.address as _
+    }
+
+    pub fn tr_base(&self) -> u64 {
+        self.tss.address as _
+    }
+
+    // physical pages of the tables and the ist stacks
+    pub fn pages(&self) -> Vec<u64> {
+        [&self.gdt, &self.idt, &self.tss, &self.ist_stacks]
+            .into_iter()
+            .flat_map(HostPages::physical_pages)
+            .collect()
+    }
+
+    // an nmi hit vmx root mode or exited the guest,it is delivered once the guest can take it
+    pub fn queue_nmi(&mut self) {
+        self.pending_nmi += 1;
+    }
+
+    // called before every vm entry. with virtual nmis the nmi-window exit says when the guest
+    // can take one,without them wait for an exit that finds the guest unblocked
+    pub fn inject_pending_nmi(&mut self) {
+        if self.pending_nmi == 0 {
+            return;
+        }
+
+        if (vmcs_read(PIN_BASED_VM_EXEC_CONTROL) as u32 & VMX_PIN_CTLS_VIRT_NMI) != 0 {
+            vmx_set_nmi_window_exiting(true);
+            return;
+        }
+
+        let interruptibility = vmcs_read(GUEST_INTERRUPTIBILITY_INFO) as u32;
+        if (interruptibility & (BLOCKING_BY_STI | BLOCKING_BY_MOV_SS | BLOCKING_BY_NMI)) != 0 {
+            return;
+        }
+
+        self.inject_nmi();
+    }
+
+    // nmi-window exit,no virtual-nmi or mov ss blocking is left
+    pub fn nmi_window(&mut self) {
+        vmx_set_nmi_window_exiting(false);
+
+        if self.pending_nmi == 0 {
+            return;
+        }
+
+        // an nmi is not held off by sti,but entry may refuse to inject it inside the shadow
+        let interruptibility = vmcs_read(GUEST_INTERRUPTIBILITY_INFO) as u32;
+        if (interruptibility & BLOCKING_BY_STI) != 0 {
+            __vmx_vmwrite(
+                GUEST_INTERRUPTIBILITY_INFO,
+                (interruptibility & !BLOCKING_BY_STI) as u64,
+            );
+        }
+
+        self.inject_nmi();
+    }
+
+    fn inject_nmi(&mut self) {
+        // an event is already queued for this entry,inject_pending_nmi tries again on the next exit
+        if (vmcs_read(VM_ENTRY_INTR_INFO_FIELD) as u32 & VALID) != 0 {
+            return;
+        }
+
+        vmx_inject_event(INTERRUPT_NMI, VECTOR_NMI_INTERRUPT, 0);
+        self.pending_nmi -= 1;
+    }
+}
+
+fn current_host_tables() -> Option<&'static mut HostTables> {
+    unsafe {
+        __GD.as_mut()?
+            .vmm
+            .as_mut()?
+            .get_current_vcpu()
+            .host_tables_mut()
+    }
+}
+
+unsafe extern "C" fn host_exception_handler(frame: &mut HostTrapFrame) {
+    let tables = current_host_tables();
+
+    if frame.vector == VECTOR_NMI_INTERRUPT as u64 {
+        if let Some(tables) = tables {
+            tables.queue_nmi();
+        }
+        return;
+    }
+
+    let record = HostFaultRecord {
+        valid: true,
+        cpu_index: get_current_processor_idx(),
+        vector: frame.vector,
+        error_code: frame.error_code,
+        rip: frame.rip,
+        rsp: frame.rsp,
+        rflags: frame.rflags,
+        cr2: read_cr2(),
+        cr3: read_cr3(),
+        exit_reason: vmcs_read(VM_EXIT_REASON),
+        guest_rip: vmcs_read(GUEST_RIP),
+    };
+
+    let record_address = match tables {
+        Some(tables) => {
+            tables.fault_record = record;
+            &tables.fault_record as *const HostFaultRecord as u64
+        }
+        None => 0,
+    };
+
+    error!("vmx root fault:{:X?}", record);
+
+    // the vmm state can not be trusted anymore
+    unsafe {
+        KeBugCheckEx(
+            HYPERVISOR_ERROR,
+            record.vector,
+            record.rip,
+            record_address,
+            record.exit_reason,
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use alloc::vec::Vec;
+use moon_driver_utils::bitfield::get_bits_value;
+use moon_instructions::{bit_scan_forward64, read_msr};
+use moon_struct::msr::{
+    ia32_mtrr_capabilities_msr, ia32_mtrr_phys_base_msr, ia32_mtrr_phys_mask_msr,
+    msr_index::{MSR_IA32_MTRR_CAPABILITIES, MSR_IA32_MTRR_PHYSBASE0, MSR_IA32_MTRR_PHYSMASK0},
+};
+
+use super::data::ept_memory_type::MEMORY_TYPE_WRITE_BACK;
+
+pub use moon_vm::identity_map::*;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+// enabled variable ranges that are not write back,write back is the default type
+pub fn read_mtrr_ranges() -> Vec<MemoryRange> {
+    let mtrr_cap = read_msr(MSR_IA32_MTRR_CAPABILITIES);
+
+    let variable_range_count = get_bits_value(
+        mtrr_cap,
+        ia32_mtrr_capabilities_msr::VARIABLE_RANGE_COUNT_START,
+        ia32_mtrr_capabilities_msr::VARIABLE_RANGE_COUNT_LEN,
+    );
+
+    let mut ranges = Vec::new();
+    for i in 0..variable_range_count {
+        let phys_base = read_msr(MSR_IA32_MTRR_PHYSBASE0 + (i * 2) as u32);
+        let phys_mask = read_msr(MSR_IA32_MTRR_PHYSMASK0 + (i * 2) as u32);
+
+        if (phys_mask & ia32_mtrr_phys_mask_msr::VALID) == 0 {
+            continue;
+        }
+
+        let base = get_bits_value(
+            phys_base,
+            ia32_mtrr_phys_base_msr::PAGE_FRAME_NUMBER_START,
+            ia32_mtrr_capabilities_msr::PAGE_FRAME_NUMBER_LEN,
+        ) * PAGE_SIZE;
+
+        let mut number_of_bitmask = 0u32;
+        bit_scan_forward64(
+            &mut number_of_bitmask,
+            get_bits_value(
+                phys_mask,
+                ia32_mtrr_phys_mask_msr::PAGE_FRAME_NUMBER_START,
+                ia32_mtrr_phys_mask_msr::PAGE_FRAME_NUMBER_LEN,
+            ) * PAGE_SIZE,
+        );
+
+        let memory_type = get_bits_value(
+            phys_base,
+            ia32_mtrr_phys_base_msr::TYPE_START,
+            ia32_mtrr_phys_base_msr::TYPE_LEN,
+        ) as u8;
+
+        if memory_type != MEMORY_TYPE_WRITE_BACK {
+            ranges.push(MemoryRange {
+                base,
+                end: base + ((1u64 << number_of_bitmask) - 1),
+                memory_type,
+            });
+        }
+    }
+
+    ranges
+}
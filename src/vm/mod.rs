@@ -1,8 +1,43 @@
+pub mod access_dirty;
+pub mod backend;
 pub mod check;
+pub mod code_integrity;
+pub mod config;
+pub mod controls;
+pub mod cr_access;
 pub mod data;
+pub mod desc_table;
+pub mod dirty_log;
+pub mod dump;
+pub mod entry_check;
 pub mod ept;
+pub mod ept_view;
+pub mod ept_violation;
+pub mod event_ring;
+pub mod exec_breakpoint;
+pub mod exit_handler;
+pub mod exit_trace;
+pub mod guest_memory;
+pub mod hidden_memory;
+pub mod host;
+pub mod identity_map;
+pub mod msr_bitmap;
+pub mod msr_permission;
+pub mod msr_shadow;
+pub mod npt;
+pub mod preemption;
+pub mod process_view;
+pub mod soft_vmcs;
+pub mod spp;
+pub mod svm;
+pub mod svm_exit;
+pub mod syscall_trace;
+pub mod virt_exception;
+pub mod vmcb;
 pub mod vmm;
 pub mod vmx;
+pub mod vpid;
+pub mod watchpoint;
 
 pub mod ins {
     use core::{arch::asm, ffi::c_void};
@@ -11,7 +46,7 @@ pub mod ins {
 
     use crate::vm::data::VM_INSTRUCTION_ERROR_MAP;
 
-    use super::data::vmcs_encoding::VM_INSTRUCTION_ERROR;
+    use super::data::{vm_call, vmcs_encoding::VM_INSTRUCTION_ERROR};
 
     #[derive(Debug, PartialEq, Eq)]
     pub enum VmxInstructionResult {
@@ -170,20 +205,40 @@ pub mod ins {
         }
     }
 
+    // the handler reports a failed call through rax
     pub fn __vmx_vmcall(vmcall_no: u64, arg1: u64, arg2: u64, arg3: u64) -> VmxInstructionResult {
+        let status: u64;
         unsafe {
             asm!(
-                "xor rax,rax",
                 "vmcall",
+                inout("rax") vm_call::STATUS_SUCCESS => status,
                 in("rcx") vmcall_no,
                 in("rdx") arg1,
                 in("r8") arg2,
                 in("r9") arg3,
+                // EXIT_VT comes back through r11
+                out("r11") _,
                 options(nostack, nomem)
             );
         }
 
-        VmxInstructionResult::VmxSuccess
+        match status {
+            vm_call::STATUS_SUCCESS => VmxInstructionResult::VmxSuccess,
+            _ => VmxInstructionResult::VmxFailValid,
+        }
+    }
+
+    // guest side,an index outside the eptp list exits and the guest gets #ud
+    pub fn __vmx_vmfunc(function: u32, index: u32) {
+        unsafe {
+            asm!(
+                "vmfunc",
+                in("eax") function,
+                in("ecx") index,
+                // memory reads after the switch go through the new view
+                options(nostack)
+            );
+        }
     }
 
     pub fn __invept(invept_type: u64, ept_ctx: *mut c_void) -> VmxInstructionResult {
@@ -205,6 +260,25 @@ pub mod ins {
         VmxInstructionResult::from(result)
     }
 
+    pub fn __invvpid(invvpid_type: u64, vpid_ctx: *mut c_void) -> VmxInstructionResult {
+        let mut result: u64;
+        unsafe {
+            asm!(
+                "xor rax,rax",
+                "invvpid rcx, [rdx]",
+                "setc al",
+                "setz cl",
+                "adc al,cl",
+                in("rcx") invvpid_type,
+                in("rdx") vpid_ctx,
+                out("rax") result,
+                options(nostack, nomem)
+            );
+        }
+
+        VmxInstructionResult::from(result)
+    }
+
     pub fn __vmx_read_error() -> &'static str {
         let mut error_code: u64 = 0;
         match __vmx_vmread(VM_INSTRUCTION_ERROR, &mut error_code) {
@@ -216,3 +290,50 @@ pub mod ins {
         }
     }
 }
+
+pub mod svm_ins {
+    use core::arch::asm;
+
+    // vmcb physical address in rax,loads fs,gs,tr,ldtr,kernel_gs_base,star,lstar,cstar,sfmask
+    // and the sysenter msrs
+    pub fn __svm_vmload(vmcb_pa: u64) {
+        unsafe {
+            asm!("vmload rax", in("rax") vmcb_pa, options(nostack));
+        }
+    }
+
+    // the same state back to the vmcb
+    pub fn __svm_vmsave(vmcb_pa: u64) {
+        unsafe {
+            asm!("vmsave rax", in("rax") vmcb_pa, options(nostack));
+        }
+    }
+
+    // #VMEXIT clears the global interrupt flag
+    pub fn __svm_stgi() {
+        unsafe {
+            asm!("stgi", options(nostack, nomem));
+        }
+    }
+
+    pub fn __svm_clgi() {
+        unsafe {
+            asm!("clgi", options(nostack, nomem));
+        }
+    }
+
+    // #ud outside a guest,same numbers as vmcall
+    pub fn __svm_vmmcall(vmmcall_no: u64, arg1: u64, arg2: u64, arg3: u64) {
+        unsafe {
+            asm!(
+                "vmmcall",
+                in("rcx") vmmcall_no,
+                in("rdx") arg1,
+                in("r8") arg2,
+                in("r9") arg3,
+                out("rax") _,
+                options(nostack)
+            );
+        }
+    }
+}
@@ -0,0 +1 @@
+pub use moon_vm::msr_bitmap::*;
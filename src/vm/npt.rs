@@ -0,0 +1,64 @@
+use core::{ffi::c_void, mem::size_of};
+
+use moon_log::info;
+use wdk_sys::{
+    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PHYSICAL_ADDRESS,
+};
+
+use crate::utils::virtual_address_to_physical_address;
+
+use super::identity_map::{fill_identity_map, read_mtrr_ranges, NptFormat, IDENTITY_MAP_ENTRIES};
+
+// same tables as the ept,only long mode entries
+#[repr(C)]
+#[repr(align(0x1000))]
+pub struct NptPageTable {
+    pml4: [u64; IDENTITY_MAP_ENTRIES],
+    pml3: [u64; IDENTITY_MAP_ENTRIES],
+    pml2: [[u64; IDENTITY_MAP_ENTRIES]; IDENTITY_MAP_ENTRIES],
+}
+
+// nested page tables shared by every vcpu
+pub struct NptState {
+    page_table: *mut NptPageTable,
+}
+
+impl NptState {
+    // passive level
+    pub fn new() -> Result<Self, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let page_table: *mut NptPageTable =
+            unsafe { MmAllocateContiguousMemory(size_of::<NptPageTable>() as _, max_size) } as _;
+        if page_table.is_null() {
+            return Err("error to allocate npt memory");
+        }
+
+        unsafe { memset(page_table as *mut c_void, 0, size_of::<NptPageTable>() as _) };
+
+        let table = unsafe { &mut *page_table };
+        fill_identity_map::<NptFormat>(
+            &mut table.pml4,
+            &mut table.pml3,
+            &mut table.pml2,
+            |entry| virtual_address_to_physical_address(entry as *mut c_void),
+            &read_mtrr_ranges(),
+        );
+
+        Ok(Self { page_table })
+    }
+
+    // vmcb n_cr3,physical address of the pml4
+    pub fn n_cr3(&self) -> u64 {
+        virtual_address_to_physical_address(self.page_table as _)
+    }
+}
+
+impl Drop for NptState {
+    fn drop(&mut self) {
+        info!("NptState Drop");
+        unsafe { MmFreeContiguousMemory(self.page_table as _) };
+    }
+}
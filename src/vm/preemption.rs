@@ -0,0 +1,95 @@
+use moon_instructions::rdtsc;
+
+use super::{data::vmcs_encoding::VMX_PREEMPTION_TIMER_VALUE, ins::__vmx_vmwrite};
+
+// about 20ms on a 3ghz tsc,keeps pending host work from waiting on a guest exit forever
+pub const DEFAULT_PREEMPTION_INTERVAL_TSC: u64 = 1 << 26;
+
+pub type PreemptionTimerCallback = fn(cpu_index: usize, guest_rip: u64);
+
+// the timer counts down once every time bit `rate` of the tsc changes
+pub fn tsc_to_timer_ticks(tsc_ticks: u64, rate: u8) -> u32 {
+    (tsc_ticks >> rate).clamp(1, u32::MAX as u64) as u32
+}
+
+pub struct PreemptionTimer {
+    pub interval_tsc: u64,
+    callback: Option<PreemptionTimerCallback>,
+    rate: u8,
+    enabled: bool,
+    // cpu stores the remaining value on vm-exit,otherwise we track a tsc deadline
+    save_supported: bool,
+    deadline_tsc: u64,
+    pub expirations: u64,
+}
+
+impl Default for PreemptionTimer {
+    fn default() -> Self {
+        Self {
+            interval_tsc: DEFAULT_PREEMPTION_INTERVAL_TSC,
+            callback: None,
+            rate: 0,
+            enabled: false,
+            save_supported: false,
+            deadline_tsc: 0,
+            expirations: 0,
+        }
+    }
+}
+
+impl PreemptionTimer {
+    pub fn set_callback(&mut self, interval_tsc: u64, callback: Option<PreemptionTimerCallback>) {
+        self.interval_tsc = interval_tsc;
+        self.callback = callback;
+    }
+
+    // the timer control is only requested when someone listens
+    pub fn has_callback(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // called after the controls are resolved
+    pub fn enable(&mut self, rate: u8, save_supported: bool) {
+        self.rate = rate;
+        self.enabled = true;
+        self.save_supported = save_supported;
+        self.reload();
+    }
+
+    // arm a full interval
+    pub fn reload(&mut self) {
+        self.deadline_tsc = rdtsc().wrapping_add(self.interval_tsc);
+        __vmx_vmwrite(
+            VMX_PREEMPTION_TIMER_VALUE,
+            tsc_to_timer_ticks(self.interval_tsc, self.rate) as u64,
+        );
+    }
+
+    // every other exit,keep the countdown going instead of restarting it on vm-entry
+    pub fn save(&mut self) {
+        if !self.enabled || self.save_supported {
+            return;
+        }
+
+        let remaining = self.deadline_tsc.saturating_sub(rdtsc());
+        __vmx_vmwrite(
+            VMX_PREEMPTION_TIMER_VALUE,
+            tsc_to_timer_ticks(remaining, self.rate) as u64,
+        );
+    }
+
+    // exit reason 52
+    pub fn expire(&mut self, cpu_index: usize, guest_rip: u64) {
+        self.expirations += 1;
+
+        if let Some(callback) = self.callback {
+            callback(cpu_index, guest_rip);
+        }
+
+        self.reload();
+    }
+}
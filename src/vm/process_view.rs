@@ -0,0 +1,240 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::vec::Vec;
+use moon_log::error;
+use wdk_sys::{
+    ntddk::{
+        memset, MmAllocateContiguousMemory, MmFreeContiguousMemory, ObfDereferenceObject,
+        PsSetCreateProcessNotifyRoutine,
+    },
+    BOOLEAN, HANDLE, NT_SUCCESS, PAGE_SIZE, PHYSICAL_ADDRESS, _KPROCESS,
+};
+
+use crate::{symbol::lookup_process, utils::virtual_address_to_physical_address, __GD};
+
+use super::vmx::VMM_LOCK;
+
+pub const PROCESS_REGION_MAX: usize = 32;
+// large enough for a driver image,every page gets its own 4kb entry in every view
+pub const PROCESS_REGION_MAX_SIZE: u64 = 0x100_0000;
+// kprocess.directorytablebase,the kernel cr3 of the process
+pub const KPROCESS_DIRECTORY_TABLE_BASE: usize = 0x28;
+// owner of a view entered by fetches from its regions instead of by a cr3 load
+pub const VIEW_OWNER_CODE: u64 = u64::MAX;
+// without the pcid and the no flush bit
+const CR3_FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+static EXIT_NOTIFY_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+pub fn process_cr3(process: *mut _KPROCESS) -> u64 {
+    unsafe { *((process as *const u8).add(KPROCESS_DIRECTORY_TABLE_BASE) as *const u64) }
+}
+
+// passive level,a view must not outlive its owner. the cr3 frame goes to the next process
+unsafe extern "C" fn process_exit_notify(_parent_id: HANDLE, process_id: HANDLE, create: BOOLEAN) {
+    if create != 0 {
+        return;
+    }
+
+    // ioctls use the vmm from other threads
+    let _lock = VMM_LOCK.write();
+    let vmm = match unsafe { __GD.as_mut().and_then(|gd| gd.vmm.as_mut()) } {
+        Some(vmm) => vmm,
+        None => return,
+    };
+
+    let process = lookup_process(process_id as u64 as _);
+    if process.is_null() {
+        return;
+    }
+    let cr3 = process_cr3(process);
+    unsafe { ObfDereferenceObject(process as _) };
+
+    if let Err(e) = vmm.release_process_view(cr3) {
+        error!("{}", e);
+    }
+}
+
+// passive level,registered while process views are enabled. removing it waits for running
+// notifies,which take VMM_LOCK,so it must be removed before the lock is held
+pub fn set_process_exit_notify(enable: bool) -> Result<(), &'static str> {
+    if EXIT_NOTIFY_REGISTERED.load(Ordering::Acquire) == enable {
+        return Ok(());
+    }
+
+    let status =
+        unsafe { PsSetCreateProcessNotifyRoutine(Some(process_exit_notify), (!enable) as _) };
+    if !NT_SUCCESS(status) {
+        return Err("PsSetCreateProcessNotifyRoutine error");
+    }
+    EXIT_NOTIFY_REGISTERED.store(enable, Ordering::Release);
+    Ok(())
+}
+
+#[derive(Default)]
+struct ProtectedRegion {
+    // vmx root reads the other fields only while set
+    active: AtomicBool,
+    view: usize,
+    // physical page and its rights in the default view before it was protected
+    pages: Vec<(u64, u64)>,
+}
+
+// view 0 is the default ept and has no owner. every other view belongs to one cr3 or to the
+// code of hidden regions,pages of a region are only present in the view of its owner
+pub struct ProcessViews {
+    // cr3 frame owning each view,0 while free
+    owners: Vec<AtomicU64>,
+    regions: Vec<ProtectedRegion>,
+    // per cpu,denied reads and writes go to a zeroed page instead. virtual and physical
+    scratch: Vec<(*mut u8, u64)>,
+    denied: AtomicU64,
+}
+
+impl ProcessViews {
+    // passive level,count includes the default view
+    pub fn new(count: usize, cpu_count: usize) -> Result<Self, &'static str> {
+        let mut process_views = Self {
+            owners: (0..count).map(|_| AtomicU64::new(0)).collect(),
+            regions: (0..PROCESS_REGION_MAX)
+                .map(|_| ProtectedRegion::default())
+                .collect(),
+            scratch: Vec::with_capacity(cpu_count),
+            denied: AtomicU64::new(0),
+        };
+
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        for _ in 0..cpu_count {
+            let page: *mut u8 =
+                unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+            if page.is_null() {
+                return Err("error to allocate scratch page");
+            }
+            unsafe { memset(page as _, 0, PAGE_SIZE as _) };
+            process_views
+                .scratch
+                .push((page, virtual_address_to_physical_address(page as _)));
+        }
+
+        Ok(process_views)
+    }
+
+    pub fn owner(&self, view: usize) -> Option<u64> {
+        self.owners
+            .get(view)
+            .map(|owner| owner.load(Ordering::Acquire))
+    }
+
+    // passive level,the view of owner or a free one
+    pub fn assign(&mut self, owner: u64) -> Result<usize, &'static str> {
+        let owner = match owner {
+            VIEW_OWNER_CODE => VIEW_OWNER_CODE,
+            cr3 => cr3 & CR3_FRAME_MASK,
+        };
+        if let Some(view) = (1..self.owners.len()).find(|view| self.owner(*view) == Some(owner)) {
+            return Ok(view);
+        }
+
+        let view = (1..self.owners.len())
+            .find(|view| self.owner(*view) == Some(0))
+            .ok_or("no free process view")?;
+        self.owners[view].store(owner, Ordering::Release);
+        Ok(view)
+    }
+
+    // passive level,the view of owner is free again and its regions are dropped. returns the
+    // pages of those regions with their default view rights,none when owner has no view
+    pub fn release(&mut self, owner: u64) -> Option<Vec<(u64, u64)>> {
+        let owner = owner & CR3_FRAME_MASK;
+        let view = (1..self.owners.len()).find(|view| self.owner(*view) == Some(owner))?;
+        self.owners[view].store(0, Ordering::Release);
+
+        let mut pages = Vec::new();
+        for region in self.regions.iter_mut() {
+            if region.active.load(Ordering::Acquire) && region.view == view {
+                // the slot keeps its pages until reused,vmx root may still be reading them
+                region.active.store(false, Ordering::Release);
+                pages.extend(region.pages.iter().copied());
+            }
+        }
+        Some(pages)
+    }
+
+    // passive level,cr3 loads pick the default view from now on
+    pub fn release_owners(&self) {
+        for owner in self.owners.iter() {
+            owner.store(0, Ordering::Release);
+        }
+    }
+
+    // vmx root,the view a cr3 load switches to
+    pub fn view_for_cr3(&self, cr3: u64) -> usize {
+        let cr3 = cr3 & CR3_FRAME_MASK;
+        (1..self.owners.len())
+            .find(|view| self.owner(*view) == Some(cr3))
+            .unwrap_or(0)
+    }
+
+    fn active(&self) -> impl Iterator<Item = &ProtectedRegion> {
+        self.regions
+            .iter()
+            .filter(|region| region.active.load(Ordering::Acquire))
+    }
+
+    pub fn is_protected(&self, page: u64) -> bool {
+        self.region_view(page).is_some()
+    }
+
+    // passive level,pages are (physical page,default view rights)
+    pub fn insert(&mut self, view: usize, pages: Vec<(u64, u64)>) -> Result<(), &'static str> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|region| !region.active.load(Ordering::Acquire))
+            .ok_or("too many protected regions")?;
+
+        region.view = view;
+        region.pages = pages;
+        region.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // passive level,every protected page with its default view rights
+    pub fn protected_pages(&self) -> Vec<(u64, u64)> {
+        self.active()
+            .flat_map(|region| region.pages.iter().copied())
+            .collect()
+    }
+
+    // vmx root,the owner view of a protected page
+    pub fn region_view(&self, page: u64) -> Option<usize> {
+        self.active()
+            .find(|region| region.pages.iter().any(|(physical, _)| *physical == page))
+            .map(|region| region.view)
+    }
+
+    // vmx root,zeroed again for every denied access
+    pub fn scratch_page(&self, cpu_index: usize) -> Option<u64> {
+        let (page, physical) = *self.scratch.get(cpu_index)?;
+        unsafe { memset(page as _, 0, PAGE_SIZE as _) };
+        Some(physical)
+    }
+
+    pub fn count_denied(&self) {
+        self.denied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn denied_count(&self) -> u64 {
+        self.denied.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for ProcessViews {
+    fn drop(&mut self) {
+        for (page, _) in self.scratch.iter() {
+            unsafe { MmFreeContiguousMemory(*page as _) };
+        }
+    }
+}
@@ -0,0 +1 @@
+pub use moon_vm::soft_vmcs::*;
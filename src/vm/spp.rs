@@ -0,0 +1,157 @@
+use alloc::{collections::BTreeSet, vec::Vec};
+use wdk_sys::{
+    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::utils::virtual_address_to_physical_address;
+
+use super::guest_memory::physical_to_virtual;
+
+pub use moon_vm::spp::*;
+
+// non-leaf entry
+const SPPT_VALID: u64 = 1 << 0;
+const SPPT_FRAME_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// exit qualification of an spp-related event,a miss when clear
+pub const SPP_EVENT_MISCONFIG: u64 = 1 << 11;
+pub const SPP_EVENT_NMI_UNBLOCKING: u64 = 1 << 12;
+
+// a write to a protected sub-page,the write is let through once the callback returns
+#[derive(Debug, Clone, Copy)]
+pub struct SubPageWrite {
+    pub cpu_index: u32,
+    pub rip: u64,
+    pub cr3: u64,
+    pub linear_address: Option<u64>,
+    pub physical_address: u64,
+}
+
+// vmx root,any irql of the guest. must not touch paged memory
+pub type SubPageWriteCallback = fn(write: &SubPageWrite);
+
+// layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SubPagePermissionRequest {
+    pub physical: u64,
+    // bit i set,sub-page i accepts writes
+    pub writable: u32,
+    pub reserved: u32,
+}
+
+// index into the table of a level,4 is the root
+fn sppt_index(physical: u64, level: u32) -> usize {
+    ((physical >> (12 + 9 * (level - 1))) & 0x1FF) as usize
+}
+
+// sub-page permission table,walked by the cpu like a 4-level ept. tables are only added,
+// a leaf is written before the ept entry that points the cpu at it
+pub struct SppTable {
+    root: *mut u64,
+    root_physical: u64,
+    // every table page besides the root
+    pages: Vec<*mut u64>,
+    // pages with a leaf the ept may point the cpu at
+    selected: BTreeSet<u64>,
+}
+
+impl SppTable {
+    fn allocate_table() -> Result<*mut u64, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let table: *mut u64 = unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+        if table.is_null() {
+            return Err("error to allocate spp table");
+        }
+        unsafe { memset(table as _, 0, PAGE_SIZE as _) };
+        Ok(table)
+    }
+
+    // passive level
+    pub fn new() -> Result<Self, &'static str> {
+        let root = Self::allocate_table()?;
+
+        Ok(Self {
+            root,
+            root_physical: virtual_address_to_physical_address(root as _),
+            pages: Vec::new(),
+            selected: BTreeSet::new(),
+        })
+    }
+
+    // value of the spp table pointer field
+    pub fn pointer(&self) -> u64 {
+        self.root_physical & SPPT_FRAME_MASK
+    }
+
+    // passive level,the missing levels are allocated
+    pub fn set(&mut self, physical: u64, writable: SubPageMask) -> Result<(), &'static str> {
+        let mut table = self.root;
+
+        for level in (2..=4).rev() {
+            let entry = unsafe { table.add(sppt_index(physical, level)) };
+            let value = unsafe { core::ptr::read_volatile(entry) };
+
+            table = if (value & SPPT_VALID) != 0 {
+                physical_to_virtual(value & SPPT_FRAME_MASK) as *mut u64
+            } else {
+                let next = Self::allocate_table()?;
+                self.pages.push(next);
+                let next_physical = virtual_address_to_physical_address(next as _);
+                unsafe {
+                    core::ptr::write_volatile(entry, (next_physical & SPPT_FRAME_MASK) | SPPT_VALID)
+                };
+                next
+            };
+
+            if table.is_null() {
+                return Err("spp table is not mapped");
+            }
+        }
+
+        let leaf = unsafe { table.add(sppt_index(physical, 1)) };
+        unsafe { core::ptr::write_volatile(leaf, writable.to_leaf()) };
+        self.selected.insert(physical & !(PAGE_SIZE as u64 - 1));
+        Ok(())
+    }
+
+    // the leaf stays,the ept no longer points the cpu at it
+    pub fn unselect(&mut self, physical: u64) {
+        self.selected.remove(&(physical & !(PAGE_SIZE as u64 - 1)));
+    }
+
+    pub fn selected(&self) -> impl Iterator<Item = u64> + '_ {
+        self.selected.iter().copied()
+    }
+
+    // vmx root safe,none when a level is missing
+    pub fn get(&self, physical: u64) -> Option<SubPageMask> {
+        let mut table = self.root;
+
+        for level in (2..=4).rev() {
+            let value = unsafe { core::ptr::read_volatile(table.add(sppt_index(physical, level))) };
+            if (value & SPPT_VALID) == 0 {
+                return None;
+            }
+            table = physical_to_virtual(value & SPPT_FRAME_MASK) as *mut u64;
+            if table.is_null() {
+                return None;
+            }
+        }
+
+        let leaf = unsafe { core::ptr::read_volatile(table.add(sppt_index(physical, 1))) };
+        Some(SubPageMask::from_leaf(leaf))
+    }
+}
+
+impl Drop for SppTable {
+    fn drop(&mut self) {
+        for page in self.pages.drain(..) {
+            unsafe { MmFreeContiguousMemory(page as _) };
+        }
+        unsafe { MmFreeContiguousMemory(self.root as _) };
+    }
+}
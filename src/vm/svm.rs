@@ -0,0 +1,460 @@
+use core::{ffi::c_void, mem::size_of, ptr::null_mut};
+
+use alloc::{boxed::Box, vec::Vec};
+use moon_feature::svm_next_rip_support;
+use moon_instructions::{read_msr, segment_limit, write_msr};
+use moon_log::{error, info};
+use moon_struct::{
+    inner::KPROCESSOR_STATE,
+    msr::{
+        efer_msr::EFER_SVME,
+        msr_index::{MSR_EFER, MSR_IA32_PAT, MSR_VM_HSAVE_PA},
+    },
+};
+use wdk_sys::{
+    ntddk::{
+        KeQueryActiveProcessorCount, KeRevertToUserAffinityThread, KeSetSystemAffinityThread,
+        MmAllocateContiguousMemory, MmFreeContiguousMemory, RtlCaptureContext,
+    },
+    KERNEL_STACK_SIZE, PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::{
+    inner::{KeSaveStateForHibernate, RtlRestoreContext},
+    utils::{get_current_processor_idx, virtual_address_to_physical_address},
+    __GD,
+};
+
+use super::{
+    backend::HypervisorBackend,
+    data::{
+        svm_intercept_misc1::{INTERCEPT_CPUID, INTERCEPT_MSR_PROT, INTERCEPT_SHUTDOWN},
+        svm_intercept_misc2::{INTERCEPT_VMMCALL, INTERCEPT_VMRUN},
+        svm_nested_control::NP_ENABLE,
+        vm_call::INVEPT_ALL_CONTEXT,
+    },
+    msr_bitmap::MsrAccess,
+    msr_permission::{msr_permission_position, MsrPermissionMap, MSR_PERMISSION_MAP_SIZE},
+    msr_shadow::{MsrPolicy, MsrShadowTable, MSR_IA32_FEATURE_CONTROL},
+    npt::NptState,
+    svm_ins::{__svm_vmmcall, __svm_vmsave},
+    vmcb::{segment_attributes, Vmcb, VmcbSegment},
+};
+
+extern "C" {
+    // runs the guest vmcb on the stack at stack_top until the guest leaves svm
+    fn svm_launch(stack_top: u64) -> !;
+}
+
+// asid 0 belongs to the host
+const SVM_GUEST_ASID: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VcpuSvmState {
+    SvmStateOff,
+    SvmStateTransition,
+    SvmStateOn,
+}
+
+// first qwords of the vmm stack,read by svm_launch on every exit
+#[repr(C)]
+struct SvmLaunchFrame {
+    guest_vmcb_pa: u64,
+    host_vmcb_pa: u64,
+    vcpu: *mut SvmVcpu,
+    reserved: u64,
+}
+
+struct SvmResources {
+    guest_vmcb: *mut c_void,
+    // host fs,gs,tr and syscall msrs for vmload after every exit
+    host_vmcb: *mut c_void,
+    // vm_hsave_pa,vmrun keeps the rest of the host state here
+    host_save_area: *mut c_void,
+    vmm_stack: *mut c_void,
+}
+
+pub struct SvmVcpu {
+    cpu_index: usize,
+    host_state: Box<KPROCESSOR_STATE>,
+    svm_state: VcpuSvmState,
+    // vmrun rejected the guest state,the cpu is back on the captured context
+    launch_failed: bool,
+    resources: SvmResources,
+    // the vmcb reports the rip after intercepted instructions
+    next_rip: bool,
+    // shared by every vcpu,set before the launch
+    n_cr3: u64,
+    msr_permission_map_pa: u64,
+    msr_shadow: MsrShadowTable,
+}
+
+pub struct Svm {
+    pub cpu_count: u32,
+    pub vcpu: Vec<Box<SvmVcpu>>,
+    pub npt_state: Option<NptState>,
+    // shared by every vcpu,vmrun reads it on every msr access
+    msr_permission_map: *mut c_void,
+    pub next_rip: bool,
+}
+
+fn allocate_page(size: u64) -> *mut c_void {
+    let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+    max_size.QuadPart = i64::MAX;
+
+    let memory = unsafe { MmAllocateContiguousMemory(size, max_size) };
+    if !memory.is_null() {
+        unsafe { core::ptr::write_bytes(memory as *mut u8, 0, size as _) };
+    }
+    memory
+}
+
+fn free_page(memory: &mut *mut c_void) {
+    if !memory.is_null() {
+        unsafe { MmFreeContiguousMemory(core::mem::replace(memory, null_mut())) };
+    }
+}
+
+// amd has no feature control msr,the guest gets the #gp of the bare cpu
+fn svm_msr_shadow() -> MsrShadowTable {
+    let mut table = MsrShadowTable::default();
+    table.set_policy(MSR_IA32_FEATURE_CONTROL, MsrPolicy::DenyGp);
+    table
+}
+
+// cs,ds,es and ss,long mode ignores their base
+fn vmcb_segment(gdt_base: u64, selector: u16) -> VmcbSegment {
+    let descriptor = unsafe { *((gdt_base + (selector as u64 & !7)) as *const u64) };
+    VmcbSegment {
+        selector,
+        attrib: segment_attributes(descriptor),
+        limit: segment_limit(selector as _) as _,
+        base: 0,
+    }
+}
+
+impl SvmVcpu {
+    pub fn free_physical_memory(&mut self) {
+        let resources = &mut self.resources;
+        free_page(&mut resources.guest_vmcb);
+        free_page(&mut resources.host_vmcb);
+        free_page(&mut resources.host_save_area);
+        free_page(&mut resources.vmm_stack);
+    }
+
+    pub fn set_cpu_index(&mut self, index: usize) {
+        self.cpu_index = index;
+    }
+
+    pub fn cpu_index(&self) -> usize {
+        self.cpu_index
+    }
+
+    pub fn next_rip(&self) -> bool {
+        self.next_rip
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.svm_state == VcpuSvmState::SvmStateOn
+    }
+
+    pub fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+        &mut self.msr_shadow
+    }
+
+    pub fn guest_vmcb(&self) -> *mut Vmcb {
+        self.resources.guest_vmcb as _
+    }
+
+    pub fn guest_vmcb_pa(&self) -> u64 {
+        virtual_address_to_physical_address(self.resources.guest_vmcb as _)
+    }
+
+    pub fn set_svm_state(&mut self, state: VcpuSvmState) {
+        self.svm_state = state;
+    }
+
+    pub fn set_launch_failed(&mut self) {
+        self.launch_failed = true;
+    }
+
+    fn set_vmcb_data(&mut self) {
+        let vmcb = unsafe { &mut *self.guest_vmcb() };
+        let special_registers = &self.host_state.SpecialRegisters;
+        let context = &self.host_state.Context_frame;
+
+        // intercepts,everything else runs untouched
+        let control = &mut vmcb.control_area;
+        control.intercept_misc1 = INTERCEPT_CPUID | INTERCEPT_MSR_PROT | INTERCEPT_SHUTDOWN;
+        control.intercept_misc2 = INTERCEPT_VMRUN | INTERCEPT_VMMCALL;
+        control.msrpm_base_pa = self.msr_permission_map_pa;
+        control.guest_asid = SVM_GUEST_ASID;
+
+        // nested paging
+        control.np_enable = NP_ENABLE;
+        control.n_cr3 = self.n_cr3;
+
+        // segments
+        let state = &mut vmcb.state_save_area;
+        let gdt_base = special_registers.Gdtr.Base;
+        state.gdtr.base = gdt_base;
+        state.gdtr.limit = special_registers.Gdtr.Limit as _;
+        state.idtr.base = special_registers.Idtr.Base;
+        state.idtr.limit = special_registers.Idtr.Limit as _;
+        state.cs = vmcb_segment(gdt_base, context.SegCs);
+        state.ds = vmcb_segment(gdt_base, context.SegDs);
+        state.es = vmcb_segment(gdt_base, context.SegEs);
+        state.ss = vmcb_segment(gdt_base, context.SegSs);
+        state.cpl = 0;
+
+        // control registers,efer already has svme
+        state.efer = read_msr(MSR_EFER);
+        state.cr0 = special_registers.Cr0;
+        state.cr2 = special_registers.Cr2;
+        state.cr3 = special_registers.Cr3;
+        state.cr4 = special_registers.Cr4;
+        state.dr7 = special_registers.KernelDr7;
+        state.g_pat = read_msr(MSR_IA32_PAT);
+
+        // guest address after execute vmrun
+        state.rflags = context.EFlags as _;
+        state.rsp = context.Rsp;
+        state.rip = context.Rip;
+        state.rax = context.Rax;
+    }
+
+    fn subvert_cpu(&mut self) {
+        // need free it youself on drop fuction
+        let guest_vmcb = allocate_page(PAGE_SIZE as _);
+        let host_vmcb = allocate_page(PAGE_SIZE as _);
+        let host_save_area = allocate_page(PAGE_SIZE as _);
+        let vmm_stack = allocate_page(KERNEL_STACK_SIZE as _);
+
+        self.resources.guest_vmcb = guest_vmcb;
+        self.resources.host_vmcb = host_vmcb;
+        self.resources.host_save_area = host_save_area;
+        self.resources.vmm_stack = vmm_stack;
+
+        // allocate fault
+        if guest_vmcb.is_null()
+            || host_vmcb.is_null()
+            || host_save_area.is_null()
+            || vmm_stack.is_null()
+        {
+            error!("error to allocate svm memory");
+            return;
+        }
+
+        // enable svm
+        write_msr(MSR_EFER, read_msr(MSR_EFER) | EFER_SVME);
+        write_msr(
+            MSR_VM_HSAVE_PA,
+            virtual_address_to_physical_address(host_save_area),
+        );
+
+        self.set_vmcb_data();
+
+        // the state vmrun does not load,the same for guest and host right now
+        let guest_vmcb_pa = self.guest_vmcb_pa();
+        let host_vmcb_pa = virtual_address_to_physical_address(host_vmcb);
+        __svm_vmsave(guest_vmcb_pa);
+        __svm_vmsave(host_vmcb_pa);
+
+        // vmm stack top,16 byte aligned for the exit handler
+        let stack_top =
+            vmm_stack as u64 + KERNEL_STACK_SIZE as u64 - size_of::<SvmLaunchFrame>() as u64;
+        unsafe {
+            (stack_top as *mut SvmLaunchFrame).write(SvmLaunchFrame {
+                guest_vmcb_pa,
+                host_vmcb_pa,
+                vcpu: self as *mut SvmVcpu,
+                reserved: 0,
+            })
+        };
+
+        self.svm_state = VcpuSvmState::SvmStateTransition;
+
+        // from vmm to guest,the guest continues after RtlCaptureContext in start_svm
+        unsafe { svm_launch(stack_top) };
+    }
+
+    pub(crate) fn start_svm(&mut self) {
+        unsafe {
+            let host_state: &mut KPROCESSOR_STATE = &mut self.host_state;
+            KeSaveStateForHibernate(host_state as _);
+
+            // important!!!!
+            // the guest starts on the next code,only rip,rsp,rax and rflags come from here
+            RtlCaptureContext(&mut host_state.Context_frame as _);
+        }
+
+        // other registers are left from svm_launch,reach the vcpu through the global again
+        let vcpu = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .svm
+                .as_mut()
+                .unwrap()
+                .get_current_vcpu()
+        };
+
+        match vcpu.svm_state {
+            VcpuSvmState::SvmStateOff if vcpu.launch_failed => {
+                error!("CPU:{} vmrun rejected the guest state", vcpu.cpu_index);
+            }
+            VcpuSvmState::SvmStateOff => {
+                // begin start svm
+                vcpu.subvert_cpu();
+            }
+            VcpuSvmState::SvmStateTransition => {
+                // vmrun execute successed
+                vcpu.svm_state = VcpuSvmState::SvmStateOn;
+                unsafe { RtlRestoreContext(&mut vcpu.host_state.Context_frame as _, null_mut()) };
+            }
+            VcpuSvmState::SvmStateOn => {
+                // all success
+                info!("CPU:{} start svm success", vcpu.cpu_index);
+            }
+        }
+    }
+}
+
+impl Default for Svm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Svm {
+    pub fn new() -> Self {
+        let cpu_count = unsafe { KeQueryActiveProcessorCount(core::ptr::null_mut()) } as u32;
+
+        info!("cpu_count:{}", cpu_count);
+
+        let vcpu = (0..cpu_count)
+            .map(|_| {
+                Box::new(SvmVcpu {
+                    cpu_index: 0,
+                    host_state: Box::new(KPROCESSOR_STATE::default()),
+                    svm_state: VcpuSvmState::SvmStateOff,
+                    launch_failed: false,
+                    resources: SvmResources {
+                        guest_vmcb: null_mut(),
+                        host_vmcb: null_mut(),
+                        host_save_area: null_mut(),
+                        vmm_stack: null_mut(),
+                    },
+                    next_rip: false,
+                    n_cr3: 0,
+                    msr_permission_map_pa: 0,
+                    msr_shadow: svm_msr_shadow(),
+                })
+            })
+            .collect();
+
+        Self {
+            cpu_count,
+            vcpu,
+            npt_state: None,
+            msr_permission_map: null_mut(),
+            next_rip: false,
+        }
+    }
+
+    fn msr_permission_map(&mut self) -> MsrPermissionMap<'_> {
+        MsrPermissionMap::new(unsafe {
+            &mut *(self.msr_permission_map as *mut [u8; MSR_PERMISSION_MAP_SIZE])
+        })
+    }
+
+    // passive level,before the first cpu launches
+    pub(crate) fn prepare(&mut self) -> Result<(), &'static str> {
+        self.next_rip = svm_next_rip_support();
+
+        self.msr_permission_map = allocate_page(MSR_PERMISSION_MAP_SIZE as _);
+        if self.msr_permission_map.is_null() {
+            return Err("error to allocate msr permission map");
+        }
+        // efer writes must keep svme,reads hide it
+        self.msr_permission_map()
+            .set(MSR_EFER, MsrAccess::ReadWrite, true)?;
+        let msr_permission_map_pa = virtual_address_to_physical_address(self.msr_permission_map);
+
+        let n_cr3 = self.npt_state.insert(NptState::new()?).n_cr3();
+
+        for vcpu in &mut self.vcpu {
+            vcpu.next_rip = self.next_rip;
+            vcpu.n_cr3 = n_cr3;
+            vcpu.msr_permission_map_pa = msr_permission_map_pa;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_current_vcpu(&mut self) -> &mut SvmVcpu {
+        &mut self.vcpu[get_current_processor_idx() as usize]
+    }
+
+    fn vmmcall_all_cpus(&self, vmmcall_no: u64, arg1: u64) {
+        for cvcpu in &self.vcpu {
+            if cvcpu.svm_state != VcpuSvmState::SvmStateOn {
+                continue;
+            }
+
+            unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+            __svm_vmmcall(vmmcall_no, arg1, 0, 0);
+            unsafe { KeRevertToUserAffinityThread() };
+        }
+    }
+
+    // every running vcpu flushes its tlb on the next vmrun,nested translations included
+    pub fn flush_npt(&mut self) {
+        self.vmmcall_all_cpus(INVEPT_ALL_CONTEXT, 0);
+    }
+
+    // the map is shared,vmrun reads it on every msr access so no vcpu has to be told
+    pub fn set_msr_intercept(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        msr_permission_position(msr, false)?;
+        if msr == MSR_EFER && !intercept {
+            return Err("efer stays intercepted to keep svme");
+        }
+        if self.msr_permission_map.is_null() {
+            return Err("svm is not running");
+        }
+
+        self.msr_permission_map().set(msr, access, intercept)
+    }
+}
+
+impl Drop for Svm {
+    fn drop(&mut self) {
+        HypervisorBackend::stop(self);
+
+        for cvcpu in &mut self.vcpu {
+            match cvcpu.svm_state {
+                VcpuSvmState::SvmStateOn => {
+                    error!("CPU:{} still runs svm", cvcpu.cpu_index);
+                    // the guest may still use the vmcb,leak it
+                    continue;
+                }
+                _ => {
+                    info!("CPU:{} Close SVM Success", cvcpu.cpu_index);
+                }
+            }
+
+            // a failed launch left svme set
+            unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+            write_msr(MSR_EFER, read_msr(MSR_EFER) & !EFER_SVME);
+            unsafe { KeRevertToUserAffinityThread() };
+
+            cvcpu.free_physical_memory();
+        }
+
+        free_page(&mut self.msr_permission_map);
+    }
+}
@@ -0,0 +1,436 @@
+use core::arch::{asm, global_asm};
+
+use moon_driver_utils::bitfield::set_bits_value;
+use moon_instructions::{debugbreak, read_msr, write_cr3, write_msr};
+use moon_log::{error, warn};
+use moon_struct::{
+    msr::{
+        efer_msr::EFER_SVME,
+        msr_index::{
+            MSR_CSTAR, MSR_EFER, MSR_FS_BASE, MSR_GS_BASE, MSR_IA32_DEBUGCTL, MSR_IA32_PAT,
+            MSR_IA32_SYSENTER_CS, MSR_IA32_SYSENTER_EIP, MSR_IA32_SYSENTER_ESP, MSR_LSTAR,
+            MSR_SFMASK, MSR_SHADOW_GS_BASE, MSR_STAR,
+        },
+    },
+    x86::X86_CR0_PG,
+};
+
+use super::{
+    backend::BackendVcpu,
+    data::{
+        svm_event_inject::{
+            DELIVER_ERROR_CODE, ERROR_CODE_LEN, ERROR_CODE_START, TYPE_EXCEPTION, TYPE_LEN,
+            TYPE_START, VALID, VECTOR_LEN, VECTOR_START,
+        },
+        svm_exit_code::{
+            VMEXIT_CLGI, VMEXIT_CPUID, VMEXIT_INVALID, VMEXIT_MSR, VMEXIT_NPF, VMEXIT_SHUTDOWN,
+            VMEXIT_SKINIT, VMEXIT_STGI, VMEXIT_VMLOAD, VMEXIT_VMMCALL, VMEXIT_VMRUN, VMEXIT_VMSAVE,
+        },
+        svm_tlb_control::{DO_NOTHING, FLUSH_ALL},
+        vector_exception::{VECTOR_GENERAL_PROTECTION_EXCEPTION, VECTOR_INVALID_OPCODE_EXCEPTION},
+        vm_call::{self, INVEPT_ALL_CONTEXT, INVEPT_SINGLE_CONTEXT},
+    },
+    exit_handler::{
+        exit_cpuid, exit_msr_read, exit_msr_write, ExitResult, ExitVmm, ProcessorCpuid,
+    },
+    msr_shadow::{check_pat_write, check_svm_efer_write, MsrBacking, MsrShadowTable},
+    svm::{SvmVcpu, VcpuSvmState},
+    svm_ins::{__svm_stgi, __svm_vmload},
+    vmcb::Vmcb,
+    vmm::Context,
+};
+
+global_asm!(r#"
+.section .text
+
+.macro svm_pushaq
+    push    -1      // rsp
+    push    rax
+    push    rcx
+    push    rdx
+    push    rbx
+
+    push    rbp
+    push    rsi
+    push    rdi
+    push    r8
+    push    r9
+    push    r10
+    push    r11
+    push    r12
+    push    r13
+    push    r14
+    push    r15
+.endm
+
+.macro svm_popaq
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdi
+    pop     rsi
+    pop     rbp
+
+    pop     rbx
+    pop     rdx
+    pop     rcx
+    pop     rax
+    add     rsp, 8  // rsp
+.endm
+
+.macro svm_popaq_exit
+    pop     r15
+    pop     r14
+    pop     r13
+    pop     r12
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdi
+    pop     rsi
+    pop     rbp
+
+    pop     rbx
+    pop     rdx
+    pop     rcx
+.endm
+
+// rcx is the top of the vmm stack,guest vmcb pa,host vmcb pa and the vcpu
+.global svm_launch
+svm_launch:
+    mov rsp, rcx
+
+svm_run_loop:
+    mov rax, [rsp]
+    vmload rax
+    vmrun rax
+    vmsave rax
+    mov rax, [rsp + 8]
+    vmload rax
+
+    svm_pushaq
+    mov rcx, rsp
+    mov rdx, [rsp + 0x90]   // vcpu,above the 16 pushed registers
+
+    sub rsp, 0x60
+    movaps [rsp +  0x0], xmm0
+    movaps [rsp + 0x10], xmm1
+    movaps [rsp + 0x20], xmm2
+    movaps [rsp + 0x30], xmm3
+    movaps [rsp + 0x40], xmm4
+    movaps [rsp + 0x50], xmm5
+
+    sub rsp, 0x20
+    call {}
+    add rsp, 0x20
+
+    movaps xmm0, [rsp + 0x0]
+    movaps xmm1, [rsp + 0x10]
+    movaps xmm2, [rsp + 0x20]
+    movaps xmm3, [rsp + 0x30]
+    movaps xmm4, [rsp + 0x40]
+    movaps xmm5, [rsp + 0x50]
+    add rsp, 0x60
+
+    cmp rax, 0
+    jne svm_exit_branch
+
+    svm_popaq
+    jmp svm_run_loop
+
+svm_exit_branch:
+    svm_popaq_exit
+    add rsp, 8      // rax
+    pop rsp         // rsp
+    jmp rax         // guest_rip
+
+    int 3
+"#,sym svm_exit_handler);
+
+// instruction lengths without next rip save
+const CPUID_LENGTH: u64 = 2;
+const MSR_LENGTH: u64 = 2;
+const VMMCALL_LENGTH: u64 = 3;
+
+struct SvmGuestState {
+    guest_regs: *mut Context,
+    vmcb: *mut Vmcb,
+    vcpu: *mut SvmVcpu,
+    next_rip: bool,
+    exit_code: u64,
+    exit_pending: bool,
+}
+
+impl SvmGuestState {
+    fn regs(&mut self) -> &mut Context {
+        unsafe { &mut *self.guest_regs }
+    }
+
+    fn vmcb(&mut self) -> &mut Vmcb {
+        unsafe { &mut *self.vmcb }
+    }
+}
+
+fn svm_advance_rip(guest_state: &mut SvmGuestState, length: u64) {
+    let next_rip = guest_state.next_rip;
+    let vmcb = guest_state.vmcb();
+    vmcb.state_save_area.rip = match vmcb.control_area.n_rip {
+        n_rip if next_rip && n_rip != 0 => n_rip,
+        _ => vmcb.state_save_area.rip + length,
+    };
+}
+
+// rip is left on the faulting instruction
+fn svm_inject_exception(guest_state: &mut SvmGuestState, vector: u8, error_code: Option<u32>) {
+    let mut event = set_bits_value(0, VECTOR_START, VECTOR_LEN, vector as _);
+    event = set_bits_value(event, TYPE_START, TYPE_LEN, TYPE_EXCEPTION);
+    event |= VALID;
+    if let Some(error_code) = error_code {
+        event |= DELIVER_ERROR_CODE;
+        event = set_bits_value(event, ERROR_CODE_START, ERROR_CODE_LEN, error_code as _);
+    }
+    guest_state.vmcb().control_area.event_inj = event;
+}
+
+fn svm_inject_ud(guest_state: &mut SvmGuestState) {
+    svm_inject_exception(guest_state, VECTOR_INVALID_OPCODE_EXCEPTION, None);
+}
+
+// how the moon-vm handlers end an exit,length is used without next rip save
+fn svm_end_exit(guest_state: &mut SvmGuestState, result: ExitResult, length: u64) {
+    match result {
+        ExitResult::Advance => svm_advance_rip(guest_state, length),
+        ExitResult::Ignored(reason) => {
+            warn!("{}", reason);
+            svm_advance_rip(guest_state, length);
+        }
+        ExitResult::Fault {
+            vector,
+            error_code,
+            reason,
+        } => {
+            warn!("{}", reason);
+            svm_inject_exception(guest_state, vector, error_code);
+        }
+    }
+}
+
+fn svm_exit_cpuid(guest_state: &mut SvmGuestState) {
+    let result = exit_cpuid(guest_state.regs().slots_mut(), &mut ProcessorCpuid);
+    svm_end_exit(guest_state, result, CPUID_LENGTH);
+}
+
+// msrs vmload and vmrun switch,the real msr holds the host value while we run
+fn vmcb_msr(vmcb: &mut Vmcb, msr: u32) -> Option<&mut u64> {
+    let state = &mut vmcb.state_save_area;
+    match msr {
+        MSR_FS_BASE => Some(&mut state.fs.base),
+        MSR_GS_BASE => Some(&mut state.gs.base),
+        MSR_SHADOW_GS_BASE => Some(&mut state.kernel_gs_base),
+        MSR_STAR => Some(&mut state.star),
+        MSR_LSTAR => Some(&mut state.lstar),
+        MSR_CSTAR => Some(&mut state.cstar),
+        MSR_SFMASK => Some(&mut state.sf_mask),
+        MSR_IA32_SYSENTER_CS => Some(&mut state.sysenter_cs),
+        MSR_IA32_SYSENTER_ESP => Some(&mut state.sysenter_esp),
+        MSR_IA32_SYSENTER_EIP => Some(&mut state.sysenter_eip),
+        MSR_IA32_PAT => Some(&mut state.g_pat),
+        MSR_IA32_DEBUGCTL => Some(&mut state.dbg_ctl),
+        _ => None,
+    }
+}
+
+// the guest value of a msr,in the vmcb or on the processor
+struct VmcbMsrBacking<'a> {
+    vmcb: &'a mut Vmcb,
+}
+
+impl MsrBacking for VmcbMsrBacking<'_> {
+    fn read(&mut self, msr: u32) -> u64 {
+        match msr {
+            MSR_EFER => self.vmcb.state_save_area.efer & !EFER_SVME,
+            _ => match vmcb_msr(self.vmcb, msr) {
+                Some(field) => *field,
+                None => read_msr(msr),
+            },
+        }
+    }
+
+    // a bad value would fail the next vmrun or #gp the host instead of the guest
+    fn write(&mut self, msr: u32, value: u64) -> Result<(), &'static str> {
+        match msr {
+            MSR_EFER => {
+                let state = &mut self.vmcb.state_save_area;
+                let paging = (state.cr0 & X86_CR0_PG as u64) != 0;
+                state.efer = check_svm_efer_write(state.efer, value, paging)?;
+            }
+            MSR_IA32_PAT => {
+                check_pat_write(value)?;
+                self.vmcb.state_save_area.g_pat = value;
+            }
+            _ => match vmcb_msr(self.vmcb, msr) {
+                Some(field) => *field = value,
+                None => write_msr(msr, value),
+            },
+        }
+        Ok(())
+    }
+}
+
+fn svm_exit_msr(guest_state: &mut SvmGuestState) {
+    let write = guest_state.vmcb().control_area.exit_info1 != 0;
+    let registers = unsafe { &mut *guest_state.guest_regs }.slots_mut();
+    let vcpu = unsafe { &mut *guest_state.vcpu };
+    let mut backing = VmcbMsrBacking {
+        vmcb: unsafe { &mut *guest_state.vmcb },
+    };
+
+    let result = if write {
+        exit_msr_write(registers, vcpu, &mut backing)
+    } else {
+        exit_msr_read(registers, vcpu, &mut backing)
+    };
+    svm_end_exit(guest_state, result, MSR_LENGTH);
+}
+
+fn svm_exit_vmmcall(guest_state: &mut SvmGuestState) {
+    // only the kernel talks to the vmm
+    if guest_state.vmcb().state_save_area.cpl != 0 {
+        svm_inject_ud(guest_state);
+        return;
+    }
+
+    match guest_state.regs().rcx & 0xFFFFFFFF {
+        vm_call::EXIT_VT => {
+            guest_state.exit_pending = true;
+        }
+        INVEPT_SINGLE_CONTEXT | INVEPT_ALL_CONTEXT => {
+            // one asid,so the single context flush is a full one as well
+            guest_state.vmcb().control_area.tlb_control = FLUSH_ALL;
+        }
+        _ => {
+            error!("Unknown vmmcall command");
+        }
+    }
+
+    svm_advance_rip(guest_state, VMMCALL_LENGTH);
+}
+
+// the guest has no svm,every svm instruction is #ud. vmrun is always intercepted
+fn svm_exit_svm_instruction(guest_state: &mut SvmGuestState) {
+    svm_inject_ud(guest_state);
+}
+
+// the npt maps the first 512gb with full rights,a fault is an address above it
+fn svm_exit_npf(guest_state: &mut SvmGuestState) {
+    let vmcb = guest_state.vmcb();
+    warn!(
+        "svm_exit_npf,gpa:{:X},error:{:X},rip:{:X}",
+        vmcb.control_area.exit_info2, vmcb.control_area.exit_info1, vmcb.state_save_area.rip
+    );
+    svm_inject_exception(guest_state, VECTOR_GENERAL_PROTECTION_EXCEPTION, Some(0));
+}
+
+fn svm_exit_unknown(guest_state: &mut SvmGuestState) {
+    warn!(
+        "svm_exit_unknown,code:{:X},rip:{:X}",
+        guest_state.exit_code,
+        guest_state.vmcb().state_save_area.rip
+    );
+    debugbreak!();
+}
+
+fn svm_dispatch_exit(guest_state: &mut SvmGuestState) {
+    match guest_state.exit_code {
+        VMEXIT_CPUID => svm_exit_cpuid(guest_state),
+        VMEXIT_MSR => svm_exit_msr(guest_state),
+        VMEXIT_VMMCALL => svm_exit_vmmcall(guest_state),
+        VMEXIT_VMRUN | VMEXIT_VMLOAD | VMEXIT_VMSAVE | VMEXIT_STGI | VMEXIT_CLGI
+        | VMEXIT_SKINIT => svm_exit_svm_instruction(guest_state),
+        VMEXIT_NPF => svm_exit_npf(guest_state),
+        VMEXIT_SHUTDOWN => {
+            error!("guest shutdown,triple fault");
+            svm_exit_unknown(guest_state);
+        }
+        _ => svm_exit_unknown(guest_state),
+    }
+}
+
+// host rflags after #VMEXIT,interrupts come back with the guest flags
+fn write_rflags(rflags: u64) {
+    unsafe {
+        asm!("push {}", "popfq", in(reg) rflags);
+    }
+}
+
+// one exit of a svm vcpu,true leaves svm on this cpu
+pub(crate) fn svm_vcpu_exit(vcpu: &mut SvmVcpu, context: &mut Context) -> bool {
+    let vmcb = vcpu.guest_vmcb();
+
+    // guest rax lives in the vmcb,svm_launch pushed the vmcb address
+    context.rax = unsafe { (*vmcb).state_save_area.rax };
+    unsafe { (*vmcb).control_area.tlb_control = DO_NOTHING };
+
+    let mut guest_state = SvmGuestState {
+        guest_regs: context,
+        vmcb,
+        vcpu,
+        next_rip: vcpu.next_rip(),
+        exit_code: unsafe { (*vmcb).control_area.exit_code },
+        exit_pending: false,
+    };
+
+    // vmrun refused the guest state before it ran,the launch goes back to start_svm
+    if guest_state.exit_code == VMEXIT_INVALID {
+        error!("CPU:{} vmrun invalid guest state", vcpu.cpu_index());
+        vcpu.set_launch_failed();
+        guest_state.exit_pending = true;
+    } else {
+        svm_dispatch_exit(&mut guest_state);
+    }
+
+    // normal situation
+    if !guest_state.exit_pending {
+        unsafe { (*vmcb).state_save_area.rax = context.rax };
+    }
+
+    guest_state.exit_pending
+}
+
+unsafe extern "C" fn svm_exit_handler(context: &mut Context, vcpu: &mut SvmVcpu) -> u64 {
+    if !vcpu.dispatch_exit(context) {
+        return 0;
+    }
+
+    let vmcb = vcpu.guest_vmcb();
+
+    // guest fs,gs,tr and syscall msrs,cr3 and stack
+    __svm_vmload(vcpu.guest_vmcb_pa());
+    write_cr3((*vmcb).state_save_area.cr3);
+    context.rsp = (*vmcb).state_save_area.rsp;
+
+    // interrupts stay off until the guest rflags are back
+    asm!("cli", options(nostack, nomem));
+    __svm_stgi();
+    write_msr(MSR_EFER, read_msr(MSR_EFER) & !EFER_SVME);
+    vcpu.set_svm_state(VcpuSvmState::SvmStateOff);
+    write_rflags((*vmcb).state_save_area.rflags);
+
+    (*vmcb).state_save_area.rip
+}
+
+impl ExitVmm for SvmVcpu {
+    fn cpu_index(&mut self) -> usize {
+        SvmVcpu::cpu_index(self)
+    }
+
+    fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+        SvmVcpu::msr_shadow_mut(self)
+    }
+}
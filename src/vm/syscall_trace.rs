@@ -0,0 +1,291 @@
+use core::arch::global_asm;
+
+use alloc::{boxed::Box, vec::Vec};
+use moon_driver_utils::rwlock::ReadWriteLock;
+use moon_log::warn;
+use wdk_sys::{
+    ntddk::{KeIpiGenericCall, PsGetCurrentProcessId, PsGetCurrentThreadId},
+    ULONG_PTR,
+};
+
+use crate::symbol::get_ntdll_function_id;
+
+use super::event_ring::EventRing;
+
+// syscall entry runs with if=0 on the user stack,the stub moves to a private stack,
+// records the call and jumps to the original lstar. data slots are rip relative so
+// every cpu gets its own copy of the template
+global_asm!(
+    r#"
+.section .text
+
+.align 16
+syscall_trace_stub:
+    swapgs
+    mov [rip + syscall_trace_saved_rsp], rsp
+    mov rsp, [rip + syscall_trace_stack_top]
+
+    push    rax
+    push    rcx
+    push    rdx
+    push    r8
+    push    r9
+    push    r10
+    push    r11
+
+    mov rcx, rsp
+    mov rdx, [rip + syscall_trace_context]
+    sub rsp, 0x28
+    call [rip + syscall_trace_handler]
+    add rsp, 0x28
+
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdx
+    pop     rcx
+    pop     rax
+
+    mov rsp, [rip + syscall_trace_saved_rsp]
+    swapgs
+    jmp [rip + syscall_trace_target]
+
+.align 8
+syscall_trace_saved_rsp:
+    .quad 0
+syscall_trace_stack_top:
+    .quad 0
+syscall_trace_context:
+    .quad 0
+syscall_trace_handler:
+    .quad 0
+syscall_trace_target:
+    .quad 0
+syscall_trace_stub_end:
+"#
+);
+
+extern "C" {
+    static syscall_trace_stub: u8;
+    static syscall_trace_stack_top: u8;
+    static syscall_trace_context: u8;
+    static syscall_trace_handler: u8;
+    static syscall_trace_target: u8;
+    static syscall_trace_stub_end: u8;
+}
+
+pub const SYSCALL_RING_CAPACITY: usize = 4096;
+const SYSCALL_TRACE_STACK_SIZE: usize = 0x2000;
+const SYSCALL_NAME_LEN: usize = 32;
+
+// resolved to service numbers when tracing starts,unknown numbers are reported without a name
+pub const TRACED_SYSCALL_NAMES: [&str; 24] = [
+    "NtAllocateVirtualMemory",
+    "NtClose",
+    "NtCreateFile",
+    "NtCreateProcessEx",
+    "NtCreateSection",
+    "NtCreateThreadEx",
+    "NtCreateUserProcess",
+    "NtDeviceIoControlFile",
+    "NtFreeVirtualMemory",
+    "NtLoadDriver",
+    "NtMapViewOfSection",
+    "NtOpenFile",
+    "NtOpenKey",
+    "NtOpenProcess",
+    "NtOpenThread",
+    "NtProtectVirtualMemory",
+    "NtQueryInformationProcess",
+    "NtQuerySystemInformation",
+    "NtQueryVirtualMemory",
+    "NtReadFile",
+    "NtReadVirtualMemory",
+    "NtSetValueKey",
+    "NtWriteFile",
+    "NtWriteVirtualMemory",
+];
+
+lazy_static! {
+    // service number,name
+    pub static ref SYSCALL_NAMES: ReadWriteLock<Vec<(u32, &'static str)>> =
+        ReadWriteLock::new(Vec::new());
+}
+
+// volatile registers in push order,rsp points at r11
+#[repr(C)]
+#[allow(unused)]
+struct SyscallFrame {
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rdx: u64,
+    rcx: u64,
+    rax: u64,
+}
+
+// layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallEvent {
+    pub service: u32,
+    pub cpu_index: u32,
+    pub process_id: u64,
+    pub thread_id: u64,
+    pub args: [u64; 4],
+    // zero terminated,empty when the number is not in the name table
+    pub name: [u8; SYSCALL_NAME_LEN],
+}
+
+impl Default for SyscallEvent {
+    fn default() -> Self {
+        Self {
+            service: 0,
+            cpu_index: 0,
+            process_id: 0,
+            thread_id: 0,
+            args: [0; 4],
+            name: [0; SYSCALL_NAME_LEN],
+        }
+    }
+}
+
+impl SyscallEvent {
+    pub fn set_name(&mut self, name: &str) {
+        let len = name.len().min(SYSCALL_NAME_LEN - 1);
+        self.name = [0; SYSCALL_NAME_LEN];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+}
+
+extern "C" fn syscall_trace_record(frame: &SyscallFrame, trace: &SyscallTraceCpu) {
+    let event = SyscallEvent {
+        service: frame.rax as _,
+        cpu_index: trace.cpu_index,
+        process_id: unsafe { PsGetCurrentProcessId() } as _,
+        thread_id: unsafe { PsGetCurrentThreadId() } as _,
+        // syscall moves rcx to r10,rcx holds the user return address
+        args: [frame.r10, frame.rdx, frame.r8, frame.r9],
+        ..Default::default()
+    };
+
+    trace.ring.push(event);
+}
+
+// kiSystemCall64Shadow switches cr3 right after swapgs,our stub is not mapped in the user cr3
+pub fn is_kva_shadow_entry(lstar: u64) -> bool {
+    let code = unsafe { core::slice::from_raw_parts(lstar as *const u8, 0x40) };
+    code.windows(3).any(|w| w == [0x0F, 0x22, 0xDC]) // mov cr3,rsp
+}
+
+// fill the service number table,passive level
+pub fn resolve_syscall_names() {
+    let names: Vec<(u32, &'static str)> = TRACED_SYSCALL_NAMES
+        .iter()
+        .filter_map(|name| match get_ntdll_function_id(name) {
+            u32::MAX => {
+                warn!("syscall {} not found", name);
+                None
+            }
+            id => Some((id, *name)),
+        })
+        .collect();
+
+    *SYSCALL_NAMES.write() = names;
+}
+
+pub fn syscall_name(service: u32) -> Option<&'static str> {
+    SYSCALL_NAMES
+        .read()
+        .iter()
+        .find(|(id, _)| *id == service)
+        .map(|(_, name)| *name)
+}
+
+unsafe extern "C" fn syscall_trace_quiesce(_argument: ULONG_PTR) -> ULONG_PTR {
+    0
+}
+
+// passive level,after lstar was restored on every cpu. the stub runs with if=0,so once every
+// cpu took an ipi none of them is still inside a stub and the stubs can be freed
+pub fn wait_for_stub_quiescence() {
+    unsafe { KeIpiGenericCall(Some(syscall_trace_quiesce), 0) };
+}
+
+// filled by the stub of one cpu
+pub type SyscallRing = EventRing<SyscallEvent>;
+
+// per cpu stub,stack and ring. freed when tracing is turned off,a syscall can still be inside
+// the stub right after lstar is restored so wait_for_stub_quiescence runs first
+pub struct SyscallTraceCpu {
+    cpu_index: u32,
+    stub: Vec<u8>,
+    stack: Vec<u8>,
+    pub ring: SyscallRing,
+    enabled: bool,
+}
+
+fn template_offset(label: *const u8) -> usize {
+    label as usize - unsafe { core::ptr::addr_of!(syscall_trace_stub) } as usize
+}
+
+impl SyscallTraceCpu {
+    pub fn new(cpu_index: usize) -> Box<Self> {
+        let template = unsafe {
+            let start = core::ptr::addr_of!(syscall_trace_stub);
+            let end = core::ptr::addr_of!(syscall_trace_stub_end);
+            core::slice::from_raw_parts(start, end as usize - start as usize)
+        };
+
+        let mut trace = Box::new(Self {
+            cpu_index: cpu_index as _,
+            stub: template.to_vec(),
+            stack: alloc::vec![0u8; SYSCALL_TRACE_STACK_SIZE],
+            ring: SyscallRing::new(SYSCALL_RING_CAPACITY),
+            enabled: false,
+        });
+
+        let stack_top = (trace.stack.as_ptr() as u64 + SYSCALL_TRACE_STACK_SIZE as u64) & !0xF;
+        let context = trace.as_ref() as *const Self as u64;
+        trace.patch(
+            unsafe { core::ptr::addr_of!(syscall_trace_stack_top) },
+            stack_top,
+        );
+        trace.patch(
+            unsafe { core::ptr::addr_of!(syscall_trace_context) },
+            context,
+        );
+        trace.patch(
+            unsafe { core::ptr::addr_of!(syscall_trace_handler) },
+            syscall_trace_record as u64,
+        );
+
+        trace
+    }
+
+    fn patch(&mut self, label: *const u8, value: u64) {
+        let offset = template_offset(label);
+        let slot = self.stub[offset..offset + 8].as_mut_ptr() as *mut u64;
+        unsafe { core::ptr::write_unaligned(slot, value) };
+    }
+
+    pub fn stub_address(&self) -> u64 {
+        self.stub.as_ptr() as _
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // where the stub continues,the lstar value the guest believes in
+    pub fn set_target(&mut self, target: u64) {
+        self.patch(unsafe { core::ptr::addr_of!(syscall_trace_target) }, target);
+    }
+
+    // vmx root,called on the cpu that owns the stub
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
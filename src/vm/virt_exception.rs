@@ -0,0 +1,369 @@
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::boxed::Box;
+use moon_log::error;
+use wdk_sys::{
+    ntddk::{memset, MmAllocateContiguousMemory, MmFreeContiguousMemory},
+    PAGE_SIZE, PHYSICAL_ADDRESS,
+};
+
+use crate::{
+    utils::{get_current_processor_idx, virtual_address_to_physical_address},
+    __GD,
+};
+
+use super::{
+    data::{ept_violation_qualification::LINEAR_ADDRESS_VALID, vm_call::VIRT_EXCEPTION_SUPPRESS},
+    desc_table::DescriptorTableRegister,
+    ept_violation::EptViolation,
+    ins::{VmxInstructionResult, __vmx_vmcall},
+};
+
+// #ve has no error code and runs through an interrupt gate,so if=0 until iretq. the cpu
+// aligns rsp before pushing the 5 qword frame,7 pushes and the xmm area keep the call aligned
+global_asm!(
+    r#"
+.section .text
+
+.align 16
+virt_exception_stub:
+    test byte ptr [rsp + 8], 1
+    jz 1f
+    swapgs
+1:
+    push    rax
+    push    rcx
+    push    rdx
+    push    r8
+    push    r9
+    push    r10
+    push    r11
+
+    sub rsp, 0x60
+    movdqu [rsp], xmm0
+    movdqu [rsp + 0x10], xmm1
+    movdqu [rsp + 0x20], xmm2
+    movdqu [rsp + 0x30], xmm3
+    movdqu [rsp + 0x40], xmm4
+    movdqu [rsp + 0x50], xmm5
+
+    lea rcx, [rsp + 0x60]
+    sub rsp, 0x20
+    call {}
+    add rsp, 0x20
+
+    movdqu xmm0, [rsp]
+    movdqu xmm1, [rsp + 0x10]
+    movdqu xmm2, [rsp + 0x20]
+    movdqu xmm3, [rsp + 0x30]
+    movdqu xmm4, [rsp + 0x40]
+    movdqu xmm5, [rsp + 0x50]
+    add rsp, 0x60
+
+    pop     r11
+    pop     r10
+    pop     r9
+    pop     r8
+    pop     rdx
+    pop     rcx
+    pop     rax
+
+    test byte ptr [rsp + 8], 1
+    jz 2f
+    swapgs
+2:
+    iretq
+"#,
+    sym virt_exception_dispatch
+);
+
+extern "C" {
+    static virt_exception_stub: u8;
+}
+
+pub fn virt_exception_stub_address() -> u64 {
+    unsafe { core::ptr::addr_of!(virt_exception_stub) as u64 }
+}
+
+// the cpu only delivers a #ve while busy is zero and sets it to this value
+pub const VE_BUSY: u32 = 0xFFFF_FFFF;
+
+// virtualization exception information area,one page per vcpu
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VeInformation {
+    // always ept violation
+    pub exit_reason: u32,
+    pub busy: u32,
+    // same layout as the ept violation exit qualification
+    pub exit_qualification: u64,
+    pub guest_linear_address: u64,
+    pub guest_physical_address: u64,
+    pub eptp_index: u16,
+}
+
+// what the guest handler is told about one #ve
+#[derive(Debug, Clone, Copy)]
+pub struct VeFault {
+    pub cpu_index: u32,
+    pub rip: u64,
+    // cpl 3 when the access happened
+    pub user: bool,
+    pub violation: EptViolation,
+    pub linear_address: Option<u64>,
+    pub physical_address: u64,
+    pub eptp_index: u16,
+}
+
+// guest context with if=0,any irql. true when the fault was resolved and the instruction can
+// run again,false stops #ve for the page so the access exits to the hypervisor instead
+pub type VeCallback = fn(fault: &VeFault) -> bool;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VeCounts {
+    pub delivered: u64,
+    // the callback gave the page back
+    pub unhandled: u64,
+    // violations on selected pages that exited,the handler was still busy
+    pub missed: u64,
+}
+
+// volatile registers in push order followed by the interrupt frame
+#[repr(C)]
+#[allow(unused)]
+struct VeFrame {
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rdx: u64,
+    rcx: u64,
+    rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+// 64-bit idt gate
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct IdtGate {
+    pub low: u64,
+    pub high: u64,
+}
+
+const IDT_GATE_SIZE: u64 = 16;
+const IDT_GATE_IST: u64 = 0x7 << 32;
+const IDT_GATE_TYPE_INTERRUPT: u64 = 0xE << 40;
+const IDT_GATE_PRESENT: u64 = 1 << 47;
+
+impl IdtGate {
+    pub fn address(idt_base: u64, vector: u8) -> u64 {
+        idt_base + vector as u64 * IDT_GATE_SIZE
+    }
+
+    pub fn from_bytes(bytes: &[u8; 16]) -> Self {
+        Self {
+            low: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            high: u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.low.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.high.to_le_bytes());
+        bytes
+    }
+
+    pub fn is_present(&self) -> bool {
+        (self.low & IDT_GATE_PRESENT) != 0
+    }
+
+    pub fn handler(&self) -> u64 {
+        (self.low & 0xFFFF) | ((self.low >> 32) & 0xFFFF_0000) | ((self.high & 0xFFFF_FFFF) << 32)
+    }
+
+    // dpl 0 interrupt gate,keeps the ist stack of a present gate
+    pub fn with_handler(&self, handler: u64, selector: u16) -> Self {
+        let ist = if self.is_present() {
+            self.low & IDT_GATE_IST
+        } else {
+            0
+        };
+
+        Self {
+            low: (handler & 0xFFFF)
+                | ((selector as u64) << 16)
+                | ist
+                | IDT_GATE_TYPE_INTERRUPT
+                | IDT_GATE_PRESENT
+                | ((handler & 0xFFFF_0000) << 32),
+            high: handler >> 32,
+        }
+    }
+}
+
+// per vcpu information page and idt copy,freed only after #ve is off on the cpu
+pub struct VirtExceptionCpu {
+    info: *mut VeInformation,
+    physical: u64,
+    // copy of the guest idt with the stub in gate 20,the guest table is never written
+    idt: *mut u8,
+    // idtr loaded before the copy,sidt reports it
+    original_idtr: Option<DescriptorTableRegister>,
+    delivered: AtomicU64,
+    unhandled: AtomicU64,
+    missed: AtomicU64,
+}
+
+impl VirtExceptionCpu {
+    // passive level
+    pub fn new() -> Result<Box<Self>, &'static str> {
+        let mut max_size: PHYSICAL_ADDRESS = PHYSICAL_ADDRESS::default();
+        max_size.QuadPart = i64::MAX;
+
+        let info: *mut VeInformation =
+            unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+        if info.is_null() {
+            return Err("error to allocate #ve information page");
+        }
+        // busy starts cleared
+        unsafe { memset(info as _, 0, PAGE_SIZE as _) };
+
+        let idt: *mut u8 = unsafe { MmAllocateContiguousMemory(PAGE_SIZE as _, max_size) } as _;
+        if idt.is_null() {
+            unsafe { MmFreeContiguousMemory(info as _) };
+            return Err("error to allocate #ve idt");
+        }
+        unsafe { memset(idt as _, 0, PAGE_SIZE as _) };
+
+        Ok(Box::new(Self {
+            info,
+            physical: virtual_address_to_physical_address(info as _),
+            idt,
+            original_idtr: None,
+            delivered: AtomicU64::new(0),
+            unhandled: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn physical_address(&self) -> u64 {
+        self.physical
+    }
+
+    pub fn original_idtr(&self) -> Option<DescriptorTableRegister> {
+        self.original_idtr
+    }
+
+    pub fn idt_address(&self) -> u64 {
+        self.idt as u64
+    }
+
+    pub fn idt_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.idt, PAGE_SIZE as _) }
+    }
+
+    // table holds the guest idt,the copy takes it with the stub in the #ve gate
+    pub fn load_idt(
+        &mut self,
+        idtr: DescriptorTableRegister,
+        vector: u8,
+        selector: u16,
+    ) -> Result<(), &'static str> {
+        let offset = IdtGate::address(0, vector) as usize;
+        let len = idtr.limit as usize + 1;
+        if len > PAGE_SIZE as usize || len < offset + IDT_GATE_SIZE as usize {
+            return Err("idt limit does not fit the #ve gate");
+        }
+
+        let gate_bytes = &mut self.idt_mut()[offset..offset + IDT_GATE_SIZE as usize];
+        let original = IdtGate::from_bytes(gate_bytes.try_into().unwrap());
+        let gate = original.with_handler(virt_exception_stub_address(), selector);
+        gate_bytes.copy_from_slice(&gate.to_bytes());
+
+        self.original_idtr = Some(idtr);
+        Ok(())
+    }
+
+    pub fn unload_idt(&mut self) -> Option<DescriptorTableRegister> {
+        self.original_idtr.take()
+    }
+
+    // copy the area before it is re-armed
+    fn read(&self) -> VeInformation {
+        unsafe { core::ptr::read_volatile(self.info) }
+    }
+
+    // the next violation on a selected page raises #ve again
+    fn rearm(&self) {
+        unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!((*self.info).busy), 0) };
+    }
+
+    pub fn count_missed(&self) {
+        self.missed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_counts(&self, counts: &mut VeCounts) {
+        counts.delivered += self.delivered.load(Ordering::Relaxed);
+        counts.unhandled += self.unhandled.load(Ordering::Relaxed);
+        counts.missed += self.missed.load(Ordering::Relaxed);
+    }
+}
+
+impl Drop for VirtExceptionCpu {
+    fn drop(&mut self) {
+        unsafe {
+            MmFreeContiguousMemory(self.info as _);
+            MmFreeContiguousMemory(self.idt as _);
+        }
+    }
+}
+
+extern "C" fn virt_exception_dispatch(frame: &mut VeFrame) {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let cpu_index = get_current_processor_idx();
+
+    let cpu = match vmm.vcpu[cpu_index as usize].virt_exception() {
+        Some(cpu) => cpu,
+        None => return,
+    };
+    cpu.delivered.fetch_add(1, Ordering::Relaxed);
+
+    let info = cpu.read();
+    let fault = VeFault {
+        cpu_index,
+        rip: frame.rip,
+        user: (frame.cs & 3) != 0,
+        violation: EptViolation::decode(
+            info.exit_qualification,
+            vmm.mode_based_execute,
+            vmm.vmx_features.ept_violation_info,
+        ),
+        linear_address: ((info.exit_qualification & LINEAR_ADDRESS_VALID) != 0)
+            .then_some(info.guest_linear_address),
+        physical_address: info.guest_physical_address,
+        eptp_index: info.eptp_index,
+    };
+
+    let handled = match vmm.virt_exception_callback {
+        Some(callback) => callback(&fault),
+        None => false,
+    };
+
+    if !handled {
+        cpu.unhandled.fetch_add(1, Ordering::Relaxed);
+        let page = fault.physical_address & !(PAGE_SIZE as u64 - 1);
+        match __vmx_vmcall(VIRT_EXCEPTION_SUPPRESS, page, 0, 0) {
+            VmxInstructionResult::VmxSuccess => {}
+            _ => error!("Vmxcall execute error"),
+        }
+    }
+
+    cpu.rearm();
+}
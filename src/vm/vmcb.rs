@@ -0,0 +1,124 @@
+use core::mem::{offset_of, size_of};
+
+pub use moon_vm::vmcb::*;
+
+// amd apm vol 2 appendix b. vmrun,vmload and vmsave take its physical address,the cpu keeps
+// parts of it cached between runs while the clean bits say so. we never set clean bits
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VmcbSegment {
+    pub selector: u16,
+    // descriptor bits 40-47 and 52-55 packed into 12 bits
+    pub attrib: u16,
+    pub limit: u32,
+    pub base: u64,
+}
+
+#[repr(C)]
+pub struct VmcbControlArea {
+    pub intercept_cr_read: u16,
+    pub intercept_cr_write: u16,
+    pub intercept_dr_read: u16,
+    pub intercept_dr_write: u16,
+    pub intercept_exception: u32,
+    pub intercept_misc1: u32,
+    pub intercept_misc2: u32,
+    pub intercept_misc3: u32,
+    reserved1: [u8; 0x24],
+    pub pause_filter_threshold: u16,
+    pub pause_filter_count: u16,
+    pub iopm_base_pa: u64,
+    pub msrpm_base_pa: u64,
+    pub tsc_offset: u64,
+    pub guest_asid: u32,
+    pub tlb_control: u32,
+    pub vintr: u64,
+    pub interrupt_shadow: u64,
+    pub exit_code: u64,
+    pub exit_info1: u64,
+    pub exit_info2: u64,
+    pub exit_int_info: u64,
+    pub np_enable: u64,
+    pub avic_apic_bar: u64,
+    pub ghcb_pa: u64,
+    pub event_inj: u64,
+    pub n_cr3: u64,
+    pub lbr_virtualization_enable: u64,
+    pub vmcb_clean: u64,
+    // rip after the intercepted instruction,with next rip save
+    pub n_rip: u64,
+    pub num_of_bytes_fetched: u8,
+    pub guest_instruction_bytes: [u8; 15],
+    reserved2: [u8; 0x320],
+}
+
+#[repr(C)]
+pub struct VmcbStateSaveArea {
+    pub es: VmcbSegment,
+    pub cs: VmcbSegment,
+    pub ss: VmcbSegment,
+    pub ds: VmcbSegment,
+    pub fs: VmcbSegment,
+    pub gs: VmcbSegment,
+    pub gdtr: VmcbSegment,
+    pub ldtr: VmcbSegment,
+    pub idtr: VmcbSegment,
+    pub tr: VmcbSegment,
+    reserved1: [u8; 0x2B],
+    pub cpl: u8,
+    reserved2: u32,
+    pub efer: u64,
+    reserved3: [u8; 0x70],
+    pub cr4: u64,
+    pub cr3: u64,
+    pub cr0: u64,
+    pub dr7: u64,
+    pub dr6: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    reserved4: [u8; 0x58],
+    pub rsp: u64,
+    reserved5: [u8; 0x18],
+    pub rax: u64,
+    pub star: u64,
+    pub lstar: u64,
+    pub cstar: u64,
+    pub sf_mask: u64,
+    pub kernel_gs_base: u64,
+    pub sysenter_cs: u64,
+    pub sysenter_esp: u64,
+    pub sysenter_eip: u64,
+    pub cr2: u64,
+    reserved6: [u8; 0x20],
+    pub g_pat: u64,
+    pub dbg_ctl: u64,
+    pub br_from: u64,
+    pub br_to: u64,
+    pub last_excep_from: u64,
+    pub last_excep_to: u64,
+    reserved7: [u8; 0x968],
+}
+
+#[repr(C)]
+#[repr(align(0x1000))]
+pub struct Vmcb {
+    pub control_area: VmcbControlArea,
+    pub state_save_area: VmcbStateSaveArea,
+}
+
+// a wrong reserved length moves every field after it
+const _: () = assert!(size_of::<VmcbControlArea>() == 0x400);
+const _: () = assert!(offset_of!(VmcbControlArea, iopm_base_pa) == 0x40);
+const _: () = assert!(offset_of!(VmcbControlArea, exit_code) == 0x70);
+const _: () = assert!(offset_of!(VmcbControlArea, n_cr3) == 0xB0);
+const _: () = assert!(offset_of!(VmcbControlArea, n_rip) == 0xC8);
+const _: () = assert!(size_of::<VmcbStateSaveArea>() == 0xC00);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, cpl) == 0xCB);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, efer) == 0xD0);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, cr4) == 0x148);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, rip) == 0x178);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, rsp) == 0x1D8);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, rax) == 0x1F8);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, cr2) == 0x240);
+const _: () = assert!(offset_of!(VmcbStateSaveArea, g_pat) == 0x268);
+const _: () = assert!(size_of::<Vmcb>() == 0x1000);
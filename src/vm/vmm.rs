@@ -1,27 +1,25 @@
 use core::arch::global_asm;
 
+use alloc::vec::Vec;
 use moon_driver_utils::{bitfield::set_bits_value32, page_align};
-use moon_instructions::{cpuidex, debugbreak, lgdt, lidt, read_msr, write_cr3, write_msr};
-use moon_log::{error, warn};
+use moon_instructions::{debugbreak, lgdt, lidt, ltr, read_cr8, write_cr2, write_cr3, write_cr8};
+use moon_log::{error, info, warn};
 use moon_struct::{
     inner::KDESCRIPTOR,
-    msr::{
-        self,
-        ia32_feature_control_msr::{MSR_IA32_FEATURE_CONTROL_LOCK, MSR_IA32_FEATURE_CONTROL_VMXON},
-        msr_index::{MSR_FS_BASE, MSR_GS_BASE, MSR_IA32_DEBUGCTL, MSR_IA32_FEATURE_CONTROL},
-    },
+    x86::{X86_CR0_WP, X86_CR4_SMAP, X86_CR4_SMEP},
 };
-use wdk_sys::{ntddk::KeGetCurrentIrql, LARGE_INTEGER};
+use moon_vm::leave_vmx::leave_vmx_to_guest;
+use wdk_sys::ntddk::KeGetCurrentIrql;
 
 use crate::{
-    utils::virtual_address_to_physical_address,
+    utils::{get_current_processor_idx, virtual_address_to_physical_address},
     vm::{
         data::{
+            exit_reason::EXIT_REASON_PREEMPT_TIMER,
             vmcs_encoding::{
                 EXIT_QUALIFICATION, GUEST_LINEAR_ADDRESS, GUEST_PHYSICAL_ADDRESS, GUEST_RFLAGS,
                 GUEST_RIP, GUEST_RSP, VM_EXIT_REASON,
             },
-            TYPE_CR_READ, TYPE_CR_WRITE,
         },
         ins::vmcs_read,
     },
@@ -29,22 +27,78 @@ use crate::{
 };
 
 use super::{
+    backend::BackendVcpu,
+    code_integrity::{CodeIntegrityMonitor, CodeIntegrityPolicy, CodeWriteEvent},
+    cr_access::{ControlRegisterMasks, Cr3Load},
     data::{
-        interrupt_inject_info::{TYPE_LEN, TYPE_START, VALID, VECTOR_LEN, VECTOR_START},
-        interrupt_type::INTERRUPT_HARDWARE_EXCEPTION,
-        mov_cr_qualification,
-        page_hook_attrib::{PAGE_ATTRIBE_EXECUTE, PAGE_ATTRIBE_READ, PAGE_ATTRIBE_WRITE},
-        vector_exception::VECTOR_INVALID_OPCODE_EXCEPTION,
+        ept_violation_qualification,
+        interrupt_inject_info::{
+            DELIVER_ERROR_CODE, NMI_UNBLOCKING_DUE_TO_IRET, TYPE_LEN, TYPE_START, VALID,
+            VECTOR_LEN, VECTOR_START,
+        },
+        interrupt_type::{INTERRUPT_HARDWARE_EXCEPTION, INTERRUPT_NMI},
+        ptee,
+        vector_exception::{
+            VECTOR_GENERAL_PROTECTION_EXCEPTION, VECTOR_INVALID_OPCODE_EXCEPTION,
+            VECTOR_PAGE_FAULT_EXCEPTION, VECTOR_SEGMENT_NOT_PRESENT,
+            VECTOR_VIRTUALIZATION_EXCEPTION,
+        },
         vm_call::{self, INVEPT_ALL_CONTEXT, INVEPT_SINGLE_CONTEXT},
         vmcs_encoding::{
-            CR0_READ_SHADOW, CR4_READ_SHADOW, GUEST_CR0, GUEST_CR3, GUEST_CR4, GUEST_FS_BASE,
-            GUEST_GDTR_BASE, GUEST_GDTR_LIMIT, GUEST_GS_BASE, GUEST_IA32_DEBUGCTL, GUEST_IDTR_BASE,
-            GUEST_IDTR_LIMIT, VM_ENTRY_INSTRUCTION_LEN, VM_ENTRY_INTR_INFO_FIELD,
-            VM_EXIT_INSTRUCTION_LEN,
+            CPU_BASED_VM_EXEC_CONTROL, EPTP_INDEX, EPTP_LIST_ADDRESS, EPT_POINTER, GUEST_CR0,
+            GUEST_CR3, GUEST_CR4, GUEST_CS_AR_BYTES, GUEST_CS_BASE, GUEST_CS_SELECTOR,
+            GUEST_DS_BASE, GUEST_ES_BASE, GUEST_FS_BASE, GUEST_GDTR_BASE, GUEST_GDTR_LIMIT,
+            GUEST_GS_BASE, GUEST_IDTR_BASE, GUEST_IDTR_LIMIT, GUEST_INTERRUPTIBILITY_INFO,
+            GUEST_LDTR_AR_BYTES, GUEST_LDTR_BASE, GUEST_LDTR_LIMIT, GUEST_LDTR_SELECTOR,
+            GUEST_PML_INDEX, GUEST_SS_AR_BYTES, GUEST_SS_BASE, GUEST_TR_AR_BYTES, GUEST_TR_BASE,
+            GUEST_TR_LIMIT, GUEST_TR_SELECTOR, IDT_VECTORING_ERROR_CODE, IDT_VECTORING_INFO_FIELD,
+            PML_ADDRESS, SECONDARY_VM_EXEC_CONTROL, SPPT_POINTER,
+            VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS, VMX_INSTRUCTION_INFO,
+            VM_ENTRY_EXCEPTION_ERROR_CODE, VM_ENTRY_INSTRUCTION_LEN, VM_ENTRY_INTR_INFO_FIELD,
+            VM_EXIT_INSTRUCTION_LEN, VM_EXIT_INTR_INFO, VM_FUNCTION_CONTROLS,
+        },
+        vmx_cpu_based_controls::{
+            VMX_PROC_CTLS_CR3_LOAD_EXIT, VMX_PROC_CTLS_MONITOR_TRAP_FLAG,
+            VMX_PROC_CTLS_NMI_WINDOW_EXIT,
         },
+        vmx_secondary_cpu_based_controls::{
+            VMX_PROC_CTLS2_EPT_XCPT_VE, VMX_PROC_CTLS2_PML, VMX_PROC_CTLS2_SPP_EPT,
+            VMX_PROC_CTLS2_VMFUNC,
+        },
+    },
+    desc_table::{
+        decode_desc_table_access, system_descriptor_address, DescTableAccess, DescTableFault,
+        DescTableInstruction, DescTableOperand, DescriptorTableRegister, SystemDescriptor,
+        DESC_TYPE_TSS_BUSY, SEGMENT_AR_LONG_MODE, SEGMENT_AR_UNUSABLE,
+    },
+    dirty_log::{DirtyLogCommand, BLOCKING_BY_NMI, PML_FULL_NMI_UNBLOCKING, PML_INDEX_START},
+    dump::dump_current_vmcs,
+    entry_check::read_vmx_capabilities,
+    ept::{
+        EptPermissions, EptRestore, EptState, InveptDescriptor, EPT_ACCESS_MASK,
+        EPT_ENTRY_EXEC_BREAKPOINT, EPT_ENTRY_HIDDEN, EPT_ENTRY_MONITORED, EPT_ENTRY_PROTECTED,
+        EPT_ENTRY_WATCHPOINT, EPT_EXECUTE_MASK,
+    },
+    ept_view::VMFUNC_EPTP_SWITCHING_BIT,
+    ept_violation::EptViolation,
+    exec_breakpoint::{ExecBreakpointHit, GuestRegisters},
+    exit_handler::{
+        exit_cpuid, exit_cr_access, exit_msr_read, exit_msr_write, inject_exception, CrAccessVmm,
+        ExitResult, ExitVmm, GuestRegisterSlots, HardwareVmcs, ProcessorCpuid, ProcessorMsrs,
+        VmcsMsrBacking,
+    },
+    exit_trace::{self, ExitRecord, RecordingSource, ReplayMismatch, EXIT_TRACE_REGISTER_COUNT},
+    guest_memory::{
+        read_guest_memory, write_guest_memory, GuestAccess, GuestMapping, GuestPageFault, RFLAGS_AC,
     },
-    ept::InveptDescriptor,
     ins::{VmxInstructionResult, __invept, __vmx_off, __vmx_vmwrite},
+    msr_bitmap::{check_msr_intercept, MsrAccess},
+    msr_shadow::{MsrBacking, MsrShadowTable, EFER_NXE, MSR_EFER},
+    process_view::VIEW_OWNER_CODE,
+    spp::{SubPageWrite, SPP_EVENT_MISCONFIG, SPP_EVENT_NMI_UNBLOCKING},
+    vmx::{Vcpu, Vmm},
+    vpid::flush_guest_context,
+    watchpoint::{WatchEvent, WATCH_READ, WATCH_WRITE},
 };
 
 global_asm!(r#"
@@ -91,24 +145,6 @@ global_asm!(r#"
     add     rsp, 8  // rsp
 .endm
 
-.macro popaq_exit
-    pop     r15
-    pop     r14
-    pop     r13
-    pop     r12
-    pop     r11
-    pop     r10
-    pop     r9
-    pop     r8
-    pop     rdi
-    pop     rsi
-    pop     rbp
-       
-    pop     rbx
-    pop     rdx
-    pop     rcx
-.endm
-
 vmm_entry_point:
     pushaq
     mov rcx, rsp
@@ -141,34 +177,50 @@ vmm_entry_point:
     int 3
 
 exit_branch:
-    popaq_exit
-    add rsp, 8      // rax
-    pop rsp         // rsp
-    jmp rax         // guest_rip
+    mov rdx, rax    // guest_rip
+    mov rcx, rsp    // the registers pushaq saved
+    jmp {}
 
     int 3
-"#,sym vmx_exit_handler);
+"#,sym vmx_exit_handler, sym leave_vmx_to_guest);
 
+// registers pushed by pushaq,svm_exit pushes the same layout
 #[repr(C)]
 #[derive(Debug)]
-struct Context {
-    r15: u64,
-    r14: u64,
-    r13: u64,
-    r12: u64,
-    r11: u64,
-    r10: u64,
-    r9: u64,
-    r8: u64,
-    rdi: u64,
-    rsi: u64,
-    rbp: u64,
-
-    rbx: u64,
-    rdx: u64,
-    rcx: u64,
-    rax: u64,
-    rsp: u64,
+pub(crate) struct Context {
+    pub(crate) r15: u64,
+    pub(crate) r14: u64,
+    pub(crate) r13: u64,
+    pub(crate) r12: u64,
+    pub(crate) r11: u64,
+    pub(crate) r10: u64,
+    pub(crate) r9: u64,
+    pub(crate) r8: u64,
+    pub(crate) rdi: u64,
+    pub(crate) rsi: u64,
+    pub(crate) rbp: u64,
+
+    pub(crate) rbx: u64,
+    pub(crate) rdx: u64,
+    pub(crate) rcx: u64,
+    pub(crate) rax: u64,
+    pub(crate) rsp: u64,
+}
+
+impl Context {
+    // in push order,the layout of exit trace records
+    fn registers(&self) -> [u64; EXIT_TRACE_REGISTER_COUNT] {
+        [
+            self.r15, self.r14, self.r13, self.r12, self.r11, self.r10, self.r9, self.r8, self.rdi,
+            self.rsi, self.rbp, self.rbx, self.rdx, self.rcx, self.rax, self.rsp,
+        ]
+    }
+
+    // the same registers as the moon-vm handlers index them
+    pub(crate) fn slots_mut(&mut self) -> &mut GuestRegisterSlots {
+        // repr(C) and sixteen u64 in push order
+        unsafe { &mut *(self as *mut Self as *mut GuestRegisterSlots) }
+    }
 }
 
 #[allow(unused)]
@@ -184,6 +236,63 @@ struct GuestState {
     exit_reason: u16,
     exit_qualification: u64,
     exit_pending: bool,
+    // what cpuid and rdmsr returned to the handler,kept for the exit trace
+    cpuid: [u32; 4],
+    msr_value: u64,
+}
+
+fn guest_registers(guest_state: &GuestState) -> GuestRegisters {
+    let context = unsafe { guest_state.guest_regs.as_ref().unwrap() };
+
+    GuestRegisters {
+        rax: context.rax,
+        rcx: context.rcx,
+        rdx: context.rdx,
+        rbx: context.rbx,
+        rsp: guest_state.guest_rsp,
+        rbp: context.rbp,
+        rsi: context.rsi,
+        rdi: context.rdi,
+        r8: context.r8,
+        r9: context.r9,
+        r10: context.r10,
+        r11: context.r11,
+        r12: context.r12,
+        r13: context.r13,
+        r14: context.r14,
+        r15: context.r15,
+        rip: guest_state.guest_rip,
+        rflags: guest_state.guest_rflags,
+        cr3: vmcs_read(GUEST_CR3),
+    }
+}
+
+// cr3 is not written back
+fn set_guest_registers(guest_state: &mut GuestState, registers: &GuestRegisters) {
+    let context = unsafe { guest_state.guest_regs.as_mut().unwrap() };
+
+    context.rax = registers.rax;
+    context.rcx = registers.rcx;
+    context.rdx = registers.rdx;
+    context.rbx = registers.rbx;
+    context.rbp = registers.rbp;
+    context.rsi = registers.rsi;
+    context.rdi = registers.rdi;
+    context.r8 = registers.r8;
+    context.r9 = registers.r9;
+    context.r10 = registers.r10;
+    context.r11 = registers.r11;
+    context.r12 = registers.r12;
+    context.r13 = registers.r13;
+    context.r14 = registers.r14;
+    context.r15 = registers.r15;
+
+    guest_state.guest_rsp = registers.rsp;
+    guest_state.guest_rip = registers.rip;
+    guest_state.guest_rflags = registers.rflags;
+    __vmx_vmwrite(GUEST_RSP, registers.rsp);
+    __vmx_vmwrite(GUEST_RIP, registers.rip);
+    __vmx_vmwrite(GUEST_RFLAGS, registers.rflags);
 }
 
 fn vmx_advance_eip(guest_state: &mut GuestState) {
@@ -191,7 +300,7 @@ fn vmx_advance_eip(guest_state: &mut GuestState) {
     __vmx_vmwrite(GUEST_RIP, guest_state.guest_rip);
 }
 
-fn vmx_inject_event(interrupt_type: u32, vector_exception: u8, write_length: u32) {
+pub(crate) fn vmx_inject_event(interrupt_type: u32, vector_exception: u8, write_length: u32) {
     let mut inject_event: u32 = 0;
 
     inject_event = set_bits_value32(
@@ -209,21 +318,47 @@ fn vmx_inject_event(interrupt_type: u32, vector_exception: u8, write_length: u32
     }
 }
 
-fn vm_exit_cpuid(guest_state: &mut GuestState) {
-    let cpuinfo = cpuidex(
-        unsafe { guest_state.guest_regs.as_ref().unwrap().rax as _ },
-        unsafe { guest_state.guest_regs.as_ref().unwrap().rcx as _ },
-    );
+// hardware exception with an error code,rip is left on the faulting instruction
+fn vmx_inject_fault(vector_exception: u8, error_code: u32) {
+    inject_exception(&mut HardwareVmcs, vector_exception, Some(error_code));
+}
 
-    unsafe {
-        let reg = guest_state.guest_regs.as_mut().unwrap();
-        reg.rax = cpuinfo.eax as _;
-        reg.rbx = cpuinfo.ebx as _;
-        reg.rcx = cpuinfo.ecx as _;
-        reg.rdx = cpuinfo.edx as _;
+// #gp(0)
+fn vmx_inject_gp() {
+    vmx_inject_fault(VECTOR_GENERAL_PROTECTION_EXCEPTION, 0);
+}
+
+// vm-entry does not touch cr2,root and guest share it
+fn vmx_inject_page_fault(fault: GuestPageFault) {
+    write_cr2(fault.address);
+    vmx_inject_fault(VECTOR_PAGE_FAULT_EXCEPTION, fault.error_code);
+}
+
+// how the moon-vm handlers end an exit,rip stays cached in the guest state
+fn vmx_end_exit(guest_state: &mut GuestState, result: ExitResult) {
+    match result {
+        ExitResult::Advance => vmx_advance_eip(guest_state),
+        ExitResult::Ignored(reason) => {
+            warn!("{}", reason);
+            vmx_advance_eip(guest_state);
+        }
+        ExitResult::Fault {
+            vector,
+            error_code,
+            reason,
+        } => {
+            warn!("{}", reason);
+            inject_exception(&mut HardwareVmcs, vector, error_code);
+        }
     }
+}
 
-    vmx_advance_eip(guest_state);
+fn vm_exit_cpuid(guest_state: &mut GuestState) {
+    let registers = unsafe { guest_state.guest_regs.as_mut().unwrap() }.slots_mut();
+    let mut cpuid = RecordingSource::new(ProcessorCpuid);
+    let result = exit_cpuid(registers, &mut cpuid);
+    guest_state.cpuid = cpuid.cpuid;
+    vmx_end_exit(guest_state, result);
 }
 
 fn invept_single(eptp: u64) {
@@ -244,9 +379,7 @@ fn ept_perform_page_hook(
     _hook_function_address: *mut u8,
     page_attribe: u64,
 ) -> Result<(), &'static str> {
-    let _r = page_attribe & PAGE_ATTRIBE_READ;
-    let _w = page_attribe & PAGE_ATTRIBE_WRITE;
-    let _e = page_attribe & PAGE_ATTRIBE_EXECUTE;
+    let _permissions = EptPermissions::from_page_attribe(page_attribe);
 
     let virtual_target = page_align!(target_address);
 
@@ -279,6 +412,27 @@ fn vm_exit_vmcall(guest_state: &mut GuestState) {
         //获取第一个参数，功能类型编号
         match reg.rcx & 0xFFFFFFFF {
             vm_call::EXIT_VT => {
+                // the stub goes away with the driver,give the os its lstar back
+                let result = __GD
+                    .as_mut()
+                    .unwrap()
+                    .vmm
+                    .as_mut()
+                    .unwrap()
+                    .get_current_vcpu()
+                    .set_syscall_trace(false);
+
+                if let Err(e) = result {
+                    error!("{}", e);
+                }
+
+                // same for the #ve gate
+                if let Err(e) = vmx_set_virt_exception(false) {
+                    error!("{}", e);
+                }
+
+                // the exit stub hands rax back,the caller of the vmcall checks it
+                reg.rax = vm_call::STATUS_SUCCESS;
                 guest_state.exit_pending = true;
                 return;
             }
@@ -287,8 +441,10 @@ fn vm_exit_vmcall(guest_state: &mut GuestState) {
                     ept_perform_page_hook(option_param1 as _, option_param2 as _, option_param3);
             }
             vm_call::INVEPT_SINGLE_CONTEXT => {
-                invept_single(
-                    __GD.as_mut()
+                // rdx names an ept view,0 is the default ept
+                let eptp = match option_param1 {
+                    0 => __GD
+                        .as_mut()
                         .unwrap()
                         .vmm
                         .as_mut()
@@ -297,11 +453,107 @@ fn vm_exit_vmcall(guest_state: &mut GuestState) {
                         .as_mut()
                         .unwrap()
                         .get_ept_pointer(),
-                );
+                    eptp => eptp,
+                };
+                invept_single(eptp);
             }
             vm_call::INVEPT_ALL_CONTEXT => {
                 invept_all();
             }
+            vm_call::MSR_INTERCEPT => {
+                // any ring 0 code can get here,the deny list is checked again
+                let result = MsrAccess::from_u64(option_param2)
+                    .ok_or("Unknown msr access type")
+                    .and_then(|access| {
+                        check_msr_intercept(option_param1 as _)?;
+                        __GD.as_mut()
+                            .unwrap()
+                            .vmm
+                            .as_mut()
+                            .unwrap()
+                            .get_current_vcpu()
+                            .msr_bitmap()
+                            .set(option_param1 as _, access, option_param3 != 0)
+                    });
+
+                reg.rax = match result {
+                    Ok(_) => vm_call::STATUS_SUCCESS,
+                    Err(e) => {
+                        error!("{}", e);
+                        vm_call::STATUS_FAILED
+                    }
+                };
+            }
+            vm_call::SYSCALL_TRACE => {
+                let result = __GD
+                    .as_mut()
+                    .unwrap()
+                    .vmm
+                    .as_mut()
+                    .unwrap()
+                    .get_current_vcpu()
+                    .set_syscall_trace(option_param1 != 0);
+
+                if let Err(e) = result {
+                    error!("{}", e);
+                }
+            }
+            vm_call::DIRTY_LOG => match DirtyLogCommand::from_u64(option_param1) {
+                Some(command) => {
+                    if let Err(e) = vmx_set_dirty_log(command) {
+                        error!("{}", e);
+                    }
+                }
+                None => {
+                    error!("Unknown dirty log command");
+                }
+            },
+            vm_call::VIRT_EXCEPTION => {
+                if let Err(e) = vmx_set_virt_exception(option_param1 != 0) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::VIRT_EXCEPTION_SUPPRESS => {
+                if let Err(e) = vmx_suppress_virt_exception(option_param1) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::EPT_VIEWS => {
+                if let Err(e) = vmx_set_ept_views(option_param1 != 0) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::EPT_VIEW_SWITCH => {
+                let vmm = __GD.as_mut().unwrap().vmm.as_mut().unwrap();
+                if let Err(e) = vmx_load_ept_view(vmm, option_param1 as _) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::SUB_PAGE_WRITE => {
+                if let Err(e) = vmx_set_sub_page_write(option_param1 != 0) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::PROCESS_VIEWS => {
+                if let Err(e) = vmx_set_process_views(option_param1 != 0) {
+                    error!("{}", e);
+                }
+            }
+            vm_call::EXIT_TRACE => {
+                match __GD
+                    .as_mut()
+                    .unwrap()
+                    .vmm
+                    .as_mut()
+                    .unwrap()
+                    .get_current_vcpu()
+                    .exit_trace_mut()
+                {
+                    Some(trace) => trace.set_enabled(option_param1 != 0),
+                    None if option_param1 == 0 => {}
+                    None => error!("exit trace is not allocated"),
+                }
+            }
             _ => {
                 error!("Unknown vmcall command");
             }
@@ -312,117 +564,31 @@ fn vm_exit_vmcall(guest_state: &mut GuestState) {
 }
 
 fn vm_exit_msr_read(guest_state: &mut GuestState) {
-    let mut msr_value = LARGE_INTEGER::default();
-
-    let ecx: u32 = unsafe { guest_state.guest_regs.as_mut().unwrap().rcx } as u32;
-
-    match ecx {
-        MSR_GS_BASE => {
-            msr_value.QuadPart = vmcs_read(GUEST_GS_BASE) as _;
-        }
-        MSR_FS_BASE => {
-            msr_value.QuadPart = vmcs_read(GUEST_FS_BASE) as _;
-        }
-        MSR_IA32_DEBUGCTL => {
-            msr_value.QuadPart = vmcs_read(GUEST_IA32_DEBUGCTL) as _;
-        }
-        MSR_IA32_FEATURE_CONTROL => {
-            msr_value.QuadPart = vmcs_read(ecx as _) as _;
-            unsafe {
-                msr_value.QuadPart |= MSR_IA32_FEATURE_CONTROL_VMXON as i64;
-                msr_value.QuadPart |= MSR_IA32_FEATURE_CONTROL_LOCK as i64;
-            }
-        }
-        msr::msr_index::MSR_IA32_VMX_BASIC..=msr::msr_index::MSR_IA32_VMX_VMFUNC => {
-            // todo vmx msr
-            warn!("vmx msr:{:X}", ecx);
-        }
-        msr::msr_index::MSR_CRASH_CTL => {
-            msr_value.QuadPart = read_msr(ecx as _) as _;
-        }
-        _ => {
-            // if ecx >= msr::msr_index::MSR_RESERVED_MIN && ecx <= msr::msr_index::MSR_RESERVED_MAX {
-            //     // todo inject event
-            // } else if ecx == msr::msr_index::MSR_UNKNOWN || ecx == msr::msr_index::MSR_UNKNOWN2 {
-            //     // todo inject event
-            // }
-
-            msr_value.QuadPart = read_msr(ecx) as _;
-        }
-    }
-
-    unsafe {
-        guest_state.guest_regs.as_mut().unwrap().rax = msr_value.u.LowPart as _;
-        guest_state.guest_regs.as_mut().unwrap().rdx = msr_value.u.HighPart as _;
-    }
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let registers = unsafe { guest_state.guest_regs.as_mut().unwrap() }.slots_mut();
 
-    vmx_advance_eip(guest_state);
+    let mut msrs = RecordingSource::new(ProcessorMsrs);
+    let result = exit_msr_read(
+        registers,
+        vmm,
+        &mut VmcsMsrBacking::new(&mut HardwareVmcs, &mut msrs),
+    );
+    guest_state.msr_value = msrs.msr_value;
+    vmx_end_exit(guest_state, result);
 }
 
 fn vm_exit_msr_write(guest_state: &mut GuestState) {
-    let mut msr_value = LARGE_INTEGER::default();
-    let ecx: u32 = unsafe { guest_state.guest_regs.as_mut().unwrap().rcx } as u32;
-
-    unsafe {
-        msr_value.u.LowPart = guest_state.guest_regs.as_ref().unwrap().rax as _;
-        msr_value.u.HighPart = guest_state.guest_regs.as_ref().unwrap().rdx as _;
-    }
-
-    match ecx {
-        MSR_GS_BASE => {
-            write_msr(GUEST_GS_BASE as _, unsafe { msr_value.QuadPart } as _);
-        }
-        MSR_FS_BASE => {
-            write_msr(GUEST_FS_BASE as _, unsafe { msr_value.QuadPart } as _);
-        }
-        MSR_IA32_DEBUGCTL => unsafe {
-            __vmx_vmwrite(GUEST_IA32_DEBUGCTL, msr_value.QuadPart as _);
-            write_msr(MSR_IA32_DEBUGCTL, msr_value.QuadPart as _);
-        },
-        msr::msr_index::MSR_IA32_VMX_BASIC..=msr::msr_index::MSR_IA32_VMX_VMFUNC => {
-            // todo vmx msr
-            warn!("vmx msr:{:X}", ecx);
-        }
-        // VMware
-        msr::msr_index::MSR_STIMER0_CONFIG
-        | msr::msr_index::MSR_STIMER0_COUNT
-        | msr::msr_index::MSR_CRASH_P0 => {
-            write_msr(ecx as _, unsafe { msr_value.QuadPart } as _);
-        }
-        _ => {
-            if unsafe {
-                __GD.as_mut()
-                    .unwrap()
-                    .vmm
-                    .as_mut()
-                    .unwrap()
-                    .vmx_features
-                    .in_vmware
-            } {
-                write_msr(ecx, unsafe { msr_value.QuadPart } as _);
-            } else if (msr::msr_index::MSR_RESERVED_MIN..=msr::msr_index::MSR_RESERVED_MAX)
-                .contains(&ecx)
-            {
-                warn!("MSR_RESERVED:{:X}", ecx);
-                vmx_inject_event(
-                    INTERRUPT_HARDWARE_EXCEPTION,
-                    VECTOR_INVALID_OPCODE_EXCEPTION,
-                    0,
-                );
-                return;
-            } else if ecx == msr::msr_index::MSR_UNKNOWN || ecx == msr::msr_index::MSR_UNKNOWN2 {
-                warn!("MSR_UNKNOWN:{:X}", ecx);
-                vmx_inject_event(
-                    INTERRUPT_HARDWARE_EXCEPTION,
-                    VECTOR_INVALID_OPCODE_EXCEPTION,
-                    0,
-                );
-                return;
-            }
-        }
-    }
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let registers = unsafe { guest_state.guest_regs.as_mut().unwrap() }.slots_mut();
 
-    vmx_advance_eip(guest_state);
+    let mut msrs = RecordingSource::new(ProcessorMsrs);
+    let result = exit_msr_write(
+        registers,
+        vmm,
+        &mut VmcsMsrBacking::new(&mut HardwareVmcs, &mut msrs),
+    );
+    guest_state.msr_value = msrs.msr_value;
+    vmx_end_exit(guest_state, result);
 }
 
 fn get_cr_select_register(index: u32, guest_state: &mut GuestState) -> &mut u64 {
@@ -450,53 +616,322 @@ fn get_cr_select_register(index: u32, guest_state: &mut GuestState) -> &mut u64
     };
 }
 
+// the pushed rsp is a placeholder,the guest value lives in the vmcs
+fn read_guest_register(index: u8, guest_state: &mut GuestState) -> u64 {
+    if index == 4 {
+        guest_state.guest_rsp
+    } else {
+        *get_cr_select_register(index as _, guest_state)
+    }
+}
+
+fn write_guest_register(index: u8, value: u64, guest_state: &mut GuestState) {
+    if index == 4 {
+        guest_state.guest_rsp = value;
+        __vmx_vmwrite(GUEST_RSP, value);
+    } else {
+        *get_cr_select_register(index as _, guest_state) = value;
+    }
+}
+
 fn vm_exit_cr_access(guest_state: &mut GuestState) {
-    let data = guest_state.exit_qualification; // MOV_CR_QUALIFICATION
-    let reg = get_cr_select_register(
-        (data & mov_cr_qualification::REGISTER_MASK as u64) as u32,
-        guest_state,
-    );
-    let access_type = (data & mov_cr_qualification::ACCESS_TYPE_MASK as u64) as u32;
-
-    match access_type {
-        TYPE_CR_WRITE => {
-            let control_register = data & mov_cr_qualification::CONTROL_REGISTER_MASK as u64;
-            match control_register {
-                0 => {
-                    __vmx_vmwrite(GUEST_CR0, *reg);
-                    __vmx_vmwrite(CR0_READ_SHADOW, *reg);
-                }
-                3 => {
-                    __vmx_vmwrite(GUEST_CR3, *reg & !(1u64 << 63));
-                }
-                4 => {
-                    __vmx_vmwrite(GUEST_CR4, *reg);
-                    __vmx_vmwrite(CR4_READ_SHADOW, *reg);
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let registers = unsafe { guest_state.guest_regs.as_mut().unwrap() }.slots_mut();
+
+    let result = exit_cr_access(&mut HardwareVmcs, registers, vmm);
+    vmx_end_exit(guest_state, result);
+}
+
+// in 64-bit mode only fs and gs have a base
+fn guest_segment_base(segment: u8, long_mode: bool) -> u64 {
+    match segment {
+        0 if !long_mode => vmcs_read(GUEST_ES_BASE),
+        1 if !long_mode => vmcs_read(GUEST_CS_BASE),
+        2 if !long_mode => vmcs_read(GUEST_SS_BASE),
+        3 if !long_mode => vmcs_read(GUEST_DS_BASE),
+        4 => vmcs_read(GUEST_FS_BASE),
+        5 => vmcs_read(GUEST_GS_BASE),
+        _ => 0,
+    }
+}
+
+// every launched vcpu owns one
+fn current_guest_mapping() -> &'static mut GuestMapping {
+    unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+            .guest_mapping_mut()
+            .unwrap()
+    }
+}
+
+// rights of an explicit data access by the current guest instruction
+fn guest_data_access() -> GuestAccess {
+    let cr4 = vmcs_read(GUEST_CR4);
+
+    GuestAccess {
+        user: ((vmcs_read(GUEST_SS_AR_BYTES) >> 5) & 3) == 3,
+        write_protect: (vmcs_read(GUEST_CR0) & X86_CR0_WP as u64) != 0,
+        smap: (cr4 & X86_CR4_SMAP as u64) != 0 && (vmcs_read(GUEST_RFLAGS) & RFLAGS_AC) == 0,
+        smep: (cr4 & X86_CR4_SMEP as u64) != 0,
+        no_execute: (VmcsMsrBacking::new(&mut HardwareVmcs, &mut ProcessorMsrs).read(MSR_EFER)
+            & EFER_NXE)
+            != 0,
+        ..Default::default()
+    }
+}
+
+fn emulate_desc_table_access(
+    access: &DescTableAccess,
+    guest_state: &mut GuestState,
+) -> Result<(), DescTableFault> {
+    let vcpu = unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+    };
+
+    let long_mode = (vmcs_read(GUEST_CS_AR_BYTES) & SEGMENT_AR_LONG_MODE) != 0;
+    let mapping = current_guest_mapping();
+    let cr3 = vmcs_read(GUEST_CR3);
+    let memory_access = guest_data_access();
+    // descriptor reads and the busy bit are implicit supervisor accesses whatever the cpl
+    let system_access = GuestAccess {
+        user: false,
+        smap: (vmcs_read(GUEST_CR4) & X86_CR4_SMAP as u64) != 0,
+        ..memory_access
+    };
+
+    let displacement = guest_state.exit_qualification;
+    let address = match access.operand {
+        DescTableOperand::Memory { segment, .. } => access
+            .operand
+            .linear_address(
+                displacement,
+                guest_segment_base(segment, long_mode),
+                |index| read_guest_register(index, guest_state),
+            )
+            .unwrap_or(0),
+        DescTableOperand::Register(_) => 0,
+    };
+
+    let gdtr = DescriptorTableRegister {
+        base: vmcs_read(GUEST_GDTR_BASE),
+        limit: vmcs_read(GUEST_GDTR_LIMIT) as _,
+    };
+    let idtr = DescriptorTableRegister {
+        base: vmcs_read(GUEST_IDTR_BASE),
+        limit: vmcs_read(GUEST_IDTR_LIMIT) as _,
+    };
+
+    match access.instruction {
+        DescTableInstruction::Sgdt | DescTableInstruction::Sidt => {
+            let value = match access.instruction {
+                DescTableInstruction::Sgdt => gdtr,
+                _ => match vcpu.desc_table_guard_mut() {
+                    Some(guard) => guard.stored_idtr(idtr),
+                    None => idtr,
+                },
+            };
+
+            let (bytes, len) = value.to_bytes(long_mode);
+            write_guest_memory(mapping, cr3, address, &bytes[..len], memory_access)?;
+        }
+        DescTableInstruction::Lgdt | DescTableInstruction::Lidt => {
+            let mut bytes = [0u8; 10];
+            let len = if long_mode { 10 } else { 6 };
+            read_guest_memory(mapping, cr3, address, &mut bytes[..len], memory_access)?;
+
+            let value =
+                DescriptorTableRegister::from_bytes(&bytes, long_mode, access.operand_size_32);
+            if !value.is_canonical() {
+                return Err(DescTableFault::GeneralProtection(0));
+            }
+
+            let is_gdt = access.instruction == DescTableInstruction::Lgdt;
+            if let Some(guard) = vcpu.desc_table_guard_mut() {
+                let result = if is_gdt {
+                    guard.load_gdtr(value)
+                } else {
+                    guard.load_idtr(value)
+                };
+
+                match result {
+                    Ok(true) => info!(
+                        "{} base:{:X} limit:{:X},rip:{:X}",
+                        access.instruction.name(),
+                        value.base,
+                        value.limit,
+                        guest_state.guest_rip
+                    ),
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("{},rip:{:X}", e, guest_state.guest_rip);
+                        return Err(DescTableFault::GeneralProtection(0));
+                    }
                 }
-                _ => {
-                    error!("unknown cr write");
+            }
+
+            if is_gdt {
+                __vmx_vmwrite(GUEST_GDTR_BASE, value.base);
+                __vmx_vmwrite(GUEST_GDTR_LIMIT, value.limit as _);
+            } else {
+                __vmx_vmwrite(GUEST_IDTR_BASE, value.base);
+                __vmx_vmwrite(GUEST_IDTR_LIMIT, value.limit as _);
+
+                // the #ve copy stays loaded for the same table,another table turns #ve off
+                match vcpu.virt_exception().and_then(|cpu| cpu.original_idtr()) {
+                    Some(original) if original == value => {
+                        let copy = vcpu.virt_exception().unwrap().idt_address();
+                        __vmx_vmwrite(GUEST_IDTR_BASE, copy);
+                    }
+                    Some(_) => {
+                        let secondary = vmcs_read(SECONDARY_VM_EXEC_CONTROL);
+                        __vmx_vmwrite(
+                            SECONDARY_VM_EXEC_CONTROL,
+                            secondary & !(VMX_PROC_CTLS2_EPT_XCPT_VE as u64),
+                        );
+                        vmx_unload_virt_exception_idt(vcpu);
+                        // the new table is loaded,not the one #ve replaced
+                        __vmx_vmwrite(GUEST_IDTR_BASE, value.base);
+                        warn!("idt reloaded,#ve is off on this cpu");
+                    }
+                    None => {}
                 }
             }
         }
-        TYPE_CR_READ => {
-            let control_register = data & mov_cr_qualification::CONTROL_REGISTER_MASK as u64;
-            match control_register {
-                0 => {
-                    *reg = vmcs_read(GUEST_CR0);
+        DescTableInstruction::Sldt | DescTableInstruction::Str => {
+            let selector = if access.instruction == DescTableInstruction::Sldt {
+                vmcs_read(GUEST_LDTR_SELECTOR)
+            } else {
+                vmcs_read(GUEST_TR_SELECTOR)
+            } as u16;
+
+            match access.operand {
+                // register destinations are zero extended
+                DescTableOperand::Register(index) => {
+                    write_guest_register(index, selector as _, guest_state)
                 }
-                3 => {
-                    *reg = vmcs_read(GUEST_CR3);
+                DescTableOperand::Memory { .. } => write_guest_memory(
+                    mapping,
+                    cr3,
+                    address,
+                    &selector.to_le_bytes(),
+                    memory_access,
+                )?,
+            }
+        }
+        DescTableInstruction::Lldt | DescTableInstruction::Ltr => {
+            let selector = match access.operand {
+                DescTableOperand::Register(index) => read_guest_register(index, guest_state) as u16,
+                DescTableOperand::Memory { .. } => {
+                    let mut bytes = [0u8; 2];
+                    read_guest_memory(mapping, cr3, address, &mut bytes, memory_access)?;
+                    u16::from_le_bytes(bytes)
                 }
-                4 => {
-                    *reg = vmcs_read(GUEST_CR4);
+            };
+
+            let is_ldt = access.instruction == DescTableInstruction::Lldt;
+            let current = if is_ldt {
+                vmcs_read(GUEST_LDTR_SELECTOR)
+            } else {
+                vmcs_read(GUEST_TR_SELECTOR)
+            } as u16;
+
+            if let Some(guard) = vcpu.desc_table_guard_mut() {
+                match guard.load_selector(current, selector) {
+                    Ok(true) => info!(
+                        "{} selector:{:X},rip:{:X}",
+                        access.instruction.name(),
+                        selector,
+                        guest_state.guest_rip
+                    ),
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("{},rip:{:X}", e, guest_state.guest_rip);
+                        return Err(DescTableFault::GeneralProtection(0));
+                    }
                 }
-                _ => {
-                    error!("unknown cr read");
+            }
+
+            // a null ldt selector leaves ldtr unusable,a null tss selector #gp
+            if (selector & !3) == 0 {
+                if !is_ldt {
+                    return Err(DescTableFault::GeneralProtection(0));
                 }
+                __vmx_vmwrite(GUEST_LDTR_SELECTOR, selector as _);
+                __vmx_vmwrite(GUEST_LDTR_AR_BYTES, SEGMENT_AR_UNUSABLE);
+                return Ok(());
+            }
+
+            let descriptor_address = system_descriptor_address(selector, &gdtr)?;
+            let mut bytes = [0u8; 16];
+            read_guest_memory(mapping, cr3, descriptor_address, &mut bytes, system_access)?;
+
+            let low = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+            let high = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+            let mut descriptor = SystemDescriptor::from_raw(low, high);
+            descriptor.check(access.instruction, selector)?;
+
+            if is_ldt {
+                __vmx_vmwrite(GUEST_LDTR_SELECTOR, selector as _);
+                __vmx_vmwrite(GUEST_LDTR_BASE, descriptor.base);
+                __vmx_vmwrite(GUEST_LDTR_LIMIT, descriptor.limit as _);
+                __vmx_vmwrite(GUEST_LDTR_AR_BYTES, descriptor.access_rights as _);
+            } else {
+                // ltr marks the tss busy in the gdt
+                descriptor.access_rights |= DESC_TYPE_TSS_BUSY as u32;
+                let busy = low | ((DESC_TYPE_TSS_BUSY as u64) << 40);
+                write_guest_memory(
+                    mapping,
+                    cr3,
+                    descriptor_address,
+                    &busy.to_le_bytes(),
+                    system_access,
+                )?;
+
+                __vmx_vmwrite(GUEST_TR_SELECTOR, selector as _);
+                __vmx_vmwrite(GUEST_TR_BASE, descriptor.base);
+                __vmx_vmwrite(GUEST_TR_LIMIT, descriptor.limit as _);
+                __vmx_vmwrite(GUEST_TR_AR_BYTES, descriptor.access_rights as _);
             }
         }
-        _ => {
-            error!("error cr access type");
+    }
+
+    Ok(())
+}
+
+fn vm_exit_dtr_access(guest_state: &mut GuestState) {
+    let info = vmcs_read(VMX_INSTRUCTION_INFO) as u32;
+    let access = match decode_desc_table_access(guest_state.exit_reason, info) {
+        Ok(access) => access,
+        Err(e) => {
+            error!("descriptor table access:{},info:{:X}", e, info);
+            vmx_inject_gp();
+            return;
+        }
+    };
+
+    match emulate_desc_table_access(&access, guest_state) {
+        Ok(()) => vmx_advance_eip(guest_state),
+        Err(fault) => {
+            warn!("{} fault:{:?}", access.instruction.name(), fault);
+            match fault {
+                DescTableFault::GeneralProtection(error_code) => {
+                    vmx_inject_fault(VECTOR_GENERAL_PROTECTION_EXCEPTION, error_code as _)
+                }
+                DescTableFault::NotPresent(error_code) => {
+                    vmx_inject_fault(VECTOR_SEGMENT_NOT_PRESENT, error_code as _)
+                }
+                DescTableFault::PageFault(fault) => vmx_inject_page_fault(fault),
+            }
         }
     }
 }
@@ -514,42 +949,1023 @@ fn vm_exit_unknown(guest_state: &mut GuestState) {
         "vm_exit_unknown,resaoin:{},rip:{:X}",
         guest_state.exit_reason, guest_state.guest_rip
     );
+    dump_current_vmcs();
     debugbreak!();
 }
 
-fn vm_exit_ept_misconfig(_guest_state: &mut GuestState) {
-    warn!("todo vm_exit_ept_misconfig");
-    debugbreak!();
+// nmi exiting,the guest nmi waits in the same queue as the ones that hit root
+fn vm_exit_exception_nmi(guest_state: &mut GuestState) {
+    let exit_info = vmcs_read(VM_EXIT_INTR_INFO) as u32;
+    if ((exit_info >> TYPE_START) & ((1 << TYPE_LEN) - 1)) != INTERRUPT_NMI {
+        vm_exit_unknown(guest_state);
+        return;
+    }
+
+    // the nmi arrived while an event was delivered,the event goes in again first
+    let vectoring_info = vmcs_read(IDT_VECTORING_INFO_FIELD) as u32;
+    if (vectoring_info & VALID) != 0 {
+        __vmx_vmwrite(
+            VM_ENTRY_INTR_INFO_FIELD,
+            (vectoring_info & !NMI_UNBLOCKING_DUE_TO_IRET) as _,
+        );
+        if (vectoring_info & DELIVER_ERROR_CODE) != 0 {
+            __vmx_vmwrite(
+                VM_ENTRY_EXCEPTION_ERROR_CODE,
+                vmcs_read(IDT_VECTORING_ERROR_CODE),
+            );
+        }
+        __vmx_vmwrite(VM_ENTRY_INSTRUCTION_LEN, vmcs_read(VM_EXIT_INSTRUCTION_LEN));
+    } else if (exit_info & NMI_UNBLOCKING_DUE_TO_IRET) != 0 {
+        // the nmi interrupted an iret,virtual nmis stay blocked until it executes again
+        let interruptibility = vmcs_read(GUEST_INTERRUPTIBILITY_INFO);
+        __vmx_vmwrite(
+            GUEST_INTERRUPTIBILITY_INFO,
+            interruptibility | BLOCKING_BY_NMI,
+        );
+    }
+
+    let vcpu = unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+    };
+    if let Some(host_tables) = vcpu.host_tables_mut() {
+        host_tables.queue_nmi();
+    }
 }
 
-fn vm_exit_ept_violation(_guest_state: &mut GuestState) {
-    warn!("todo vm_exit_ept_violation");
-    debugbreak!();
+fn vm_exit_nmi_window(_guest_state: &mut GuestState) {
+    let vcpu = unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+    };
+    match vcpu.host_tables_mut() {
+        Some(host_tables) => host_tables.nmi_window(),
+        None => vmx_set_nmi_window_exiting(false),
+    }
 }
 
-type ExitHandler = fn(guest_state: &mut GuestState);
-static EXIT_HANDLER: [ExitHandler; 65] = [
-    vm_exit_unknown,       // 00 EXIT_REASON_EXCEPTION_NMI
-    vm_exit_unknown,       // 01 EXIT_REASON_EXTERNAL_INTERRUPT
-    vm_exit_unknown,       // 02 EXIT_REASON_TRIPLE_FAULT
-    vm_exit_unknown,       // 03 EXIT_REASON_INIT
-    vm_exit_unknown,       // 04 EXIT_REASON_SIPI
-    vm_exit_unknown,       // 05 EXIT_REASON_IO_SMI
-    vm_exit_unknown,       // 06 EXIT_REASON_OTHER_SMI
-    vm_exit_unknown,       // 07 EXIT_REASON_PENDING_INTERRUPT
-    vm_exit_unknown,       // 08 EXIT_REASON_NMI_WINDOW
-    vm_exit_unknown,       // 09 EXIT_REASON_TASK_SWITCH
-    vm_exit_cpuid,         // 10 EXIT_REASON_CPUID
-    vm_exit_unknown,       // 11 EXIT_REASON_GETSEC
-    vm_exit_unknown,       // 12 EXIT_REASON_HLT
-    vm_exit_unknown,       // 13 EXIT_REASON_INVD
-    vm_exit_unknown,       // 14 EXIT_REASON_INVLPG
-    vm_exit_unknown,       // 15 EXIT_REASON_RDPMC
-    vm_exit_unknown,       // 16 EXIT_REASON_RDTSC
-    vm_exit_unknown,       // 17 EXIT_REASON_RSM
-    vm_exit_vmcall,        // 18 EXIT_REASON_VMCALL
-    vm_exit_vmop,          // 19 EXIT_REASON_VMCLEAR
-    vm_exit_vmop,          // 20 EXIT_REASON_VMLAUNCH
+pub(crate) fn vmx_set_nmi_window_exiting(enable: bool) {
+    let mut controls = vmcs_read(CPU_BASED_VM_EXEC_CONTROL);
+    if enable {
+        controls |= VMX_PROC_CTLS_NMI_WINDOW_EXIT as u64;
+    } else {
+        controls &= !(VMX_PROC_CTLS_NMI_WINDOW_EXIT as u64);
+    }
+    __vmx_vmwrite(CPU_BASED_VM_EXEC_CONTROL, controls);
+}
+
+fn vm_exit_preempt_timer(guest_state: &mut GuestState) {
+    let vcpu = unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+    };
+    let cpu_index = vcpu.cpu_index();
+    vcpu.preemption_timer_mut()
+        .expire(cpu_index, guest_state.guest_rip);
+}
+
+// vmx root,the eptp already has the accessed and dirty flag setting for the command
+fn vmx_set_dirty_log(command: DirtyLogCommand) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let eptp = vmm
+        .ept_state
+        .as_mut()
+        .ok_or("ept is not enabled")?
+        .get_ept_pointer();
+    let pml_log = vmm
+        .get_current_vcpu()
+        .pml_log()
+        .ok_or("pml log is not allocated")?;
+
+    let mut secondary = vmcs_read(SECONDARY_VM_EXEC_CONTROL);
+
+    match command {
+        DirtyLogCommand::Enable => {
+            __vmx_vmwrite(PML_ADDRESS, pml_log.physical_address());
+            __vmx_vmwrite(GUEST_PML_INDEX, PML_INDEX_START);
+            secondary |= VMX_PROC_CTLS2_PML as u64;
+        }
+        DirtyLogCommand::Disable => {
+            secondary &= !(VMX_PROC_CTLS2_PML as u64);
+        }
+        DirtyLogCommand::Flush => {}
+    }
+
+    pml_log.drain(vmcs_read(GUEST_PML_INDEX));
+    __vmx_vmwrite(GUEST_PML_INDEX, PML_INDEX_START);
+
+    __vmx_vmwrite(SECONDARY_VM_EXEC_CONTROL, secondary);
+    __vmx_vmwrite(EPT_POINTER, eptp);
+    // dirty flags cleared at passive level are only seen after the flush
+    invept_single(eptp);
+    Ok(())
+}
+
+// the guest idt is copied into a hypervisor page with the stub in vector 20 and the copy
+// is loaded,sidt keeps reporting the guest table so nothing the os checks is touched
+fn vmx_set_virt_exception(enable: bool) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let view_index = match vmm.ept_views.as_ref() {
+        Some(views) => views.index_of(vmcs_read(EPT_POINTER)).unwrap_or(0),
+        None => 0,
+    };
+    let vcpu = vmm.get_current_vcpu();
+    let secondary = vmcs_read(SECONDARY_VM_EXEC_CONTROL);
+
+    if !enable {
+        // the control goes first,the copy must never be reached without it
+        __vmx_vmwrite(
+            SECONDARY_VM_EXEC_CONTROL,
+            secondary & !(VMX_PROC_CTLS2_EPT_XCPT_VE as u64),
+        );
+        vmx_unload_virt_exception_idt(vcpu);
+        return Ok(());
+    }
+
+    // sidt has to exit to hide the copy
+    if vcpu.desc_table_guard_mut().is_none() {
+        return Err("#ve needs descriptor table exiting");
+    }
+    let virt_exception = vcpu
+        .virt_exception_mut()
+        .ok_or("#ve information page is not allocated")?;
+
+    if virt_exception.original_idtr().is_none() {
+        let idtr = DescriptorTableRegister {
+            base: vmcs_read(GUEST_IDTR_BASE),
+            limit: vmcs_read(GUEST_IDTR_LIMIT) as _,
+        };
+        let len = (idtr.limit as usize + 1).min(virt_exception.idt_mut().len());
+        read_guest_memory(
+            current_guest_mapping(),
+            vmcs_read(GUEST_CR3),
+            idtr.base,
+            &mut virt_exception.idt_mut()[..len],
+            GuestAccess::default(),
+        )
+        .map_err(|_| "idt is not mapped")?;
+        virt_exception.load_idt(
+            idtr,
+            VECTOR_VIRTUALIZATION_EXCEPTION,
+            vmcs_read(GUEST_CS_SELECTOR) as _,
+        )?;
+        __vmx_vmwrite(GUEST_IDTR_BASE, virt_exception.idt_address());
+
+        if let Some(guard) = vcpu.desc_table_guard_mut() {
+            guard.shadow_idtr.get_or_insert(idtr);
+        }
+    }
+
+    let virt_exception = vcpu.virt_exception_mut().unwrap();
+    __vmx_vmwrite(
+        VIRTUALIZATION_EXCEPTION_INFO_ADDDRESS,
+        virt_exception.physical_address(),
+    );
+    __vmx_vmwrite(EPTP_INDEX, view_index as _);
+    __vmx_vmwrite(
+        SECONDARY_VM_EXEC_CONTROL,
+        secondary | VMX_PROC_CTLS2_EPT_XCPT_VE as u64,
+    );
+
+    Ok(())
+}
+
+// the guest idt is loaded again and sidt reports the real idtr
+fn vmx_unload_virt_exception_idt(vcpu: &mut Vcpu) {
+    let original = match vcpu.virt_exception_mut().and_then(|cpu| cpu.unload_idt()) {
+        Some(original) => original,
+        None => return,
+    };
+
+    __vmx_vmwrite(GUEST_IDTR_BASE, original.base);
+    if let Some(guard) = vcpu.desc_table_guard_mut() {
+        if guard.shadow_idtr == Some(original) {
+            guard.shadow_idtr = None;
+        }
+    }
+}
+
+// vmx root,translations are tagged with the eptp so switching needs no invept
+fn vmx_load_ept_view(vmm: &Vmm, index: usize) -> Result<(), &'static str> {
+    let views = vmm.ept_views.as_ref().ok_or("ept views are not enabled")?;
+    let eptp = views.eptp(index).ok_or("ept view index is out of range")?;
+
+    __vmx_vmwrite(EPT_POINTER, eptp);
+    // vmfunc and #ve information report the index
+    if views.is_vmfunc_enabled() || vmm.vmx_features.virt_exception {
+        __vmx_vmwrite(EPTP_INDEX, index as _);
+    }
+    Ok(())
+}
+
+fn vmx_set_ept_views(enable: bool) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let views = vmm
+        .ept_views
+        .as_ref()
+        .ok_or("ept views are not allocated")?;
+    let secondary = vmcs_read(SECONDARY_VM_EXEC_CONTROL);
+
+    if enable {
+        if views.is_vmfunc_enabled() {
+            __vmx_vmwrite(EPTP_LIST_ADDRESS, views.list_physical_address());
+            __vmx_vmwrite(VM_FUNCTION_CONTROLS, VMFUNC_EPTP_SWITCHING_BIT);
+            __vmx_vmwrite(
+                SECONDARY_VM_EXEC_CONTROL,
+                secondary | VMX_PROC_CTLS2_VMFUNC as u64,
+            );
+        }
+        vmx_load_ept_view(vmm, 0)
+    } else {
+        if views.is_vmfunc_enabled() {
+            __vmx_vmwrite(
+                SECONDARY_VM_EXEC_CONTROL,
+                secondary & !(VMX_PROC_CTLS2_VMFUNC as u64),
+            );
+        }
+        vmx_load_ept_view(vmm, 0)?;
+        // the other views are freed next,nothing may stay cached for them
+        invept_all();
+        Ok(())
+    }
+}
+
+// vmx root,cr3 loads exit while enabled and pick the view owned by the new address space
+fn vmx_set_process_views(enable: bool) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let controls = vmcs_read(CPU_BASED_VM_EXEC_CONTROL);
+
+    if enable {
+        let views = vmm
+            .process_views
+            .as_ref()
+            .ok_or("process views are not allocated")?;
+        __vmx_vmwrite(
+            CPU_BASED_VM_EXEC_CONTROL,
+            controls | VMX_PROC_CTLS_CR3_LOAD_EXIT as u64,
+        );
+        vmx_load_ept_view(vmm, views.view_for_cr3(vmcs_read(GUEST_CR3)))
+    } else {
+        // the cr3 switch callback keeps the exits,so does a cpu that can not clear them
+        let capabilities = read_vmx_capabilities(vmm.vmx_features.true_msrs);
+        if vmm.cr3_switch_callback.is_none()
+            && (capabilities.proc_controls & VMX_PROC_CTLS_CR3_LOAD_EXIT as u64) == 0
+        {
+            __vmx_vmwrite(
+                CPU_BASED_VM_EXEC_CONTROL,
+                controls & !(VMX_PROC_CTLS_CR3_LOAD_EXIT as u64),
+            );
+        }
+        vmx_load_ept_view(vmm, 0)
+    }
+}
+
+// the guest handler gave the page back,later violations exit
+fn vmx_suppress_virt_exception(physical: u64) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let ept_state = vmm.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+    ept_state.set_page_suppress_ve(physical & !0xFFF, true)?;
+    invept_single(ept_state.get_ept_pointer());
+    Ok(())
+}
+
+fn vmx_set_sub_page_write(enable: bool) -> Result<(), &'static str> {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let secondary = vmcs_read(SECONDARY_VM_EXEC_CONTROL);
+
+    if enable {
+        let spp_table = vmm.spp_table.as_ref().ok_or("spp table is not allocated")?;
+        __vmx_vmwrite(SPPT_POINTER, spp_table.pointer());
+        __vmx_vmwrite(
+            SECONDARY_VM_EXEC_CONTROL,
+            secondary | VMX_PROC_CTLS2_SPP_EPT as u64,
+        );
+    } else {
+        // selected entries keep the spp bit,it is ignored with the control clear
+        __vmx_vmwrite(
+            SECONDARY_VM_EXEC_CONTROL,
+            secondary & !(VMX_PROC_CTLS2_SPP_EPT as u64),
+        );
+        __vmx_vmwrite(SPPT_POINTER, 0);
+        // the table is freed next,nothing may stay cached for it
+        invept_all();
+    }
+
+    Ok(())
+}
+
+// the spp table had no leaf for a selected page or a bad entry. the page falls back to
+// write protection as a whole,the write then comes back as an ept violation
+fn vm_exit_spp_event(guest_state: &mut GuestState) {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let page = guest_state.physical_address & !0xFFF;
+
+    if (guest_state.exit_qualification & SPP_EVENT_MISCONFIG) != 0 {
+        error!("spp table misconfiguration physical:{:x}", page);
+    } else {
+        warn!("spp table miss physical:{:x}", page);
+    }
+
+    if let Some(ept_state) = vmm.ept_state.as_mut() {
+        if ept_state.drop_page_sub_page_write(page).is_err() {
+            error!("spp event on a page without a 4kb entry {:x}", page);
+        }
+        invept_single(ept_state.get_ept_pointer());
+    }
+
+    // same as a pml full exit,the event interrupted an iret
+    if (guest_state.exit_qualification & SPP_EVENT_NMI_UNBLOCKING) != 0 {
+        let interruptibility = vmcs_read(GUEST_INTERRUPTIBILITY_INFO);
+        __vmx_vmwrite(
+            GUEST_INTERRUPTIBILITY_INFO,
+            interruptibility | BLOCKING_BY_NMI,
+        );
+    }
+}
+
+// the write that found the log full has not happened yet,rip stays on it
+fn vm_exit_pml_full(guest_state: &mut GuestState) {
+    let vcpu = unsafe {
+        __GD.as_mut()
+            .unwrap()
+            .vmm
+            .as_mut()
+            .unwrap()
+            .get_current_vcpu()
+    };
+
+    if let Some(pml_log) = vcpu.pml_log() {
+        pml_log.drain(vmcs_read(GUEST_PML_INDEX));
+    }
+    __vmx_vmwrite(GUEST_PML_INDEX, PML_INDEX_START);
+
+    // the exit interrupted an iret,nmis stay blocked until the guest executes it again
+    if (guest_state.exit_qualification & PML_FULL_NMI_UNBLOCKING) != 0 {
+        let interruptibility = vmcs_read(GUEST_INTERRUPTIBILITY_INFO);
+        __vmx_vmwrite(
+            GUEST_INTERRUPTIBILITY_INFO,
+            interruptibility | BLOCKING_BY_NMI,
+        );
+    }
+}
+
+fn vm_exit_ept_misconfig(_guest_state: &mut GuestState) {
+    warn!("todo vm_exit_ept_misconfig");
+    debugbreak!();
+}
+
+fn vmx_set_monitor_trap_flag(enable: bool) {
+    let mut controls = vmcs_read(CPU_BASED_VM_EXEC_CONTROL);
+    if enable {
+        controls |= VMX_PROC_CTLS_MONITOR_TRAP_FLAG as u64;
+    } else {
+        controls &= !(VMX_PROC_CTLS_MONITOR_TRAP_FLAG as u64);
+    }
+    __vmx_vmwrite(CPU_BASED_VM_EXEC_CONTROL, controls);
+}
+
+// vmx root,the next instruction runs on the overlay of the vcpu where page maps entry.
+// the monitor trap flag exit loads the shared ept again
+fn vmx_load_ept_overlay(
+    vcpu: &mut Vcpu,
+    ept_state: &EptState,
+    page: u64,
+    entry: u64,
+) -> Result<(), &'static str> {
+    let overlay = vcpu
+        .ept_overlay_mut()
+        .ok_or("ept overlay is not allocated")?;
+    let eptp = overlay.load(ept_state, vmcs_read(EPT_POINTER), page, entry)?;
+
+    invept_single(eptp);
+    __vmx_vmwrite(EPT_POINTER, eptp);
+    vmx_set_monitor_trap_flag(true);
+    Ok(())
+}
+
+// write to a protected code page,the write executes for one instruction against an entry only
+// this cpu sees
+fn ept_code_integrity_write(guest_state: &mut GuestState, vmm: &mut Vmm, entry: u64) -> bool {
+    if (guest_state.exit_qualification & ept_violation_qualification::DATA_WRITE) == 0
+        || !CodeIntegrityMonitor::is_protected(entry)
+    {
+        return false;
+    }
+
+    let monitor = match vmm.code_integrity.as_ref() {
+        Some(monitor) => monitor,
+        None => return false,
+    };
+    let ept_state = match vmm.ept_state.as_ref() {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let page = guest_state.physical_address & !0xFFF;
+    let block = monitor.policy() == CodeIntegrityPolicy::Block;
+
+    let linear_address = if (guest_state.exit_qualification
+        & ept_violation_qualification::LINEAR_ADDRESS_VALID)
+        != 0
+    {
+        guest_state.linear_address
+    } else {
+        0
+    };
+
+    monitor.cpus()[cpu_index].ring.push(CodeWriteEvent {
+        rip: guest_state.guest_rip,
+        cr3: vmcs_read(GUEST_CR3),
+        linear_address,
+        physical_address: guest_state.physical_address,
+        cpu_index: cpu_index as _,
+        blocked: block as _,
+    });
+
+    let vcpu = &mut vmm.vcpu[cpu_index];
+    let slot = vcpu
+        .ept_overlay_mut()
+        .map_or(0, |overlay| overlay.page_count());
+    let loosened = match vcpu.guest_mapping_mut() {
+        Some(mapping) => monitor.loosen(cpu_index, slot, mapping, page, entry, block),
+        None => return false,
+    };
+
+    if let Err(e) = vmx_load_ept_overlay(vcpu, ept_state, page, loosened) {
+        error!("{}", e);
+        return false;
+    }
+    true
+}
+
+// the page raises #ve but the cpu exited,the guest handler has not re-armed yet. the
+// access is let through for one instruction on an entry only this cpu sees
+fn ept_virt_exception_missed(vmm: &mut Vmm, page: u64, entry: u64) -> bool {
+    if (entry & ptee::SUPPRESS_VE) != 0 || !vmm.vmx_features.monitor_trap_flag {
+        return false;
+    }
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let vcpu = &mut vmm.vcpu[cpu_index];
+    match vcpu.virt_exception() {
+        Some(virt_exception) => virt_exception.count_missed(),
+        None => return false,
+    }
+    let ept_state = match vmm.ept_state.as_ref() {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+
+    let open = EptPermissions {
+        read: true,
+        write: true,
+        execute: true,
+        execute_user: true,
+    };
+    if let Err(e) = vmx_load_ept_overlay(vcpu, ept_state, page, open.apply(entry)) {
+        error!("{}", e);
+        return false;
+    }
+    true
+}
+
+// fetch from a breakpoint page. the breakpoint at rip is reported,then the instruction runs
+// against an executable entry for one instruction
+fn ept_exec_breakpoint(
+    guest_state: &mut GuestState,
+    vmm: &mut Vmm,
+    violation: &EptViolation,
+    entry: u64,
+) -> bool {
+    if !violation.fetch || (entry & EPT_ENTRY_EXEC_BREAKPOINT) == 0 {
+        return false;
+    }
+
+    let breakpoints = match vmm.exec_breakpoints.as_ref() {
+        Some(breakpoints) => breakpoints,
+        None => return false,
+    };
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let page = guest_state.physical_address & !0xFFF;
+
+    if let Some(callback) = breakpoints.find(guest_state.guest_rip, page) {
+        let mut hit = ExecBreakpointHit {
+            address: guest_state.guest_rip,
+            physical_address: guest_state.physical_address,
+            cpu_index: cpu_index as _,
+            registers: guest_registers(guest_state),
+            ..Default::default()
+        };
+        breakpoints.record(cpu_index, hit);
+
+        if let Some(callback) = callback {
+            let rip = hit.registers.rip;
+            callback(&mut hit);
+            set_guest_registers(guest_state, &hit.registers);
+
+            // the guest resumes elsewhere,a fetch back on the page exits again
+            if hit.registers.rip != rip {
+                return true;
+            }
+        }
+    }
+
+    let ept_state = match vmm.ept_state.as_mut() {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+
+    let restore = EptRestore {
+        eptp: ept_state.get_ept_pointer(),
+        physical: page,
+        entry,
+    };
+    if vmm.vcpu[cpu_index].ept_restore_mut().push(restore).is_err() {
+        error!("too many pending ept restores");
+        return false;
+    }
+
+    if ept_state
+        .set_page_entry(page, entry | EPT_EXECUTE_MASK)
+        .is_err()
+    {
+        return false;
+    }
+
+    invept_single(ept_state.get_ept_pointer());
+    vmx_set_monitor_trap_flag(true);
+    true
+}
+
+// access to a watched page. one touching a watched range is recorded once the instruction ran,
+// the instruction runs against the rights the page had before it was watched
+fn ept_watchpoint(
+    guest_state: &mut GuestState,
+    vmm: &mut Vmm,
+    violation: &EptViolation,
+    entry: u64,
+) -> bool {
+    if (entry & EPT_ENTRY_WATCHPOINT) == 0 {
+        return false;
+    }
+
+    let watchpoints = match vmm.watchpoints.as_ref() {
+        Some(watchpoints) => watchpoints,
+        None => return false,
+    };
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let page = guest_state.physical_address & !0xFFF;
+
+    let mut access = 0;
+    if violation.read {
+        access |= WATCH_READ;
+    }
+    if violation.write {
+        access |= WATCH_WRITE;
+    }
+
+    if let Some((id, linear_address)) = watchpoints.find(guest_state.physical_address, access) {
+        let event = WatchEvent {
+            id,
+            access,
+            cpu_index: cpu_index as _,
+            rip: guest_state.guest_rip,
+            cr3: vmcs_read(GUEST_CR3),
+            linear_address,
+            physical_address: guest_state.physical_address,
+            value_before: WatchEvent::read_value(
+                current_guest_mapping(),
+                guest_state.physical_address,
+            ),
+            ..Default::default()
+        };
+        if vmm.vcpu[cpu_index].pending_watch_mut().push(event).is_err() {
+            watchpoints.record(cpu_index, event);
+        }
+    }
+
+    let rights = watchpoints
+        .page_rights(page)
+        .unwrap_or(entry & EPT_ACCESS_MASK);
+    let ept_state = match vmm.ept_state.as_mut() {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+
+    let restore = EptRestore {
+        eptp: ept_state.get_ept_pointer(),
+        physical: page,
+        entry,
+    };
+    if vmm.vcpu[cpu_index].ept_restore_mut().push(restore).is_err() {
+        error!("too many pending ept restores");
+        return false;
+    }
+
+    if ept_state
+        .set_page_entry(page, (entry & !EPT_ACCESS_MASK) | rights)
+        .is_err()
+    {
+        return false;
+    }
+
+    invept_single(ept_state.get_ept_pointer());
+    vmx_set_monitor_trap_flag(true);
+    true
+}
+
+// write to a protected sub-page,reported and then let through for one instruction
+fn ept_sub_page_write(
+    guest_state: &mut GuestState,
+    vmm: &mut Vmm,
+    violation: &EptViolation,
+    entry: u64,
+) -> bool {
+    if !violation.sub_page_write || (entry & ptee::SUB_PAGE_WRITE) == 0 {
+        return false;
+    }
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let page = guest_state.physical_address & !0xFFF;
+
+    if let Some(callback) = vmm.sub_page_write_callback {
+        callback(&SubPageWrite {
+            cpu_index: cpu_index as _,
+            rip: guest_state.guest_rip,
+            cr3: vmcs_read(GUEST_CR3),
+            linear_address: violation
+                .linear_address_valid
+                .then_some(guest_state.linear_address),
+            physical_address: guest_state.physical_address,
+        });
+    }
+
+    let ept_state = match vmm.ept_state.as_mut() {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+
+    let restore = EptRestore {
+        eptp: ept_state.get_ept_pointer(),
+        physical: page,
+        entry,
+    };
+    if vmm.vcpu[cpu_index].ept_restore_mut().push(restore).is_err() {
+        error!("too many pending ept restores");
+        return false;
+    }
+
+    if ept_state
+        .set_page_entry(page, entry | ptee::WRITE_ACCESS)
+        .is_err()
+    {
+        return false;
+    }
+
+    invept_single(ept_state.get_ept_pointer());
+    vmx_set_monitor_trap_flag(true);
+    true
+}
+
+// access to a page another view owns. a fetch from hidden code enters the code view,any
+// other access is denied. a denied fetch gets #ud,a read or write goes to a zeroed page
+fn ept_protected_page(
+    vmm: &mut Vmm,
+    page: u64,
+    violation: &EptViolation,
+    eptp: u64,
+    entry: u64,
+) -> bool {
+    if (entry & EPT_ENTRY_PROTECTED) == 0 {
+        return false;
+    }
+
+    let views = match vmm.process_views.as_ref() {
+        Some(views) => views,
+        None => return false,
+    };
+    let view = match views.region_view(page) {
+        Some(view) => view,
+        None => return false,
+    };
+
+    if violation.fetch && views.owner(view) == Some(VIEW_OWNER_CODE) {
+        return vmx_load_ept_view(vmm, view).is_ok();
+    }
+
+    views.count_denied();
+    if violation.fetch {
+        vmx_inject_event(
+            INTERRUPT_HARDWARE_EXCEPTION,
+            VECTOR_INVALID_OPCODE_EXCEPTION,
+            0,
+        );
+        return true;
+    }
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let scratch = match views.scratch_page(cpu_index) {
+        Some(scratch) => scratch,
+        None => return false,
+    };
+
+    // the protected entry comes back on the monitor trap flag exit
+    let restore = EptRestore {
+        eptp,
+        physical: page,
+        entry,
+    };
+    if vmm.vcpu[cpu_index].ept_restore_mut().push(restore).is_err() {
+        error!("too many pending ept restores");
+        return false;
+    }
+
+    let ept_state = match vmm.ept_state_by_pointer(eptp) {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+    let redirected =
+        EptState::page_entry_with_frame(entry | ptee::READ_ACCESS | ptee::WRITE_ACCESS, scratch);
+    if ept_state.set_page_entry(page, redirected).is_err() {
+        return false;
+    }
+
+    invept_single(eptp);
+    vmx_set_monitor_trap_flag(true);
+    true
+}
+
+// access to hypervisor memory. our own code reaches the real page for one instruction,other
+// code gets #ud for a fetch and a zeroed sink page for a read or write
+fn ept_hidden_page(
+    guest_state: &mut GuestState,
+    vmm: &mut Vmm,
+    violation: &EptViolation,
+    eptp: u64,
+    entry: u64,
+) -> bool {
+    if (entry & EPT_ENTRY_HIDDEN) == 0 {
+        return false;
+    }
+
+    let hidden_memory = match vmm.hidden_memory.as_ref() {
+        Some(hidden_memory) => hidden_memory,
+        None => return false,
+    };
+
+    let cpu_index = get_current_processor_idx() as usize;
+    let page = guest_state.physical_address & !0xFFF;
+
+    let frame = if !violation.fetch && hidden_memory.is_driver_code(guest_state.guest_rip) {
+        page
+    } else {
+        hidden_memory.count_denied();
+        if violation.fetch {
+            vmx_inject_event(
+                INTERRUPT_HARDWARE_EXCEPTION,
+                VECTOR_INVALID_OPCODE_EXCEPTION,
+                0,
+            );
+            return true;
+        }
+        match hidden_memory.sink_page(cpu_index) {
+            Some(sink) => sink,
+            None => return false,
+        }
+    };
+
+    // the hidden entry comes back on the monitor trap flag exit
+    let restore = EptRestore {
+        eptp,
+        physical: page,
+        entry,
+    };
+    if vmm.vcpu[cpu_index].ept_restore_mut().push(restore).is_err() {
+        error!("too many pending ept restores");
+        return false;
+    }
+
+    let ept_state = match vmm.ept_state_by_pointer(eptp) {
+        Some(ept_state) => ept_state,
+        None => return false,
+    };
+    let loosened =
+        EptState::page_entry_with_frame(entry | ptee::READ_ACCESS | ptee::WRITE_ACCESS, frame);
+    if ept_state.set_page_entry(page, loosened).is_err() {
+        return false;
+    }
+
+    invept_single(eptp);
+    vmx_set_monitor_trap_flag(true);
+    true
+}
+
+// another view was given rights for the page that grant the access,the cpu runs on it until
+// an access needs a different one. pages a monitor owns never switch
+fn ept_view_switch(vmm: &mut Vmm, page: u64, violation: &EptViolation, eptp: u64) -> bool {
+    if vmm.ept_monitor_active() {
+        return false;
+    }
+    let monitored = vmm
+        .ept_state
+        .as_mut()
+        .and_then(|default| default.page_entry(page))
+        .is_some_and(|entry| (entry & EPT_ENTRY_MONITORED) != 0);
+    if monitored {
+        return false;
+    }
+
+    let allowing = match (vmm.ept_views.as_ref(), vmm.ept_state.as_ref()) {
+        (Some(views), Some(default)) => views
+            .view_allowing(default, page, violation, vmm.mode_based_execute)
+            .and_then(|index| views.eptp(index).map(|view_eptp| (index, view_eptp))),
+        _ => None,
+    };
+
+    match allowing {
+        // a 2mb page of the current view,its translation is stale
+        Some((_, view_eptp)) if view_eptp == eptp => {
+            invept_single(eptp);
+            true
+        }
+        Some((index, _)) => vmx_load_ept_view(vmm, index).is_ok(),
+        None => false,
+    }
+}
+
+// rip is never advanced,the faulting instruction runs again
+fn vm_exit_ept_violation(guest_state: &mut GuestState) {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let page = guest_state.physical_address & !0xFFF;
+    // a second page of the same instruction,the loaded overlay stands for the ept it copies
+    let overlay_eptp = vmm
+        .get_current_vcpu()
+        .ept_overlay_mut()
+        .and_then(|overlay| overlay.return_eptp());
+    let eptp = overlay_eptp.unwrap_or_else(|| vmcs_read(EPT_POINTER));
+
+    let default_view = match vmm.ept_state.as_mut() {
+        Some(ept_state) => ept_state.get_ept_pointer() == eptp,
+        None => false,
+    };
+    let entry = match vmm.ept_state_by_pointer(eptp) {
+        Some(ept_state) => ept_state.page_entry(page),
+        None => None,
+    };
+    let violation = EptViolation::decode(
+        guest_state.exit_qualification,
+        vmm.mode_based_execute,
+        vmm.vmx_features.ept_violation_info,
+    );
+
+    if let Some(entry) = entry {
+        if violation.allowed_by(EptPermissions::from_entry(entry), vmm.mode_based_execute) {
+            // another cpu already changed the entry,drop the stale translation.
+            // the overlay may hold a copy of the old one
+            if overlay_eptp.is_some() {
+                let cpu_index = get_current_processor_idx() as usize;
+                if let Some(ept_state) = vmm.ept_state.as_ref() {
+                    if let Err(e) =
+                        vmx_load_ept_overlay(&mut vmm.vcpu[cpu_index], ept_state, page, entry)
+                    {
+                        error!("{}", e);
+                    }
+                }
+            } else {
+                invept_single(eptp);
+            }
+            return;
+        }
+
+        if ept_hidden_page(guest_state, vmm, &violation, eptp, entry) {
+            return;
+        }
+
+        if ept_protected_page(vmm, page, &violation, eptp, entry) {
+            return;
+        }
+
+        // the monitors only change the default view
+        if default_view && ept_code_integrity_write(guest_state, vmm, entry) {
+            return;
+        }
+
+        if default_view && ept_exec_breakpoint(guest_state, vmm, &violation, entry) {
+            return;
+        }
+
+        if default_view && ept_watchpoint(guest_state, vmm, &violation, entry) {
+            return;
+        }
+
+        if default_view && ept_sub_page_write(guest_state, vmm, &violation, entry) {
+            return;
+        }
+
+        if default_view && ept_virt_exception_missed(vmm, page, entry) {
+            return;
+        }
+    }
+
+    // with process views the address space picks the view,never the access
+    if vmm.process_views.is_none() && ept_view_switch(vmm, page, &violation, eptp) {
+        return;
+    }
+
+    warn!(
+        "unhandled ept violation physical:{:x} rip:{:x} {:?}",
+        guest_state.physical_address, guest_state.guest_rip, violation
+    );
+    dump_current_vmcs();
+    debugbreak!();
+}
+
+// one guest instruction ran against loosened ept entries
+fn vm_exit_mtf(_guest_state: &mut GuestState) {
+    let vmm = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
+    let cpu_index = get_current_processor_idx() as usize;
+
+    // the shared ept is loaded again,flags the cpu set in the overlay belong to it
+    if let Some((eptp, pages)) = vmm.vcpu[cpu_index]
+        .ept_overlay_mut()
+        .and_then(|overlay| overlay.unload())
+    {
+        if let Some(ept_state) = vmm.ept_state_by_pointer(eptp) {
+            for (page, entry) in pages.into_iter().flatten() {
+                if let Err(e) = ept_state.merge_accessed_dirty(page, entry) {
+                    error!("{}", e);
+                }
+            }
+        }
+        __vmx_vmwrite(EPT_POINTER, eptp);
+    }
+
+    // each entry goes back into the view it was loosened in
+    let mut restores = core::mem::take(vmm.vcpu[cpu_index].ept_restore_mut());
+    restores.drain(|restore| {
+        let restored = match vmm.ept_state_by_pointer(restore.eptp) {
+            Some(ept_state) => ept_state
+                .set_page_entry(restore.physical, restore.entry)
+                .is_ok(),
+            None => false,
+        };
+        if !restored {
+            error!("failed to restore ept entry {:x}", restore.physical);
+        }
+        invept_single(restore.eptp);
+    });
+
+    // the watched access is done,its value after is in memory now
+    if let Some(watchpoints) = vmm.watchpoints.as_ref() {
+        let mapping = current_guest_mapping();
+        vmm.vcpu[cpu_index].pending_watch_mut().drain(|mut event| {
+            event.value_after = WatchEvent::read_value(mapping, event.physical_address);
+            watchpoints.record(cpu_index, event);
+        });
+    }
+
+    vmx_set_monitor_trap_flag(false);
+}
+
+type ExitHandler = fn(guest_state: &mut GuestState);
+static EXIT_HANDLER: [ExitHandler; 67] = [
+    vm_exit_exception_nmi, // 00 EXIT_REASON_EXCEPTION_NMI
+    vm_exit_unknown,       // 01 EXIT_REASON_EXTERNAL_INTERRUPT
+    vm_exit_unknown,       // 02 EXIT_REASON_TRIPLE_FAULT
+    vm_exit_unknown,       // 03 EXIT_REASON_INIT
+    vm_exit_unknown,       // 04 EXIT_REASON_SIPI
+    vm_exit_unknown,       // 05 EXIT_REASON_IO_SMI
+    vm_exit_unknown,       // 06 EXIT_REASON_OTHER_SMI
+    vm_exit_unknown,       // 07 EXIT_REASON_PENDING_INTERRUPT
+    vm_exit_nmi_window,    // 08 EXIT_REASON_NMI_WINDOW
+    vm_exit_unknown,       // 09 EXIT_REASON_TASK_SWITCH
+    vm_exit_cpuid,         // 10 EXIT_REASON_CPUID
+    vm_exit_unknown,       // 11 EXIT_REASON_GETSEC
+    vm_exit_unknown,       // 12 EXIT_REASON_HLT
+    vm_exit_unknown,       // 13 EXIT_REASON_INVD
+    vm_exit_unknown,       // 14 EXIT_REASON_INVLPG
+    vm_exit_unknown,       // 15 EXIT_REASON_RDPMC
+    vm_exit_unknown,       // 16 EXIT_REASON_RDTSC
+    vm_exit_unknown,       // 17 EXIT_REASON_RSM
+    vm_exit_vmcall,        // 18 EXIT_REASON_VMCALL
+    vm_exit_vmop,          // 19 EXIT_REASON_VMCLEAR
+    vm_exit_vmop,          // 20 EXIT_REASON_VMLAUNCH
     vm_exit_vmop,          // 21 EXIT_REASON_VMPTRLD
     vm_exit_vmop,          // 22 EXIT_REASON_VMPTRST
     vm_exit_vmop,          // 23 EXIT_REASON_VMREAD
@@ -566,7 +1982,7 @@ static EXIT_HANDLER: [ExitHandler; 65] = [
     vm_exit_unknown,       // 34 EXIT_REASON_MSR_LOADING
     vm_exit_unknown,       // 35 EXIT_REASON_RESERVED_35
     vm_exit_unknown,       // 36 EXIT_REASON_MWAIT_INSTRUCTION
-    vm_exit_unknown,       // 37 EXIT_REASOM_MTF
+    vm_exit_mtf,           // 37 EXIT_REASOM_MTF
     vm_exit_unknown,       // 38 EXIT_REASON_RESERVED_38
     vm_exit_unknown,       // 39 EXIT_REASON_MONITOR_INSTRUCTION
     vm_exit_unknown,       // 40 EXIT_REASON_PAUSE_INSTRUCTION
@@ -575,28 +1991,31 @@ static EXIT_HANDLER: [ExitHandler; 65] = [
     vm_exit_unknown,       // 43 EXIT_REASON_TPR_BELOW_THRESHOLD
     vm_exit_unknown,       // 44 EXIT_REASON_APIC_ACCESS
     vm_exit_unknown,       // 45 EXIT_REASON_VIRTUALIZED_EIO
-    vm_exit_unknown,       // 46 EXIT_REASON_XDTR_ACCESS
-    vm_exit_unknown,       // 47 EXIT_REASON_TR_ACCESS
+    vm_exit_dtr_access,    // 46 EXIT_REASON_XDTR_ACCESS
+    vm_exit_dtr_access,    // 47 EXIT_REASON_TR_ACCESS
     vm_exit_ept_violation, // 48 EXIT_REASON_EPT_VIOLATION
     vm_exit_ept_misconfig, // 49 EXIT_REASON_EPT_MISCONFIG
     vm_exit_vmop,          // 50 EXIT_REASON_INVEPT
     vm_exit_unknown,       // 51 EXIT_REASON_RDTSCP
-    vm_exit_unknown,       // 52 EXIT_REASON_PREEMPT_TIMER
+    vm_exit_preempt_timer, // 52 EXIT_REASON_PREEMPT_TIMER
     vm_exit_vmop,          // 53 EXIT_REASON_INVVPID
     vm_exit_unknown,       // 54 EXIT_REASON_WBINVD
     vm_exit_unknown,       // 55 EXIT_REASON_XSETBV
     vm_exit_unknown,       // 56 EXIT_REASON_APIC_WRITE
     vm_exit_unknown,       // 57 EXIT_REASON_RDRAND
     vm_exit_unknown,       // 58 EXIT_REASON_INVPCID
-    vm_exit_unknown,       // 59 EXIT_REASON_VMFUNC
+    vm_exit_vmop,          // 59 EXIT_REASON_VMFUNC
     vm_exit_unknown,       // 60 EXIT_REASON_RESERVED_60
     vm_exit_unknown,       // 61 EXIT_REASON_RDSEED
-    vm_exit_unknown,       // 62 EXIT_REASON_RESERVED_62
+    vm_exit_pml_full,      // 62 EXIT_REASON_PML_FULL
     vm_exit_unknown,       // 63 EXIT_REASON_XSAVES
     vm_exit_unknown,       // 64 EXIT_REASON_XRSTORS
+    vm_exit_unknown,       // 65 EXIT_REASON_PCONFIG
+    vm_exit_spp_event,     // 66 EXIT_REASON_SPP_EVENT
 ];
 
-unsafe extern "C" fn vmx_exit_handler(context: &mut Context) -> u64 {
+// the part of an exit that only sees the vmcs,the registers and the vmm
+fn vmx_dispatch_exit(context: &mut Context, guest_irql: u8) -> GuestState {
     let mut guest_state = GuestState {
         guest_regs: context,
         // vcpu: __GD.as_mut().unwrap().vmx_data.as_mut().unwrap().get_current_vcpu(),
@@ -605,21 +2024,85 @@ unsafe extern "C" fn vmx_exit_handler(context: &mut Context) -> u64 {
         guest_rflags: vmcs_read(GUEST_RFLAGS),
         linear_address: vmcs_read(GUEST_LINEAR_ADDRESS),
         physical_address: vmcs_read(GUEST_PHYSICAL_ADDRESS),
-        guest_irql: unsafe { KeGetCurrentIrql() } as _,
+        guest_irql,
         exit_reason: vmcs_read(VM_EXIT_REASON) as u16,
         exit_qualification: vmcs_read(EXIT_QUALIFICATION),
         exit_pending: false,
+        cpuid: [0; 4],
+        msr_value: 0,
     };
 
     EXIT_HANDLER[guest_state.exit_reason as usize](&mut guest_state);
+    guest_state
+}
+
+// every record of a trace file against the cr masks of this system,mismatches with the
+// record index
+pub fn replay_trace(trace: &[u8]) -> Result<Vec<(usize, ReplayMismatch)>, &'static str> {
+    let cr_masks = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() }.cr_masks();
+    exit_trace::replay_trace(trace, cr_masks)
+}
+
+// one exit of a vmx vcpu,true leaves vmx on this cpu
+pub(crate) fn vmx_vcpu_exit(vcpu: &mut Vcpu, context: &mut Context) -> bool {
+    let guest_irql = unsafe { KeGetCurrentIrql() } as u8;
+
+    // snapshot before the handler when this cpu records exits
+    let mut record = match vcpu.exit_trace_mut() {
+        Some(trace) if trace.is_enabled() => Some(ExitRecord::capture(
+            &mut HardwareVmcs,
+            vcpu.cpu_index(),
+            guest_irql,
+            context.registers(),
+        )),
+        _ => None,
+    };
+
+    let guest_state = vmx_dispatch_exit(context, guest_irql);
+
+    // the handler may have turned the trace off
+    if let Some(record) = record.as_mut().filter(|_| !guest_state.exit_pending) {
+        record.cpuid = guest_state.cpuid;
+        record.msr_value = guest_state.msr_value;
+        record.capture_after(&mut HardwareVmcs, context.registers());
+        if let Some(trace) = vcpu.exit_trace_mut().filter(|trace| trace.is_enabled()) {
+            trace.ring.push(*record);
+        }
+    }
+
+    if guest_state.exit_pending {
+        return true;
+    }
 
     // normal situation
-    if !guest_state.exit_pending {
+    if let Some(host_tables) = vcpu.host_tables_mut() {
+        host_tables.inject_pending_nmi();
+    }
+
+    // the timer handler already reloaded a full interval
+    if guest_state.exit_reason != EXIT_REASON_PREEMPT_TIMER {
+        vcpu.preemption_timer_mut().save();
+    }
+
+    false
+}
+
+unsafe extern "C" fn vmx_exit_handler(context: &mut Context) -> u64 {
+    let vcpu = __GD
+        .as_mut()
+        .unwrap()
+        .vmm
+        .as_mut()
+        .unwrap()
+        .get_current_vcpu();
+
+    if !vcpu.dispatch_exit(context) {
         return 0;
     }
 
-    let ins_len = vmcs_read(VM_EXIT_INSTRUCTION_LEN);
-    unsafe { guest_state.guest_regs.as_mut().unwrap().rsp = guest_state.guest_rsp };
+    // vmread stops working after vmxoff
+    let guest_rip = vmcs_read(GUEST_RIP) + vmcs_read(VM_EXIT_INSTRUCTION_LEN);
+    context.rsp = vmcs_read(GUEST_RSP);
 
     // gdt,idt
     let gdtr: KDESCRIPTOR = KDESCRIPTOR {
@@ -635,6 +2118,13 @@ unsafe extern "C" fn vmx_exit_handler(context: &mut Context) -> u64 {
 
     lgdt(&gdtr);
     lidt(&idtr);
+
+    // tr still points to the host tss,reload the guest one. ltr faults on a busy tss
+    let tr_selector = vmcs_read(GUEST_TR_SELECTOR) as u16;
+    let tss_descriptor = (gdtr.Base + (tr_selector as u64 & !7)) as *mut u64;
+    *tss_descriptor &= !(1u64 << 41);
+    ltr(tr_selector);
+
     write_cr3(vmcs_read(GUEST_CR3));
 
     if __vmx_off() != VmxInstructionResult::VmxSuccess {
@@ -642,5 +2132,61 @@ unsafe extern "C" fn vmx_exit_handler(context: &mut Context) -> u64 {
         debugbreak!();
     }
 
-    guest_state.guest_rip + ins_len
+    guest_rip
+}
+
+impl ExitVmm for Vmm {
+    fn cpu_index(&mut self) -> usize {
+        self.get_current_vcpu().cpu_index()
+    }
+
+    fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+        self.get_current_vcpu().msr_shadow_mut()
+    }
+
+    // the trace stub keeps following the lstar the guest sets
+    fn lstar_written(&mut self, lstar: u64) {
+        if let Some(trace) = self.get_current_vcpu().syscall_trace_mut() {
+            if trace.is_enabled() {
+                trace.set_target(lstar);
+            }
+        }
+    }
+
+    fn in_vmware(&mut self) -> bool {
+        self.vmx_features.in_vmware
+    }
+}
+
+impl CrAccessVmm for Vmm {
+    fn cr_masks(&mut self) -> ControlRegisterMasks {
+        *self.get_current_vcpu().cr_masks()
+    }
+
+    // mov to cr3 in the guest,flush what the instruction would and report the switch
+    fn guest_cr3_loaded(&mut self, old_cr3: u64, cr3: Cr3Load) {
+        if cr3.flush && flush_guest_context(&self.vmx_features) != VmxInstructionResult::VmxSuccess
+        {
+            error!("invvpid execute error");
+        }
+
+        // the process views follow the address space
+        if let Some(views) = self.process_views.as_ref() {
+            if let Err(e) = vmx_load_ept_view(self, views.view_for_cr3(cr3.cr3)) {
+                error!("{}", e);
+            }
+        }
+
+        if let Some(callback) = self.cr3_switch_callback {
+            callback(self.get_current_vcpu().cpu_index(), old_cr3, cr3.cr3);
+        }
+    }
+
+    fn read_cr8(&mut self) -> u64 {
+        read_cr8()
+    }
+
+    fn write_cr8(&mut self, value: u64) {
+        write_cr8(value)
+    }
 }
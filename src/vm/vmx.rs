@@ -1,39 +1,61 @@
 use core::{ffi::c_void, mem::size_of, ptr::null_mut};
 
 use alloc::{boxed::Box, vec::Vec};
-use moon_driver_utils::bitfield::{create_end_mask, get_bits_value, set_bits_value};
+use moon_driver_utils::{
+    bitfield::{create_end_mask, get_bits_value, set_bits_value},
+    rwlock::ReadWriteLock,
+};
 use moon_feature::in_vmware;
-use moon_instructions::{read_msr, segment_limit, write_cr0, write_cr4};
-use moon_log::{error, info};
+use moon_instructions::{read_msr, segment_limit, write_cr0, write_cr4, write_msr};
+use moon_log::{error, info, warn};
 use moon_struct::{
     inner::{GdtEntry64, GDTENTRY64_ACCESS_RIGHTS, KGDTENTRY64, KPROCESSOR_STATE},
     msr::{
-        self, ia32_vmx_ept_vpid_cap_msr,
+        self, ia32_vmx_ept_vpid_cap_msr, ia32_vmx_misc_msr,
         msr_index::{
-            MSR_IA32_VMX_BASIC, MSR_IA32_VMX_EPT_VPID_CAP, MSR_IA32_VMX_PROCBASED_CTLS,
-            MSR_IA32_VMX_PROCBASED_CTLS2, MSR_IA32_VMX_TRUE_PROCBASED_CTLS,
+            MSR_IA32_VMX_BASIC, MSR_IA32_VMX_EPT_VPID_CAP, MSR_IA32_VMX_MISC,
+            MSR_IA32_VMX_PINBASED_CTLS, MSR_IA32_VMX_PROCBASED_CTLS, MSR_IA32_VMX_PROCBASED_CTLS2,
+            MSR_IA32_VMX_TRUE_PINBASED_CTLS, MSR_IA32_VMX_TRUE_PROCBASED_CTLS, MSR_IA32_VMX_VMFUNC,
         },
     },
 };
 use wdk_sys::{
     ntddk::{
         KeQueryActiveProcessorCount, KeRevertToUserAffinityThread, KeSetSystemAffinityThread,
-        MmAllocateContiguousMemory, MmFreeContiguousMemory, MmGetPhysicalAddress,
-        RtlCaptureContext, RtlInitializeBitMap, RtlSetBit,
+        KeStackAttachProcess, KeUnstackDetachProcess, MmAllocateContiguousMemory,
+        MmFreeContiguousMemory, MmGetPhysicalAddress, MmIsAddressValid, ObfDereferenceObject,
+        RtlCaptureContext,
     },
-    KERNEL_STACK_SIZE, PAGE_READWRITE, PHYSICAL_ADDRESS, RTL_BITMAP, USHORT, _LARGE_INTEGER,
+    KAPC_STATE, KERNEL_STACK_SIZE, PAGE_READWRITE, PHYSICAL_ADDRESS, USHORT, _KPROCESS,
+    _LARGE_INTEGER,
 };
 
 use crate::{
     inner::{KeSaveStateForHibernate, RtlRestoreContext},
-    utils::{get_current_processor_idx, protect_non_paged_memory},
+    symbol::{get_process_by_name, lookup_process},
+    utils::{
+        get_current_processor_idx, protect_non_paged_memory, virtual_address_to_physical_address,
+        LockedPages,
+    },
     vm::ins::{__vmx_read_error, __vmx_vmlaunch},
     __GD,
 };
 
 use super::{
+    access_dirty::{harvest_access_dirty, AccessDirtyClear, AccessDirtyPage},
+    backend::HypervisorBackend,
+    code_integrity::{CodeIntegrityMonitor, CodeIntegrityPolicy, CodeWriteEvent},
+    controls::{
+        EntryControls, ExitControls, PinControls, PrimaryControls, SecondaryControls, VmcsControls,
+    },
+    cr_access::{ControlRegisterMasks, Cr3SwitchCallback},
     data::{
-        vm_call::EXIT_VT,
+        ptee,
+        vm_call::{
+            DIRTY_LOG, EPT_VIEWS, EPT_VIEW_SWITCH, EXIT_TRACE, INVEPT_ALL_CONTEXT,
+            INVEPT_SINGLE_CONTEXT, MSR_INTERCEPT, PROCESS_VIEWS, SUB_PAGE_WRITE, SYSCALL_TRACE,
+            VIRT_EXCEPTION,
+        },
         vmcs_encoding::{
             CPU_BASED_VM_EXEC_CONTROL, CR0_GUEST_HOST_MASK, CR0_READ_SHADOW, CR4_GUEST_HOST_MASK,
             CR4_READ_SHADOW, EPT_POINTER, GUEST_CR0, GUEST_CR3, GUEST_CR4, GUEST_CS_AR_BYTES,
@@ -53,17 +75,54 @@ use super::{
             VM_EXIT_CONTROLS,
         },
         vmx_basic::VMX_BASIC_TRUE_CTLS,
-        vmx_cpu_based_controls::{self, VMX_PROC_CTLS_USE_SECONDARY_CTLS},
+        vmx_cpu_based_controls::{
+            VMX_PROC_CTLS_MONITOR_TRAP_FLAG, VMX_PROC_CTLS_NMI_WINDOW_EXIT,
+            VMX_PROC_CTLS_USE_SECONDARY_CTLS,
+        },
+        vmx_pin_based_controls::{
+            VMX_PIN_CTLS_NMI_EXIT, VMX_PIN_CTLS_PREEMPT_TIMER, VMX_PIN_CTLS_VIRT_NMI,
+        },
         vmx_secondary_cpu_based_controls::{
-            self, VMX_PROC_CTLS2_EPT, VMX_PROC_CTLS2_VMFUNC, VMX_PROC_CTLS2_VPID,
+            VMX_PROC_CTLS2_EPT, VMX_PROC_CTLS2_EPT_XCPT_VE, VMX_PROC_CTLS2_MODE_BASED_EPT_PERM,
+            VMX_PROC_CTLS2_PML, VMX_PROC_CTLS2_SPP_EPT, VMX_PROC_CTLS2_VMFUNC, VMX_PROC_CTLS2_VPID,
         },
-        vmx_vm_enter_controls, vmx_vm_exit_controls,
     },
-    ept::EptState,
+    desc_table::{DescTableGuard, DescTablePolicy, DescriptorTableRegister},
+    dirty_log::{DirtyLogCommand, DirtyRange, PmlLog},
+    dump::dump_current_vmcs,
+    entry_check::{read_vmx_capabilities, validate_current_vmcs},
+    ept::{
+        EptOverlay, EptPermissions, EptState, PendingEptRestore, EPT_ACCESS_MASK,
+        EPT_ENTRY_CODE_INTEGRITY, EPT_ENTRY_EXEC_BREAKPOINT, EPT_ENTRY_PROTECTED,
+        EPT_ENTRY_WATCHPOINT, EPT_EXECUTE_MASK,
+    },
+    ept_view::{EptViews, VMFUNC_EPTP_SWITCHING_BIT},
+    exec_breakpoint::{ExecBreakpointCallback, ExecBreakpointHit, ExecBreakpoints},
+    exit_trace::{ExitRecord, ExitTraceCpu},
+    guest_memory::GuestMapping,
+    hidden_memory::{HiddenMemory, HiddenMode},
+    host::HostTables,
     ins::{
         VmxInstructionResult, __vmx_off, __vmx_on, __vmx_vmcall, __vmx_vmclear, __vmx_vmptrld,
         __vmx_vmwrite,
     },
+    msr_bitmap::{check_msr_intercept, msr_bit_position, MsrAccess, MsrBitmap, MSR_BITMAP_SIZE},
+    msr_shadow::{MsrPolicy, MsrShadowTable},
+    preemption::{PreemptionTimer, PreemptionTimerCallback},
+    process_view::{
+        process_cr3, set_process_exit_notify, ProcessViews, PROCESS_REGION_MAX_SIZE,
+        VIEW_OWNER_CODE,
+    },
+    spp::{SppTable, SubPageMask, SubPageWriteCallback},
+    syscall_trace::{
+        is_kva_shadow_entry, resolve_syscall_names, syscall_name, wait_for_stub_quiescence,
+        SyscallEvent, SyscallTraceCpu,
+    },
+    virt_exception::{VeCallback, VeCounts, VirtExceptionCpu},
+    vpid::GUEST_VPID,
+    watchpoint::{
+        PendingWatchEvents, WatchEvent, Watchpoints, WATCHPOINT_MAX_SIZE, WATCH_READ, WATCH_WRITE,
+    },
 };
 
 extern "C" {
@@ -82,18 +141,65 @@ pub struct Vcpu {
     host_state: Box<KPROCESSOR_STATE>,
     vcpu_vmx_state: VcpuVmxState,
     vm_resources: VmcsResources,
+    host_tables: Option<Box<HostTables>>,
+    // guest frames are read and written through it in vmx root
+    guest_mapping: Option<GuestMapping>,
+    preemption_timer: PreemptionTimer,
+    msr_shadow: MsrShadowTable,
+    syscall_trace: Option<Box<SyscallTraceCpu>>,
+    exit_trace: Option<Box<ExitTraceCpu>>,
+    cr_masks: ControlRegisterMasks,
+    desc_table_guard: Option<DescTableGuard>,
+    ept_restore: PendingEptRestore,
+    // private copy of the default ept for entries loosened for one instruction
+    ept_overlay: Option<EptOverlay>,
+    pml_log: Option<Box<PmlLog>>,
+    virt_exception: Option<Box<VirtExceptionCpu>>,
+    pending_watch: PendingWatchEvents,
     vmxon: bool,
 }
 
+// ioctls and os callbacks reach the vmm from any thread,each one holds this while it uses the
+// vmm
+pub static VMM_LOCK: ReadWriteLock<()> = ReadWriteLock::new(());
+
 pub struct Vmm {
     pub cpu_count: u32,
     pub vmx_features: VMXFeatures,
     pub ept_state: Option<EptState>,
     pub vcpu: Vec<Box<Vcpu>>,
+    pub cr3_switch_callback: Option<Cr3SwitchCallback>,
+    pub desc_table_policy: Option<DescTablePolicy>,
+    pub code_integrity: Option<CodeIntegrityMonitor>,
+    // ept accessed and dirty flags,set before start
+    pub ept_access_dirty: bool,
+    // separate supervisor and user execute rights in the ept,set before start
+    pub mode_based_execute: bool,
+    // hide hypervisor memory from the guest once every cpu runs,set before start
+    pub hide_memory: bool,
+    // guest physical ranges tracked by page modification logging
+    dirty_ranges: Vec<DirtyRange>,
+    // guest side #ve handler,set while virtualization exceptions are enabled
+    pub virt_exception_callback: Option<VeCallback>,
+    // views besides ept_state,none while only the default ept is used
+    pub ept_views: Option<EptViews>,
+    // 128 byte write permissions of pages in the default view
+    pub spp_table: Option<SppTable>,
+    pub sub_page_write_callback: Option<SubPageWriteCallback>,
+    // execute breakpoints in the default view,created with the first one
+    pub exec_breakpoints: Option<ExecBreakpoints>,
+    // the page of each breakpoint address stays resident while it is set
+    exec_breakpoint_pages: Vec<(u64, LockedPages)>,
+    // read and write watchpoints in the default view,created with the first one
+    pub watchpoints: Option<Watchpoints>,
+    // the range of each watchpoint id stays resident while it is set
+    watchpoint_pages: Vec<(u32, LockedPages)>,
+    // owners of the ept views and the regions only their view maps
+    pub process_views: Option<ProcessViews>,
+    // entries of hypervisor pages before they were hidden
+    pub hidden_memory: Option<HiddenMemory>,
 }
 
-pub struct StartVTError {}
-
 impl Vcpu {
     // free vmm relate physical memory self
     pub fn free_physical_memory(&mut self) {
@@ -121,6 +227,40 @@ impl Vcpu {
                 MmFreeContiguousMemory(core::mem::replace(msr_bitmap, core::ptr::null_mut()) as _)
             };
         }
+
+        self.guest_mapping = None;
+        self.ept_overlay = None;
+    }
+
+    // physical pages only vmx root uses after launch
+    fn vmm_pages(&self) -> Vec<u64> {
+        let resources = &self.vm_resources;
+        let mut pages = Vec::new();
+
+        for (memory, size) in [
+            (resources.vmxon as *mut c_void, PAGE_SIZE),
+            (resources.vmcs as *mut c_void, PAGE_SIZE),
+            (resources.msr_bitmap, PAGE_SIZE),
+            (resources.vmm_stack, KERNEL_STACK_SIZE as usize),
+        ] {
+            if memory.is_null() {
+                continue;
+            }
+            let physical = virtual_address_to_physical_address(memory);
+            pages.extend(
+                (0..size as u64)
+                    .step_by(PAGE_SIZE as _)
+                    .map(|offset| physical + offset),
+            );
+        }
+        if let Some(tables) = self.host_tables.as_ref() {
+            pages.extend(tables.pages());
+        }
+        if let Some(overlay) = self.ept_overlay.as_ref() {
+            pages.extend(overlay.pages());
+        }
+
+        pages
     }
 
     fn enter_vmx_root_mode(&mut self) -> Result<(), &'static str> {
@@ -187,13 +327,6 @@ impl Vcpu {
         Ok(())
     }
 
-    fn vmxp_adjust_msr(&self, control_value: u64, desired_value: u32) -> u32 {
-        let mut result = desired_value;
-        result &= (control_value >> 32) as u32;
-        result |= control_value as u32;
-        result
-    }
-
     fn convert_gdt_entry(&mut self, base: u64, selector: USHORT) -> GdtEntry64 {
         // limit
         let limit = segment_limit(selector as _);
@@ -247,86 +380,36 @@ impl Vcpu {
         }
     }
 
+    pub fn msr_bitmap(&mut self) -> MsrBitmap<'_> {
+        MsrBitmap::new(unsafe {
+            &mut *(self.vm_resources.msr_bitmap as *mut [u8; MSR_BITMAP_SIZE])
+        })
+    }
+
     fn init_msr_bitmap(&mut self) {
-        let bit_map_read_low: *mut u32 = self.vm_resources.msr_bitmap as _; // 0-00001FFF
-        let bit_map_read_high: *mut u32 = (bit_map_read_low as u64 + 1024) as _; // C0000000 - C0001FFF
-        let bit_map_write_low: *mut u32 = (bit_map_read_high as u64 + 1024) as _;
-        let bit_map_write_high: *mut u32 = (bit_map_write_low as u64 + 1024) as _;
-
-        let mut bit_map_read_low_header: RTL_BITMAP = RTL_BITMAP {
-            SizeOfBitMap: 0,
-            Buffer: core::ptr::null_mut(),
-        };
-        let mut bit_map_read_high_header: RTL_BITMAP = RTL_BITMAP {
-            SizeOfBitMap: 0,
-            Buffer: core::ptr::null_mut(),
-        };
-        let mut bit_map_write_low_header: RTL_BITMAP = RTL_BITMAP {
-            SizeOfBitMap: 0,
-            Buffer: core::ptr::null_mut(),
-        };
-        let mut bit_map_write_high_header: RTL_BITMAP = RTL_BITMAP {
-            SizeOfBitMap: 0,
-            Buffer: core::ptr::null_mut(),
-        };
+        // policies on msrs outside the bitmap ranges apply anyway,those always exit
+        let shadowed: Vec<(u32, MsrAccess)> = self
+            .msr_shadow
+            .intercepted_msrs()
+            .filter(|(msr, _)| msr_bit_position(*msr, false).is_ok())
+            .collect();
 
-        unsafe {
-            RtlInitializeBitMap(
-                &mut bit_map_read_low_header as _,
-                bit_map_read_low,
-                1024 * 8,
-            );
-            RtlInitializeBitMap(
-                &mut bit_map_read_high_header as _,
-                bit_map_read_high,
-                1024 * 8,
-            );
-            RtlInitializeBitMap(
-                &mut bit_map_write_low_header as _,
-                bit_map_write_low,
-                1024 * 8,
-            );
-            RtlInitializeBitMap(
-                &mut bit_map_write_high_header as _,
-                bit_map_write_high,
-                1024 * 8,
-            );
-        }
+        let mut bitmap = self.msr_bitmap();
 
         // rw msr will vm-exit when specific msr bit set
-        unsafe {
-            RtlSetBit(
-                &mut bit_map_read_low_header as _,
-                msr::msr_index::MSR_IA32_FEATURE_CONTROL,
-            );
-            RtlSetBit(
-                &mut bit_map_read_low_header as _,
-                msr::msr_index::MSR_IA32_DEBUGCTL,
-            );
-            RtlSetBit(
-                &mut bit_map_read_high_header as _,
-                msr::msr_index::MSR_LSTAR - 0xC0000000,
-            );
-
-            RtlSetBit(
-                &mut bit_map_write_low_header as _,
-                msr::msr_index::MSR_IA32_FEATURE_CONTROL,
-            );
-            RtlSetBit(
-                &mut bit_map_write_low_header as _,
-                msr::msr_index::MSR_IA32_DEBUGCTL,
-            );
-            RtlSetBit(
-                &mut bit_map_write_high_header as _,
-                msr::msr_index::MSR_LSTAR - 0xC0000000,
-            );
-        }
-
-        for i in msr::msr_index::MSR_IA32_VMX_BASIC..=msr::msr_index::MSR_IA32_VMX_VMFUNC {
-            unsafe {
-                RtlSetBit(&mut bit_map_read_low_header, i);
-                RtlSetBit(&mut bit_map_write_low_header, i);
-            }
+        let mut intercepted = [
+            msr::msr_index::MSR_IA32_FEATURE_CONTROL,
+            msr::msr_index::MSR_IA32_DEBUGCTL,
+            msr::msr_index::MSR_LSTAR,
+        ]
+        .into_iter()
+        .chain(msr::msr_index::MSR_IA32_VMX_BASIC..=msr::msr_index::MSR_IA32_VMX_VMFUNC)
+        .map(|msr| (msr, MsrAccess::ReadWrite))
+        .chain(shadowed);
+
+        if let Err(e) = intercepted.try_for_each(|(index, access)| bitmap.set(index, access, true))
+        {
+            error!("{}", e);
         }
 
         // MSR BitMap
@@ -335,78 +418,131 @@ impl Vcpu {
         });
     }
 
-    fn set_vmcs_data(&mut self) {
-        let vm_pin_ctl_requested: u32 = 0;
-        let mut vm_cpu_ctl_requested: u32 = 0;
-
-        let mut vm_enter_ctl_requested: u32 = 0;
-        let mut vm_exit_ctl_requested: u32 = 0;
-
+    fn set_vmcs_data(&mut self) -> Result<(), &'static str> {
         let vmx_feature = unsafe { &__GD.as_mut().unwrap().vmm.as_mut().unwrap().vmx_features };
+        let cr3_switch_callback = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .vmm
+                .as_mut()
+                .unwrap()
+                .cr3_switch_callback
+        };
+        let desc_table_policy = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .vmm
+                .as_mut()
+                .unwrap()
+                .desc_table_policy
+        };
+        let mode_based_execute = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .vmm
+                .as_mut()
+                .unwrap()
+                .mode_based_execute
+        };
 
-        // fixed bit
-        let mut vmx_pin: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_PINBASED_CTLS);
-        let mut vmx_cpu: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_PROCBASED_CTLS);
-
-        // let vmx_tertiary: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_PROCBASED_CTLS3); // maybe need
-        let mut vmx_exit: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_EXIT_CTLS);
-        // let mut vmx_exit_secondary: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_EXIT_CTLS2); // maybe need
-        let mut vmx_entry: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_ENTRY_CTLS);
-
-        // true msr
-        if vmx_feature.true_msrs {
-            vmx_pin = read_msr(msr::msr_index::MSR_IA32_VMX_TRUE_PINBASED_CTLS);
-            vmx_cpu = read_msr(msr::msr_index::MSR_IA32_VMX_TRUE_PROCBASED_CTLS);
-            vmx_exit = read_msr(msr::msr_index::MSR_IA32_VMX_TRUE_EXIT_CTLS);
-            vmx_entry = read_msr(msr::msr_index::MSR_IA32_VMX_TRUE_ENTRY_CTLS);
-        }
-
-        if vmx_feature.secondary_controls {
-            vm_cpu_ctl_requested |= vmx_cpu_based_controls::VMX_PROC_CTLS_USE_SECONDARY_CTLS;
-
-            let mut vm_cpu_ctl2_requested: u32 = 0;
-
-            // ept
-            if vmx_feature.ept {
-                let vmx_data = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
-                vm_cpu_ctl2_requested |= vmx_secondary_cpu_based_controls::VMX_PROC_CTLS2_EPT;
-
-                if vmx_feature.vpid {
-                    vm_cpu_ctl2_requested |= vmx_secondary_cpu_based_controls::VMX_PROC_CTLS2_VPID;
-                    __vmx_vmwrite(VIRTUAL_PROCESSOR_ID, 1); // greater than 0
+        let preemption_timer = vmx_feature.preemption_timer && self.preemption_timer.has_callback();
+
+        // fixed bit,true msr if supported
+        let capabilities = read_vmx_capabilities(vmx_feature.true_msrs);
+
+        let controls = VmcsControls::new()
+            // cpu
+            .require(PrimaryControls::USE_MSR_BITMAPS) // msr
+            .request(PrimaryControls::USE_TSC_OFFSETTING) // combine with rdtscp
+            .require_if(
+                cr3_switch_callback.is_some(),
+                PrimaryControls::CR3_LOAD_EXIT,
+            ) // address space switch
+            // secondary
+            .require_if(
+                desc_table_policy.is_some(),
+                SecondaryControls::DESC_TABLE_EXIT,
+            ) // lgdt,lidt,lldt,ltr and stores
+            .require_if(vmx_feature.ept, SecondaryControls::EPT)
+            // user and supervisor execute rights
+            .require_if(mode_based_execute, SecondaryControls::MODE_BASED_EPT_PERM)
+            .request_if(vmx_feature.ept && vmx_feature.vpid, SecondaryControls::VPID)
+            .request_if(vmx_feature.secondary_controls, SecondaryControls::RDTSCP)
+            .request_if(vmx_feature.secondary_controls, SecondaryControls::INVPCID)
+            .request_if(
+                vmx_feature.secondary_controls,
+                SecondaryControls::XSAVES_XRSTORS,
+            )
+            // vm_enter
+            .request(EntryControls::LOAD_DEBUG) // dr
+            .require(EntryControls::IA32E_MODE_GUEST)
+            // vm_exit
+            .require(ExitControls::HOST_ADDR_SPACE_SIZE)
+            // guest nmis exit and queue behind the ones that hit root,the nmi-window exit
+            // delivers them
+            .request_if(
+                vmx_feature.virtual_nmi,
+                PinControls::NMI_EXIT | PinControls::VIRT_NMI,
+            )
+            // preemption timer,only armed for a registered callback
+            .request_if(preemption_timer, PinControls::PREEMPT_TIMER)
+            .request_if(preemption_timer, ExitControls::SAVE_PREEMPT_TIMER);
+
+        let controls = match controls.resolve(&capabilities) {
+            Ok(controls) => controls,
+            Err(missing) => {
+                for control in missing.iter() {
+                    error!("unsupported vmx control:{:?} {}", control, control.name());
                 }
-
-                __vmx_vmwrite(
-                    EPT_POINTER,
-                    vmx_data.ept_state.as_mut().unwrap().get_ept_pointer(),
-                );
+                return Err("mandatory vmx control is not supported");
             }
+        };
 
-            let vmx_cpu_secondary: u64 = read_msr(msr::msr_index::MSR_IA32_VMX_PROCBASED_CTLS2);
-
-            // other
-            vm_cpu_ctl2_requested |= vmx_secondary_cpu_based_controls::VMX_PROC_CTLS2_RDTSCP;
-            vm_cpu_ctl2_requested |= vmx_secondary_cpu_based_controls::VMX_PROC_CTLS2_INVPCID;
-            vm_cpu_ctl2_requested |=
-                vmx_secondary_cpu_based_controls::VMX_PROC_CTLS2_XSAVES_XRSTORS;
+        for control in controls.dropped.iter() {
+            warn!("vmx control dropped:{:?} {}", control, control.name());
+        }
 
-            //Secondary
+        // ept
+        if controls.secondary.contains(SecondaryControls::EPT) {
+            let vmx_data = unsafe { __GD.as_mut().unwrap().vmm.as_mut().unwrap() };
             __vmx_vmwrite(
-                SECONDARY_VM_EXEC_CONTROL as _,
-                self.vmxp_adjust_msr(vmx_cpu_secondary, vm_cpu_ctl2_requested) as u64,
+                EPT_POINTER,
+                vmx_data.ept_state.as_mut().unwrap().get_ept_pointer(),
             );
         }
 
-        // cpu
-        vm_cpu_ctl_requested |= vmx_cpu_based_controls::VMX_PROC_CTLS_USE_MSR_BITMAPS; // msr
-        vm_cpu_ctl_requested |= vmx_cpu_based_controls::VMX_PROC_CTLS_USE_TSC_OFFSETTING; // combine with rdtscp
+        if controls.secondary.contains(SecondaryControls::VPID) {
+            __vmx_vmwrite(VIRTUAL_PROCESSOR_ID, GUEST_VPID as _); // greater than 0
+        }
+
+        if controls.pin.contains(PinControls::PREEMPT_TIMER) {
+            self.preemption_timer.enable(
+                vmx_feature.preemption_timer_rate,
+                controls.exit.contains(ExitControls::SAVE_PREEMPT_TIMER),
+            );
+        }
 
-        // vm_enter
-        vm_enter_ctl_requested |= vmx_vm_enter_controls::VMX_ENTRY_CTLS_LOAD_DEBUG; // dr
-        vm_enter_ctl_requested |= vmx_vm_enter_controls::VMX_ENTRY_CTLS_IA32E_MODE_GUEST;
+        self.cr_masks = ControlRegisterMasks::new(
+            &capabilities,
+            controls
+                .secondary
+                .contains(SecondaryControls::UNRESTRICTED_GUEST),
+        );
 
-        // vm_exit
-        vm_exit_ctl_requested |= vmx_vm_exit_controls::VMX_EXIT_CTLS_HOST_ADDR_SPACE_SIZE;
+        // the tables the guest booted with,later loads are checked against them
+        if let Some(policy) = desc_table_policy {
+            self.desc_table_guard = Some(DescTableGuard::new(
+                policy,
+                DescriptorTableRegister {
+                    base: self.host_state.SpecialRegisters.Gdtr.Base,
+                    limit: self.host_state.SpecialRegisters.Gdtr.Limit,
+                },
+                DescriptorTableRegister {
+                    base: self.host_state.SpecialRegisters.Idtr.Base,
+                    limit: self.host_state.SpecialRegisters.Idtr.Limit,
+                },
+            ));
+        }
 
         // msr bitmap
         self.init_msr_bitmap();
@@ -415,28 +551,30 @@ impl Vcpu {
         __vmx_vmwrite(VMCS_LINK_POINTER as _, u64::MAX);
 
         //PIN
-        __vmx_vmwrite(
-            PIN_BASED_VM_EXEC_CONTROL as _,
-            self.vmxp_adjust_msr(vmx_pin, vm_pin_ctl_requested) as u64,
-        );
+        __vmx_vmwrite(PIN_BASED_VM_EXEC_CONTROL as _, controls.pin.bits() as u64);
 
         //CPU Processor
         __vmx_vmwrite(
             CPU_BASED_VM_EXEC_CONTROL as _,
-            self.vmxp_adjust_msr(vmx_cpu, vm_cpu_ctl_requested) as u64,
+            controls.primary.bits() as u64,
         );
 
+        //Secondary
+        if controls
+            .primary
+            .contains(PrimaryControls::USE_SECONDARY_CTLS)
+        {
+            __vmx_vmwrite(
+                SECONDARY_VM_EXEC_CONTROL as _,
+                controls.secondary.bits() as u64,
+            );
+        }
+
         //VM Exit
-        __vmx_vmwrite(
-            VM_EXIT_CONTROLS as _,
-            self.vmxp_adjust_msr(vmx_exit, vm_exit_ctl_requested) as u64,
-        );
+        __vmx_vmwrite(VM_EXIT_CONTROLS as _, controls.exit.bits() as u64);
 
         //VM Entry
-        __vmx_vmwrite(
-            VM_ENTRY_CONTROLS as _,
-            self.vmxp_adjust_msr(vmx_entry, vm_enter_ctl_requested) as u64,
-        );
+        __vmx_vmwrite(VM_ENTRY_CONTROLS as _, controls.entry.bits() as u64);
 
         // cs
         let gdt_entry = self.convert_gdt_entry(
@@ -553,7 +691,6 @@ impl Vcpu {
             gdt_entry.access_rights.access_rights as _
         });
         __vmx_vmwrite(GUEST_TR_BASE, gdt_entry.base as _);
-        __vmx_vmwrite(HOST_TR_BASE, gdt_entry.base as _);
         __vmx_vmwrite(
             HOST_TR_SELECTOR,
             (self.host_state.SpecialRegisters.Tr & !3) as _,
@@ -577,7 +714,6 @@ impl Vcpu {
             GUEST_GDTR_LIMIT,
             self.host_state.SpecialRegisters.Gdtr.Limit as _,
         );
-        __vmx_vmwrite(HOST_GDTR_BASE, self.host_state.SpecialRegisters.Gdtr.Base);
 
         // IDT
         __vmx_vmwrite(GUEST_IDTR_BASE, self.host_state.SpecialRegisters.Idtr.Base);
@@ -585,11 +721,20 @@ impl Vcpu {
             GUEST_IDTR_LIMIT,
             self.host_state.SpecialRegisters.Idtr.Limit as _,
         );
-        __vmx_vmwrite(HOST_IDTR_BASE, self.host_state.SpecialRegisters.Idtr.Base);
 
-        // CR0
-        __vmx_vmwrite(CR0_GUEST_HOST_MASK, 0xffffffff);
-        __vmx_vmwrite(CR0_READ_SHADOW, self.host_state.SpecialRegisters.Cr0);
+        // host gdt,idt and tss,never run root mode faults through the guest idt
+        let host_tables = self.host_tables.as_ref().unwrap();
+        __vmx_vmwrite(HOST_GDTR_BASE, host_tables.gdt_base());
+        __vmx_vmwrite(HOST_IDTR_BASE, host_tables.idt_base());
+        __vmx_vmwrite(HOST_TR_BASE, host_tables.tr_base());
+
+        // CR0,fixed bits are host owned
+        __vmx_vmwrite(CR0_GUEST_HOST_MASK, self.cr_masks.cr0_guest_host_mask());
+        __vmx_vmwrite(
+            CR0_READ_SHADOW,
+            self.cr_masks
+                .cr0_read_shadow(self.host_state.SpecialRegisters.Cr0),
+        );
         __vmx_vmwrite(HOST_CR0, self.host_state.SpecialRegisters.Cr0);
         __vmx_vmwrite(GUEST_CR0, self.host_state.SpecialRegisters.Cr0);
 
@@ -600,10 +745,11 @@ impl Vcpu {
         // CR4
         __vmx_vmwrite(HOST_CR4, self.host_state.SpecialRegisters.Cr4);
         __vmx_vmwrite(GUEST_CR4, self.host_state.SpecialRegisters.Cr4);
-        __vmx_vmwrite(CR4_GUEST_HOST_MASK, 0x2000);
+        __vmx_vmwrite(CR4_GUEST_HOST_MASK, self.cr_masks.cr4_guest_host_mask());
         __vmx_vmwrite(
             CR4_READ_SHADOW,
-            self.host_state.SpecialRegisters.Cr4 & !0x2000,
+            self.cr_masks
+                .cr4_read_shadow(self.host_state.SpecialRegisters.Cr4),
         );
 
         // Debug MSR and DR7
@@ -624,6 +770,8 @@ impl Vcpu {
             (self.vm_resources.vmm_stack as u64 + KERNEL_STACK_SIZE as u64 - 8 * 2) as _,
         );
         __vmx_vmwrite(HOST_RIP, vmm_entry_point as _);
+
+        Ok(())
     }
 
     fn subvert_cpu(&mut self) {
@@ -674,6 +822,46 @@ impl Vcpu {
             core::ptr::write_bytes(msr_bitmap, 0, PAGE_SIZE as _);
         }
 
+        match HostTables::new(
+            self.host_state.SpecialRegisters.Gdtr.Base,
+            self.host_state.SpecialRegisters.Gdtr.Limit,
+            self.host_state.Context_frame.SegCs,
+            self.host_state.SpecialRegisters.Tr,
+        ) {
+            Ok(tables) => self.host_tables = Some(Box::new(tables)),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+
+        match GuestMapping::new() {
+            Ok(mapping) => self.guest_mapping = Some(mapping),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+
+        let ept = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .vmm
+                .as_mut()
+                .unwrap()
+                .ept_state
+                .is_some()
+        };
+        if ept {
+            match EptOverlay::new() {
+                Ok(overlay) => self.ept_overlay = Some(overlay),
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+        }
+
         // enter vmx root
         match self.enter_vmx_root_mode() {
             Ok(_) => {}
@@ -685,15 +873,36 @@ impl Vcpu {
 
         info!("already enter vmx root mode");
 
-        self.set_vmcs_data();
+        // fill the vmcs,then run the vm-entry checks in software
+        // vmlaunch itself only reports invalid guest state
+        let true_msrs = unsafe {
+            __GD.as_mut()
+                .unwrap()
+                .vmm
+                .as_mut()
+                .unwrap()
+                .vmx_features
+                .true_msrs
+        };
+
+        if let Err(e) = self.set_vmcs_data() {
+            error!("{}", e);
+            dump_current_vmcs();
+        } else {
+            // the software checks only explain a failure,the cpu has the last word
+            if let Err(e) = validate_current_vmcs(true_msrs) {
+                warn!("{},launching anyway", e);
+            }
 
-        self.vcpu_vmx_state = VcpuVmxState::VmxStateTransition;
+            self.vcpu_vmx_state = VcpuVmxState::VmxStateTransition;
 
-        // vm-entry by execute vmlaunch instruction
-        // from vmm to guest
-        __vmx_vmlaunch();
+            // vm-entry by execute vmlaunch instruction
+            // from vmm to guest
+            __vmx_vmlaunch();
 
-        error!("Vmlaunch error:{}", __vmx_read_error());
+            error!("Vmlaunch error:{}", __vmx_read_error());
+            dump_current_vmcs();
+        }
 
         // this signifies an error occurrence if reaches next code during execution
         if self.vmxon {
@@ -710,7 +919,7 @@ impl Vcpu {
         }
     }
 
-    fn start_vt(&mut self) {
+    pub(crate) fn start_vt(&mut self) {
         unsafe {
             let host_state: &mut KPROCESSOR_STATE = &mut self.host_state;
             KeSaveStateForHibernate(host_state as _);
@@ -747,6 +956,104 @@ impl Vcpu {
     pub fn set_cpu_index(&mut self, index: usize) {
         self.cpu_index = index;
     }
+
+    pub fn host_tables_mut(&mut self) -> Option<&mut HostTables> {
+        self.host_tables.as_deref_mut()
+    }
+
+    pub fn guest_mapping_mut(&mut self) -> Option<&mut GuestMapping> {
+        self.guest_mapping.as_mut()
+    }
+
+    pub fn cpu_index(&self) -> usize {
+        self.cpu_index
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.vcpu_vmx_state == VcpuVmxState::VmxStateOn
+    }
+
+    pub fn preemption_timer_mut(&mut self) -> &mut PreemptionTimer {
+        &mut self.preemption_timer
+    }
+
+    pub fn msr_shadow_mut(&mut self) -> &mut MsrShadowTable {
+        &mut self.msr_shadow
+    }
+
+    pub fn cr_masks(&self) -> &ControlRegisterMasks {
+        &self.cr_masks
+    }
+
+    pub fn pml_log(&self) -> Option<&PmlLog> {
+        self.pml_log.as_deref()
+    }
+
+    pub fn virt_exception(&self) -> Option<&VirtExceptionCpu> {
+        self.virt_exception.as_deref()
+    }
+
+    pub fn virt_exception_mut(&mut self) -> Option<&mut VirtExceptionCpu> {
+        self.virt_exception.as_deref_mut()
+    }
+
+    pub fn pending_watch_mut(&mut self) -> &mut PendingWatchEvents {
+        &mut self.pending_watch
+    }
+
+    pub fn ept_restore_mut(&mut self) -> &mut PendingEptRestore {
+        &mut self.ept_restore
+    }
+
+    pub fn ept_overlay_mut(&mut self) -> Option<&mut EptOverlay> {
+        self.ept_overlay.as_mut()
+    }
+
+    pub fn desc_table_guard_mut(&mut self) -> Option<&mut DescTableGuard> {
+        self.desc_table_guard.as_mut()
+    }
+
+    pub fn syscall_trace_mut(&mut self) -> Option<&mut SyscallTraceCpu> {
+        self.syscall_trace.as_deref_mut()
+    }
+
+    pub fn exit_trace_mut(&mut self) -> Option<&mut ExitTraceCpu> {
+        self.exit_trace.as_deref_mut()
+    }
+
+    // vmx root,the real lstar points at the stub while guest reads return the original
+    pub fn set_syscall_trace(&mut self, enable: bool) -> Result<(), &'static str> {
+        let trace = match self.syscall_trace.as_deref_mut() {
+            Some(trace) => trace,
+            None if !enable => return Ok(()),
+            None => return Err("syscall trace is not allocated"),
+        };
+
+        if trace.is_enabled() == enable {
+            return Ok(());
+        }
+
+        if enable {
+            let original = read_msr(msr::msr_index::MSR_LSTAR);
+            trace.set_target(original);
+            self.msr_shadow
+                .set_policy(msr::msr_index::MSR_LSTAR, MsrPolicy::Shadow);
+            self.msr_shadow
+                .set_shadow(msr::msr_index::MSR_LSTAR, original);
+            write_msr(msr::msr_index::MSR_LSTAR, trace.stub_address());
+        } else {
+            let original = self
+                .msr_shadow
+                .shadow(msr::msr_index::MSR_LSTAR)
+                .ok_or("lstar shadow is missing")?;
+            write_msr(msr::msr_index::MSR_LSTAR, original);
+            self.msr_shadow
+                .set_policy(msr::msr_index::MSR_LSTAR, MsrPolicy::PassThrough);
+        }
+
+        trace.set_enabled(enable);
+        Ok(())
+    }
 }
 
 impl Drop for Vcpu {
@@ -777,6 +1084,19 @@ impl Vmm {
                     vmm_stack: core::ptr::null_mut(),
                     msr_bitmap: core::ptr::null_mut(),
                 },
+                host_tables: None,
+                guest_mapping: None,
+                preemption_timer: PreemptionTimer::default(),
+                msr_shadow: MsrShadowTable::default(),
+                syscall_trace: None,
+                exit_trace: None,
+                cr_masks: ControlRegisterMasks::default(),
+                desc_table_guard: None,
+                ept_restore: PendingEptRestore::default(),
+                ept_overlay: None,
+                pml_log: None,
+                virt_exception: None,
+                pending_watch: PendingWatchEvents::default(),
                 vmxon: false,
                 cpu_index: 0,
             };
@@ -789,6 +1109,23 @@ impl Vmm {
             vmx_features: VMXFeatures::default(),
             ept_state: Option::None,
             vcpu: vcpus,
+            cr3_switch_callback: None,
+            desc_table_policy: None,
+            code_integrity: None,
+            ept_access_dirty: false,
+            mode_based_execute: false,
+            hide_memory: false,
+            dirty_ranges: Vec::new(),
+            virt_exception_callback: None,
+            ept_views: None,
+            spp_table: None,
+            sub_page_write_callback: None,
+            exec_breakpoints: None,
+            exec_breakpoint_pages: Vec::new(),
+            watchpoints: None,
+            watchpoint_pages: Vec::new(),
+            process_views: None,
+            hidden_memory: None,
         }
     }
 
@@ -803,6 +1140,24 @@ impl Vmm {
 
         self.vmx_features.secondary_controls =
             (vmx_proc & VMX_PROC_CTLS_USE_SECONDARY_CTLS as u64) != 0;
+        self.vmx_features.monitor_trap_flag =
+            (vmx_proc & VMX_PROC_CTLS_MONITOR_TRAP_FLAG as u64) != 0;
+
+        let mut vmx_pin = read_msr(MSR_IA32_VMX_PINBASED_CTLS) >> 32;
+        if self.vmx_features.true_msrs {
+            vmx_pin = read_msr(MSR_IA32_VMX_TRUE_PINBASED_CTLS) >> 32;
+        }
+
+        self.vmx_features.virtual_nmi = (vmx_pin & VMX_PIN_CTLS_NMI_EXIT as u64) != 0
+            && (vmx_pin & VMX_PIN_CTLS_VIRT_NMI as u64) != 0
+            && (vmx_proc & VMX_PROC_CTLS_NMI_WINDOW_EXIT as u64) != 0;
+
+        self.vmx_features.preemption_timer = (vmx_pin & VMX_PIN_CTLS_PREEMPT_TIMER as u64) != 0;
+        if self.vmx_features.preemption_timer {
+            self.vmx_features.preemption_timer_rate = (read_msr(MSR_IA32_VMX_MISC)
+                & ia32_vmx_misc_msr::MSR_IA32_VMX_MISC_PREEMPT_TIMER_TSC_SHIFT_MASK)
+                as u8;
+        }
 
         if self.vmx_features.secondary_controls {
             let vmx_proc2 = read_msr(MSR_IA32_VMX_PROCBASED_CTLS2) >> 32;
@@ -810,8 +1165,17 @@ impl Vmm {
             self.vmx_features.ept = (vmx_proc2 & VMX_PROC_CTLS2_EPT as u64) != 0;
             self.vmx_features.vpid = (vmx_proc2 & VMX_PROC_CTLS2_VPID as u64) != 0;
             self.vmx_features.vmfunc = (vmx_proc2 & VMX_PROC_CTLS2_VMFUNC as u64) != 0;
+            if self.vmx_features.vmfunc {
+                self.vmx_features.eptp_switching =
+                    (read_msr(MSR_IA32_VMX_VMFUNC) & VMFUNC_EPTP_SWITCHING_BIT) != 0;
+            }
+            self.vmx_features.pml = (vmx_proc2 & VMX_PROC_CTLS2_PML as u64) != 0;
+            self.vmx_features.virt_exception = (vmx_proc2 & VMX_PROC_CTLS2_EPT_XCPT_VE as u64) != 0;
+            self.vmx_features.sub_page_write = (vmx_proc2 & VMX_PROC_CTLS2_SPP_EPT as u64) != 0;
+            self.vmx_features.mode_based_execute =
+                (vmx_proc2 & VMX_PROC_CTLS2_MODE_BASED_EPT_PERM as u64) != 0;
 
-            if self.vmx_features.ept {
+            if self.vmx_features.ept || self.vmx_features.vpid {
                 let ept_vpid_cap = read_msr(MSR_IA32_VMX_EPT_VPID_CAP);
                 self.vmx_features.exec_only_ept = (ept_vpid_cap
                     & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_RWX_X_ONLY)
@@ -819,6 +1183,18 @@ impl Vmm {
                 self.vmx_features.inv_single_address = (ept_vpid_cap
                     & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_INVVPID_INDIV_ADDR)
                     != 0;
+                self.vmx_features.inv_single_context = (ept_vpid_cap
+                    & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_INVVPID_SINGLE_CONTEXT)
+                    != 0;
+                self.vmx_features.inv_retain_globals = (ept_vpid_cap
+                    & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_INVVPID_SINGLE_CONTEXT_RETAIN_GLOBALS)
+                    != 0;
+                self.vmx_features.ept_access_dirty = (ept_vpid_cap
+                    & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_ACCESS_DIRTY)
+                    != 0;
+                self.vmx_features.ept_violation_info = (ept_vpid_cap
+                    & ia32_vmx_ept_vpid_cap_msr::MSR_IA32_VMX_EPT_VPID_CAP_ADVEXITINFO_EPT_VIOLATION)
+                    != 0;
             }
         }
 
@@ -827,51 +1203,1418 @@ impl Vmm {
         }
     }
 
-    pub fn start(&mut self) -> Result<(), StartVTError> {
+    // passive level,before the first cpu launches
+    pub(crate) fn prepare(&mut self) {
         self.check_and_set_features();
+        if self.ept_access_dirty && !self.vmx_features.ept_access_dirty {
+            warn!("ept accessed and dirty flags are not supported");
+            self.ept_access_dirty = false;
+        }
+        if self.mode_based_execute
+            && !(self.vmx_features.ept && self.vmx_features.mode_based_execute)
+        {
+            warn!("mode based execute control is not supported");
+            self.mode_based_execute = false;
+        }
         if self.vmx_features.ept {
-            self.ept_state = Some(EptState::new());
+            self.ept_state = Some(EptState::new(self.ept_access_dirty));
+        }
+    }
+
+    // every cpu runs the guest
+    pub(crate) fn started(&mut self) {
+        // the guest keeps running visible,only the hiding is lost
+        if self.hide_memory {
+            if let Err(e) = self.hide_vmm_memory() {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    pub fn get_current_vcpu(&mut self) -> &mut Vcpu {
+        &mut self.vcpu[get_current_processor_idx() as usize]
+    }
+
+    // the setters below only take effect when the vcpus are launched
+    fn ensure_not_started(&self) -> Result<(), &'static str> {
+        if self
+            .vcpu
+            .iter()
+            .any(|cvcpu| cvcpu.vcpu_vmx_state != VcpuVmxState::VmxStateOff)
+        {
+            return Err("the vmm already started");
+        }
+
+        Ok(())
+    }
+
+    // register before start,cr3-load exiting is only enabled with a callback
+    pub fn set_cr3_switch_callback(
+        &mut self,
+        callback: Option<Cr3SwitchCallback>,
+    ) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+
+        self.cr3_switch_callback = callback;
+        Ok(())
+    }
+
+    // register before start,the eptp only enables the flags when the cpu supports them
+    pub fn set_ept_access_dirty(&mut self, enable: bool) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+
+        self.ept_access_dirty = enable;
+        Ok(())
+    }
+
+    // register before start,the control is only enabled when the cpu supports it
+    pub fn set_mode_based_execute(&mut self, enable: bool) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+
+        self.mode_based_execute = enable;
+        Ok(())
+    }
+
+    // register before start,descriptor-table exiting is only enabled with a policy
+    pub fn set_descriptor_table_exiting(
+        &mut self,
+        policy: Option<DescTablePolicy>,
+    ) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+
+        self.desc_table_policy = policy;
+        Ok(())
+    }
+
+    // what sidt reports on one cpu,none reports the real idtr
+    pub fn set_shadow_idtr(
+        &mut self,
+        cpu_index: usize,
+        idtr: Option<DescriptorTableRegister>,
+    ) -> Result<(), &'static str> {
+        let vcpu = self.vcpu.get_mut(cpu_index).ok_or("invalid cpu index")?;
+        let guard = vcpu
+            .desc_table_guard
+            .as_mut()
+            .ok_or("descriptor table exiting is not enabled")?;
+        guard.shadow_idtr = idtr;
+        Ok(())
+    }
+
+    // register before start,the interval is in tsc ticks.
+    // the timer only runs on cpus with a callback
+    pub fn set_preemption_timer(
+        &mut self,
+        cpu_index: usize,
+        interval_tsc: u64,
+        callback: Option<PreemptionTimerCallback>,
+    ) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+        let vcpu = self.vcpu.get_mut(cpu_index).ok_or("invalid cpu index")?;
+        vcpu.preemption_timer.set_callback(interval_tsc, callback);
+        Ok(())
+    }
+
+    // register before start,the bitmap picks up every msr that needs an exit
+    pub fn set_msr_policy(&mut self, msr: u32, policy: MsrPolicy) -> Result<(), &'static str> {
+        self.ensure_not_started()?;
+
+        for cvcpu in &mut self.vcpu {
+            cvcpu.msr_shadow.set_policy(msr, policy);
         }
 
-        for i in 0..self.cpu_count {
-            unsafe { KeSetSystemAffinityThread(1 << i) };
-            let vcpu = &mut self.vcpu[i as usize];
-            vcpu.set_cpu_index(i as _);
-            vcpu.start_vt();
+        Ok(())
+    }
+
+    // passive level,stubs are allocated on enable and freed on disable
+    pub fn set_syscall_trace(&mut self, enable: bool) -> Result<(), &'static str> {
+        if enable {
+            if is_kva_shadow_entry(read_msr(msr::msr_index::MSR_LSTAR)) {
+                return Err("kva shadow is active,syscall trace is not supported");
+            }
+            resolve_syscall_names();
+        }
+
+        for cvcpu in &mut self.vcpu {
+            if cvcpu.vcpu_vmx_state != VcpuVmxState::VmxStateOn {
+                continue;
+            }
+
+            if enable && cvcpu.syscall_trace.is_none() {
+                cvcpu.syscall_trace = Some(SyscallTraceCpu::new(cvcpu.cpu_index));
+            }
+
+            unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+
+            let result = __vmx_vmcall(SYSCALL_TRACE, enable as _, 0, 0);
+
             unsafe { KeRevertToUserAffinityThread() };
+
+            match result {
+                VmxInstructionResult::VmxSuccess => {}
+                _ => {
+                    error!("Vmxcall execute error");
+                    return Err("syscall trace vmcall failed");
+                }
+            }
         }
 
-        for item in &self.vcpu {
-            if item.vcpu_vmx_state != VcpuVmxState::VmxStateOn {
-                return Err(StartVTError {});
+        // events nobody read yet go with the rings
+        if !enable && self.vcpu.iter().any(|cvcpu| cvcpu.syscall_trace.is_some()) {
+            wait_for_stub_quiescence();
+            for cvcpu in &mut self.vcpu {
+                if matches!(cvcpu.syscall_trace.as_ref(), Some(trace) if !trace.is_enabled()) {
+                    cvcpu.syscall_trace = None;
+                }
             }
         }
 
         Ok(())
     }
 
-    pub fn get_current_vcpu(&mut self) -> &mut Vcpu {
-        &mut self.vcpu[get_current_processor_idx() as usize]
+    // drain every cpu ring into out,returns the number of events written
+    pub fn read_syscall_events(&self, out: &mut [SyscallEvent]) -> usize {
+        let mut count = 0;
+
+        for cvcpu in &self.vcpu {
+            if let Some(trace) = cvcpu.syscall_trace.as_ref() {
+                trace.ring.drain(|mut event| {
+                    if count == out.len() {
+                        return false;
+                    }
+                    if let Some(name) = syscall_name(event.service) {
+                        event.set_name(name);
+                    }
+                    out[count] = event;
+                    count += 1;
+                    true
+                });
+            }
+        }
+
+        count
     }
-}
 
-impl Drop for Vmm {
-    fn drop(&mut self) {
-        for cvcpu in &mut self.vcpu {
-            match cvcpu.vcpu_vmx_state {
-                VcpuVmxState::VmxStateOn => {
-                    unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+    // passive level,rings are allocated on first use and kept until the vmm is freed
+    pub fn set_exit_trace(&mut self, enable: bool) -> Result<(), &'static str> {
+        if enable {
+            for cvcpu in &mut self.vcpu {
+                if cvcpu.vcpu_vmx_state == VcpuVmxState::VmxStateOn && cvcpu.exit_trace.is_none() {
+                    cvcpu.exit_trace = Some(Box::default());
+                }
+            }
+        }
 
-                    match __vmx_vmcall(EXIT_VT, 0, 0, 0) {
-                        VmxInstructionResult::VmxSuccess => {
-                            info!("CPU:{} Close VT Success", cvcpu.cpu_index);
-                        }
-                        _ => {
-                            error!("Vmxcall execute error");
-                        }
+        self.vmcall_all_cpus(EXIT_TRACE, enable as _)
+    }
+
+    // drain every cpu ring into out,returns the number of records written
+    pub fn read_exit_records(&self, out: &mut [ExitRecord]) -> usize {
+        let mut count = 0;
+
+        for cvcpu in &self.vcpu {
+            if let Some(trace) = cvcpu.exit_trace.as_ref() {
+                trace.ring.drain(|record| {
+                    if count == out.len() {
+                        return false;
                     }
+                    out[count] = record;
+                    count += 1;
+                    true
+                });
+            }
+        }
+
+        count
+    }
+
+    // run the vmcall on every running vcpu
+    fn vmcall_all_cpus(&self, vmcall_no: u64, arg1: u64) -> Result<(), &'static str> {
+        for cvcpu in &self.vcpu {
+            if cvcpu.vcpu_vmx_state != VcpuVmxState::VmxStateOn {
+                continue;
+            }
+
+            unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+
+            let result = __vmx_vmcall(vmcall_no, arg1, 0, 0);
 
-                    unsafe { KeRevertToUserAffinityThread() };
+            unsafe { KeRevertToUserAffinityThread() };
+
+            match result {
+                VmxInstructionResult::VmxSuccess => {}
+                _ => {
+                    error!("Vmxcall execute error");
+                    return Err("vmcall failed");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // every running vcpu drops its cached ept translations
+    pub(crate) fn invept_all_cpus(&mut self) -> Result<(), &'static str> {
+        self.vmcall_all_cpus(INVEPT_SINGLE_CONTEXT, 0)
+    }
+
+    // vmx root,the ept behind an eptp. without views it is always the default ept
+    pub fn ept_state_by_pointer(&mut self, eptp: u64) -> Option<&mut EptState> {
+        let index = match self.ept_views.as_ref() {
+            Some(views) => views.index_of(eptp)?,
+            None => 0,
+        };
+
+        match index {
+            0 => self.ept_state.as_mut(),
+            _ => self.ept_views.as_mut()?.view_mut(index),
+        }
+    }
+
+    // a monitor took rights from pages of the default ept
+    pub fn ept_monitor_active(&self) -> bool {
+        self.code_integrity.is_some()
+            || self.exec_breakpoints.is_some()
+            || self.watchpoints.is_some()
+            || self.spp_table.is_some()
+            || self.hidden_memory.is_some()
+    }
+
+    // guest code switches views without an exit
+    pub fn vmfunc_views_enabled(&self) -> bool {
+        self.ept_views
+            .as_ref()
+            .is_some_and(|views| views.is_vmfunc_enabled())
+    }
+
+    // passive level,count includes the default view and every view starts as the same identity
+    // map. with vmfunc guest code switches views itself. code integrity,#ve and page hooks
+    // only watch the default view
+    pub fn enable_ept_views(&mut self, count: usize, vmfunc: bool) -> Result<(), &'static str> {
+        if vmfunc && !self.vmx_features.eptp_switching {
+            return Err("vmfunc eptp switching is not supported");
+        }
+        // guest code could leave the default view and every override in it
+        if vmfunc && self.ept_monitor_active() {
+            return Err("vmfunc can not be used while an ept monitor is active");
+        }
+        if self.ept_views.is_some() {
+            return Err("ept views are already enabled");
+        }
+        if !self.dirty_ranges.is_empty() {
+            return Err("ept views can not be used with dirty logging");
+        }
+        // new views would map the hidden pages
+        if self.hidden_memory.is_some() {
+            return Err("ept views can not be enabled while hypervisor memory is hidden");
+        }
+
+        let default_eptp = self
+            .ept_state
+            .as_mut()
+            .ok_or("ept is not enabled")?
+            .get_ept_pointer();
+
+        self.ept_views = Some(EptViews::new(
+            default_eptp,
+            count,
+            self.ept_access_dirty,
+            vmfunc,
+        )?);
+
+        if let Err(e) = self.vmcall_all_cpus(EPT_VIEWS, true as _) {
+            let _ = self.disable_ept_views();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // passive level,every cpu goes back to the default view before the others are freed
+    pub fn disable_ept_views(&mut self) -> Result<(), &'static str> {
+        if self.ept_views.is_none() {
+            return Ok(());
+        }
+
+        self.vmcall_all_cpus(EPT_VIEWS, false as _)?;
+        self.ept_views = None;
+        Ok(())
+    }
+
+    // passive level,load the view on every cpu
+    pub fn switch_ept_view(&mut self, index: usize) -> Result<(), &'static str> {
+        let views = self.ept_views.as_ref().ok_or("ept views are not enabled")?;
+        if index >= views.count() {
+            return Err("ept view index is out of range");
+        }
+
+        self.vmcall_all_cpus(EPT_VIEW_SWITCH, index as _)
+    }
+
+    // passive level,rights of the page in one view. frame maps the page to another page frame
+    // in that view,none keeps the current one. returns the previous rights
+    pub fn set_view_page(
+        &mut self,
+        index: usize,
+        physical: u64,
+        permissions: EptPermissions,
+        frame: Option<u64>,
+    ) -> Result<EptPermissions, &'static str> {
+        if permissions.execute != permissions.execute_user && !self.mode_based_execute {
+            return Err("separate user execute rights need mode based execute control");
+        }
+
+        let ept_state = match index {
+            0 => self.ept_state.as_mut().ok_or("ept is not enabled")?,
+            _ => self
+                .ept_views
+                .as_mut()
+                .and_then(|views| views.view_mut(index))
+                .ok_or("ept view index is out of range")?,
+        };
+        ept_state.split_large_page(physical)?;
+
+        let page = physical & !0xFFF;
+        let entry = ept_state
+            .page_entry(page)
+            .ok_or("page is not split to 4kb")?;
+        let mut new_entry = permissions.apply(entry);
+        if let Some(frame) = frame {
+            new_entry = EptState::page_entry_with_frame(new_entry, frame);
+        }
+        ept_state.set_page_entry(page, new_entry)?;
+
+        // only translations tagged with this view are stale
+        let eptp = ept_state.get_ept_pointer();
+        if let Some(views) = self.ept_views.as_mut() {
+            views.add_override(index, page);
+        }
+        self.vmcall_all_cpus(INVEPT_SINGLE_CONTEXT, eptp)?;
+        Ok(EptPermissions::from_entry(entry))
+    }
+
+    // passive level,the page gets its own 4kb entry. returns the previous rights
+    pub fn set_page_permissions(
+        &mut self,
+        physical: u64,
+        permissions: EptPermissions,
+    ) -> Result<EptPermissions, &'static str> {
+        if permissions.execute != permissions.execute_user && !self.mode_based_execute {
+            return Err("separate user execute rights need mode based execute control");
+        }
+
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        ept_state.split_large_page(physical)?;
+
+        let page = physical & !0xFFF;
+        let entry = ept_state
+            .page_entry(page)
+            .ok_or("page is not split to 4kb")?;
+        ept_state.set_page_entry(page, permissions.apply(entry))?;
+
+        self.invept_all_cpus()?;
+        Ok(EptPermissions::from_entry(entry))
+    }
+
+    // passive level,leaves in [start,start + size) with accessed or dirty set. cleared flags
+    // are flushed from every cpu before returning so the next access sets them again
+    pub fn harvest_access_dirty(
+        &mut self,
+        start: u64,
+        size: u64,
+        clear: AccessDirtyClear,
+    ) -> Result<Vec<AccessDirtyPage>, &'static str> {
+        let ept_state = self.ept_state.as_ref().ok_or("ept is not enabled")?;
+        if !ept_state.is_access_dirty_enabled() {
+            return Err("ept accessed and dirty flags are not enabled");
+        }
+        // dirty logging owns the dirty flags while it is enabled
+        if !self.dirty_ranges.is_empty() {
+            return Err("ept accessed and dirty flags are used by dirty logging");
+        }
+
+        let mut pages = Vec::new();
+        harvest_access_dirty(
+            ept_state,
+            start,
+            start.saturating_add(size),
+            clear,
+            &mut pages,
+        );
+
+        if clear != AccessDirtyClear::None {
+            self.invept_all_cpus()?;
+        }
+        Ok(pages)
+    }
+
+    // passive level,writes to the ranges are logged per 4kb page so their 2mb pages get split
+    pub fn enable_dirty_logging(&mut self, ranges: &[DirtyRange]) -> Result<(), &'static str> {
+        if !self.vmx_features.pml || !self.vmx_features.ept_access_dirty {
+            return Err("page modification logging is not supported");
+        }
+        if !self.dirty_ranges.is_empty() {
+            return Err("dirty logging is already enabled");
+        }
+        // marking every page dirty would wipe the flags harvest_access_dirty reports
+        if self.ept_access_dirty {
+            return Err("dirty logging can not be used with ept accessed and dirty harvesting");
+        }
+        // the log only follows the default ept
+        if self.ept_views.is_some() {
+            return Err("dirty logging can not be used with ept views");
+        }
+        if ranges.is_empty() {
+            return Err("no dirty range");
+        }
+
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+        for range in ranges {
+            let mut large_page = range.start & !0x1F_FFFF;
+            while large_page < range.end() {
+                ept_state.split_large_page(large_page)?;
+                large_page += 0x20_0000;
+            }
+        }
+
+        for cvcpu in &mut self.vcpu {
+            if cvcpu.vcpu_vmx_state == VcpuVmxState::VmxStateOn {
+                cvcpu.pml_log = Some(PmlLog::new(ranges)?);
+            }
+        }
+
+        // only the first write to a tracked page after each snapshot is logged
+        ept_state.mark_all_dirty();
+        for page in ranges.iter().flat_map(DirtyRange::page_addresses) {
+            ept_state.clear_page_dirty(page)?;
+        }
+        ept_state.set_access_dirty(true);
+
+        self.dirty_ranges = ranges.to_vec();
+        self.vmcall_all_cpus(DIRTY_LOG, DirtyLogCommand::Enable as _)
+    }
+
+    // passive level,pages dirtied since the last snapshot are lost
+    pub fn disable_dirty_logging(&mut self) -> Result<(), &'static str> {
+        if self.dirty_ranges.is_empty() {
+            return Ok(());
+        }
+
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        ept_state.set_access_dirty(self.ept_access_dirty);
+
+        self.vmcall_all_cpus(DIRTY_LOG, DirtyLogCommand::Disable as _)?;
+
+        for cvcpu in &mut self.vcpu {
+            cvcpu.pml_log = None;
+        }
+        self.dirty_ranges.clear();
+        Ok(())
+    }
+
+    // passive level,guest physical pages written since the previous call
+    pub fn dirty_pages_since_snapshot(&mut self) -> Result<Vec<u64>, &'static str> {
+        if self.dirty_ranges.is_empty() {
+            return Err("dirty logging is not enabled");
+        }
+
+        // re-arm before draining,a write racing the snapshot shows up now or next time
+        let ranges = &self.dirty_ranges;
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        for page in ranges.iter().flat_map(DirtyRange::page_addresses) {
+            ept_state.clear_page_dirty(page)?;
+        }
+
+        self.vmcall_all_cpus(DIRTY_LOG, DirtyLogCommand::Flush as _)?;
+
+        let mut words = Vec::new();
+        for cvcpu in &self.vcpu {
+            if let Some(pml_log) = cvcpu.pml_log.as_ref() {
+                words.resize(pml_log.dirty.words(), 0);
+                pml_log.dirty.take_into(&mut words);
+            }
+        }
+
+        // bit i is the i-th page of the ranges laid out one after another
+        let pages = self
+            .dirty_ranges
+            .iter()
+            .flat_map(DirtyRange::page_addresses)
+            .enumerate()
+            .filter(|(i, _)| (words.get(i / 64).copied().unwrap_or(0) & (1 << (i % 64))) != 0)
+            .map(|(_, page)| page)
+            .collect();
+
+        Ok(pages)
+    }
+
+    // passive level,executable sections of the module become read+execute only in the ept.
+    // returns the number of newly protected pages
+    pub fn protect_module_code(
+        &mut self,
+        name: &str,
+        policy: CodeIntegrityPolicy,
+    ) -> Result<usize, &'static str> {
+        // writes are let through or blocked one instruction at a time
+        if !self.vmx_features.monitor_trap_flag {
+            return Err("monitor trap flag is not supported");
+        }
+        if self.vmfunc_views_enabled() {
+            return Err("ept monitors can not be used with vmfunc views");
+        }
+
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+        if self.code_integrity.is_none() {
+            self.code_integrity = Some(CodeIntegrityMonitor::new(self.cpu_count as _, policy)?);
+        }
+
+        let monitor = self.code_integrity.as_mut().unwrap();
+        monitor.set_policy(policy);
+
+        let pages = monitor.protect_module(ept_state, name)?;
+        info!("{} code pages protected:{}", name, pages);
+
+        self.invept_all_cpus()?;
+        Ok(pages)
+    }
+
+    // passive level,none releases every module
+    pub fn unprotect_module_code(&mut self, name: Option<&str>) -> Result<usize, &'static str> {
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let monitor = match self.code_integrity.as_mut() {
+            Some(monitor) => monitor,
+            None => return Ok(0),
+        };
+
+        let pages = monitor.unprotect_module(ept_state, name)?;
+        self.invept_all_cpus()?;
+        Ok(pages)
+    }
+
+    // drain every cpu ring into out,returns the number of events written
+    pub fn read_code_write_events(&self, out: &mut [CodeWriteEvent]) -> usize {
+        let mut count = 0;
+
+        if let Some(monitor) = self.code_integrity.as_ref() {
+            for cpu in monitor.cpus() {
+                cpu.ring.drain(|event| {
+                    if count == out.len() {
+                        return false;
+                    }
+                    out[count] = event;
+                    count += 1;
+                    true
+                });
+            }
+        }
+
+        count
+    }
+
+    // passive level,the page holding address loses its execute rights. fetches at address are
+    // recorded and reported to the callback,other instructions on the page are single stepped.
+    // a user address must belong to the current process and be resident
+    pub fn set_exec_breakpoint(
+        &mut self,
+        address: u64,
+        callback: Option<ExecBreakpointCallback>,
+    ) -> Result<(), &'static str> {
+        if !self.vmx_features.monitor_trap_flag {
+            return Err("monitor trap flag is not supported");
+        }
+        if self.vmfunc_views_enabled() {
+            return Err("ept monitors can not be used with vmfunc views");
+        }
+        if unsafe { MmIsAddressValid(address as _) } == 0 {
+            return Err("breakpoint address is not mapped");
+        }
+
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let breakpoints = self
+            .exec_breakpoints
+            .get_or_insert_with(|| ExecBreakpoints::new(self.cpu_count as _));
+        if breakpoints.contains(address) {
+            return Err("exec breakpoint already set");
+        }
+
+        // a user page could be paged out or moved to another frame under the ept entry
+        let locked = unsafe { LockedPages::lock(address, 1)? };
+        let physical = locked
+            .physical_page(0)
+            .ok_or("breakpoint address is not mapped")?
+            | (address & 0xFFF);
+
+        ept_state.split_large_page(physical)?;
+        let page = physical & !0xFFF;
+        let entry = ept_state
+            .page_entry(page)
+            .ok_or("page is not split to 4kb")?;
+        let execute = breakpoints
+            .page_execute(page)
+            .unwrap_or(entry & EPT_EXECUTE_MASK);
+
+        breakpoints.insert(address, page, execute, callback)?;
+        ept_state.set_page_entry(
+            page,
+            (entry & !EPT_EXECUTE_MASK) | EPT_ENTRY_EXEC_BREAKPOINT,
+        )?;
+        self.exec_breakpoint_pages.push((address, locked));
+
+        self.invept_all_cpus()
+    }
+
+    // passive level,the page gets its execute rights back with its last breakpoint
+    pub fn clear_exec_breakpoint(&mut self, address: u64) -> Result<(), &'static str> {
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let breakpoints = self
+            .exec_breakpoints
+            .as_mut()
+            .ok_or("exec breakpoint is not set")?;
+        let (page, execute) = breakpoints
+            .remove(address)
+            .ok_or("exec breakpoint is not set")?;
+
+        if breakpoints.page_execute(page).is_none() {
+            if let Some(entry) = ept_state.page_entry(page) {
+                ept_state.set_page_entry(page, (entry & !EPT_ENTRY_EXEC_BREAKPOINT) | execute)?;
+            }
+        }
+
+        // the slot may be reused once no cpu can be inside the exit handler with it
+        self.invept_all_cpus()?;
+        self.exec_breakpoint_pages
+            .retain(|(locked_address, _)| *locked_address != address);
+        Ok(())
+    }
+
+    pub fn read_exec_breakpoint_hits(&self, out: &mut [ExecBreakpointHit]) -> usize {
+        match self.exec_breakpoints.as_ref() {
+            Some(breakpoints) => breakpoints.read_hits(out),
+            None => 0,
+        }
+    }
+
+    // passive level,accesses touching [address,address + size) are recorded with the value
+    // before and after. the range belongs to the current address space,its pages must be
+    // resident and stay locked while watched. returns the id of the watchpoint
+    pub fn add_watchpoint(
+        &mut self,
+        address: u64,
+        size: u64,
+        access: u32,
+    ) -> Result<u32, &'static str> {
+        if !self.vmx_features.monitor_trap_flag {
+            return Err("monitor trap flag is not supported");
+        }
+        if self.vmfunc_views_enabled() {
+            return Err("ept monitors can not be used with vmfunc views");
+        }
+        if size == 0 || size > WATCHPOINT_MAX_SIZE {
+            return Err("watchpoint size is out of range");
+        }
+        if access == 0 || (access & !(WATCH_READ | WATCH_WRITE)) != 0 {
+            return Err("invalid watchpoint access");
+        }
+        let end = address
+            .checked_add(size)
+            .ok_or("watchpoint size is out of range")?;
+
+        for linear in ((address & !0xFFF)..end).step_by(PAGE_SIZE as _) {
+            if unsafe { MmIsAddressValid(linear as _) } == 0 {
+                return Err("watchpoint address is not mapped");
+            }
+        }
+
+        // the frames are what the ept watches,they must not change under it
+        let locked = unsafe { LockedPages::lock(address, size)? };
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+        let mut pages = Vec::new();
+        let mut index = 0;
+        while let Some(physical) = locked.physical_page(index) {
+            ept_state.split_large_page(physical)?;
+            pages.push(physical);
+            index += 1;
+        }
+
+        let watchpoints = self
+            .watchpoints
+            .get_or_insert_with(|| Watchpoints::new(self.cpu_count as _));
+
+        for page in pages.iter() {
+            let entry = ept_state
+                .page_entry(*page)
+                .ok_or("page is not split to 4kb")?;
+            watchpoints.watch_page(*page, entry & EPT_ACCESS_MASK);
+        }
+
+        let id = watchpoints.insert(address, size, access, pages.clone())?;
+        for page in pages {
+            watchpoints.apply(ept_state, page, self.vmx_features.exec_only_ept)?;
+        }
+        self.watchpoint_pages.push((id, locked));
+
+        self.invept_all_cpus()?;
+        Ok(id)
+    }
+
+    // passive level,pages no other watchpoint covers get their rights back
+    pub fn remove_watchpoint(&mut self, id: u32) -> Result<(), &'static str> {
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let watchpoints = self.watchpoints.as_mut().ok_or("watchpoint is not set")?;
+
+        for page in watchpoints.remove(id)? {
+            watchpoints.apply(ept_state, page, self.vmx_features.exec_only_ept)?;
+        }
+        watchpoints.release_pages();
+
+        // the slot may be reused once no cpu can be inside the exit handler with it
+        self.invept_all_cpus()?;
+        self.watchpoint_pages
+            .retain(|(locked_id, _)| *locked_id != id);
+        Ok(())
+    }
+
+    pub fn read_watch_events(&self, out: &mut [WatchEvent]) -> usize {
+        match self.watchpoints.as_ref() {
+            Some(watchpoints) => watchpoints.read_events(out),
+            None => 0,
+        }
+    }
+
+    // passive level,selected pages raise #ve in the guest instead of exiting. the gate of
+    // vector 20 points at our stub until disabled
+    pub fn enable_virt_exception(&mut self, callback: VeCallback) -> Result<(), &'static str> {
+        if !self.vmx_features.virt_exception {
+            return Err("ept violation #ve is not supported");
+        }
+        if self.ept_state.is_none() {
+            return Err("ept is not enabled");
+        }
+        if self.virt_exception_callback.is_some() {
+            return Err("virtualization exceptions are already enabled");
+        }
+        // user mode faults would run the stub under the user cr3
+        if is_kva_shadow_entry(read_msr(msr::msr_index::MSR_LSTAR)) {
+            return Err("kva shadow is active,#ve is not supported");
+        }
+        // the stub lives in a copied idt that sidt must not report
+        if self.desc_table_policy.is_none() {
+            return Err("#ve needs a descriptor table policy");
+        }
+
+        for cvcpu in &mut self.vcpu {
+            if cvcpu.vcpu_vmx_state == VcpuVmxState::VmxStateOn {
+                cvcpu.virt_exception = Some(VirtExceptionCpu::new()?);
+            }
+        }
+
+        self.virt_exception_callback = Some(callback);
+        if let Err(e) = self.vmcall_all_cpus(VIRT_EXCEPTION, true as _) {
+            let _ = self.disable_virt_exception();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // passive level,selected pages keep their selection and exit again
+    pub fn disable_virt_exception(&mut self) -> Result<(), &'static str> {
+        if self.virt_exception_callback.is_none() {
+            return Ok(());
+        }
+
+        // each cpu takes #ve with if=0,once its vmcall ran no handler is left on it
+        self.vmcall_all_cpus(VIRT_EXCEPTION, false as _)?;
+
+        for cvcpu in &mut self.vcpu {
+            cvcpu.virt_exception = None;
+        }
+        self.virt_exception_callback = None;
+        Ok(())
+    }
+
+    // passive level,violations on the 4kb page raise #ve when enable is set. the #ve information
+    // pages and the handler must never be selected. returns the previous selection
+    pub fn set_page_virt_exception(
+        &mut self,
+        physical: u64,
+        enable: bool,
+    ) -> Result<bool, &'static str> {
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        ept_state.split_large_page(physical)?;
+
+        let suppressed = ept_state.set_page_suppress_ve(physical & !0xFFF, !enable)?;
+
+        self.invept_all_cpus()?;
+        Ok(!suppressed)
+    }
+
+    pub fn virt_exception_counts(&self) -> VeCounts {
+        let mut counts = VeCounts::default();
+        for cvcpu in &self.vcpu {
+            if let Some(cpu) = cvcpu.virt_exception() {
+                cpu.add_counts(&mut counts);
+            }
+        }
+        counts
+    }
+
+    // passive level,writes to protected sub-pages are reported to the callback and then let
+    // through. only pages of the default view can be selected
+    pub fn enable_sub_page_write(
+        &mut self,
+        callback: SubPageWriteCallback,
+    ) -> Result<(), &'static str> {
+        if !self.vmx_features.sub_page_write || !self.vmx_features.monitor_trap_flag {
+            return Err("sub-page write permissions are not supported");
+        }
+        if self.ept_state.is_none() {
+            return Err("ept is not enabled");
+        }
+        if self.spp_table.is_some() {
+            return Err("sub-page write permissions are already enabled");
+        }
+        if self.vmfunc_views_enabled() {
+            return Err("ept monitors can not be used with vmfunc views");
+        }
+
+        self.spp_table = Some(SppTable::new()?);
+        self.sub_page_write_callback = Some(callback);
+        if let Err(e) = self.vmcall_all_cpus(SUB_PAGE_WRITE, true as _) {
+            let _ = self.disable_sub_page_write();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // passive level,selected pages get back the write right they had before
+    pub fn disable_sub_page_write(&mut self) -> Result<(), &'static str> {
+        let spp_table = match self.spp_table.as_ref() {
+            Some(spp_table) => spp_table,
+            None => return Ok(()),
+        };
+
+        // every cpu flushes its ept translations when the control goes off
+        if let Some(ept_state) = self.ept_state.as_mut() {
+            for page in spp_table.selected() {
+                ept_state.set_page_sub_page_write(page, false)?;
+            }
+        }
+
+        self.vmcall_all_cpus(SUB_PAGE_WRITE, false as _)?;
+        self.spp_table = None;
+        self.sub_page_write_callback = None;
+        Ok(())
+    }
+
+    // passive level,only the sub-pages in writable accept writes without an exit. the page gets
+    // its own 4kb entry and loses its write right. returns the previous rights
+    pub fn set_sub_page_permissions(
+        &mut self,
+        physical: u64,
+        writable: SubPageMask,
+    ) -> Result<EptPermissions, &'static str> {
+        let spp_table = self
+            .spp_table
+            .as_mut()
+            .ok_or("sub-page write permissions are not enabled")?;
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        ept_state.split_large_page(physical)?;
+
+        // the leaf must exist before the entry sends the cpu to it
+        let page = physical & !0xFFF;
+        spp_table.set(page, writable)?;
+        let entry = ept_state.set_page_sub_page_write(page, true)?;
+
+        self.invept_all_cpus()?;
+        Ok(EptPermissions::from_entry(entry))
+    }
+
+    // passive level,the page goes back to the write right it had before it was selected.
+    // returns the previous writable sub-pages
+    pub fn clear_sub_page_permissions(
+        &mut self,
+        physical: u64,
+    ) -> Result<SubPageMask, &'static str> {
+        if self.spp_table.is_none() {
+            return Err("sub-page write permissions are not enabled");
+        }
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+        // the leaf is left behind,it is unreachable once the entry stops pointing at it
+        let page = physical & !0xFFF;
+        let entry = ept_state.set_page_sub_page_write(page, false)?;
+
+        self.invept_all_cpus()?;
+        let spp_table = self.spp_table.as_mut().unwrap();
+        spp_table.unselect(page);
+        if (entry & ptee::SUB_PAGE_WRITE) == 0 {
+            return Ok(SubPageMask::NONE);
+        }
+        Ok(spp_table.get(page).unwrap_or(SubPageMask::NONE))
+    }
+
+    // passive level,count includes the default view. isolated processes and hidden code get a
+    // view of their own,a cr3 load loads the view of the new address space. the views are
+    // used for nothing else while enabled
+    pub fn enable_process_views(&mut self, count: usize) -> Result<(), &'static str> {
+        if !self.vmx_features.monitor_trap_flag {
+            return Err("monitor trap flag is not supported");
+        }
+        if self.process_views.is_some() {
+            return Err("process views are already enabled");
+        }
+        // user mode runs on a second cr3 no view is owned by
+        if is_kva_shadow_entry(read_msr(msr::msr_index::MSR_LSTAR)) {
+            return Err("kva shadow is active,process views are not supported");
+        }
+
+        self.enable_ept_views(count, false)?;
+        match ProcessViews::new(count, self.cpu_count as _) {
+            Ok(process_views) => self.process_views = Some(process_views),
+            Err(e) => {
+                let _ = self.disable_ept_views();
+                return Err(e);
+            }
+        }
+        // views of exiting processes are released
+        if let Err(e) = set_process_exit_notify(true) {
+            self.process_views = None;
+            let _ = self.disable_ept_views();
+            return Err(e);
+        }
+
+        if let Err(e) = self.vmcall_all_cpus(PROCESS_VIEWS, true as _) {
+            let _ = self.disable_process_views();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // passive level,protected pages get their default view rights back before the views go
+    pub fn disable_process_views(&mut self) -> Result<(), &'static str> {
+        let process_views = match self.process_views.as_ref() {
+            Some(process_views) => process_views,
+            None => return Ok(()),
+        };
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        if let Err(e) = set_process_exit_notify(false) {
+            error!("{}", e);
+        }
+
+        for (page, rights) in process_views.protected_pages() {
+            if let Some(entry) = ept_state.page_entry(page) {
+                ept_state.set_page_entry(
+                    page,
+                    (entry & !(EPT_ACCESS_MASK | EPT_ENTRY_PROTECTED)) | rights,
+                )?;
+            }
+        }
+
+        // a cr3 load exiting for the switch callback must not enter a view again
+        process_views.release_owners();
+        self.vmcall_all_cpus(PROCESS_VIEWS, false as _)?;
+        self.disable_ept_views()?;
+        self.process_views = None;
+        Ok(())
+    }
+
+    // passive level,the view of an exiting process is free again and its regions are present in
+    // every view,their frames go back to the memory manager with the process
+    pub fn release_process_view(&mut self, cr3: u64) -> Result<(), &'static str> {
+        let pages = match self
+            .process_views
+            .as_mut()
+            .and_then(|process_views| process_views.release(cr3))
+        {
+            Some(pages) => pages,
+            None => return Ok(()),
+        };
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let ept_views = self.ept_views.as_mut().ok_or("ept views are not enabled")?;
+
+        for index in 0..ept_views.count() {
+            let state = match index {
+                0 => &mut *ept_state,
+                _ => ept_views
+                    .view_mut(index)
+                    .ok_or("ept view index is out of range")?,
+            };
+            for (page, rights) in pages.iter() {
+                let entry = match state.page_entry(*page) {
+                    Some(entry) if (entry & EPT_ENTRY_PROTECTED) != 0 => entry,
+                    _ => continue,
+                };
+                state.set_page_entry(
+                    *page,
+                    (entry & !(EPT_ACCESS_MASK | EPT_ENTRY_PROTECTED)) | rights,
+                )?;
+            }
+        }
+
+        // every view changed
+        self.vmcall_all_cpus(INVEPT_ALL_CONTEXT, 0)
+    }
+
+    // passive level,the process gets a view of its own until it exits or process views are
+    // disabled. returns the view
+    pub fn isolate_process(&mut self, pid: u32) -> Result<usize, &'static str> {
+        let cr3 = Self::process_cr3_by(lookup_process(pid))?;
+        self.assign_process_view(cr3)
+    }
+
+    pub fn isolate_process_by_name(&mut self, name: &str) -> Result<usize, &'static str> {
+        let cr3 = Self::process_cr3_by(get_process_by_name(name))?;
+        self.assign_process_view(cr3)
+    }
+
+    // the view of owner. a cpu already running the owner loads it with its vmcall,the others
+    // on their next cr3 load
+    fn assign_process_view(&mut self, owner: u64) -> Result<usize, &'static str> {
+        let view = self
+            .process_views
+            .as_mut()
+            .ok_or("process views are not enabled")?
+            .assign(owner)?;
+        self.vmcall_all_cpus(PROCESS_VIEWS, true as _)?;
+        Ok(view)
+    }
+
+    // drops the reference of the lookup
+    fn process_cr3_by(process: *mut _KPROCESS) -> Result<u64, &'static str> {
+        if process.is_null() {
+            return Err("process not found");
+        }
+        let cr3 = process_cr3(process);
+        unsafe { ObfDereferenceObject(process as _) };
+        Ok(cr3)
+    }
+
+    // passive level,[address,address + size) of the process is only present in its view,the
+    // process is isolated first. the pages must be resident and stay locked,a page the memory
+    // manager moves is not followed
+    pub fn protect_process_region(
+        &mut self,
+        pid: u32,
+        address: u64,
+        size: u64,
+    ) -> Result<(), &'static str> {
+        let process = lookup_process(pid);
+        if process.is_null() {
+            return Err("process not found");
+        }
+        let cr3 = process_cr3(process);
+        let pages = Self::region_pages(process, address, size);
+        unsafe { ObfDereferenceObject(process as _) };
+
+        let view = self.assign_process_view(cr3)?;
+        self.protect_region(view, pages?)
+    }
+
+    // passive level,[start,start + size) is only present in a view entered by fetches from it,
+    // pass the mapped image of the driver. the view is left on the next cr3 load
+    pub fn hide_code_region(&mut self, start: u64, size: u64) -> Result<(), &'static str> {
+        let pages = Self::region_pages(core::ptr::null_mut(), start, size)?;
+        let view = self.assign_process_view(VIEW_OWNER_CODE)?;
+        self.protect_region(view, pages)
+    }
+
+    // passive level,physical pages of the range in the address space of process,the current
+    // one when null. the tables of another process are only walked while attached to it
+    fn region_pages(
+        process: *mut _KPROCESS,
+        address: u64,
+        size: u64,
+    ) -> Result<Vec<u64>, &'static str> {
+        if size == 0 || size > PROCESS_REGION_MAX_SIZE {
+            return Err("region size is out of range");
+        }
+        let end = address
+            .checked_add(size)
+            .ok_or("region size is out of range")?;
+
+        let mut apc_state = KAPC_STATE::default();
+        if !process.is_null() {
+            unsafe { KeStackAttachProcess(process as _, &mut apc_state as _) };
+        }
+
+        let pages = ((address & !0xFFF)..end)
+            .step_by(PAGE_SIZE as _)
+            .map(|linear| {
+                if unsafe { MmIsAddressValid(linear as _) } == 0 {
+                    return Err("region address is not mapped");
+                }
+                Ok(virtual_address_to_physical_address(linear as _) & !0xFFF)
+            })
+            .collect();
+
+        if !process.is_null() {
+            unsafe { KeUnstackDetachProcess(&mut apc_state as _) };
+        }
+        pages
+    }
+
+    // pages of the region become non-present in every view but the owner view
+    fn protect_region(&mut self, view: usize, region: Vec<u64>) -> Result<(), &'static str> {
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+        let ept_views = self.ept_views.as_mut().ok_or("ept views are not enabled")?;
+        let process_views = self
+            .process_views
+            .as_mut()
+            .ok_or("process views are not enabled")?;
+
+        let mut pages = Vec::new();
+        for page in region {
+            if process_views.is_protected(page) {
+                return Err("page is already protected");
+            }
+
+            ept_state.split_large_page(page)?;
+            let entry = ept_state
+                .page_entry(page)
+                .ok_or("page is not split to 4kb")?;
+            // a monitor would give the page its rights back
+            if (entry
+                & (EPT_ENTRY_CODE_INTEGRITY
+                    | EPT_ENTRY_EXEC_BREAKPOINT
+                    | EPT_ENTRY_WATCHPOINT
+                    | ptee::SUB_PAGE_WRITE))
+                != 0
+            {
+                return Err("page is monitored");
+            }
+            pages.push((page, entry & EPT_ACCESS_MASK));
+        }
+
+        // recorded first,disabling restores whatever was protected
+        process_views.insert(view, pages.clone())?;
+
+        for index in (0..ept_views.count()).filter(|index| *index != view) {
+            let state = match index {
+                0 => &mut *ept_state,
+                _ => ept_views
+                    .view_mut(index)
+                    .ok_or("ept view index is out of range")?,
+            };
+            for (page, _) in pages.iter() {
+                state.split_large_page(*page)?;
+                let entry = state.page_entry(*page).ok_or("page is not split to 4kb")?;
+                state.set_page_entry(*page, (entry & !EPT_ACCESS_MASK) | EPT_ENTRY_PROTECTED)?;
+            }
+        }
+
+        // every view changed
+        self.vmcall_all_cpus(INVEPT_ALL_CONTEXT, 0)
+    }
+
+    pub fn process_view_denied_count(&self) -> u64 {
+        match self.process_views.as_ref() {
+            Some(process_views) => process_views.denied_count(),
+            None => 0,
+        }
+    }
+
+    // passive level,vmxon and vmcs regions,msr bitmaps,vmm stacks,host tables and ist stacks and
+    // the eptp list read as a zeroed page and the ept tables of every view are non-present,in
+    // the default view and every ept view. our own code reaches the tables one instruction at a
+    // time. pool allocations share pages with the os and stay visible. returns the hidden page
+    // count
+    pub fn hide_vmm_memory(&mut self) -> Result<usize, &'static str> {
+        if !self.vmx_features.monitor_trap_flag {
+            return Err("monitor trap flag is not supported");
+        }
+        if self.ept_state.is_none() {
+            return Err("ept is not enabled");
+        }
+        if self.hidden_memory.is_some() {
+            return Err("hypervisor memory is already hidden");
+        }
+
+        let mut vmm_pages = Vec::new();
+        for cvcpu in &self.vcpu {
+            if cvcpu.vcpu_vmx_state == VcpuVmxState::VmxStateOn {
+                vmm_pages.extend(cvcpu.vmm_pages());
+            }
+        }
+        if let Some(views) = self.ept_views.as_ref() {
+            vmm_pages.push(views.list_physical_address());
+        }
+
+        self.hidden_memory = Some(HiddenMemory::new(self.cpu_count as _)?);
+        if let Err(e) = self.hide_pages(vmm_pages) {
+            let _ = self.reveal_vmm_memory();
+            return Err(e);
+        }
+
+        self.vmcall_all_cpus(INVEPT_ALL_CONTEXT, 0)?;
+        Ok(self
+            .hidden_memory
+            .as_ref()
+            .map_or(0, |hidden_memory| hidden_memory.page_count()))
+    }
+
+    fn hide_pages(&mut self, vmm_pages: Vec<u64>) -> Result<(), &'static str> {
+        let hidden_memory = self
+            .hidden_memory
+            .as_mut()
+            .ok_or("hypervisor memory is not hidden")?;
+        let ept_state = self.ept_state.as_mut().ok_or("ept is not enabled")?;
+
+        for page in vmm_pages {
+            Self::hide_page(
+                hidden_memory,
+                ept_state,
+                &mut self.ept_views,
+                page,
+                HiddenMode::Dummy,
+            )?;
+        }
+
+        // hiding splits large pages,the new tables are hidden in the next round
+        loop {
+            let mut tables = ept_state.table_pages();
+            if let Some(views) = self.ept_views.as_ref() {
+                tables.extend(views.table_pages());
+            }
+            let tables: Vec<u64> = tables
+                .into_iter()
+                .filter(|page| !hidden_memory.is_hidden(0, *page))
+                .collect();
+            if tables.is_empty() {
+                return Ok(());
+            }
+
+            for page in tables {
+                Self::hide_page(
+                    hidden_memory,
+                    ept_state,
+                    &mut self.ept_views,
+                    page,
+                    HiddenMode::NonPresent,
+                )?;
+            }
+        }
+    }
+
+    fn hide_page(
+        hidden_memory: &mut HiddenMemory,
+        ept_state: &mut EptState,
+        ept_views: &mut Option<EptViews>,
+        physical: u64,
+        mode: HiddenMode,
+    ) -> Result<(), &'static str> {
+        ept_state.split_large_page(physical)?;
+        hidden_memory.hide(0, ept_state, physical, mode)?;
+
+        if let Some(views) = ept_views.as_mut() {
+            for index in 1..views.count() {
+                let view = views
+                    .view_mut(index)
+                    .ok_or("ept view index is out of range")?;
+                view.split_large_page(physical)?;
+                hidden_memory.hide(index, view, physical, mode)?;
+            }
+        }
+        Ok(())
+    }
+
+    // passive level,every hidden page gets its entry back
+    pub fn reveal_vmm_memory(&mut self) -> Result<(), &'static str> {
+        let hidden_memory = match self.hidden_memory.as_mut() {
+            Some(hidden_memory) => hidden_memory,
+            None => return Ok(()),
+        };
+
+        if let Some(ept_state) = self.ept_state.as_mut() {
+            hidden_memory.reveal(0, ept_state)?;
+        }
+        if let Some(views) = self.ept_views.as_mut() {
+            for index in 1..views.count() {
+                if let Some(view) = views.view_mut(index) {
+                    hidden_memory.reveal(index, view)?;
+                }
+            }
+        }
+        // views disabled since took their entries with them
+        hidden_memory.forget();
+
+        // no cpu may be inside the exit handler with it once every one flushed
+        self.vmcall_all_cpus(INVEPT_ALL_CONTEXT, 0)?;
+        self.hidden_memory = None;
+        Ok(())
+    }
+
+    pub fn hidden_memory_denied_count(&self) -> u64 {
+        match self.hidden_memory.as_ref() {
+            Some(hidden_memory) => hidden_memory.denied_count(),
+            None => 0,
+        }
+    }
+
+    // change msr interception on every running vcpu,each one updates its own bitmap
+    pub fn set_msr_intercept(
+        &mut self,
+        msr: u32,
+        access: MsrAccess,
+        intercept: bool,
+    ) -> Result<(), &'static str> {
+        check_msr_intercept(msr)?;
+
+        for cvcpu in &mut self.vcpu {
+            if cvcpu.vcpu_vmx_state != VcpuVmxState::VmxStateOn {
+                continue;
+            }
+
+            unsafe { KeSetSystemAffinityThread(1 << cvcpu.cpu_index) };
+
+            let result = __vmx_vmcall(MSR_INTERCEPT, msr as _, access.as_u64(), intercept as _);
+
+            unsafe { KeRevertToUserAffinityThread() };
+
+            match result {
+                VmxInstructionResult::VmxSuccess => {}
+                _ => {
+                    error!("Vmxcall execute error");
+                    return Err("msr intercept vmcall failed");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Vmm {
+    fn drop(&mut self) {
+        // no exit notify may be left running once the lock is held
+        if let Err(e) = set_process_exit_notify(false) {
+            error!("{}", e);
+        }
+        let _lock = VMM_LOCK.write();
+
+        // the views go while every cpu can still load the default one
+        if let Err(e) = self.disable_process_views() {
+            error!("{}", e);
+        }
+
+        if let Err(e) = self.reveal_vmm_memory() {
+            error!("{}", e);
+        }
+
+        // lstar back on every cpu before any stub is freed
+        if let Err(e) = self.set_syscall_trace(false) {
+            error!("{}", e);
+        }
+
+        HypervisorBackend::stop(self);
+
+        for cvcpu in &mut self.vcpu {
+            match cvcpu.vcpu_vmx_state {
+                VcpuVmxState::VmxStateOn => {
+                    error!("CPU:{} still runs vt", cvcpu.cpu_index);
+                    // the guest may still use the vmcs,leak it
+                    continue;
                 }
                 _ => {
                     if cvcpu.vmxon {
@@ -912,13 +2655,26 @@ const PAGE_SIZE: usize = 4096;
 
 #[derive(Default)]
 pub struct VMXFeatures {
-    pub secondary_controls: bool, // Secondary controls are enabled
-    pub true_msrs: bool,          // True VMX MSR values are supported
-    pub ept: bool,                // EPT supported by CPU
-    pub vpid: bool,               // VPID supported by CPU
-    pub exec_only_ept: bool,      // EPT translation with execute-only access is supported
-    pub inv_single_address: bool, // IVVPID for single address
-    pub vmfunc: bool,             // VMFUNC is supported
+    pub secondary_controls: bool,  // Secondary controls are enabled
+    pub true_msrs: bool,           // True VMX MSR values are supported
+    pub ept: bool,                 // EPT supported by CPU
+    pub vpid: bool,                // VPID supported by CPU
+    pub exec_only_ept: bool,       // EPT translation with execute-only access is supported
+    pub inv_single_address: bool,  // IVVPID for single address
+    pub inv_single_context: bool,  // INVVPID for single context
+    pub inv_retain_globals: bool,  // INVVPID for single context,retaining globals
+    pub vmfunc: bool,              // VMFUNC is supported
+    pub eptp_switching: bool,      // VMFUNC can switch between EPT views
+    pub monitor_trap_flag: bool,   // single step the guest with mtf exits
+    pub pml: bool,                 // page modification logging is supported
+    pub ept_access_dirty: bool,    // EPT accessed and dirty flags are supported
+    pub mode_based_execute: bool,  // separate EPT execute rights for user and supervisor
+    pub ept_violation_info: bool,  // EPT violations report the guest translation rights
+    pub virt_exception: bool,      // EPT violations can be delivered to the guest as #VE
+    pub sub_page_write: bool,      // EPT write permissions per 128 byte sub-page
+    pub preemption_timer: bool,    // VMX-preemption timer is supported
+    pub preemption_timer_rate: u8, // timer ticks once every 2^rate tsc ticks
+    pub virtual_nmi: bool,         // NMI exiting with virtual NMIs and NMI-window exiting
     pub in_vmware: bool,
     // meltdown: bool,                 // intel meltdown
     // spectre: bool,                  // intel and amd spectre
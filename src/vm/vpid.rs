@@ -0,0 +1,53 @@
+use super::{
+    data::{
+        invvpid_type::{ALL_CONTEXT, SINGLE_CONTEXT, SINGLE_CONTEXT_RETAINING_GLOBALS},
+        vmcs_encoding::VIRTUAL_PROCESSOR_ID,
+    },
+    ins::{VmxInstructionResult, __invvpid, vmcs_read},
+    vmx::VMXFeatures,
+};
+
+// vpid 0 belongs to the host,every vcpu runs the guest with the same tag
+pub const GUEST_VPID: u16 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+pub struct InvvpidDescriptor {
+    pub vpid: u16,
+    pub reserved: [u16; 3],
+    pub linear_address: u64,
+}
+
+// vpid of the current vmcs,0 when vpid is not enabled
+fn current_vpid() -> u16 {
+    vmcs_read(VIRTUAL_PROCESSOR_ID) as u16
+}
+
+fn invvpid(invvpid_type: u64, vpid: u16, linear_address: u64) -> VmxInstructionResult {
+    let mut descriptor = InvvpidDescriptor {
+        vpid,
+        linear_address,
+        ..Default::default()
+    };
+
+    __invvpid(invvpid_type, &mut descriptor as *mut _ as _)
+}
+
+// what a mov to cr3 flushes,global translations survive when the cpu can keep them.
+// invvpid has no per pcid type so with cr4.pcide the other pcids go too.
+// invlpg never exits,the guest runs it tagged with its own vpid.
+// without vpid every vm-entry already flushes the guest mappings
+pub fn flush_guest_context(features: &VMXFeatures) -> VmxInstructionResult {
+    let vpid = current_vpid();
+    if vpid == 0 {
+        return VmxInstructionResult::VmxSuccess;
+    }
+
+    if features.inv_retain_globals {
+        invvpid(SINGLE_CONTEXT_RETAINING_GLOBALS, vpid, 0)
+    } else if features.inv_single_context {
+        invvpid(SINGLE_CONTEXT, vpid, 0)
+    } else {
+        invvpid(ALL_CONTEXT, 0, 0)
+    }
+}
@@ -0,0 +1,277 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+use moon_vm::watch_range::watched_linear_address;
+use wdk_sys::PAGE_SIZE;
+
+use super::{
+    data::ptee,
+    ept::{EptState, EPT_ACCESS_MASK, EPT_ENTRY_WATCHPOINT, EPT_EXECUTE_MASK},
+    event_ring::EventRing,
+    guest_memory::GuestMapping,
+};
+
+pub const WATCHPOINT_MAX: usize = 16;
+// every page of a range gets its own 4kb entry
+pub const WATCHPOINT_MAX_SIZE: u64 = 0x10_0000;
+pub const WATCH_EVENT_RING_CAPACITY: usize = 1024;
+const WATCH_MAX_PENDING_EVENTS: usize = 4;
+
+pub const WATCH_READ: u32 = 1 << 0;
+pub const WATCH_WRITE: u32 = 1 << 1;
+
+// access bits of a page watched for access. write without read is a misconfiguration,so a
+// read watch takes both. without execute only support it takes execute as well
+pub fn watched_rights(rights: u64, access: u32, exec_only: bool) -> u64 {
+    let mut removed = 0;
+    if (access & WATCH_WRITE) != 0 {
+        removed |= ptee::WRITE_ACCESS;
+    }
+    if (access & WATCH_READ) != 0 {
+        removed |= ptee::READ_ACCESS | ptee::WRITE_ACCESS;
+        if !exec_only {
+            removed |= EPT_EXECUTE_MASK;
+        }
+    }
+    rights & EPT_ACCESS_MASK & !removed
+}
+
+// layout shared with user mode
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WatchpointRequest {
+    pub address: u64,
+    pub size: u64,
+    // WATCH_READ | WATCH_WRITE
+    pub access: u32,
+    pub reserved: u32,
+}
+
+// layout shared with user mode. values are the bytes at the accessed address,at most 8 and
+// never past its page
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WatchEvent {
+    pub id: u32,
+    pub access: u32,
+    pub cpu_index: u32,
+    pub reserved: u32,
+    pub rip: u64,
+    pub cr3: u64,
+    pub linear_address: u64,
+    pub physical_address: u64,
+    pub value_before: u64,
+    pub value_after: u64,
+}
+
+impl WatchEvent {
+    // vmx root,through the mapping window of the cpu
+    pub fn read_value(mapping: &mut GuestMapping, physical: u64) -> u64 {
+        let size = (PAGE_SIZE as u64 - (physical & (PAGE_SIZE as u64 - 1))).min(8) as usize;
+        let source = mapping.map(physical);
+
+        let mut bytes = [0u8; 8];
+        unsafe { core::ptr::copy_nonoverlapping(source, bytes.as_mut_ptr(), size) };
+        u64::from_le_bytes(bytes)
+    }
+}
+
+// per vcpu,completed with the value after the access on the next monitor trap flag exit
+#[derive(Default)]
+pub struct PendingWatchEvents {
+    events: [Option<WatchEvent>; WATCH_MAX_PENDING_EVENTS],
+}
+
+impl PendingWatchEvents {
+    pub fn push(&mut self, event: WatchEvent) -> Result<(), &'static str> {
+        match self.events.iter_mut().find(|event| event.is_none()) {
+            Some(slot) => {
+                *slot = Some(event);
+                Ok(())
+            }
+            None => Err("too many pending watch events"),
+        }
+    }
+
+    pub fn drain<F: FnMut(WatchEvent)>(&mut self, mut f: F) {
+        for event in self.events.iter_mut() {
+            if let Some(event) = event.take() {
+                f(event);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct WatchpointSlot {
+    // vmx root reads the other fields only while set
+    active: AtomicBool,
+    address: u64,
+    size: u64,
+    access: u32,
+    // physical page of every virtual page of the range
+    pages: Vec<u64>,
+}
+
+// a watched page keeps the rights it had before its first watchpoint
+struct WatchedPage {
+    physical: u64,
+    rights: u64,
+}
+
+pub struct Watchpoints {
+    slots: Vec<WatchpointSlot>,
+    pages: Vec<WatchedPage>,
+    // per cpu
+    rings: Vec<EventRing<WatchEvent>>,
+}
+
+impl Watchpoints {
+    pub fn new(cpu_count: usize) -> Self {
+        Self {
+            slots: (0..WATCHPOINT_MAX)
+                .map(|_| WatchpointSlot::default())
+                .collect(),
+            pages: Vec::new(),
+            rings: (0..cpu_count)
+                .map(|_| EventRing::new(WATCH_EVENT_RING_CAPACITY))
+                .collect(),
+        }
+    }
+
+    fn active(&self) -> impl Iterator<Item = (usize, &WatchpointSlot)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.active.load(Ordering::Acquire))
+    }
+
+    // accesses watched on the page by every active watchpoint
+    pub fn page_access(&self, physical: u64) -> u32 {
+        let page = physical & !(PAGE_SIZE as u64 - 1);
+        self.active()
+            .filter(|(_, slot)| slot.pages.contains(&page))
+            .fold(0, |access, (_, slot)| access | slot.access)
+    }
+
+    // rights of the page before it was watched
+    pub fn page_rights(&self, physical: u64) -> Option<u64> {
+        let page = physical & !(PAGE_SIZE as u64 - 1);
+        self.pages
+            .iter()
+            .find(|watched| watched.physical == page)
+            .map(|watched| watched.rights)
+    }
+
+    // passive level,rights are saved only for the first watchpoint on the page
+    pub fn watch_page(&mut self, physical: u64, rights: u64) {
+        if self.page_rights(physical).is_none() {
+            self.pages.push(WatchedPage { physical, rights });
+        }
+    }
+
+    // passive level,forget the rights of pages no active watchpoint covers any more
+    pub fn release_pages(&mut self) {
+        let slots = &self.slots;
+        self.pages.retain(|watched| {
+            slots
+                .iter()
+                .filter(|slot| slot.active.load(Ordering::Acquire))
+                .any(|slot| slot.pages.contains(&watched.physical))
+        });
+    }
+
+    // passive level,returns the id
+    pub fn insert(
+        &mut self,
+        address: u64,
+        size: u64,
+        access: u32,
+        pages: Vec<u64>,
+    ) -> Result<u32, &'static str> {
+        let (index, slot) = self
+            .slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| !slot.active.load(Ordering::Acquire))
+            .ok_or("too many watchpoints")?;
+
+        slot.address = address;
+        slot.size = size;
+        slot.access = access;
+        slot.pages = pages;
+        slot.active.store(true, Ordering::Release);
+        Ok(index as u32 + 1)
+    }
+
+    // passive level,returns the pages of the watchpoint. the slot keeps them until reused
+    pub fn remove(&mut self, id: u32) -> Result<Vec<u64>, &'static str> {
+        let slot = (id as usize)
+            .checked_sub(1)
+            .and_then(|index| self.slots.get(index))
+            .filter(|slot| slot.active.load(Ordering::Acquire))
+            .ok_or("watchpoint is not set")?;
+        slot.active.store(false, Ordering::Release);
+        Ok(slot.pages.clone())
+    }
+
+    // passive level,the entry of the page follows the active watchpoints on it. the caller
+    // flushes the ept on every cpu
+    pub fn apply(
+        &self,
+        ept_state: &mut EptState,
+        physical: u64,
+        exec_only: bool,
+    ) -> Result<(), &'static str> {
+        let page = physical & !(PAGE_SIZE as u64 - 1);
+        let entry = ept_state
+            .page_entry(page)
+            .ok_or("page is not split to 4kb")?;
+        let rights = self.page_rights(page).unwrap_or(entry & EPT_ACCESS_MASK);
+        let access = self.page_access(page);
+
+        let entry = if access == 0 {
+            (entry & !(EPT_ACCESS_MASK | EPT_ENTRY_WATCHPOINT)) | rights
+        } else {
+            (entry & !EPT_ACCESS_MASK)
+                | watched_rights(rights, access, exec_only)
+                | EPT_ENTRY_WATCHPOINT
+        };
+        ept_state.set_page_entry(page, entry)?;
+        Ok(())
+    }
+
+    // vmx root,the first watchpoint with the byte at physical in its range. any address space
+    // mapping the watched frames hits,the linear address is the one of the watchpoint
+    pub fn find(&self, physical: u64, access: u32) -> Option<(u32, u64)> {
+        self.active()
+            .filter(|(_, slot)| (slot.access & access) != 0)
+            .find_map(|(index, slot)| {
+                watched_linear_address(slot.address, slot.size, &slot.pages, physical)
+                    .map(|linear| (index as u32 + 1, linear))
+            })
+    }
+
+    pub fn record(&self, cpu_index: usize, event: WatchEvent) {
+        if let Some(ring) = self.rings.get(cpu_index) {
+            ring.push(event);
+        }
+    }
+
+    pub fn read_events(&self, out: &mut [WatchEvent]) -> usize {
+        let mut count = 0;
+
+        for ring in self.rings.iter() {
+            ring.drain(|event| {
+                if count == out.len() {
+                    return false;
+                }
+                out[count] = event;
+                count += 1;
+                true
+            });
+        }
+
+        count
+    }
+}